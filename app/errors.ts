@@ -0,0 +1,67 @@
+// Translation of error codes to the numbering of Solido v1.
+//
+// Monitoring built for Solido v1 matches on its numeric custom error codes.
+// This program numbers its errors from 6000, as Anchor does. The errors that
+// exist in both programs have the same meaning, this maps them to their v1
+// code. Keep in sync with `LidoError::solido_v1_code`.
+
+import {Program} from "@project-serum/anchor";
+import {Asolido} from "../target/types/asolido";
+
+// Solido v1 error code by error name, for errors that exist in both programs.
+export const SOLIDO_V1_ERROR_CODES: {[name: string]: number} = {
+  InvalidOwner: 1,
+  InvalidAmount: 2,
+  SignatureMissing: 3,
+  InvalidReserveAccount: 4,
+  CalculationFailure: 5,
+  WrongStakeState: 6,
+  InvalidFeeAmount: 7,
+  MaximumNumberOfAccountsExceeded: 8,
+  UnexpectedMaxValidators: 9,
+  InvalidManager: 10,
+  InvalidMaintainer: 11,
+  InvalidAccountInfo: 12,
+  TooManyAccountKeys: 13,
+  InvalidFeeDistributionAccount: 14,
+  InvalidValidatorCreditAccount: 15,
+  ValidatorCreditChanged: 16,
+  InvalidFeeAccount: 17,
+  InvalidFeeRecipient: 18,
+  DuplicatedEntry: 19,
+  ValidatorCreditNotFound: 20,
+  ValidatorHasUnclaimedCredit: 21,
+  ReserveIsNotRentExempt: 22,
+  AmountExceedsReserve: 23,
+  DuplicatedMaintainer: 24,
+  InvalidAccountMember: 25,
+  NoActiveValidators: 26,
+  InvalidStakeAccount: 27,
+  InvalidStSolAccount: 28,
+  ExchangeRateAlreadyUpToDate: 29,
+  ExchangeRateNotUpdatedInThisEpoch: 30,
+  ValidatorBalanceDecreased: 31,
+  InvalidStakeAuthority: 32,
+  InvalidRewardsWithdrawAuthority: 33,
+  InvalidVoteAccount: 34,
+  InvalidTokenOwner: 35,
+  ValidatorWithMoreStakeExists: 36,
+  InvalidMint: 37,
+  StakeToInactiveValidator: 38,
+  ValidatorIsStillActive: 39,
+  ValidatorShouldHaveNoStakeAccounts: 40,
+  ValidatorWithLessStakeExists: 41,
+  ValidatorShouldHaveNoUnstakeAccounts: 42,
+  MaxUnstakeAccountsReached: 43,
+  ValidatorVoteAccountHasDifferentOwner: 44,
+  InvalidStSolAccountOwner: 45,
+};
+
+// Return the Solido v1 code of the custom error `code` of this program, or null if v1 has no such error.
+export function toSolidoV1ErrorCode(program: Program<Asolido>, code: number): number | null {
+  const error = program.idl.errors.find(e => e.code === code);
+  if (error === undefined) {
+    return null;
+  }
+  return SOLIDO_V1_ERROR_CODES[error.name] ?? null;
+}
@@ -0,0 +1,30 @@
+// Helpers for maintenance bots.
+//
+// Maintenance instructions take an optional `expectedStateHash`, the hash of
+// the state that the bot computed the instruction against. If another
+// transaction changed that state first, the program rejects the instruction
+// with `MaintenanceStateChanged`, and the bot should read the state again and
+// recompute. Keep in sync with `Lido::maintenance_state_hash`.
+
+import {createHash} from "crypto";
+import {BN} from "@project-serum/anchor";
+
+// Encode `value` as a little-endian u64, as the program hashes it.
+function u64(value: BN | number): Buffer {
+  return new BN(value).toArrayLike(Buffer, "le", 8);
+}
+
+// Return the hash of the state that maintenance instructions are computed against, given a fetched Lido account.
+export function maintenanceStateHash(lido: any): number[] {
+  const hash = createHash("sha256");
+  const entries = lido.validators.entries;
+  hash.update(u64(entries.length));
+  for (const validator of entries) {
+    hash.update(validator.pubkey.toBuffer());
+    hash.update(u64(validator.entry.stakeSeeds.begin));
+    hash.update(u64(validator.entry.stakeSeeds.end));
+    hash.update(u64(validator.entry.unstakeSeeds.begin));
+    hash.update(u64(validator.entry.unstakeSeeds.end));
+  }
+  return Array.from(hash.digest());
+}
@@ -0,0 +1,40 @@
+// Helpers to find the official Solido instances through a registry.
+//
+// Anybody can initialize a Lido account with this program, so an instance
+// address from an untrusted source may belong to a look-alike. Integrators
+// should instead resolve the instance from the program id and the published
+// registry authority, which only lists instances that both the authority and
+// their managers registered.
+
+import * as anchor from "@project-serum/anchor";
+import {Program} from "@project-serum/anchor";
+import {PublicKey} from '@solana/web3.js';
+import {Asolido} from "../target/types/asolido";
+
+// Return the address of the registry of `authority`.
+export async function findRegistryAddress(programId: PublicKey, authority: PublicKey): Promise<PublicKey> {
+  const [registry, _nonce] = await PublicKey.findProgramAddress(
+    [Buffer.from(anchor.utils.bytes.utf8.encode("registry")), authority.toBuffer()], programId);
+  return registry;
+}
+
+// Return all instances that are listed in the registry of `authority`.
+export async function listOfficialInstances(program: Program<Asolido>, authority: PublicKey): Promise<PublicKey[]> {
+  const registry = await program.account.registry.fetch(await findRegistryAddress(program.programId, authority));
+  return registry.instances.entries;
+}
+
+// Return the canonical instance of the registry of `authority`, or null if it has none.
+export async function resolveCanonicalInstance(program: Program<Asolido>, authority: PublicKey): Promise<PublicKey | null> {
+  const registry = await program.account.registry.fetch(await findRegistryAddress(program.programId, authority));
+  if (registry.canonicalInstance.equals(PublicKey.default)) {
+    return null;
+  }
+  return registry.canonicalInstance;
+}
+
+// Return whether `lido` is listed in the registry of `authority`.
+export async function isOfficialInstance(program: Program<Asolido>, authority: PublicKey, lido: PublicKey): Promise<boolean> {
+  const instances = await listOfficialInstances(program, authority);
+  return instances.some((instance) => instance.equals(lido));
+}
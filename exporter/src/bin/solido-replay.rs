@@ -0,0 +1,178 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Replay `UpdateExchangeRate` and `CollectValidatorFee` against a live instance, off-chain.
+//!
+//! The replay downloads the `Lido` account, the reserve, the stake accounts,
+//! the stSOL mint, and the vote accounts of all validators, and runs the same
+//! bookkeeping as the program on them, see `SolidoSnapshot::replay`. It sends
+//! no transactions. It prints how the `Lido` account would change if both
+//! instructions ran now, so changes to that logic can be checked against
+//! mainnet state before they are deployed.
+
+use std::fmt::Display;
+
+use asolido::snapshot::{Replay, SolidoSnapshot};
+use clap::Parser;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use solido_exporter::client::SolidoProgram;
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "solido-replay",
+    about = "Replay the exchange rate update and fee collection of a Solido instance off-chain."
+)]
+struct Opts {
+    /// URL of the RPC node to read the state from.
+    #[clap(long, default_value = "http://127.0.0.1:8899")]
+    cluster: String,
+
+    /// Address of the Solido program.
+    #[clap(long)]
+    program_id: Pubkey,
+
+    /// Address of the Solido instance (the `Lido` account).
+    #[clap(long)]
+    solido_address: Pubkey,
+
+    /// Epoch to replay in, defaults to the current epoch of the cluster.
+    #[clap(long)]
+    epoch: Option<u64>,
+}
+
+/// Print `name` with its old and new value, if it changed.
+fn print_change<T: Display + PartialEq>(name: &str, before: T, after: T) {
+    if before != after {
+        println!("  {:<32} {} -> {}", name, before, after);
+    }
+}
+
+fn print_replay(snapshot: &SolidoSnapshot, replay: &Replay) {
+    let before = &snapshot.lido;
+    let after = &replay.lido;
+
+    println!("Changes to the Lido account:");
+    print_change(
+        "exchange_rate.computed_in_epoch",
+        before.exchange_rate.computed_in_epoch,
+        after.exchange_rate.computed_in_epoch,
+    );
+    print_change(
+        "exchange_rate.sol_balance",
+        before.exchange_rate.sol_balance,
+        after.exchange_rate.sol_balance,
+    );
+    print_change(
+        "exchange_rate.st_sol_supply",
+        before.exchange_rate.st_sol_supply,
+        after.exchange_rate.st_sol_supply,
+    );
+    print_change(
+        "staked_this_epoch",
+        before.staked_this_epoch,
+        after.staked_this_epoch,
+    );
+    print_change(
+        "metrics.rewards_sol_total",
+        before.metrics.rewards_sol_total,
+        after.metrics.rewards_sol_total,
+    );
+    print_change(
+        "metrics.fee_treasury_sol_total",
+        before.metrics.fee_treasury_sol_total,
+        after.metrics.fee_treasury_sol_total,
+    );
+    print_change(
+        "metrics.fee_validation_sol_total",
+        before.metrics.fee_validation_sol_total,
+        after.metrics.fee_validation_sol_total,
+    );
+    print_change(
+        "metrics.fee_developer_sol_total",
+        before.metrics.fee_developer_sol_total,
+        after.metrics.fee_developer_sol_total,
+    );
+    print_change(
+        "metrics.st_sol_appreciation_sol_total",
+        before.metrics.st_sol_appreciation_sol_total,
+        after.metrics.st_sol_appreciation_sol_total,
+    );
+    for (old, new) in before
+        .validators
+        .entries
+        .iter()
+        .zip(after.validators.entries.iter())
+    {
+        if old.entry == new.entry {
+            continue;
+        }
+        println!("  Validator {}:", old.pubkey);
+        print_change("  fee_credit", old.entry.fee_credit, new.entry.fee_credit);
+        print_change(
+            "  fee_credit_sol",
+            old.entry.fee_credit_sol,
+            new.entry.fee_credit_sol,
+        );
+        print_change(
+            "  workstate.fee_collected_epoch",
+            old.entry.workstate.fee_collected_epoch,
+            new.entry.workstate.fee_collected_epoch,
+        );
+    }
+
+    println!("Fees collected:");
+    for collected in replay.collected_fees.iter() {
+        println!("  Validator {}:", collected.vote_account);
+        println!("    Rewards:            {}", collected.rewards);
+        println!("    Treasury fee:       {}", collected.fees.treasury_st_sol);
+        println!("    Treasury fee (SOL): {}", collected.fees.treasury_sol);
+        println!(
+            "    Developer fee:      {}",
+            collected.fees.developer_st_sol
+        );
+        println!("    Developer fee (SOL): {}", collected.fees.developer_sol);
+        println!(
+            "    Validation fee:     {}",
+            collected.fees.validation_st_sol
+        );
+    }
+
+    // The exchange rate uses the balances that the instance tracks, which lag
+    // behind the observed ones until `UpdateStakeAccountBalance` runs.
+    println!(
+        "Observed SOL under management: {}, tracked: {}",
+        snapshot.total_sol, after.exchange_rate.sol_balance,
+    );
+}
+
+fn run(opts: &Opts) -> Result<(), String> {
+    let client = RpcClient::new(opts.cluster.clone());
+    let mut program = SolidoProgram::new(client, opts.program_id, opts.solido_address);
+    let state = program.get_state()?;
+    let snapshot = &state.snapshot;
+    let epoch = opts.epoch.unwrap_or(state.clock.epoch);
+
+    let replay = snapshot
+        .replay(epoch, &state.accounts, &state.rent)
+        .map_err(|err| format!("Failed to replay: {:?}", err))?;
+
+    println!(
+        "Replaying in epoch {} against {}.",
+        epoch, opts.solido_address
+    );
+    if snapshot.lido.exchange_rate.computed_in_epoch >= epoch {
+        println!("Note: the exchange rate was already updated in this epoch, the program would not update it again.");
+    }
+    print_replay(snapshot, &replay);
+
+    Ok(())
+}
+
+fn main() {
+    let opts = Opts::parse();
+    if let Err(err) = run(&opts) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
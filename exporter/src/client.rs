@@ -0,0 +1,258 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! A handle on a Solido instance, for off-chain clients.
+//!
+//! [`SolidoProgram`] derives the program addresses of an instance once, and
+//! caches the addresses of stake accounts across calls, because deriving them
+//! is slow. [`SolidoProgram::get_state`] reads everything that a
+//! `SolidoSnapshot` and its replay need in two rounds of `getMultipleAccounts`:
+//! first the `Lido` account and the accounts at fixed addresses, and then the
+//! accounts that the `Lido` state refers to.
+
+use std::collections::HashMap;
+
+use anchor_lang::AccountDeserialize;
+use asolido::pda;
+use asolido::snapshot::{iter_seeds, AccountSnapshot, SolidoSnapshot};
+use asolido::state::{Lido, StakeType};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::clock::Clock;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::rent::Rent;
+use solana_sdk::sysvar;
+
+/// The maximum number of accounts that `getMultipleAccounts` accepts.
+pub const MAX_ACCOUNTS_PER_REQUEST: usize = 100;
+
+/// Error while reading the state from chain, stringified for logging.
+pub type ClientError = String;
+
+/// The program-derived addresses of an instance that do not depend on its state.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InstanceAddresses {
+    pub reserve: Pubkey,
+    pub mint_authority: Pubkey,
+    pub stake_authority: Pubkey,
+    pub rewards_withdraw_authority: Pubkey,
+    pub config: Pubkey,
+    pub exchange_rate_history: Pubkey,
+}
+
+impl InstanceAddresses {
+    pub fn new(program_id: &Pubkey, lido_address: &Pubkey) -> InstanceAddresses {
+        InstanceAddresses {
+            reserve: pda::find_reserve_address(program_id, lido_address).0,
+            mint_authority: pda::find_mint_authority(program_id, lido_address).0,
+            stake_authority: pda::find_stake_authority(program_id, lido_address).0,
+            rewards_withdraw_authority: pda::find_rewards_withdraw_authority(
+                program_id,
+                lido_address,
+            )
+            .0,
+            config: pda::find_config_address(program_id, lido_address).0,
+            exchange_rate_history: pda::find_exchange_rate_history_address(
+                program_id,
+                lido_address,
+            )
+            .0,
+        }
+    }
+}
+
+/// The state of an instance, as read by [`SolidoProgram::get_state`].
+pub struct SolidoState {
+    pub snapshot: SolidoSnapshot,
+    pub rent: Rent,
+    pub clock: Clock,
+
+    /// All accounts that were read, by address.
+    ///
+    /// Besides the accounts of the snapshot, these include the stSOL mint and
+    /// the vote accounts, so `SolidoSnapshot::replay` needs no further reads.
+    pub accounts: HashMap<Pubkey, AccountSnapshot>,
+}
+
+/// A handle on one Solido instance, with a cache of its program-derived addresses.
+pub struct SolidoProgram {
+    client: RpcClient,
+    program_id: Pubkey,
+    lido_address: Pubkey,
+    addresses: InstanceAddresses,
+
+    /// Addresses of stake and unstake accounts by vote account and seed.
+    stake_accounts: HashMap<(Pubkey, u64, StakeType), Pubkey>,
+}
+
+impl SolidoProgram {
+    pub fn new(client: RpcClient, program_id: Pubkey, lido_address: Pubkey) -> SolidoProgram {
+        SolidoProgram {
+            client,
+            program_id,
+            lido_address,
+            addresses: InstanceAddresses::new(&program_id, &lido_address),
+            stake_accounts: HashMap::new(),
+        }
+    }
+
+    pub fn client(&self) -> &RpcClient {
+        &self.client
+    }
+
+    pub fn program_id(&self) -> &Pubkey {
+        &self.program_id
+    }
+
+    pub fn lido_address(&self) -> &Pubkey {
+        &self.lido_address
+    }
+
+    pub fn addresses(&self) -> &InstanceAddresses {
+        &self.addresses
+    }
+
+    /// Return the address of the stake or unstake account of a validator, deriving it only once.
+    pub fn stake_account_address(
+        &mut self,
+        validator_vote: &Pubkey,
+        seed: u64,
+        stake_type: StakeType,
+    ) -> Pubkey {
+        let program_id = &self.program_id;
+        let lido_address = &self.lido_address;
+        *self
+            .stake_accounts
+            .entry((*validator_vote, seed, stake_type))
+            .or_insert_with(|| match stake_type {
+                StakeType::Stake => {
+                    pda::find_stake_account(program_id, lido_address, validator_vote, seed).0
+                }
+                StakeType::Unstake => {
+                    pda::find_unstake_account(program_id, lido_address, validator_vote, seed).0
+                }
+            })
+    }
+
+    /// Fetch the given accounts into `accounts`, leaving out the ones that do not exist.
+    ///
+    /// Reads at most `MAX_ACCOUNTS_PER_REQUEST` accounts per request.
+    pub fn fetch_accounts(
+        &self,
+        addresses: &[Pubkey],
+        accounts: &mut HashMap<Pubkey, AccountSnapshot>,
+    ) -> Result<(), ClientError> {
+        for chunk in addresses.chunks(MAX_ACCOUNTS_PER_REQUEST) {
+            let chunk_accounts = self
+                .client
+                .get_multiple_accounts(chunk)
+                .map_err(|err| format!("Failed to read accounts: {}", err))?;
+            for (address, account) in chunk.iter().zip(chunk_accounts) {
+                // Accounts that do not exist are left out, the snapshot reports them.
+                if let Some(account) = account {
+                    let snapshot = AccountSnapshot {
+                        lamports: account.lamports,
+                        data: account.data,
+                        owner: account.owner,
+                    };
+                    accounts.insert(*address, snapshot);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the instance and build a snapshot of it.
+    ///
+    /// The first request reads the `Lido` account, the rent and clock
+    /// sysvars, and the reserve. The second one, split in chunks for large
+    /// instances, reads the stake accounts, the stSOL mint, and the vote
+    /// accounts that the `Lido` account lists.
+    pub fn get_state(&mut self) -> Result<SolidoState, ClientError> {
+        let mut accounts = HashMap::new();
+        let fixed_addresses = [
+            self.lido_address,
+            sysvar::rent::id(),
+            sysvar::clock::id(),
+            self.addresses.reserve,
+        ];
+        self.fetch_accounts(&fixed_addresses, &mut accounts)?;
+
+        let get_data = |address: &Pubkey, name: &str| {
+            accounts
+                .get(address)
+                .map(|account| account.data.clone())
+                .ok_or_else(|| format!("The {} account {} does not exist.", name, address))
+        };
+        let lido_data = get_data(&self.lido_address, "Solido")?;
+        let rent: Rent = bincode::deserialize(&get_data(&sysvar::rent::id(), "rent sysvar")?)
+            .map_err(|err| format!("Failed to deserialize rent sysvar: {}", err))?;
+        let clock: Clock = bincode::deserialize(&get_data(&sysvar::clock::id(), "clock sysvar")?)
+            .map_err(|err| format!("Failed to deserialize clock sysvar: {}", err))?;
+        let lido = Lido::try_deserialize(&mut &lido_data[..])
+            .map_err(|err| format!("Failed to deserialize Solido account: {:?}", err))?;
+
+        let mut addresses = vec![lido.st_sol_mint];
+        for validator in lido.validators.entries.iter() {
+            if !validator.entry.vote_account_closed {
+                addresses.push(validator.pubkey);
+            }
+            for (seed, stake_type) in iter_seeds(&validator.entry) {
+                addresses.push(self.stake_account_address(&validator.pubkey, seed, stake_type));
+            }
+        }
+        self.fetch_accounts(&addresses, &mut accounts)?;
+
+        let stake_accounts = &self.stake_accounts;
+        let snapshot = SolidoSnapshot::new_with_addresses(
+            &self.lido_address,
+            &self.addresses.reserve,
+            &lido_data,
+            &accounts,
+            &rent,
+            |validator, seed, stake_type| stake_accounts[&(validator.pubkey, seed, stake_type)],
+        )
+        .map_err(|err| format!("Failed to build snapshot: {:?}", err))?;
+
+        Ok(SolidoState {
+            snapshot,
+            rent,
+            clock,
+            accounts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cached_stake_account_addresses_match_the_program() {
+        let program_id = Pubkey::new_unique();
+        let lido_address = Pubkey::new_unique();
+        let vote = Pubkey::new_unique();
+        // The client does not connect until it sends a request.
+        let client = RpcClient::new("http://127.0.0.1:8899".to_string());
+        let mut program = SolidoProgram::new(client, program_id, lido_address);
+
+        let (stake, _) = pda::find_stake_account(&program_id, &lido_address, &vote, 3);
+        let (unstake, _) = pda::find_unstake_account(&program_id, &lido_address, &vote, 3);
+        assert_eq!(
+            program.stake_account_address(&vote, 3, StakeType::Stake),
+            stake
+        );
+        assert_eq!(
+            program.stake_account_address(&vote, 3, StakeType::Unstake),
+            unstake
+        );
+        // The second lookup comes from the cache.
+        assert_eq!(
+            program.stake_account_address(&vote, 3, StakeType::Stake),
+            stake
+        );
+        assert_eq!(program.stake_accounts.len(), 2);
+
+        let (reserve, _) = pda::find_reserve_address(&program_id, &lido_address);
+        assert_eq!(program.addresses().reserve, reserve);
+    }
+}
@@ -0,0 +1,6 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Off-chain client for Solido instances, shared by the exporter and the replay tool.
+
+pub mod client;
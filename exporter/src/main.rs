@@ -0,0 +1,403 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Export the on-chain metrics of a Solido instance in Prometheus format.
+//!
+//! The exporter periodically reads the `Lido` account, the reserve, and the
+//! stake accounts, and serves the latest values at `/metrics`. Operators can
+//! alert on e.g. `solido_current_epoch - solido_exchange_rate_computed_epoch > 0`
+//! to detect missed exchange rate updates, or on a growing
+//! `solido_validator_fee_credit_st_sol` to detect stalled fee collection.
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use asolido::snapshot::{SolidoSnapshot, ValidatorSnapshot};
+use clap::Parser;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use solido_exporter::client::{ClientError, SolidoProgram};
+
+mod prometheus;
+
+use crate::prometheus::{write_metric, Metric, MetricFamily};
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "solido-exporter",
+    about = "Serve Solido metrics in Prometheus format."
+)]
+struct Opts {
+    /// URL of the RPC node to read the state from.
+    #[clap(long, default_value = "http://127.0.0.1:8899")]
+    cluster: String,
+
+    /// Address of the Solido program.
+    #[clap(long)]
+    program_id: Pubkey,
+
+    /// Address of the Solido instance (the `Lido` account).
+    #[clap(long)]
+    solido_address: Pubkey,
+
+    /// Address and port to serve `/metrics` on.
+    #[clap(long, default_value = "0.0.0.0:8923")]
+    listen: String,
+
+    /// How often to read the state from chain.
+    #[clap(long, default_value = "30")]
+    poll_interval_seconds: u64,
+}
+
+/// The most recent state read from chain.
+struct Observation {
+    snapshot: SolidoSnapshot,
+    current_epoch: u64,
+    observed_at_unix_seconds: u64,
+}
+
+fn fetch_snapshot(program: &mut SolidoProgram) -> Result<Observation, ClientError> {
+    let state = program.get_state()?;
+
+    let observed_at_unix_seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    Ok(Observation {
+        snapshot: state.snapshot,
+        current_epoch: state.clock.epoch,
+        observed_at_unix_seconds,
+    })
+}
+
+fn write_observation<W: io::Write>(out: &mut W, observation: &Observation) -> io::Result<()> {
+    let snapshot = &observation.snapshot;
+    let lido = &snapshot.lido;
+    let metrics = &lido.metrics;
+
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_poll_unix_seconds",
+            help: "Time at which the state was last read from chain.",
+            type_: "gauge",
+            metrics: vec![Metric::new(observation.observed_at_unix_seconds)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_current_epoch",
+            help: "Epoch of the cluster at the time the state was read.",
+            type_: "gauge",
+            metrics: vec![Metric::new(observation.current_epoch)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_exchange_rate_computed_epoch",
+            help: "Epoch in which the exchange rate was last updated.",
+            type_: "gauge",
+            metrics: vec![Metric::new(lido.exchange_rate.computed_in_epoch)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_exchange_rate_sol_balance_sol",
+            help: "SOL balance used in the current exchange rate.",
+            type_: "gauge",
+            metrics: vec![Metric::new_sol(lido.exchange_rate.sol_balance.amount)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_exchange_rate_st_sol_supply_st_sol",
+            help: "stSOL supply used in the current exchange rate.",
+            type_: "gauge",
+            metrics: vec![Metric::new_sol(lido.exchange_rate.st_sol_supply.amount)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_reserve_sol",
+            help: "SOL in the reserve that is available for staking.",
+            type_: "gauge",
+            metrics: vec![Metric::new_sol(snapshot.reserve_available.amount)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_total_sol",
+            help: "Observed SOL under management, including rewards not yet reflected in the exchange rate.",
+            type_: "gauge",
+            metrics: vec![Metric::new_sol(snapshot.total_sol.amount)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_fees_sol_total",
+            help: "Fees paid since we started tracking, in SOL at the time of payment.",
+            type_: "counter",
+            metrics: vec![
+                Metric::new_sol(metrics.fee_treasury_sol_total.amount)
+                    .with_label("recipient", "treasury".to_string()),
+                Metric::new_sol(metrics.fee_validation_sol_total.amount)
+                    .with_label("recipient", "validation".to_string()),
+                Metric::new_sol(metrics.fee_developer_sol_total.amount)
+                    .with_label("recipient", "developer".to_string()),
+                Metric::new_sol(metrics.st_sol_appreciation_sol_total.amount)
+                    .with_label("recipient", "st_sol_appreciation".to_string()),
+            ],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_fees_st_sol_total",
+            help: "Fees paid since we started tracking, in stSOL.",
+            type_: "counter",
+            metrics: vec![
+                Metric::new_sol(metrics.fee_treasury_st_sol_total.amount)
+                    .with_label("recipient", "treasury".to_string()),
+                Metric::new_sol(metrics.fee_validation_st_sol_total.amount)
+                    .with_label("recipient", "validation".to_string()),
+                Metric::new_sol(metrics.fee_developer_st_sol_total.amount)
+                    .with_label("recipient", "developer".to_string()),
+            ],
+        },
+    )?;
+
+    let mut deposit_metrics = Vec::new();
+    let bounds = asolido::metrics::LamportsHistogram::BUCKET_UPPER_BOUNDS;
+    for (count, upper_bound) in metrics.deposit_amount.counts.iter().zip(bounds.iter()) {
+        let le = if upper_bound.amount == u64::MAX {
+            "+Inf".to_string()
+        } else {
+            let amount = upper_bound.amount;
+            format!("{}.{:0>9}", amount / 1_000_000_000, amount % 1_000_000_000)
+        };
+        deposit_metrics.push(
+            Metric::new(*count)
+                .with_suffix("_bucket")
+                .with_label("le", le),
+        );
+    }
+    deposit_metrics.push(Metric::new_sol(metrics.deposit_amount.total.amount).with_suffix("_sum"));
+    deposit_metrics
+        .push(Metric::new(metrics.deposit_amount.num_observations()).with_suffix("_count"));
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_deposits_sol",
+            help: "Histogram of deposits since we started tracking.",
+            type_: "histogram",
+            metrics: deposit_metrics,
+        },
+    )?;
+
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_withdrawals_total",
+            help: "Number of withdrawals since we started tracking.",
+            type_: "counter",
+            metrics: vec![Metric::new(metrics.withdraw_amount.count)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_withdrawals_sol_total",
+            help: "SOL withdrawn since we started tracking.",
+            type_: "counter",
+            metrics: vec![Metric::new_sol(
+                metrics.withdraw_amount.total_sol_amount.amount,
+            )],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_withdrawals_st_sol_total",
+            help: "stSOL burned by withdrawals since we started tracking.",
+            type_: "counter",
+            metrics: vec![Metric::new_sol(
+                metrics.withdraw_amount.total_st_sol_amount.amount,
+            )],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_validator_loss_sol_total",
+            help: "Validator losses acknowledged by the manager since we started tracking.",
+            type_: "counter",
+            metrics: vec![Metric::new_sol(metrics.validator_loss_sol_total.amount)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_donation_sol_total",
+            help: "SOL donated to the reserve since we started tracking.",
+            type_: "counter",
+            metrics: vec![Metric::new_sol(metrics.donation_sol_total.amount)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_reserve_idle_sol_total",
+            help: "Available reserve balance at every exchange rate update, summed.",
+            type_: "counter",
+            metrics: vec![Metric::new_sol(metrics.reserve_idle_sol_total.amount)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_reserve_idle_observations_total",
+            help: "Number of exchange rate updates included in solido_reserve_idle_sol_total.",
+            type_: "counter",
+            metrics: vec![Metric::new(metrics.reserve_idle_observations)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_rewards_sol_total",
+            help: "Validation rewards before fees since we started tracking.",
+            type_: "counter",
+            metrics: vec![Metric::new_sol(metrics.rewards_sol_total.amount)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_epoch_deposits_sol",
+            help: "SOL deposited since the last exchange rate update.",
+            type_: "gauge",
+            metrics: vec![Metric::new_sol(metrics.this_epoch.deposit_sol.amount)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_epoch_withdrawals_sol",
+            help: "SOL withdrawn since the last exchange rate update.",
+            type_: "gauge",
+            metrics: vec![Metric::new_sol(metrics.this_epoch.withdraw_sol.amount)],
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_epoch_rewards_sol",
+            help: "Validation rewards before fees since the last exchange rate update.",
+            type_: "gauge",
+            metrics: vec![Metric::new_sol(metrics.this_epoch.rewards_sol.amount)],
+        },
+    )?;
+
+    let per_validator = |f: &dyn Fn(&ValidatorSnapshot) -> Metric<'static>| {
+        snapshot
+            .validators
+            .iter()
+            .map(|v| f(v).with_label("vote_account", v.vote_account.to_string()))
+            .collect::<Vec<_>>()
+    };
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_validator_stake_sol",
+            help:
+                "Observed balance of the stake accounts of a validator, excluding unstake accounts.",
+            type_: "gauge",
+            metrics: per_validator(&|v| Metric::new_sol(v.effective_stake.amount)),
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_validator_unstaking_sol",
+            help: "Observed balance of the unstake accounts of a validator.",
+            type_: "gauge",
+            metrics: per_validator(&|v| Metric::new_sol(v.unstaking.amount)),
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_validator_fee_credit_st_sol",
+            help: "Validation fees owed to a validator that have not been claimed yet.",
+            type_: "gauge",
+            metrics: per_validator(&|v| Metric::new_sol(v.entry.fee_credit.amount)),
+        },
+    )?;
+    write_metric(
+        out,
+        &MetricFamily {
+            name: "solido_validator_active",
+            help: "Whether the validator is active (1) or deactivated (0).",
+            type_: "gauge",
+            metrics: per_validator(&|v| Metric::new(v.entry.active as u64)),
+        },
+    )?;
+
+    Ok(())
+}
+
+fn serve(listen: &str, latest: Arc<Mutex<Option<Observation>>>) {
+    let server = tiny_http::Server::http(listen).expect("Failed to bind the metrics server.");
+    println!("Serving metrics at http://{}/metrics", listen);
+
+    for request in server.incoming_requests() {
+        if request.url() != "/metrics" {
+            let _ = request.respond(tiny_http::Response::empty(404));
+            continue;
+        }
+
+        let mut body = Vec::new();
+        let status = match *latest.lock().unwrap() {
+            Some(ref observation) => match write_observation(&mut body, observation) {
+                Ok(()) => 200,
+                Err(_) => 500,
+            },
+            // We have not yet been able to read the state from chain.
+            None => 503,
+        };
+        let response = tiny_http::Response::from_data(body).with_status_code(status);
+        let _ = request.respond(response);
+    }
+}
+
+fn main() {
+    let opts = Opts::parse();
+    let latest: Arc<Mutex<Option<Observation>>> = Arc::new(Mutex::new(None));
+
+    let latest_for_server = latest.clone();
+    let listen = opts.listen.clone();
+    thread::spawn(move || serve(&listen, latest_for_server));
+
+    let client = RpcClient::new(opts.cluster.clone());
+    // The program keeps its cache of derived addresses between polls.
+    let mut program = SolidoProgram::new(client, opts.program_id, opts.solido_address);
+    loop {
+        match fetch_snapshot(&mut program) {
+            Ok(observation) => *latest.lock().unwrap() = Some(observation),
+            // Keep serving the previous observation; `solido_poll_unix_seconds`
+            // shows how old it is, so operators can alert on that.
+            Err(err) => eprintln!("{}", err),
+        }
+        thread::sleep(Duration::from_secs(opts.poll_interval_seconds));
+    }
+}
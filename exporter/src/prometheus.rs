@@ -0,0 +1,232 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Utilities for formatting Prometheus metrics.
+//!
+//! See also <https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format>.
+
+use std::io;
+use std::io::Write;
+
+pub struct MetricFamily<'a> {
+    /// Name of the metric, e.g. `goats_teleported_total`.
+    pub name: &'a str,
+    /// HELP line content.
+    pub help: &'a str,
+    /// TYPE line content. Most common are `counter`, `gauge`, and `histogram`.
+    pub type_: &'a str,
+    /// Values for this metric, possibly with labels or a suffix.
+    pub metrics: Vec<Metric<'a>>,
+}
+
+pub enum MetricValue {
+    /// A value that is formatted as a decimal with 9 digits after the point, for SOL and stSOL.
+    Nano(u64),
+    /// A plain integer value.
+    Int(u64),
+}
+
+pub struct Metric<'a> {
+    /// Suffix to append to the metric name, useful for e.g. the `_bucket` suffix on histograms.
+    pub suffix: &'a str,
+
+    /// Name-value label pairs.
+    pub labels: Vec<(&'a str, String)>,
+
+    /// Metric value.
+    pub value: MetricValue,
+}
+
+impl<'a> Metric<'a> {
+    /// Construct a basic metric with just a value.
+    pub fn new(value: u64) -> Self {
+        Self {
+            suffix: "",
+            labels: Vec::new(),
+            value: MetricValue::Int(value),
+        }
+    }
+
+    /// Construct a metric that measures an amount of SOL or stSOL, given in Lamports.
+    pub fn new_sol(amount: u64) -> Self {
+        Self {
+            suffix: "",
+            labels: Vec::new(),
+            value: MetricValue::Nano(amount),
+        }
+    }
+
+    /// Set the suffix.
+    pub fn with_suffix(mut self, suffix: &'a str) -> Self {
+        self.suffix = suffix;
+        self
+    }
+
+    /// Add a label.
+    pub fn with_label(mut self, label_key: &'a str, label_value: String) -> Self {
+        self.labels.push((label_key, label_value));
+        self
+    }
+}
+
+pub fn write_metric<W: Write>(out: &mut W, family: &MetricFamily) -> io::Result<()> {
+    writeln!(out, "# HELP {} {}", family.name, family.help)?;
+    writeln!(out, "# TYPE {} {}", family.name, family.type_)?;
+    for metric in &family.metrics {
+        write!(out, "{}{}", family.name, metric.suffix)?;
+
+        // If there are labels, write the key-value pairs between {}.
+        // Escaping of the value uses Rust's string syntax, which is
+        // not exactly what Prometheus wants, but it is identical for
+        // all of the values that we use it with; this is not a general
+        // Prometheus formatter, just a quick one for our use.
+        if !metric.labels.is_empty() {
+            write!(out, "{{")?;
+            let mut separator = "";
+            for (key, value) in &metric.labels {
+                write!(out, "{}{}={:?}", separator, key, value)?;
+                separator = ",";
+            }
+            write!(out, "}}")?;
+        }
+
+        match metric.value {
+            MetricValue::Nano(v) => {
+                writeln!(out, " {}.{:0>9}", v / 1_000_000_000, v % 1_000_000_000)?
+            }
+            MetricValue::Int(v) => writeln!(out, " {}", v)?,
+        }
+    }
+
+    // Add a blank line for readability by humans.
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str;
+
+    use super::{write_metric, Metric, MetricFamily};
+
+    #[test]
+    fn write_metric_without_labels() {
+        let mut out: Vec<u8> = Vec::new();
+        write_metric(
+            &mut out,
+            &MetricFamily {
+                name: "goats_teleported_total",
+                help: "Number of goats teleported since launch.",
+                type_: "counter",
+                metrics: vec![Metric::new(144)],
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            str::from_utf8(&out[..]),
+            Ok(
+                "# HELP goats_teleported_total Number of goats teleported since launch.\n\
+                 # TYPE goats_teleported_total counter\n\
+                 goats_teleported_total 144\n\n\
+                "
+            )
+        )
+    }
+
+    #[test]
+    fn write_metric_histogram() {
+        let mut out: Vec<u8> = Vec::new();
+        write_metric(
+            &mut out,
+            &MetricFamily {
+                name: "teleported_goat_weight_kg",
+                help: "Histogram of the weight of teleported goats.",
+                type_: "histogram",
+                metrics: vec![
+                    Metric::new(44)
+                        .with_suffix("_bucket")
+                        .with_label("le", "50.0".to_string()),
+                    Metric::new(67)
+                        .with_suffix("_bucket")
+                        .with_label("le", "75.0".to_string()),
+                    Metric::new(144)
+                        .with_suffix("_bucket")
+                        .with_label("le", "+Inf".to_string()),
+                    Metric::new(11520).with_suffix("_sum"),
+                    Metric::new(144).with_suffix("_count"),
+                ],
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            str::from_utf8(&out[..]),
+            Ok(
+                "# HELP teleported_goat_weight_kg Histogram of the weight of teleported goats.\n\
+                 # TYPE teleported_goat_weight_kg histogram\n\
+                 teleported_goat_weight_kg_bucket{le=\"50.0\"} 44\n\
+                 teleported_goat_weight_kg_bucket{le=\"75.0\"} 67\n\
+                 teleported_goat_weight_kg_bucket{le=\"+Inf\"} 144\n\
+                 teleported_goat_weight_kg_sum 11520\n\
+                 teleported_goat_weight_kg_count 144\n\n\
+                "
+            )
+        )
+    }
+
+    #[test]
+    fn write_metric_multiple_labels() {
+        let mut out: Vec<u8> = Vec::new();
+        write_metric(
+            &mut out,
+            &MetricFamily {
+                name: "goats_teleported_total",
+                help: "Number of goats teleported since launch by departure and arrival.",
+                type_: "counter",
+                metrics: vec![
+                    Metric::new(10)
+                        .with_label("src", "AMS".to_string())
+                        .with_label("dst", "ZRH".to_string()),
+                    Metric::new(53)
+                        .with_label("src", "ZRH".to_string())
+                        .with_label("dst", "DXB".to_string()),
+                ],
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            str::from_utf8(&out[..]),
+            Ok(
+                "# HELP goats_teleported_total Number of goats teleported since launch by departure and arrival.\n\
+                 # TYPE goats_teleported_total counter\n\
+                 goats_teleported_total{src=\"AMS\",dst=\"ZRH\"} 10\n\
+                 goats_teleported_total{src=\"ZRH\",dst=\"DXB\"} 53\n\n\
+                "
+            )
+        )
+    }
+
+    #[test]
+    fn write_metric_sol() {
+        let mut out: Vec<u8> = Vec::new();
+        write_metric(
+            &mut out,
+            &MetricFamily {
+                name: "solido_reserve_sol",
+                help: "SOL in the reserve.",
+                type_: "gauge",
+                metrics: vec![Metric::new_sol(1_500_000_001)],
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            str::from_utf8(&out[..]),
+            Ok("# HELP solido_reserve_sol SOL in the reserve.\n\
+                # TYPE solido_reserve_sol gauge\n\
+                solido_reserve_sol 1.500000001\n\n\
+               ")
+        )
+    }
+}
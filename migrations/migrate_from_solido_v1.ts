@@ -0,0 +1,121 @@
+// Migrate a Solido v1 instance into a freshly initialized instance of this program.
+//
+// Usage:
+//
+//   SOLIDO_V1=<V1_LIDO_ADDRESS> LIDO=<LIDO_ADDRESS> anchor run migrate-from-solido-v1
+//
+// The wallet of the provider must be the manager of both instances. The v1
+// instance must have staked its reserve, and handed over the mint authority of
+// its stSOL mint, and the stake and withdraw authority of its stake accounts,
+// to this instance. v1 has no instructions for the handover, it takes an
+// upgrade of the v1 program, see `solido_v1.rs`. The script runs
+// `migrateFromSolidoV1`, and then moves the stake accounts of every validator,
+// in seed order, with `migrateStakeFromSolidoV1`.
+
+import * as anchor from "@project-serum/anchor";
+import {Program, web3, BN} from "@project-serum/anchor";
+import {PublicKey} from '@solana/web3.js';
+import {Asolido} from "../target/types/asolido";
+
+const SOLIDO_V1_PROGRAM_ID = new PublicKey("CrX7kMhLC3cSsXJdT7JDgqrRVWGnUpX3gfEfxxU2NVLi");
+
+// Size of the v1 `Lido` fields before the validators, see `solido_v1.rs`.
+const LIDO_V1_HEADER_SIZE = 357;
+const VALIDATOR_V1_ENTRY_SIZE = 32 + 89;
+
+interface ValidatorV1 {
+  vote: PublicKey;
+  stakeSeeds: [number, number];
+  unstakeSeeds: [number, number];
+}
+
+function decodeLidoV1(data: Buffer): {stSolMint: PublicKey, validators: ValidatorV1[]} {
+  const stSolMint = new PublicKey(data.slice(33, 65));
+  const numValidators = data.readUInt32LE(LIDO_V1_HEADER_SIZE);
+  const validators: ValidatorV1[] = [];
+  for (let i = 0; i < numValidators; i++) {
+    const offset = LIDO_V1_HEADER_SIZE + 4 + i * VALIDATOR_V1_ENTRY_SIZE;
+    const seed = (at: number) => new BN(data.slice(offset + 32 + at, offset + 40 + at), "le").toNumber();
+    validators.push({
+      vote: new PublicKey(data.slice(offset, offset + 32)),
+      stakeSeeds: [seed(40), seed(48)],
+      unstakeSeeds: [seed(56), seed(64)],
+    });
+  }
+  return {stSolMint, validators};
+}
+
+async function findValidatorAccount(
+  programId: PublicKey, lido: PublicKey, vote: PublicKey, kind: string, seed: number
+): Promise<PublicKey> {
+  const [address, _nonce] = await PublicKey.findProgramAddress(
+    [lido.toBuffer(), vote.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode(kind)), new BN(seed).toArrayLike(Buffer, "le", 8)],
+    programId);
+  return address;
+}
+
+async function main() {
+  anchor.setProvider(anchor.Provider.env());
+  const provider = anchor.getProvider();
+  const program = anchor.workspace.Asolido as Program<Asolido>;
+
+  const solidoV1 = new PublicKey(process.env.SOLIDO_V1);
+  const lido = new PublicKey(process.env.LIDO);
+
+  const v1Account = await provider.connection.getAccountInfo(solidoV1);
+  const v1 = decodeLidoV1(v1Account.data);
+  const lidoAccount = await program.account.lido.fetch(lido);
+  const [solidoV1Reserve, _nonce] = await PublicKey.findProgramAddress(
+    [solidoV1.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("reserve_account"))],
+    SOLIDO_V1_PROGRAM_ID);
+
+  await program.methods.migrateFromSolidoV1()
+    .accounts({
+      lido: lido,
+      manager: provider.wallet.publicKey,
+      solidoV1: solidoV1,
+      solidoV1Reserve: solidoV1Reserve,
+      currentStSolMint: lidoAccount.stSolMint,
+      stSolMint: v1.stSolMint,
+      rent: web3.SYSVAR_RENT_PUBKEY,
+    })
+    .rpc();
+  console.log(`Migrated the state of ${solidoV1} into ${lido}.`);
+
+  const kinds = [
+    {stakeType: {stake: {}}, seedName: "validator_stake_account", seeds: (v: ValidatorV1) => v.stakeSeeds, field: "stakeSeeds"},
+    {stakeType: {unstake: {}}, seedName: "validator_unstake_account", seeds: (v: ValidatorV1) => v.unstakeSeeds, field: "unstakeSeeds"},
+  ];
+  for (const validator of v1.validators) {
+    for (const kind of kinds) {
+      const [begin, end] = kind.seeds(validator);
+      for (let seed = begin; seed < end; seed++) {
+        const current = await program.account.lido.fetch(lido);
+        const entry = (current.validators as any).entries.find((pe: any) => pe.pubkey.equals(validator.vote)).entry;
+        const source = await findValidatorAccount(SOLIDO_V1_PROGRAM_ID, solidoV1, validator.vote, kind.seedName, seed);
+        const destination = await findValidatorAccount(
+          program.programId, lido, validator.vote, kind.seedName, entry[kind.field].end.toNumber());
+
+        await program.methods.migrateStakeFromSolidoV1(kind.stakeType as any, new BN(seed))
+          .accounts({
+            lido: lido,
+            manager: provider.wallet.publicKey,
+            solidoV1: solidoV1,
+            validatorVote: validator.vote,
+            sourceStakeAccount: source,
+            destinationStakeAccount: destination,
+          })
+          .rpc();
+        console.log(`Moved ${source} to ${destination}.`);
+      }
+    }
+  }
+}
+
+main().then(
+  () => process.exit(0),
+  (err) => {
+    console.error(err);
+    process.exit(1);
+  },
+);
@@ -0,0 +1,117 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Proof of the SOL that backs stSOL, for consumers on other chains.
+//!
+//! Bridges and issuers of wrapped stSOL want a recent proof that the SOL is
+//! there, rather than trust the balances that the `Lido` account tracks.
+//! `AttestReserves` reads the balances of the reserve and of every stake and
+//! unstake account, and writes their sum into a `ReserveAttestation` account
+//! next to the instance. Only this program can write to that account, so its
+//! contents, together with the slot in which they were read, are the proof.
+
+use anchor_lang::prelude::*;
+
+use crate::token::{self, Lamports, StLamports};
+
+/// The observed balance of the stake and unstake accounts of one validator.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct ValidatorReserves {
+    pub validator_vote: Pubkey,
+
+    /// Sum of the balances of the validator's stake and unstake accounts.
+    pub balance: Lamports,
+}
+
+impl ValidatorReserves {
+    /// Number of bytes that a serialized `ValidatorReserves` takes.
+    pub const LEN: usize = 32 + 8;
+}
+
+#[account]
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct ReserveAttestation {
+    /// The instance whose reserves are attested.
+    pub lido: Pubkey,
+
+    /// Slot in which the balances were read.
+    pub slot: u64,
+
+    /// Epoch in which the balances were read.
+    pub epoch: u64,
+
+    /// Balance of the reserve account, including its rent-exempt minimum.
+    pub reserve_balance: Lamports,
+
+    /// Sum of `reserve_balance` and the balances of all validators.
+    pub total_sol: Lamports,
+
+    /// Supply of the stSOL mint at the same time.
+    pub st_sol_supply: StLamports,
+
+    /// The balances of the validators, in the order of `Lido::validators`.
+    pub validators: Vec<ValidatorReserves>,
+}
+
+impl ReserveAttestation {
+    /// Number of bytes needed for an attestation of at most `max_validators` validators.
+    pub fn required_bytes(max_validators: usize) -> usize {
+        8 + 32 + 8 + 8 + 8 + 8 + 8 + (4 + max_validators * ValidatorReserves::LEN)
+    }
+
+    /// Replace the attestation with the balances observed in `clock.slot`.
+    pub fn set(
+        &mut self,
+        clock: &Clock,
+        reserve_balance: Lamports,
+        st_sol_supply: StLamports,
+        validators: Vec<ValidatorReserves>,
+    ) -> token::Result<()> {
+        let validators_balance: Lamports = validators
+            .iter()
+            .map(|v| v.balance)
+            .sum::<token::Result<Lamports>>()?;
+        self.slot = clock.slot;
+        self.epoch = clock.epoch;
+        self.reserve_balance = reserve_balance;
+        self.total_sol = (reserve_balance + validators_balance)?;
+        self.st_sol_supply = st_sol_supply;
+        self.validators = validators;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_sums_the_balances() {
+        let clock = Clock {
+            slot: 1_234,
+            epoch: 5,
+            ..Clock::default()
+        };
+        let validators = vec![
+            ValidatorReserves {
+                validator_vote: Pubkey::new_unique(),
+                balance: Lamports::new(300),
+            },
+            ValidatorReserves {
+                validator_vote: Pubkey::new_unique(),
+                balance: Lamports::new(200),
+            },
+        ];
+        let mut attestation = ReserveAttestation::default();
+        attestation
+            .set(&clock, Lamports::new(50), StLamports::new(500), validators)
+            .unwrap();
+        assert_eq!(attestation.slot, 1_234);
+        assert_eq!(attestation.epoch, 5);
+        assert_eq!(attestation.total_sol, Lamports::new(550));
+        assert_eq!(
+            8 + attestation.try_to_vec().unwrap().len(),
+            ReserveAttestation::required_bytes(2)
+        );
+    }
+}
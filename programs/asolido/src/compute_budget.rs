@@ -0,0 +1,101 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Keep instructions that loop over accounts within the compute budget.
+//!
+//! Instructions like `WithdrawInactiveStake` and `UnstakeAllFromValidator` do
+//! work for a variable number of stake accounts. If they run out of compute
+//! units halfway, the transaction aborts, and the work of the iterations
+//! before is lost too. With a `ComputeBudgetGuard`, the loop stops before the
+//! budget runs out instead, the instruction persists the progress so far, and
+//! the maintainer calls it again for the remaining accounts.
+
+/// Compute units that an instruction can use by default.
+pub const DEFAULT_COMPUTE_UNITS: u64 = 200_000;
+
+/// Estimated compute units that an instruction uses before its loop, to check its accounts.
+pub const COMPUTE_UNITS_BEFORE_LOOP: u64 = 25_000;
+
+/// Compute units to keep for the work after the loop, such as serializing the `Lido` account.
+pub const COMPUTE_UNITS_AFTER_LOOP: u64 = 25_000;
+
+/// Estimated compute units to read and check one stake account.
+pub const STAKE_ACCOUNT_READ_COMPUTE_UNITS: u64 = 2_000;
+
+/// Estimated compute units for one CPI into the stake program.
+pub const STAKE_PROGRAM_CPI_COMPUTE_UNITS: u64 = 10_000;
+
+/// Estimated compute units for one stake account in a loop, reading it and one stake program CPI.
+pub const STAKE_ACCOUNT_COMPUTE_UNITS: u64 =
+    STAKE_ACCOUNT_READ_COMPUTE_UNITS + STAKE_PROGRAM_CPI_COMPUTE_UNITS;
+
+#[cfg(all(target_arch = "bpf", feature = "remaining-compute-units"))]
+extern "C" {
+    fn sol_remaining_compute_units() -> u64;
+}
+
+/// Tracks whether another iteration of a loop fits in the compute budget.
+///
+/// With the `remaining-compute-units` feature, the guard asks the runtime
+/// how many units are left, on clusters that support the
+/// `sol_remaining_compute_units` syscall. Otherwise it estimates that from
+/// the default budget and the cost of the iterations so far.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ComputeBudgetGuard {
+    /// Estimated compute units left, used if the runtime cannot tell.
+    estimated_remaining: u64,
+}
+
+impl ComputeBudgetGuard {
+    /// Start tracking, `units_used` is the estimated cost of the instruction before the loop.
+    pub fn new(units_used: u64) -> ComputeBudgetGuard {
+        ComputeBudgetGuard {
+            estimated_remaining: DEFAULT_COMPUTE_UNITS.saturating_sub(units_used),
+        }
+    }
+
+    #[cfg(all(target_arch = "bpf", feature = "remaining-compute-units"))]
+    fn remaining(&self) -> u64 {
+        // Safe because the syscall takes no arguments and only reads the meter.
+        unsafe { sol_remaining_compute_units() }
+    }
+
+    #[cfg(not(all(target_arch = "bpf", feature = "remaining-compute-units")))]
+    fn remaining(&self) -> u64 {
+        self.estimated_remaining
+    }
+
+    /// Return whether an iteration that costs `units` still fits, and account for it if so.
+    ///
+    /// An iteration fits if `COMPUTE_UNITS_AFTER_LOOP` remain after it, so
+    /// the instruction can still finish.
+    pub fn try_reserve(&mut self, units: u64) -> bool {
+        let required = units.saturating_add(COMPUTE_UNITS_AFTER_LOOP);
+        if self.remaining() < required {
+            return false;
+        }
+        self.estimated_remaining = self.estimated_remaining.saturating_sub(units);
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_guard_stops_before_the_budget_runs_out() {
+        let mut guard = ComputeBudgetGuard::new(50_000);
+        let mut iterations = 0;
+        while guard.try_reserve(STAKE_ACCOUNT_COMPUTE_UNITS) {
+            iterations += 1;
+        }
+        // 150k units are left, of which 25k are kept for after the loop.
+        assert_eq!(iterations, 125_000 / STAKE_ACCOUNT_COMPUTE_UNITS);
+        assert!(guard.estimated_remaining >= COMPUTE_UNITS_AFTER_LOOP);
+
+        // Once the budget is exhausted, nothing fits any more.
+        let mut guard = ComputeBudgetGuard::new(DEFAULT_COMPUTE_UNITS + 1);
+        assert!(!guard.try_reserve(0));
+    }
+}
@@ -0,0 +1,643 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Operational tunables of an instance.
+//!
+//! The `Lido` account holds the state of the pool, the validators, the
+//! exchange rate, and the metrics. The parameters that the manager tunes
+//! while operating the pool live in a separate `Config` account instead, so
+//! that they can change without migrating `Lido`.
+//!
+//! Accounts cannot be resized by the runtime that this program targets, so
+//! `Config::LEN` is fixed once an instance is initialized. A field added
+//! after a release needs a new `CONFIG_VERSION`, and a migration that moves
+//! the tunables into a new, larger account.
+
+use anchor_lang::prelude::*;
+
+use crate::error::LidoError;
+use crate::logic::EpochProvider;
+use crate::state::{ExchangeRate, Lido};
+use crate::token::{Lamports, StLamports};
+use crate::{
+    DEFAULT_MAX_COMMISSION, MAX_COMMISSION_CHANGE_DELAY_EPOCHS, TREASURY_WITHDRAWAL_DELAY_EPOCHS,
+};
+
+/// Version of the `Config` layout that this program creates.
+pub const CONFIG_VERSION: u8 = 0;
+
+/// Upper bound for basis points, 100%.
+pub const MAX_BASIS_POINTS: u32 = 10_000;
+
+#[account]
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Config {
+    /// Version of this layout, `CONFIG_VERSION` for accounts created by this program.
+    pub version: u8,
+
+    /// The instance that this configuration belongs to.
+    pub lido: Pubkey,
+
+    /// Smallest amount of SOL that `Deposit` accepts.
+    pub min_deposit: Lamports,
+
+    /// Amount of SOL, on top of the rent-exempt minimum, that stays in the reserve.
+    ///
+    /// `StakeDeposit` and `SweepReserve` only stake what is above this buffer.
+    pub min_reserve_balance: Lamports,
+
+    /// Highest commission that the vote account of a validator can charge to be added.
+    ///
+    /// `DEFAULT_MAX_COMMISSION` at initialization. The manager can change it
+    /// with `ScheduleMaxCommissionChange`, which takes effect
+    /// `MAX_COMMISSION_CHANGE_DELAY_EPOCHS` later.
+    pub max_commission: u8,
+
+    /// Value scheduled to replace `max_commission`.
+    pub max_commission_scheduled: u8,
+
+    /// Epoch from which `max_commission_scheduled` can be applied, or 0 if no change is scheduled.
+    pub max_commission_change_epoch: u64,
+
+    /// Whether `Deposit` and `DepositToAssociatedAccount` are disabled.
+    pub deposits_paused: bool,
+
+    /// Whether `Withdraw` and `WithdrawFromReserve` are disabled.
+    pub withdrawals_paused: bool,
+
+    /// Largest amount of stSOL that `WithdrawFromReserve` accepts per epoch before the wind-down is complete.
+    ///
+    /// Small holders can withdraw instantly from the SOL in the reserve, rather
+    /// than receive a stake account that they need to deactivate and wait an
+    /// epoch for. The budget counts all instant withdrawals of the epoch, see
+    /// `Lido::instant_withdrawn_this_epoch`, and they never take the reserve
+    /// below `min_reserve_balance`. Larger withdrawals still go through
+    /// `Withdraw`, so even many small ones cannot drain the reserve during a
+    /// liquidity crunch. 0 disables this.
+    pub max_instant_withdrawal: StLamports,
+
+    /// Largest amount of SOL that `StakeDeposit` and `SweepReserve` stake per epoch.
+    ///
+    /// New stake activates over several epochs, so staking a sudden large
+    /// deposit at once shifts the distribution of active stake, and puts all
+    /// of it through warm-up together. 0 disables the cap.
+    pub max_stake_per_epoch: Lamports,
+
+    /// Largest change of the SOL value of one stSOL that `UpdateExchangeRate` accepts, in bps.
+    ///
+    /// A larger change points at a bug or an exploit rather than at rewards or
+    /// slashing, and rather than let every depositor and withdrawer trade at
+    /// that rate, the update fails until the manager looks into it. 0 disables
+    /// the bound.
+    pub max_exchange_rate_change_bps: u32,
+
+    /// Whether the treasury forgoes its fee over the SOL that is idle in the reserve.
+    ///
+    /// SOL in the reserve earns no rewards, so a large idle reserve dilutes
+    /// stakers. When enabled, `UpdateExchangeRate` records the idle balance,
+    /// and `CollectValidatorFee` moves the idle share of the treasury fee to
+    /// stSOL appreciation, see `Lido::discount_idle_reserve`.
+    pub idle_reserve_fee_adjustment: bool,
+
+    /// Whether deposits and withdrawals must be top-level instructions of a transaction.
+    ///
+    /// Another program that calls us can deposit, withdraw, and trade around
+    /// the exchange rate update within one transaction, as with a flash loan.
+    /// When set, `Deposit`, `Withdraw`, and the like fail when invoked through
+    /// CPI, which they detect with the instructions sysvar.
+    pub reject_cpi: bool,
+
+    /// Whether to block deposits and withdrawals while the exchange rate is stale.
+    ///
+    /// After the epoch boundary, but before `UpdateExchangeRate` ran, the
+    /// exchange rate does not include the rewards of the previous epoch yet.
+    /// Depositing before the update and withdrawing after it would capture
+    /// those rewards without having been staked. When this is set, deposits
+    /// and withdrawals in that window fail.
+    pub stale_exchange_rate_guard: bool,
+
+    /// SOL that the reserve pays whoever cranks a maintenance step, 0 disables it.
+    ///
+    /// `UpdateExchangeRate` and `FinishUpdateExchangeRate` pay it once per
+    /// epoch, `CollectValidatorFee` and `WithdrawInactiveStake` once per
+    /// validator per epoch, so that anybody is paid to keep the instance up
+    /// to date if the maintainers stop. The tip is only paid while the
+    /// reserve has it available.
+    pub crank_tip: Lamports,
+
+    /// The stSOL account that `WithdrawTreasury` can transfer to, once the delay has passed.
+    pub treasury_withdrawal_recipient: Pubkey,
+
+    /// Amount of stSOL that `WithdrawTreasury` can transfer out of the protocol treasury.
+    pub treasury_withdrawal_amount: StLamports,
+
+    /// Epoch from which the scheduled treasury withdrawal can be done, or 0 if none is scheduled.
+    pub treasury_withdrawal_epoch: u64,
+
+    /// Number of epochs after `AddValidator` before `StakeDeposit` stakes with a validator.
+    ///
+    /// This gives the community time to verify a new validator before the
+    /// SOL of the pool flows to it. Until then, the validator does not count
+    /// as the one with the least stake either. 0 disables the delay.
+    pub min_epochs_before_stake: u64,
+
+    /// The Wormhole core bridge that `PostExchangeRateMessage` posts to.
+    ///
+    /// The default pubkey while the integration is disabled, see `SetWormholeProgram`.
+    pub wormhole_program: Pubkey,
+}
+
+/// New values of the tunables that `UpdateConfig` sets.
+///
+/// The max commission is not included, it can only change with a delay, see
+/// `ScheduleMaxCommissionChange`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct ConfigUpdate {
+    pub min_deposit: Lamports,
+    pub min_reserve_balance: Lamports,
+    pub deposits_paused: bool,
+    pub withdrawals_paused: bool,
+    pub max_instant_withdrawal: StLamports,
+    pub max_stake_per_epoch: Lamports,
+    pub max_exchange_rate_change_bps: u32,
+    pub idle_reserve_fee_adjustment: bool,
+    pub reject_cpi: bool,
+    pub stale_exchange_rate_guard: bool,
+    pub crank_tip: Lamports,
+    pub min_epochs_before_stake: u64,
+}
+
+impl Config {
+    /// Number of bytes needed for the account.
+    pub const LEN: usize =
+        8 + 1 + 32 + 8 + 8 + 1 + 1 + 8 + 1 + 1 + 8 + 8 + 4 + 1 + 1 + 1 + 8 + 32 + 8 + 8 + 8 + 32;
+
+    /// Return the configuration of a new instance.
+    pub fn new(lido: Pubkey) -> Config {
+        Config {
+            version: CONFIG_VERSION,
+            lido,
+            max_commission: DEFAULT_MAX_COMMISSION,
+            ..Config::default()
+        }
+    }
+
+    /// Replace the tunables that the manager can change at any time.
+    pub fn update(&mut self, update: ConfigUpdate) -> Result<()> {
+        require!(
+            update.max_exchange_rate_change_bps <= MAX_BASIS_POINTS,
+            LidoError::InvalidConfig
+        );
+        self.min_deposit = update.min_deposit;
+        self.min_reserve_balance = update.min_reserve_balance;
+        self.deposits_paused = update.deposits_paused;
+        self.withdrawals_paused = update.withdrawals_paused;
+        self.max_instant_withdrawal = update.max_instant_withdrawal;
+        self.max_stake_per_epoch = update.max_stake_per_epoch;
+        self.max_exchange_rate_change_bps = update.max_exchange_rate_change_bps;
+        self.idle_reserve_fee_adjustment = update.idle_reserve_fee_adjustment;
+        self.reject_cpi = update.reject_cpi;
+        self.stale_exchange_rate_guard = update.stale_exchange_rate_guard;
+        self.crank_tip = update.crank_tip;
+        self.min_epochs_before_stake = update.min_epochs_before_stake;
+        Ok(())
+    }
+
+    /// Check that a deposit of `amount` is allowed by the configuration.
+    pub fn check_deposit(&self, amount: Lamports) -> Result<()> {
+        require!(!self.deposits_paused, LidoError::DepositsPaused);
+        if amount < self.min_deposit {
+            msg!(
+                "Deposited {}, but the minimum deposit is {}.",
+                amount,
+                self.min_deposit
+            );
+            return err!(LidoError::DepositTooSmall);
+        }
+        Ok(())
+    }
+
+    /// Check that withdrawals are not paused.
+    pub fn check_withdrawals_not_paused(&self) -> Result<()> {
+        require!(!self.withdrawals_paused, LidoError::WithdrawalsPaused);
+        Ok(())
+    }
+
+    /// If `stale_exchange_rate_guard` is set, confirm that the exchange rate of `lido` is up to date.
+    pub fn check_stale_exchange_rate_guard(
+        &self,
+        lido: &Lido,
+        epochs: &impl EpochProvider,
+        method: &str,
+    ) -> Result<()> {
+        if self.stale_exchange_rate_guard {
+            lido.check_exchange_rate_last_epoch(epochs, method)?;
+        }
+        Ok(())
+    }
+
+    /// Return whether a withdrawal of `amount` can be taken from the reserve instantly.
+    ///
+    /// `withdrawn` is the stSOL that was withdrawn instantly in this epoch
+    /// already, the withdrawal must fit in what is left of the budget.
+    pub fn is_instant_withdrawal(&self, amount: StLamports, withdrawn: StLamports) -> bool {
+        amount <= self.max_instant_withdrawal.saturating_sub(withdrawn)
+    }
+
+    /// Return how much SOL can still be staked in this epoch, after `staked` was staked already.
+    ///
+    /// Returns `None` if there is no cap.
+    pub fn get_stake_budget_remaining(&self, staked: Lamports) -> Option<Lamports> {
+        if self.max_stake_per_epoch == Lamports::new(0) {
+            return None;
+        }
+        Some(self.max_stake_per_epoch.saturating_sub(staked))
+    }
+
+    /// Check that the SOL value of one stSOL changes by at most `max_exchange_rate_change_bps`.
+    ///
+    /// The check is skipped while either rate has no stSOL supply, there is
+    /// no meaningful price to compare then.
+    pub fn check_exchange_rate_change(&self, old: &ExchangeRate, new: &ExchangeRate) -> Result<()> {
+        if self.max_exchange_rate_change_bps == 0
+            || old.st_sol_supply.amount == 0
+            || new.st_sol_supply.amount == 0
+        {
+            return Ok(());
+        }
+
+        // Compare new_sol / new_st_sol with old_sol / old_st_sol, without
+        // dividing, by multiplying both sides by old_st_sol * new_st_sol.
+        let new_value = new.sol_balance.amount as u128 * old.st_sol_supply.amount as u128;
+        let old_value = old.sol_balance.amount as u128 * new.st_sol_supply.amount as u128;
+        let change = if new_value > old_value {
+            new_value - old_value
+        } else {
+            old_value - new_value
+        };
+        let max_change =
+            old_value / MAX_BASIS_POINTS as u128 * self.max_exchange_rate_change_bps as u128;
+        if change > max_change {
+            msg!(
+                "The exchange rate would change from {} SOL for {} stSOL to {} SOL for {} stSOL, more than {} basis points.",
+                old.sol_balance,
+                old.st_sol_supply,
+                new.sol_balance,
+                new.st_sol_supply,
+                self.max_exchange_rate_change_bps
+            );
+            return err!(LidoError::ExchangeRateChangeTooLarge);
+        }
+        Ok(())
+    }
+
+    /// Check that a validator with the given vote account commission can be added.
+    pub fn check_commission(&self, commission: u8) -> Result<()> {
+        if commission > self.max_commission {
+            msg!(
+                "The commission of the vote account is {}%, but at most {}% is allowed.",
+                commission,
+                self.max_commission
+            );
+            return err!(LidoError::ValidatorCommissionTooHigh);
+        }
+        Ok(())
+    }
+
+    /// Schedule `max_commission` to change, `MAX_COMMISSION_CHANGE_DELAY_EPOCHS` from now.
+    ///
+    /// Scheduling a new change replaces any pending one, and restarts the delay.
+    pub fn schedule_max_commission_change(
+        &mut self,
+        max_commission: u8,
+        current_epoch: u64,
+    ) -> Result<()> {
+        require!(max_commission <= 100, LidoError::InvalidMaxCommission);
+        self.max_commission_scheduled = max_commission;
+        self.max_commission_change_epoch = current_epoch + MAX_COMMISSION_CHANGE_DELAY_EPOCHS;
+        Ok(())
+    }
+
+    /// Apply the pending change of `max_commission`, if its delay has passed.
+    pub fn apply_max_commission_change(&mut self, current_epoch: u64) -> Result<()> {
+        require!(
+            self.max_commission_change_epoch != 0,
+            LidoError::NoMaxCommissionChangePending
+        );
+        if current_epoch < self.max_commission_change_epoch {
+            msg!(
+                "The max commission can be changed from epoch {}, but the current epoch is {}.",
+                self.max_commission_change_epoch,
+                current_epoch
+            );
+            return err!(LidoError::MaxCommissionChangeNotYetEffective);
+        }
+
+        self.max_commission = self.max_commission_scheduled;
+        self.max_commission_change_epoch = 0;
+        Ok(())
+    }
+
+    /// Schedule a withdrawal from the protocol treasury, `TREASURY_WITHDRAWAL_DELAY_EPOCHS` from now.
+    ///
+    /// Scheduling a new withdrawal replaces any pending one, and restarts the delay.
+    pub fn schedule_treasury_withdrawal(
+        &mut self,
+        recipient: Pubkey,
+        amount: StLamports,
+        current_epoch: u64,
+    ) -> Result<()> {
+        require!(amount > StLamports::new(0), LidoError::InvalidAmount);
+        self.treasury_withdrawal_recipient = recipient;
+        self.treasury_withdrawal_amount = amount;
+        self.treasury_withdrawal_epoch = current_epoch + TREASURY_WITHDRAWAL_DELAY_EPOCHS;
+        Ok(())
+    }
+
+    /// Complete the scheduled treasury withdrawal to `recipient`, if its delay has passed.
+    ///
+    /// Returns the amount to withdraw. A withdrawal can be completed only once.
+    pub fn complete_treasury_withdrawal(
+        &mut self,
+        recipient: &Pubkey,
+        current_epoch: u64,
+    ) -> Result<StLamports> {
+        require!(
+            self.treasury_withdrawal_epoch != 0,
+            LidoError::NoTreasuryWithdrawalPending
+        );
+        if current_epoch < self.treasury_withdrawal_epoch {
+            msg!(
+                "The treasury withdrawal can be done from epoch {}, but the current epoch is {}.",
+                self.treasury_withdrawal_epoch,
+                current_epoch
+            );
+            return err!(LidoError::TreasuryWithdrawalNotYetEffective);
+        }
+        require!(
+            *recipient == self.treasury_withdrawal_recipient,
+            LidoError::InvalidTreasuryWithdrawal
+        );
+
+        self.treasury_withdrawal_epoch = 0;
+        Ok(self.treasury_withdrawal_amount)
+    }
+
+    /// Check that the Wormhole integration is enabled, and that `program` is the core bridge.
+    pub fn check_wormhole_program(&self, program: &Pubkey) -> Result<()> {
+        require!(
+            self.wormhole_program != Pubkey::default(),
+            LidoError::WormholeDisabled
+        );
+        if *program != self.wormhole_program {
+            msg!(
+                "Expected the Wormhole core bridge {}, but {} was provided.",
+                self.wormhole_program,
+                program
+            );
+            return err!(LidoError::InvalidWormholeAccount);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rate(sol_balance: u64, st_sol_supply: u64) -> ExchangeRate {
+        ExchangeRate {
+            computed_in_epoch: 0,
+            st_sol_supply: StLamports::new(st_sol_supply),
+            sol_balance: Lamports::new(sol_balance),
+        }
+    }
+
+    #[test]
+    fn test_len_matches_serialization() {
+        let config = Config::new(Pubkey::new_unique());
+        assert_eq!(8 + config.try_to_vec().unwrap().len(), Config::LEN);
+    }
+
+    #[test]
+    fn test_check_deposit() {
+        let mut config = Config::new(Pubkey::new_unique());
+        assert!(config.check_deposit(Lamports::new(1)).is_ok());
+
+        config.min_deposit = Lamports::new(100);
+        assert!(config.check_deposit(Lamports::new(99)).is_err());
+        assert!(config.check_deposit(Lamports::new(100)).is_ok());
+
+        config.deposits_paused = true;
+        assert!(config.check_deposit(Lamports::new(100)).is_err());
+        assert!(config.check_withdrawals_not_paused().is_ok());
+    }
+
+    #[test]
+    fn test_stale_exchange_rate_guard() {
+        let mut config = Config::new(Pubkey::new_unique());
+        let mut lido = Lido::default();
+        lido.exchange_rate.computed_in_epoch = 3;
+        let clock = Clock {
+            epoch: 4,
+            ..Clock::default()
+        };
+
+        // The guard is off by default, so a stale rate is accepted.
+        assert!(config
+            .check_stale_exchange_rate_guard(&lido, &clock, "Withdraw")
+            .is_ok());
+
+        let update = ConfigUpdate {
+            stale_exchange_rate_guard: true,
+            ..ConfigUpdate::default()
+        };
+        config.update(update).unwrap();
+        assert!(config
+            .check_stale_exchange_rate_guard(&lido, &clock, "Withdraw")
+            .is_err());
+
+        lido.exchange_rate.computed_in_epoch = 4;
+        assert!(config
+            .check_stale_exchange_rate_guard(&lido, &clock, "Withdraw")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_is_instant_withdrawal() {
+        let mut config = Config::new(Pubkey::new_unique());
+        let none = StLamports::new(0);
+        assert!(!config.is_instant_withdrawal(StLamports::new(1), none));
+
+        config.max_instant_withdrawal = StLamports::new(100);
+        assert!(config.is_instant_withdrawal(StLamports::new(100), none));
+        assert!(!config.is_instant_withdrawal(StLamports::new(101), none));
+    }
+
+    #[test]
+    fn test_instant_withdrawals_share_the_budget_of_the_epoch() {
+        let mut config = Config::new(Pubkey::new_unique());
+        config.max_instant_withdrawal = StLamports::new(100);
+
+        // Splitting a withdrawal into small ones does not raise the budget.
+        let mut withdrawn = StLamports::new(0);
+        for _ in 0..4 {
+            assert!(config.is_instant_withdrawal(StLamports::new(25), withdrawn));
+            withdrawn = (withdrawn + StLamports::new(25)).unwrap();
+        }
+        assert!(!config.is_instant_withdrawal(StLamports::new(1), withdrawn));
+        assert!(config.is_instant_withdrawal(StLamports::new(40), StLamports::new(60)));
+        assert!(!config.is_instant_withdrawal(StLamports::new(41), StLamports::new(60)));
+    }
+
+    #[test]
+    fn test_get_stake_budget_remaining() {
+        let mut config = Config::new(Pubkey::new_unique());
+        assert_eq!(config.get_stake_budget_remaining(Lamports::new(500)), None);
+
+        config.max_stake_per_epoch = Lamports::new(300);
+        assert_eq!(
+            config.get_stake_budget_remaining(Lamports::new(100)),
+            Some(Lamports::new(200))
+        );
+        assert_eq!(
+            config.get_stake_budget_remaining(Lamports::new(500)),
+            Some(Lamports::new(0))
+        );
+    }
+
+    #[test]
+    fn test_update_rejects_more_than_100_percent() {
+        let mut config = Config::new(Pubkey::new_unique());
+        let update = ConfigUpdate {
+            max_exchange_rate_change_bps: MAX_BASIS_POINTS + 1,
+            ..ConfigUpdate::default()
+        };
+        assert!(config.update(update).is_err());
+    }
+
+    #[test]
+    fn test_treasury_withdrawal_applies_after_delay() {
+        let mut config = Config::new(Pubkey::new_unique());
+        let recipient = Pubkey::new_unique();
+        assert!(config.complete_treasury_withdrawal(&recipient, 10).is_err());
+        assert!(config
+            .schedule_treasury_withdrawal(recipient, StLamports::new(0), 10)
+            .is_err());
+
+        config
+            .schedule_treasury_withdrawal(recipient, StLamports::new(500), 10)
+            .unwrap();
+        let effective_epoch = 10 + TREASURY_WITHDRAWAL_DELAY_EPOCHS;
+        assert!(config
+            .complete_treasury_withdrawal(&recipient, effective_epoch - 1)
+            .is_err());
+        assert!(config
+            .complete_treasury_withdrawal(&Pubkey::new_unique(), effective_epoch)
+            .is_err());
+        assert_eq!(
+            config
+                .complete_treasury_withdrawal(&recipient, effective_epoch)
+                .unwrap(),
+            StLamports::new(500)
+        );
+
+        // The withdrawal can only be done once.
+        assert!(config
+            .complete_treasury_withdrawal(&recipient, effective_epoch)
+            .is_err());
+    }
+
+    #[test]
+    fn test_update_sets_the_crank_tip() {
+        let mut config = Config::new(Pubkey::new_unique());
+        assert_eq!(config.crank_tip, Lamports::new(0));
+
+        let update = ConfigUpdate {
+            crank_tip: Lamports::new(5_000),
+            ..ConfigUpdate::default()
+        };
+        config.update(update).unwrap();
+        assert_eq!(config.crank_tip, Lamports::new(5_000));
+    }
+
+    #[test]
+    fn test_update_sets_the_stake_delay() {
+        let mut config = Config::new(Pubkey::new_unique());
+        assert_eq!(config.min_epochs_before_stake, 0);
+
+        let update = ConfigUpdate {
+            min_epochs_before_stake: 3,
+            ..ConfigUpdate::default()
+        };
+        config.update(update).unwrap();
+        assert_eq!(config.min_epochs_before_stake, 3);
+    }
+
+    #[test]
+    fn test_check_wormhole_program() {
+        let mut config = Config::new(Pubkey::new_unique());
+        assert!(config.check_wormhole_program(&Pubkey::default()).is_err());
+
+        let wormhole_program = Pubkey::new_unique();
+        config.wormhole_program = wormhole_program;
+        assert!(config.check_wormhole_program(&wormhole_program).is_ok());
+        assert!(config
+            .check_wormhole_program(&Pubkey::new_unique())
+            .is_err());
+    }
+
+    #[test]
+    fn test_check_exchange_rate_change() {
+        let mut config = Config::new(Pubkey::new_unique());
+        // Without a bound, any change is accepted.
+        assert!(config
+            .check_exchange_rate_change(&rate(100, 100), &rate(1_000, 100))
+            .is_ok());
+
+        // At most 1%.
+        config.max_exchange_rate_change_bps = 100;
+        assert!(config
+            .check_exchange_rate_change(&rate(1_000, 1_000), &rate(1_010, 1_000))
+            .is_ok());
+        assert!(config
+            .check_exchange_rate_change(&rate(1_000, 1_000), &rate(1_011, 1_000))
+            .is_err());
+        assert!(config
+            .check_exchange_rate_change(&rate(1_000, 1_000), &rate(989, 1_000))
+            .is_err());
+
+        // Deposits and withdrawals change the supply, but not the price.
+        assert!(config
+            .check_exchange_rate_change(&rate(1_000, 1_000), &rate(3_000, 3_000))
+            .is_ok());
+
+        // Without a supply, there is no price to compare.
+        assert!(config
+            .check_exchange_rate_change(&rate(0, 0), &rate(5_000, 1_000))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_max_commission_change_applies_after_delay() {
+        let mut config = Config::new(Pubkey::new_unique());
+        assert!(config.check_commission(100).is_ok());
+        assert!(config.apply_max_commission_change(10).is_err());
+        assert!(config.schedule_max_commission_change(101, 10).is_err());
+
+        config.schedule_max_commission_change(5, 10).unwrap();
+        let effective_epoch = 10 + MAX_COMMISSION_CHANGE_DELAY_EPOCHS;
+        assert!(config
+            .apply_max_commission_change(effective_epoch - 1)
+            .is_err());
+        assert!(config.check_commission(100).is_ok());
+
+        config.apply_max_commission_change(effective_epoch).unwrap();
+        assert_eq!(config.max_commission, 5);
+        assert!(config.check_commission(5).is_ok());
+        assert!(config.check_commission(6).is_err());
+
+        // The change is applied only once.
+        assert!(config.apply_max_commission_change(effective_epoch).is_err());
+    }
+}
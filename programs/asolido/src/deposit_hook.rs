@@ -0,0 +1,211 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Program that deposits can call after minting, to put the stSOL to work.
+//!
+//! Partners such as lending markets or auto-compounding vaults want to take
+//! the stSOL of a deposit in the same transaction, on behalf of the user. The
+//! manager registers such a partner in a `DepositHook` account next to the
+//! instance: the program to call, the start of its instruction data, and a
+//! template of the accounts that it takes. A deposit that passes the hook
+//! account invokes the partner after minting. The hook is disabled until the
+//! manager enables it, and deposits that do not pass it are not affected.
+
+use anchor_lang::prelude::*;
+use solana_program::instruction::{AccountMeta, Instruction};
+
+use crate::error::LidoError;
+use crate::token::StLamports;
+
+/// Maximum number of accounts in `DepositHook::accounts`.
+pub const MAX_DEPOSIT_HOOK_ACCOUNTS: usize = 16;
+
+/// Where the address of an account that the hook takes comes from.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub enum HookAccountSource {
+    /// The address in `HookAccount::pubkey`, the same for every deposit.
+    Fixed,
+    /// The `user` that deposits, which signs the deposit, and so signs for the hook too.
+    User,
+    /// The stSOL account that received the minted stSOL.
+    Recipient,
+    /// The stSOL mint.
+    StSolMint,
+}
+
+impl Default for HookAccountSource {
+    fn default() -> Self {
+        HookAccountSource::Fixed
+    }
+}
+
+/// One account of the instruction that the hook is called with.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct HookAccount {
+    pub source: HookAccountSource,
+
+    /// Address of the account, only used for `HookAccountSource::Fixed`.
+    pub pubkey: Pubkey,
+
+    pub is_writable: bool,
+}
+
+impl HookAccount {
+    /// Number of bytes that a serialized `HookAccount` takes.
+    pub const LEN: usize = 1 + 32 + 1;
+}
+
+#[account]
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct DepositHook {
+    /// The instance that deposits into.
+    pub lido: Pubkey,
+
+    /// Whether deposits can invoke the hook, false until the manager enables it.
+    pub enabled: bool,
+
+    /// The program to invoke.
+    pub program_id: Pubkey,
+
+    /// Start of the instruction data, for example the discriminator of an Anchor instruction.
+    ///
+    /// The amount of stSOL minted follows it, as a little-endian `u64`.
+    pub instruction_prefix: [u8; 8],
+
+    /// The accounts of the instruction, in order.
+    pub accounts: Vec<HookAccount>,
+}
+
+impl DepositHook {
+    /// Number of bytes needed for the account, with the maximum number of accounts.
+    pub const LEN: usize = 8 + 32 + 1 + 32 + 8 + (4 + MAX_DEPOSIT_HOOK_ACCOUNTS * HookAccount::LEN);
+
+    /// Replace the hook, if it fits in the account and does not call this program.
+    pub fn set(
+        &mut self,
+        enabled: bool,
+        program_id: Pubkey,
+        instruction_prefix: [u8; 8],
+        accounts: Vec<HookAccount>,
+    ) -> Result<()> {
+        if accounts.len() > MAX_DEPOSIT_HOOK_ACCOUNTS {
+            msg!(
+                "The hook takes {} accounts, but at most {} are allowed.",
+                accounts.len(),
+                MAX_DEPOSIT_HOOK_ACCOUNTS
+            );
+            return err!(LidoError::InvalidDepositHook);
+        }
+        // Calling back into Solido halfway through a deposit would see the
+        // `Lido` account before the deposit is written to it.
+        if program_id == crate::ID {
+            msg!("The deposit hook cannot call the Solido program itself.");
+            return err!(LidoError::InvalidDepositHook);
+        }
+
+        self.enabled = enabled;
+        self.program_id = program_id;
+        self.instruction_prefix = instruction_prefix;
+        self.accounts = accounts;
+        Ok(())
+    }
+
+    /// Return the instruction to call the hook with, after minting `amount` to `recipient`.
+    pub fn instruction(
+        &self,
+        user: &Pubkey,
+        recipient: &Pubkey,
+        st_sol_mint: &Pubkey,
+        amount: StLamports,
+    ) -> Instruction {
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|account| {
+                let (pubkey, is_signer) = match account.source {
+                    HookAccountSource::Fixed => (account.pubkey, false),
+                    HookAccountSource::User => (*user, true),
+                    HookAccountSource::Recipient => (*recipient, false),
+                    HookAccountSource::StSolMint => (*st_sol_mint, false),
+                };
+                if account.is_writable {
+                    AccountMeta::new(pubkey, is_signer)
+                } else {
+                    AccountMeta::new_readonly(pubkey, is_signer)
+                }
+            })
+            .collect();
+
+        let mut data = Vec::with_capacity(16);
+        data.extend_from_slice(&self.instruction_prefix);
+        data.extend_from_slice(&amount.amount.to_le_bytes());
+
+        Instruction {
+            program_id: self.program_id,
+            accounts,
+            data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hook_account(source: HookAccountSource, pubkey: Pubkey, is_writable: bool) -> HookAccount {
+        HookAccount {
+            source,
+            pubkey,
+            is_writable,
+        }
+    }
+
+    #[test]
+    fn test_len_fits_the_maximum_number_of_accounts() {
+        let mut hook = DepositHook::default();
+        let accounts = vec![HookAccount::default(); MAX_DEPOSIT_HOOK_ACCOUNTS];
+        hook.set(true, Pubkey::new_unique(), [0; 8], accounts)
+            .unwrap();
+        assert_eq!(8 + hook.try_to_vec().unwrap().len(), DepositHook::LEN);
+
+        let accounts = vec![HookAccount::default(); MAX_DEPOSIT_HOOK_ACCOUNTS + 1];
+        assert!(hook
+            .set(true, Pubkey::new_unique(), [0; 8], accounts)
+            .is_err());
+        assert!(hook.set(true, crate::ID, [0; 8], Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_instruction_fills_in_the_deposit_accounts() {
+        let market = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let recipient = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let mut hook = DepositHook::default();
+        let accounts = vec![
+            hook_account(HookAccountSource::Fixed, market, true),
+            hook_account(HookAccountSource::User, Pubkey::default(), false),
+            hook_account(HookAccountSource::Recipient, Pubkey::default(), true),
+            hook_account(HookAccountSource::StSolMint, Pubkey::default(), false),
+        ];
+        let program_id = Pubkey::new_unique();
+        hook.set(true, program_id, [1, 2, 3, 4, 5, 6, 7, 8], accounts)
+            .unwrap();
+
+        let instruction = hook.instruction(&user, &recipient, &mint, StLamports::new(258));
+        assert_eq!(instruction.program_id, program_id);
+        assert_eq!(
+            instruction.accounts,
+            vec![
+                AccountMeta::new(market, false),
+                AccountMeta::new_readonly(user, true),
+                AccountMeta::new(recipient, false),
+                AccountMeta::new_readonly(mint, false),
+            ]
+        );
+        assert_eq!(
+            instruction.data,
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 2, 1, 0, 0, 0, 0, 0, 0]
+        );
+    }
+}
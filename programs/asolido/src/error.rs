@@ -151,6 +151,242 @@ pub enum LidoError {
 
     /// We expected the StSol account to be owned by the SPL token program.
     InvalidStSolAccountOwner,
+
+    /// The observed balance of the validator's stake accounts is not lower
+    /// than the tracked balance, so there is no loss to acknowledge.
+    NoLossToAcknowledge,
+
+    /// The manager has not scheduled a fee address override for this validator.
+    NoFeeAddressOverridePending,
+
+    /// The delay of the fee address override has not yet passed.
+    FeeAddressOverrideNotYetEffective,
+
+    /// The signer is not the validator identity (`node_pubkey`) of the vote account.
+    InvalidValidatorIdentity,
+
+    /// The exchange rate update was not started in this epoch, or not all
+    /// validators are included yet.
+    ExchangeRateUpdateIncomplete,
+
+    /// The fees of this validator were already collected in this epoch.
+    FeeAlreadyCollected,
+
+    /// Tried to deposit stake to a validator whose staking is paused.
+    StakeToPausedValidator,
+
+    /// Tried to deposit stake to a validator that was marked delinquent.
+    StakeToDelinquentValidator,
+
+    /// The account is not a `Lido` account of Solido v1.
+    InvalidSolidoV1Account,
+
+    /// Only a freshly initialized instance, without validators or deposits,
+    /// can be the target of a migration from Solido v1.
+    MigrationTargetNotEmpty,
+
+    /// There is no mint authority transfer scheduled.
+    NoMintAuthorityTransferPending,
+
+    /// The delay of the scheduled mint authority transfer has not passed yet.
+    MintAuthorityTransferNotYetEffective,
+
+    /// The mint authority was transferred out, this instance can no longer mint stSOL.
+    MintAuthorityTransferred,
+
+    /// The new mint authority is not the one that was scheduled.
+    InvalidMintAuthorityTransfer,
+
+    /// The instance is winding down, see `StartWindDown`.
+    WindingDown,
+
+    /// Withdrawing from the reserve requires a wind-down where all stake is back in the reserve.
+    WindDownNotComplete,
+
+    /// The signer is not the authority of the registry.
+    InvalidRegistryAuthority,
+
+    /// The signer is neither the validator identity nor the manager.
+    InvalidValidatorInfoAuthority,
+
+    /// A field of the validator info is longer than allowed.
+    ValidatorInfoTooLong,
+
+    /// The account that pays for a deposit must be owned by the system program.
+    InvalidDepositSource,
+
+    /// The commission of the validator's vote account is above `Config::max_commission`.
+    ValidatorCommissionTooHigh,
+
+    /// The max commission is a percentage, it cannot be more than 100.
+    InvalidMaxCommission,
+
+    /// There is no max commission change scheduled.
+    NoMaxCommissionChangePending,
+
+    /// The delay of the scheduled max commission change has not passed yet.
+    MaxCommissionChangeNotYetEffective,
+
+    /// The freeze authority of the stSOL mint does not match `Lido::freeze_authority_policy`.
+    InvalidFreezeAuthority,
+
+    /// The output of a deposit or withdrawal is less than the minimum that the user accepts.
+    SlippageExceeded,
+
+    /// The begin of a seed range is past its end.
+    InvalidSeedRange,
+
+    /// The stake accounts of a validator do not exist for exactly the seeds in its seed range.
+    SeedRangeMismatch,
+
+    /// Deposits are paused, see `Config::deposits_paused`.
+    DepositsPaused,
+
+    /// Withdrawals are paused, see `Config::withdrawals_paused`.
+    WithdrawalsPaused,
+
+    /// The amount to deposit is less than `Config::min_deposit`.
+    DepositTooSmall,
+
+    /// The exchange rate would change by more than `Config::max_exchange_rate_change_bps`.
+    ExchangeRateChangeTooLarge,
+
+    /// A value in the configuration update is out of range.
+    InvalidConfig,
+
+    /// The `Lido` account has a `lido_version` that this program does not support.
+    UnsupportedLidoVersion,
+
+    /// The supply of the stSOL mint did not drop by the burned amount.
+    BurnSupplyMismatch,
+
+    /// Staking the amount would exceed `Config::max_stake_per_epoch`.
+    StakeBudgetExceeded,
+
+    /// The instruction was invoked through CPI, which `Config::reject_cpi` forbids.
+    CpiNotAllowed,
+
+    /// A split would leave a stake account below `MINIMUM_STAKE_ACCOUNT_BALANCE`.
+    StakeAccountBelowMinimum,
+
+    /// Tried to off-board a validator whose vote account still exists.
+    VoteAccountNotClosed,
+
+    /// The state changed since the maintenance instruction was computed, see `Lido::maintenance_state_hash`.
+    MaintenanceStateChanged,
+
+    /// The parts of a `RewardDistribution` sum to zero, or to more than the allowed maximum.
+    InvalidRewardDistribution,
+
+    /// The deposit hook takes too many accounts, calls this program, or belongs to another instance.
+    InvalidDepositHook,
+
+    /// A deposit passed the deposit hook, but the manager has not enabled it.
+    DepositHookDisabled,
+
+    /// An instruction left the state inconsistent, detected by the `strict-checks` feature.
+    InvariantViolated,
+
+    /// Anchor did not pass the bump seed of a program-derived address that the instruction expects.
+    ///
+    /// No longer returned, the bumps are derived with the `pda` functions. It
+    /// is kept so that the codes of the errors after it stay the same.
+    MissingBumpSeed,
+
+    /// There is no treasury withdrawal scheduled.
+    NoTreasuryWithdrawalPending,
+
+    /// The delay of the scheduled treasury withdrawal has not passed yet.
+    TreasuryWithdrawalNotYetEffective,
+
+    /// The recipient of the treasury withdrawal is not the one that was scheduled.
+    InvalidTreasuryWithdrawal,
+
+    /// Tried to deposit stake to a validator before `Config::min_epochs_before_stake` passed.
+    StakeToNewValidator,
+
+    /// Tried to deposit stake to a validator with a target weight of 0.
+    StakeToZeroWeightValidator,
+
+    /// The signer is neither the manager nor the delegation authority.
+    InvalidDelegationAuthority,
+
+    /// The manager has not set `Config::wormhole_program`.
+    WormholeDisabled,
+
+    /// An account passed for the Wormhole core bridge is not the one expected.
+    InvalidWormholeAccount,
+
+    /// The effective stake of the validator, excluding its unstake accounts, cannot cover the withdrawal.
+    InsufficientValidatorLiquidity,
+
+    /// The reserve of the Solido v1 instance holds enough SOL to stake, which would be left behind.
+    SolidoV1ReserveNotStaked,
+
+    /// Validators still have stSOL fee credit, which cannot be minted once the mint authority is transferred out.
+    FeeCreditOutstanding,
+}
+
+impl LidoError {
+    /// Return the custom error code of the same error in Solido v1, if it has one.
+    ///
+    /// Monitoring that was built for Solido v1 matches on its numeric error
+    /// codes. Anchor numbers our errors from `ERROR_CODE_OFFSET` (6000), while
+    /// Solido v1 numbered them from 0, starting with `AlreadyInUse`, which has
+    /// no counterpart here because Anchor's `init` rejects initialized accounts.
+    /// The variants up to `InvalidStSolAccountOwner` have the meaning and the
+    /// order of Solido v1, newer variants have no v1 code.
+    pub fn solido_v1_code(&self) -> Option<u32> {
+        let code = match self {
+            LidoError::InvalidOwner => 1,
+            LidoError::InvalidAmount => 2,
+            LidoError::SignatureMissing => 3,
+            LidoError::InvalidReserveAccount => 4,
+            LidoError::CalculationFailure => 5,
+            LidoError::WrongStakeState => 6,
+            LidoError::InvalidFeeAmount => 7,
+            LidoError::MaximumNumberOfAccountsExceeded => 8,
+            LidoError::UnexpectedMaxValidators => 9,
+            LidoError::InvalidManager => 10,
+            LidoError::InvalidMaintainer => 11,
+            LidoError::InvalidAccountInfo => 12,
+            LidoError::TooManyAccountKeys => 13,
+            LidoError::InvalidFeeDistributionAccount => 14,
+            LidoError::InvalidValidatorCreditAccount => 15,
+            LidoError::ValidatorCreditChanged => 16,
+            LidoError::InvalidFeeAccount => 17,
+            LidoError::InvalidFeeRecipient => 18,
+            LidoError::DuplicatedEntry => 19,
+            LidoError::ValidatorCreditNotFound => 20,
+            LidoError::ValidatorHasUnclaimedCredit => 21,
+            LidoError::ReserveIsNotRentExempt => 22,
+            LidoError::AmountExceedsReserve => 23,
+            LidoError::DuplicatedMaintainer => 24,
+            LidoError::InvalidAccountMember => 25,
+            LidoError::NoActiveValidators => 26,
+            LidoError::InvalidStakeAccount => 27,
+            LidoError::InvalidStSolAccount => 28,
+            LidoError::ExchangeRateAlreadyUpToDate => 29,
+            LidoError::ExchangeRateNotUpdatedInThisEpoch => 30,
+            LidoError::ValidatorBalanceDecreased => 31,
+            LidoError::InvalidStakeAuthority => 32,
+            LidoError::InvalidRewardsWithdrawAuthority => 33,
+            LidoError::InvalidVoteAccount => 34,
+            LidoError::InvalidTokenOwner => 35,
+            LidoError::ValidatorWithMoreStakeExists => 36,
+            LidoError::InvalidMint => 37,
+            LidoError::StakeToInactiveValidator => 38,
+            LidoError::ValidatorIsStillActive => 39,
+            LidoError::ValidatorShouldHaveNoStakeAccounts => 40,
+            LidoError::ValidatorWithLessStakeExists => 41,
+            LidoError::ValidatorShouldHaveNoUnstakeAccounts => 42,
+            LidoError::MaxUnstakeAccountsReached => 43,
+            LidoError::ValidatorVoteAccountHasDifferentOwner => 44,
+            LidoError::InvalidStSolAccountOwner => 45,
+            _ => return None,
+        };
+        Some(code)
+    }
 }
 
 impl From<ArithmeticError> for LidoError {
@@ -164,3 +400,36 @@ impl From<ArithmeticError> for anchor_lang::error::Error {
         error!(LidoError::CalculationFailure)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anchor_lang::error::ERROR_CODE_OFFSET;
+    use num_traits::FromPrimitive;
+
+    #[test]
+    fn test_solido_v1_codes_follow_v1_numbering() {
+        assert_eq!(LidoError::InvalidOwner.solido_v1_code(), Some(1));
+        assert_eq!(LidoError::CalculationFailure.solido_v1_code(), Some(5));
+        assert_eq!(
+            LidoError::ExchangeRateNotUpdatedInThisEpoch.solido_v1_code(),
+            Some(30)
+        );
+        assert_eq!(LidoError::InvalidMint.solido_v1_code(), Some(37));
+        assert_eq!(
+            LidoError::InvalidStSolAccountOwner.solido_v1_code(),
+            Some(45)
+        );
+        assert_eq!(LidoError::NoLossToAcknowledge.solido_v1_code(), None);
+
+        // The v1 variants are the first ones, in v1 order, so their Anchor
+        // code is one off from their v1 code. New variants must go after them.
+        let mut index = 0;
+        while let Some(error) = LidoError::from_u32(index) {
+            let expected = if index < 45 { Some(index + 1) } else { None };
+            assert_eq!(error.solido_v1_code(), expected);
+            assert_eq!(u32::from(error), ERROR_CODE_OFFSET + index);
+            index += 1;
+        }
+    }
+}
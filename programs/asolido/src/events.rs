@@ -0,0 +1,456 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Events emitted by the program, for off-chain consumers.
+
+use anchor_lang::prelude::*;
+
+use crate::state::{FeeDenomination, StakeType};
+
+/// A maintenance instruction was called with an expected seed, count, or epoch
+/// that another transaction already moved past, so it did nothing.
+///
+/// This happens when multiple maintainer bots race to perform the same
+/// operation. The first one wins, the others succeed without effect.
+#[event]
+pub struct MaintenanceSkipped {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Name of the instruction, e.g. `"StakeDeposit"`.
+    pub instruction: String,
+
+    /// Seed, count, or epoch that the caller expected.
+    pub expected: u64,
+
+    /// Seed, count, or epoch that we observed on-chain.
+    pub actual: u64,
+}
+
+/// SOL was deposited in exchange for newly minted stSOL.
+#[event]
+pub struct Deposited {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Account that paid the SOL, the sponsor when depositing on behalf of someone else.
+    pub user: Pubkey,
+
+    /// Token account that received the stSOL.
+    pub recipient: Pubkey,
+
+    /// Owner of the token account that received the stSOL.
+    pub recipient_owner: Pubkey,
+
+    /// Amount of SOL deposited.
+    pub amount_sol: u64,
+
+    /// Amount of stSOL minted.
+    pub amount_st_sol: u64,
+
+    /// Identifier that the depositor passed to correlate the deposit, e.g. an exchange's user id.
+    pub memo: Option<[u8; 32]>,
+}
+
+/// stSOL was burned in exchange for a stake account.
+#[event]
+pub struct Withdrawn {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Owner of the burned stSOL, who now controls the stake account.
+    pub owner: Pubkey,
+
+    /// The newly split stake account.
+    pub stake_account: Pubkey,
+
+    /// Amount of stSOL burned.
+    pub amount_st_sol: u64,
+
+    /// Amount of SOL in the stake account.
+    pub amount_sol: u64,
+}
+
+/// A validator was deactivated because its vote account was closed, see `DeactivateClosedValidator`.
+#[event]
+pub struct ClosedValidatorDeactivated {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// The closed vote account of the validator.
+    pub validator_vote: Pubkey,
+}
+
+/// `CollectValidatorFee` found rewards that do not match the vote credits of the validator.
+///
+/// The fees are collected either way, the event flags the validator for a closer look.
+#[event]
+pub struct ValidatorRewardsAnomaly {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Vote account of the validator.
+    pub validator_vote: Pubkey,
+
+    /// Rewards in the vote account, in lamports.
+    pub rewards: u64,
+
+    /// Credits that the validator earned from `since_epoch` up to the current epoch.
+    pub credits_earned: u64,
+
+    /// Epoch in which the fees of the validator were last collected.
+    pub since_epoch: u64,
+}
+
+/// A stake account from an SPL stake pool was absorbed, see `ImportFromStakePool`.
+#[event]
+pub struct StakePoolStakeImported {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Vote account of the validator that the stake is delegated to.
+    pub validator_vote: Pubkey,
+
+    /// The stake account that was absorbed.
+    pub source_stake_account: Pubkey,
+
+    /// Amount of SOL that was absorbed.
+    pub amount_sol: u64,
+
+    /// Token account that received the stSOL.
+    pub recipient: Pubkey,
+
+    /// Amount of stSOL minted.
+    pub amount_st_sol: u64,
+}
+
+/// SOL was donated to the reserve, without minting stSOL.
+#[event]
+pub struct Donated {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Account that paid the SOL.
+    pub donor: Pubkey,
+
+    /// Amount of SOL donated.
+    pub amount_sol: u64,
+}
+
+/// The manager scheduled a transfer of the stSOL mint authority, see `ScheduleMintAuthorityTransfer`.
+#[event]
+pub struct MintAuthorityTransferScheduled {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// The mint authority that `TransferMintAuthorityOut` can transfer to.
+    pub new_mint_authority: Pubkey,
+
+    /// Epoch from which `TransferMintAuthorityOut` can transfer.
+    pub effective_epoch: u64,
+}
+
+/// The stSOL mint authority was transferred out, see `TransferMintAuthorityOut`.
+#[event]
+pub struct MintAuthorityTransferredOut {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// The new mint authority of stSOL.
+    pub new_mint_authority: Pubkey,
+}
+
+/// `CollectValidatorFee` collected the rewards of the last validator in an epoch.
+///
+/// Sums up the rewards of all validators in the epoch, so analytics need not
+/// add up the collections themselves. If a validator is added after this was
+/// emitted, collecting its fees emits it again, with the new totals.
+#[event]
+pub struct EpochRewardsCollected {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Epoch of the exchange rate that the fees were collected at.
+    pub epoch: u64,
+
+    /// Rewards of all validators, before fees.
+    pub rewards_sol: u64,
+
+    /// Fees of the treasury, before conversion to stSOL.
+    pub fee_treasury_sol: u64,
+
+    /// Fees of the validators, before conversion to stSOL.
+    pub fee_validation_sol: u64,
+
+    /// Fees of the developer, before conversion to stSOL.
+    pub fee_developer_sol: u64,
+
+    /// The part of the rewards that benefited stSOL holders.
+    pub st_sol_appreciation_sol: u64,
+
+    /// `ExchangeRate::st_sol_supply` of the epoch.
+    pub st_sol_supply: u64,
+
+    /// `ExchangeRate::sol_balance` of the epoch, before the rewards.
+    pub sol_balance: u64,
+
+    /// `sol_balance` with the stSOL appreciation added.
+    ///
+    /// The fees leave the SOL value of stSOL about unchanged, so compared to
+    /// `sol_balance`, this is the change of the exchange rate that the next
+    /// `UpdateExchangeRate` will see due to the rewards.
+    pub sol_balance_after: u64,
+}
+
+/// The manager transferred stSOL out of the protocol treasury, see `WithdrawTreasury`.
+#[event]
+pub struct TreasuryWithdrawn {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// The stSOL account that received the stSOL.
+    pub recipient: Pubkey,
+
+    /// Amount of stSOL transferred.
+    pub amount_st_sol: u64,
+}
+
+/// The max commission of validators changed, see `ApplyMaxCommissionChange`.
+#[event]
+pub struct MaxCommissionChanged {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// The new `Config::max_commission`.
+    pub max_commission: u8,
+}
+
+/// The manager changed how rewards are split, see `ChangeRewardDistribution`.
+#[event]
+pub struct RewardDistributionChanged {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// The new parts of the `RewardDistribution`.
+    pub treasury_fee: u32,
+    pub validation_fee: u32,
+    pub developer_fee: u32,
+    pub st_sol_appreciation: u32,
+}
+
+/// The manager changed the fee recipients, see `ChangeFeeRecipients`.
+#[event]
+pub struct FeeRecipientsChanged {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// The new `FeeRecipients`.
+    pub treasury_account: Pubkey,
+    pub developer_account: Pubkey,
+    pub treasury_fee_denomination: FeeDenomination,
+    pub developer_fee_denomination: FeeDenomination,
+}
+
+/// The target weight of a validator changed, see `SetValidatorTargetWeight`.
+#[event]
+pub struct ValidatorTargetWeightChanged {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Vote account of the validator.
+    pub validator_vote: Pubkey,
+
+    /// The new `Validator::target_weight`.
+    pub target_weight: u32,
+}
+
+/// The manager scheduled a new fee address for a validator, see `OverrideValidatorFeeAccount`.
+#[event]
+pub struct ValidatorFeeAccountOverrideScheduled {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Vote account of the validator.
+    pub validator_vote: Pubkey,
+
+    /// The new `Validator::fee_address_override`.
+    pub fee_address: Pubkey,
+
+    /// First epoch in which `ApplyValidatorFeeAccountOverride` can apply the new fee address.
+    pub effective_epoch: u64,
+}
+
+/// The delegation authority was replaced, see `SetDelegationAuthority`.
+#[event]
+pub struct DelegationAuthorityChanged {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// The new `Lido::delegation_authority`, the default pubkey if there is none.
+    pub delegation_authority: Pubkey,
+}
+
+/// The manager enabled, changed, or disabled the Wormhole integration, see `SetWormholeProgram`.
+#[event]
+pub struct WormholeProgramChanged {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// The new `Config::wormhole_program`, the default pubkey if it is disabled.
+    pub wormhole_program: Pubkey,
+}
+
+/// The manager changed the tunables of the instance, see `UpdateConfig`.
+#[event]
+pub struct ConfigUpdated {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// The new `Config::min_deposit`, in lamports.
+    pub min_deposit: u64,
+
+    /// The new `Config::min_reserve_balance`, in lamports.
+    pub min_reserve_balance: u64,
+
+    /// The new `Config::deposits_paused`.
+    pub deposits_paused: bool,
+
+    /// The new `Config::withdrawals_paused`.
+    pub withdrawals_paused: bool,
+
+    /// The new `Config::max_instant_withdrawal`, in stSOL lamports.
+    pub max_instant_withdrawal: u64,
+
+    /// The new `Config::max_stake_per_epoch`, in lamports.
+    pub max_stake_per_epoch: u64,
+
+    /// The new `Config::max_exchange_rate_change_bps`.
+    pub max_exchange_rate_change_bps: u32,
+
+    /// The new `Config::idle_reserve_fee_adjustment`.
+    pub idle_reserve_fee_adjustment: bool,
+
+    /// The new `Config::reject_cpi`.
+    pub reject_cpi: bool,
+
+    /// The new `Config::stale_exchange_rate_guard`.
+    pub stale_exchange_rate_guard: bool,
+
+    /// The new `Config::crank_tip`, in lamports.
+    pub crank_tip: u64,
+
+    /// The new `Config::min_epochs_before_stake`.
+    pub min_epochs_before_stake: u64,
+}
+
+/// The manager registered or changed the deposit hook, see `SetDepositHook`.
+#[event]
+pub struct DepositHookSet {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// The new `DepositHook::enabled`.
+    pub enabled: bool,
+
+    /// The new `DepositHook::program_id`.
+    pub program_id: Pubkey,
+
+    /// Number of accounts in the new `DepositHook::accounts`.
+    pub num_accounts: u8,
+}
+
+/// The manager replaced the seed range of a validator, see `RepairSeedRange`.
+#[event]
+pub struct SeedRangeRepaired {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Vote account of the validator.
+    pub validator_vote: Pubkey,
+
+    /// Whether the stake or the unstake seeds were replaced.
+    pub stake_type: StakeType,
+
+    /// The seed range before the repair, `begin` and `end`.
+    pub old_begin: u64,
+    pub old_end: u64,
+
+    /// The seed range after the repair, `begin` and `end`.
+    pub new_begin: u64,
+    pub new_end: u64,
+}
+
+/// The manager added a maintainer.
+#[event]
+pub struct MaintainerAdded {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    pub maintainer: Pubkey,
+}
+
+/// The manager removed a maintainer.
+#[event]
+pub struct MaintainerRemoved {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    pub maintainer: Pubkey,
+}
+
+/// The manager started retiring the instance, see `StartWindDown`.
+#[event]
+pub struct WindDownStarted {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+}
+
+/// A user withdrew stSOL from the reserve, after a wind-down or as an instant withdrawal.
+#[event]
+pub struct WithdrawnFromReserve {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Owner of the stSOL account that the stSOL was burned from.
+    pub owner: Pubkey,
+
+    /// Account that received the SOL.
+    pub recipient: Pubkey,
+
+    /// Amount of stSOL burned.
+    pub amount_st_sol: u64,
+
+    /// Amount of SOL withdrawn.
+    pub amount_sol: u64,
+}
+
+/// The reserve paid `Config::crank_tip` to whoever cranked a maintenance step.
+#[event]
+pub struct CrankTipPaid {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Name of the instruction that was cranked, like `"CollectValidatorFee"`.
+    pub instruction: String,
+
+    /// Account that received the tip.
+    pub recipient: Pubkey,
+
+    /// Amount of SOL paid.
+    pub amount: u64,
+}
+
+/// The withdraw authority of a validator's vote account moved to the validator's own authority.
+///
+/// See `RotateRewardsWithdrawAuthority`.
+#[event]
+pub struct RewardsWithdrawAuthorityRotated {
+    /// Value of `Lido::operation_nonce` after this operation.
+    pub nonce: u64,
+
+    /// Vote account of the validator.
+    pub validator_vote: Pubkey,
+
+    /// The new withdraw authority of the vote account.
+    pub rewards_withdraw_authority: Pubkey,
+}
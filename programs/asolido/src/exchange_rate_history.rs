@@ -0,0 +1,93 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Log of the most recent exchange rates of an instance.
+//!
+//! `Lido::exchange_rate` only holds the rate of the last epoch. Risk engines of
+//! DeFi protocols that accept stSOL as collateral prefer an average over
+//! several epochs, which is harder to manipulate than a single spot rate, so
+//! `UpdateExchangeRate` also appends every new rate to a fixed-size ring
+//! buffer in a companion account.
+
+use anchor_lang::prelude::*;
+
+use crate::state::ExchangeRate;
+
+/// Number of exchange rates that the history holds, the length of `ExchangeRateHistory::rates`.
+pub const EXCHANGE_RATE_HISTORY_LEN: usize = 32;
+
+#[account]
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct ExchangeRateHistory {
+    /// The instance whose exchange rates are recorded.
+    pub lido: Pubkey,
+
+    /// Number of rates recorded since the history was created.
+    ///
+    /// The most recent rate is at index `(num_recorded - 1) % EXCHANGE_RATE_HISTORY_LEN`.
+    pub num_recorded: u64,
+
+    /// Ring buffer of the recorded rates.
+    pub rates: [ExchangeRate; 32],
+}
+
+impl ExchangeRateHistory {
+    /// Number of bytes needed for the account.
+    pub const LEN: usize = 8 + 32 + 8 + EXCHANGE_RATE_HISTORY_LEN * (8 + 8 + 8);
+
+    /// Append a rate, overwriting the oldest one once the history is full.
+    pub fn record(&mut self, rate: ExchangeRate) {
+        let index = (self.num_recorded % EXCHANGE_RATE_HISTORY_LEN as u64) as usize;
+        self.rates[index] = rate;
+        self.num_recorded += 1;
+    }
+
+    /// Return the recorded rates, from oldest to most recent.
+    pub fn iter(&self) -> impl Iterator<Item = &ExchangeRate> {
+        let len = EXCHANGE_RATE_HISTORY_LEN as u64;
+        let begin = self.num_recorded.saturating_sub(len);
+        (begin..self.num_recorded).map(move |i| &self.rates[(i % len) as usize])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::token::{Lamports, StLamports};
+
+    fn rate_in_epoch(epoch: u64) -> ExchangeRate {
+        ExchangeRate {
+            computed_in_epoch: epoch,
+            st_sol_supply: StLamports::new(epoch * 100),
+            sol_balance: Lamports::new(epoch * 110),
+        }
+    }
+
+    #[test]
+    fn test_record_wraps_around_and_iterates_oldest_first() {
+        let mut history = ExchangeRateHistory::default();
+        assert_eq!(history.iter().count(), 0);
+
+        for epoch in 1..=3 {
+            history.record(rate_in_epoch(epoch));
+        }
+        let epochs: Vec<u64> = history.iter().map(|r| r.computed_in_epoch).collect();
+        assert_eq!(epochs, vec![1, 2, 3]);
+
+        for epoch in 4..=40 {
+            history.record(rate_in_epoch(epoch));
+        }
+        let epochs: Vec<u64> = history.iter().map(|r| r.computed_in_epoch).collect();
+        assert_eq!(epochs, (9..=40).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_len_matches_serialization() {
+        let history = ExchangeRateHistory::default();
+        assert_eq!(history.rates.len(), EXCHANGE_RATE_HISTORY_LEN);
+        assert_eq!(
+            8 + history.try_to_vec().unwrap().len(),
+            ExchangeRateHistory::LEN
+        );
+    }
+}
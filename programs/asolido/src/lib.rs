@@ -1,108 +1,763 @@
+use crate::attestation::ReserveAttestation;
+use crate::config::{Config, ConfigUpdate};
+use crate::deposit_hook::{DepositHook, HookAccount};
 use crate::error::LidoError;
+use crate::exchange_rate_history::ExchangeRateHistory;
+use crate::logic::{
+    check_expected_state_hash, check_invariants, require_maintainer,
+    require_maintainer_or_delegation_authority,
+};
+use crate::registry::Registry;
 use crate::state::Lido;
-use crate::state::{RewardDistribution, LIDO_VERSION};
+use crate::state::{
+    FeeDenomination, FreezeAuthorityPolicy, RewardDistribution, SeedRange, StakeType, LIDO_VERSION,
+};
 use crate::token::{Lamports, StLamports};
-use crate::vote_state::PartialVoteState;
+use crate::validator_info::ValidatorInfo;
+use crate::vote_state::{PartialVoteState, MINIMUM_VOTE_ACCOUNT_SIZE};
 use anchor_lang::prelude::*;
+use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::token::{Mint, Token, TokenAccount};
-use solana_program::program_option::COption;
+use solana_program::sysvar::epoch_schedule::EpochSchedule;
+use solana_program::sysvar::stake_history::StakeHistory;
 
 declare_id!("BjYuhzR84Wovp7KVtTcej6Rr5X1KsnDdG4qDXz8KZk3M");
 
+pub mod attestation;
+pub mod compute_budget;
+pub mod config;
+pub mod deposit_hook;
 pub mod error;
+pub mod events;
+pub mod exchange_rate_history;
 pub mod logic;
 pub mod maintainers;
 pub mod metrics;
+pub mod pda;
 pub mod process;
 pub mod process_managment;
+pub mod registry;
+pub mod remaining_accounts;
+#[cfg(all(feature = "snapshot", not(target_arch = "bpf")))]
+pub mod snapshot;
+pub mod solido_v1;
+pub mod stake_account;
 pub mod state;
 pub mod token;
+pub mod validator_info;
 pub mod validators;
 pub mod vote_state;
+pub mod wormhole;
 
 #[program]
 pub mod asolido {
 
     use super::*;
 
+    /// Initialize a new Solido instance.
+    ///
+    /// The `Lido` account is allocated for `max_validators` validators and
+    /// `max_maintainers` maintainers up front, and these maximums are fixed
+    /// for the lifetime of the instance. No instruction resizes the account:
+    /// `AddValidator` takes no payer that could fund the rent of a larger
+    /// account, and `RemoveValidator` leaves the space for the next one. So
+    /// pick maximums with room to grow.
+    ///
+    /// The stSOL mint must have no freeze authority, or the compliance
+    /// authority of the instance as freeze authority, as set by
+    /// `freeze_authority_policy`. The policy cannot be changed later.
+    ///
+    /// `fee_denomination` sets whether validators accrue their fees in stSOL or
+    /// in SOL, see `FeeDenomination`. It cannot be changed later either.
     pub fn initialize(
         ctx: Context<Initialize>,
         reward_distribution: RewardDistribution,
         max_validators: u32,
         max_maintainers: u32,
+        freeze_authority_policy: FreezeAuthorityPolicy,
+        fee_denomination: FeeDenomination,
     ) -> Result<()> {
         ctx.accounts.process(
-            &ctx.bumps,
+            ctx.program_id,
             LIDO_VERSION,
             reward_distribution,
             max_validators,
             max_maintainers,
-        )
+            freeze_authority_policy,
+            fee_denomination,
+        )?;
+        // The reserve can be funded after initialization, so it is not checked here.
+        check_invariants(&ctx.accounts.lido, None)
     }
 
     /// Deposit a given amount of SOL.
     ///
-    /// This can be called by anybody.
-    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
-        ctx.accounts.process(Lamports::new(amount))
+    /// This can be called by anybody. The `user` pays the SOL, and the stSOL
+    /// is minted to `recipient`, which can be owned by somebody else, so a
+    /// sponsor such as an onramp or custodian can fund a deposit on behalf of
+    /// its users. The `Deposited` event records both.
+    ///
+    /// The `user` does not have to be the fee payer, it can be any signing
+    /// account owned by the system program, such as a PDA of another program
+    /// that deposits via CPI with `invoke_signed`.
+    ///
+    /// Fails if less than `min_st_sol_out` stSOL would be minted, so a deposit
+    /// that lands after an unexpected exchange rate update does not go through.
+    ///
+    /// The optional `memo` is not interpreted, it is included in the
+    /// `Deposited` event, so exchanges and custodians can match the deposit
+    /// to a user in their own books without a separate memo instruction.
+    ///
+    /// To invoke the deposit hook after minting, see `SetDepositHook`, pass
+    /// the `DepositHook` account as the first remaining account, followed by
+    /// the hook program and the fixed accounts that the hook takes.
+    pub fn deposit<'info>(
+        ctx: Context<'_, '_, '_, 'info, Deposit<'info>>,
+        amount: u64,
+        min_st_sol_out: StLamports,
+        memo: Option<[u8; 32]>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(
+            ctx.program_id,
+            Lamports::new(amount),
+            min_st_sol_out,
+            memo,
+            ctx.remaining_accounts,
+        )?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
+    }
+
+    /// Deposit a given amount of SOL into the associated stSOL account of `recipient_owner`.
+    ///
+    /// This can be called by anybody. If the associated token account does
+    /// not exist yet, it is created, and the user pays for its rent, so
+    /// wallets can onboard a user in a single transaction. `memo` is
+    /// included in the `Deposited` event, and the deposit hook is invoked
+    /// with the same remaining accounts, like for `Deposit`.
+    pub fn deposit_to_associated_account<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositToAssociatedAccount<'info>>,
+        amount: u64,
+        min_st_sol_out: StLamports,
+        memo: Option<[u8; 32]>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(
+            ctx.program_id,
+            Lamports::new(amount),
+            min_st_sol_out,
+            memo,
+            ctx.remaining_accounts,
+        )?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
+    }
+
+    /// Donate a given amount of SOL to the reserve.
+    ///
+    /// Unlike `Deposit`, this does not mint stSOL, so the donation benefits all
+    /// stSOL holders once the exchange rate is updated. Donations are tracked
+    /// separately in the metrics. This can be called by anybody.
+    pub fn donate(ctx: Context<Donate>, amount: u64) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(Lamports::new(amount))?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
     }
 
     /// Withdraw a given amount of stSOL.
     ///
     /// Caller provides some `amount` of StLamports that are to be burned in
     /// order to withdraw SOL.
-    #[allow(unused_variables)]
-    pub fn withdraw(ctx: Context<Withdraw>, amount: StLamports) -> Result<()> {
-        todo!()
+    ///
+    /// The SOL is split off the validator's first stake account into a new
+    /// stake account, `destination_stake_account`, that is then handed over to
+    /// the owner of the stSOL.
+    ///
+    /// The remaining accounts are optional fallbacks: pairs of a vote account
+    /// and the first stake account of its validator, in order of decreasing
+    /// stake. If the validator with the most stake cannot serve the
+    /// withdrawal, for example because its stake is being unstaked, the next
+    /// one serves it instead.
+    ///
+    /// Fails if the new stake account would hold less than `min_sol_out`.
+    pub fn withdraw(
+        ctx: Context<Withdraw>,
+        amount: StLamports,
+        min_sol_out: Lamports,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts
+            .process(ctx.program_id, amount, min_sol_out, ctx.remaining_accounts)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Withdraw a given amount of stSOL into a stake account that the owner already has.
+    ///
+    /// Like `Withdraw`, but the new stake account is then merged into
+    /// `user_stake_account`, which must be fully active stake of the same
+    /// validator, with the owner of the stSOL as staker and withdrawer. This
+    /// saves the rent and the clutter of a stake account per withdrawal.
+    pub fn withdraw_and_merge(
+        ctx: Context<WithdrawAndMerge>,
+        amount: StLamports,
+        min_sol_out: Lamports,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.withdraw.lido)?;
+        ctx.accounts.process(ctx.program_id, amount, min_sol_out)?;
+        check_invariants(&ctx.accounts.withdraw.lido, None)
+    }
+
+    /// Withdraw a given amount of stSOL into a new stake account per validator.
+    ///
+    /// For redemptions that are larger than what `Withdraw` can split off a
+    /// single stake account. The stSOL is split over the validators in
+    /// proportion to their stake. Pass three remaining accounts per validator:
+    /// its vote account, its first stake account, and the new stake account,
+    /// which must sign. The validator with the most stake goes first. Every
+    /// new stake account is handed over to the owner of the stSOL.
+    ///
+    /// Fails if the new stake accounts would hold less than `min_sol_out` together.
+    pub fn withdraw_from_validators<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawFromValidators<'info>>,
+        amount: StLamports,
+        min_sol_out: Lamports,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts
+            .process(ctx.program_id, amount, min_sol_out, ctx.remaining_accounts)?;
+        check_invariants(&ctx.accounts.lido, None)
     }
 
     /// Move deposits from the reserve into a stake account and delegate it to a member validator.
-    #[allow(unused_variables)]
-    pub fn stake_deposit(ctx: Context<StakeDeposit>, amount: Lamports) -> Result<()> {
-        todo!()
+    ///
+    /// If the validator's last stake account was activated in this epoch,
+    /// pass it as `stake_account_merge_into`, and the new stake is merged into
+    /// it. Otherwise, pass the new stake account for both.
+    ///
+    /// If `expected_stake_deposits` is set and `Validator::stake_deposits`
+    /// already moved past it, another transaction staked first, and this does
+    /// nothing. We do not expect the end seed here, because it does not move
+    /// when merging.
+    ///
+    /// If `expected_state_hash` is set, it must match `Lido::maintenance_state_hash`,
+    /// like for the other maintenance instructions, or this fails with
+    /// `MaintenanceStateChanged`.
+    ///
+    /// Requires the manager or a maintainer to sign.
+    #[access_control(require_maintainer(&ctx.accounts.lido, &ctx.accounts.signer))]
+    pub fn stake_deposit(
+        ctx: Context<StakeDeposit>,
+        amount: Lamports,
+        expected_stake_deposits: Option<u64>,
+        expected_state_hash: Option<[u8; 32]>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        check_expected_state_hash(&ctx.accounts.lido, expected_state_hash)?;
+        ctx.accounts
+            .process(ctx.program_id, amount, expected_stake_deposits)?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
+    }
+
+    /// Stake everything in the reserve above `Config::min_reserve_balance` with a member validator.
+    ///
+    /// Takes the same accounts and `expected_state_hash` as `StakeDeposit`, and
+    /// requires the manager or a maintainer to sign.
+    #[access_control(require_maintainer(&ctx.accounts.lido, &ctx.accounts.signer))]
+    pub fn sweep_reserve(
+        ctx: Context<StakeDeposit>,
+        expected_state_hash: Option<[u8; 32]>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        check_expected_state_hash(&ctx.accounts.lido, expected_state_hash)?;
+        ctx.accounts.process_sweep(ctx.program_id)?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
     }
 
     /// Unstake from a validator to a new stake account.
-    #[allow(unused_variables)]
-    pub fn unstake(ctx: Context<Unstake>, amount: Lamports) -> Result<()> {
-        todo!()
+    ///
+    /// Splits `amount` off the validator's first stake account into a new
+    /// unstake account, and deactivates it. A validator can have at most
+    /// `MAXIMUM_UNSTAKE_ACCOUNTS` unstake accounts at a time.
+    /// `expected_state_hash` works as for `StakeDeposit`.
+    ///
+    /// Requires the manager, a maintainer, or the delegation authority to sign.
+    #[access_control(require_maintainer_or_delegation_authority(
+        &ctx.accounts.lido,
+        &ctx.accounts.signer
+    ))]
+    pub fn unstake(
+        ctx: Context<Unstake>,
+        amount: Lamports,
+        expected_state_hash: Option<[u8; 32]>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        check_expected_state_hash(&ctx.accounts.lido, expected_state_hash)?;
+        ctx.accounts.process(ctx.program_id, amount)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Deactivate all stake accounts of an inactive validator.
+    ///
+    /// Requires the manager or a maintainer to sign. The stake accounts of the
+    /// validator are passed as remaining accounts, in seed order. Once the
+    /// stake is inactive, `WithdrawInactiveStake` closes the accounts and
+    /// returns the SOL to the reserve, after which the validator can be removed.
+    /// `expected_state_hash` works as for `StakeDeposit`. Like
+    /// `WithdrawInactiveStake`, this stops early if the compute budget runs low.
+    #[access_control(require_maintainer(&ctx.accounts.lido, &ctx.accounts.signer))]
+    pub fn unstake_all_from_validator<'info>(
+        ctx: Context<'_, '_, '_, 'info, UnstakeAllFromValidator<'info>>,
+        expected_state_hash: Option<[u8; 32]>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        check_expected_state_hash(&ctx.accounts.lido, expected_state_hash)?;
+        ctx.accounts.process(ctx.program_id, ctx.remaining_accounts)
+    }
+
+    /// Write the balances of the reserve and of all stake accounts to the `ReserveAttestation` account.
+    ///
+    /// This can be called by anybody, the `payer` pays for the account if it
+    /// does not exist yet. The remaining accounts are, for every validator in
+    /// the order of `Lido::validators`, its stake accounts followed by its
+    /// unstake accounts. Bridges and issuers of wrapped stSOL read the
+    /// attestation as a proof of the SOL that backs stSOL.
+    pub fn attest_reserves(ctx: Context<AttestReserves>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(ctx.program_id, ctx.remaining_accounts)
+    }
+
+    /// Post the exchange rate of the current epoch as a message to the Wormhole core bridge.
+    ///
+    /// This can be called by anybody once the exchange rate of the epoch is
+    /// updated and the manager has set `Config::wormhole_program`. The `payer`
+    /// pays the message fee of the bridge and the rent of the new `message`
+    /// account. The message is emitted by the PDA with seeds
+    /// `[lido, "wormhole_emitter"]`, so contracts on other chains can verify
+    /// that it comes from this instance.
+    pub fn post_exchange_rate_message(
+        ctx: Context<PostExchangeRateMessage>,
+        nonce: u32,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(ctx.program_id, nonce)
+    }
+
+    /// Create the account that records the most recent exchange rates.
+    ///
+    /// This can be called by anybody, the `payer` pays for the account. Once
+    /// it exists, `UpdateExchangeRate` and `FinishUpdateExchangeRate` record
+    /// every new exchange rate in it.
+    pub fn initialize_exchange_rate_history(
+        ctx: Context<InitializeExchangeRateHistory>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()
     }
 
     /// Update the exchange rate, at the beginning of the epoch.
-    #[allow(unused_variables)]
-    pub fn update_exchange_rate(ctx: Context<UpdateExchangeRate>) -> Result<()> {
-        todo!()
+    ///
+    /// This can be called by anybody. The new rate is also recorded in the
+    /// exchange rate history.
+    ///
+    /// If `expected_epoch` is set and the exchange rate was already computed
+    /// in or after that epoch, this does nothing, rather than failing.
+    ///
+    /// Pays `Config::crank_tip` to `tip_recipient`, see `pay_crank_tip`.
+    pub fn update_exchange_rate(
+        ctx: Context<UpdateExchangeRate>,
+        expected_epoch: Option<u64>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(expected_epoch)?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
+    }
+
+    /// Add the balances of at most `max_validators` validators to an exchange rate update.
+    ///
+    /// For validator sets that are too large to update the exchange rate in
+    /// one transaction. Call this until all validators are included, then call
+    /// `FinishUpdateExchangeRate`. If the epoch changes in between, the update
+    /// starts over. This can be called by anybody.
+    pub fn begin_update_exchange_rate(
+        ctx: Context<BeginUpdateExchangeRate>,
+        max_validators: u32,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(max_validators)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Commit the exchange rate update that `BeginUpdateExchangeRate` accumulated.
+    ///
+    /// This can be called by anybody, and pays `Config::crank_tip` to `tip_recipient`.
+    pub fn finish_update_exchange_rate(ctx: Context<UpdateExchangeRate>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process_finish()?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
     }
 
     /// Observe any external changes in the balances of a validator's stake accounts.
     ///
     /// If there is inactive balance in stake accounts, withdraw this back to the reserve.
-    #[allow(unused_variables)]
-    pub fn withdraw_inactive_stake(ctx: Context<WithdrawInactiveStake>) -> Result<()> {
-        todo!()
+    ///
+    /// The stake accounts of the validator, followed by its unstake accounts,
+    /// are passed as remaining accounts, in seed order. If the compute budget
+    /// runs low, this stops early and keeps the progress so far, call it again
+    /// to continue, see `ComputeBudgetGuard`. The first call per validator
+    /// per epoch that withdraws anything pays `Config::crank_tip` to `tip_recipient`.
+    pub fn withdraw_inactive_stake<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawInactiveStake<'info>>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts
+            .process(ctx.program_id, ctx.remaining_accounts)?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
+    }
+
+    /// Check that the stake or unstake accounts of a validator exist for exactly its seed range.
+    ///
+    /// The account at the seed before the range (unless the range starts at
+    /// 0), the accounts in the range, and the account at the end seed are
+    /// passed as remaining accounts, in seed order. This changes nothing, and
+    /// can be called, or simulated, by anybody.
+    pub fn check_seed_range<'info>(
+        ctx: Context<'_, '_, '_, 'info, CheckSeedRange<'info>>,
+        stake_type: StakeType,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts
+            .process(ctx.program_id, stake_type, ctx.remaining_accounts)
+    }
+
+    /// Replace the seed range of a validator's stake or unstake accounts.
+    ///
+    /// Requires the manager to sign. This recovers validators whose seed range
+    /// got out of sync with their stake accounts. The remaining accounts are
+    /// the evidence for the new range, as for `CheckSeedRange`. The tracked
+    /// balances are not changed, `WithdrawInactiveStake` observes the balances
+    /// of the new range, and a loss has to be acknowledged with `AcknowledgeLoss`.
+    pub fn repair_seed_range<'info>(
+        ctx: Context<'_, '_, '_, 'info, RepairSeedRange<'info>>,
+        stake_type: StakeType,
+        seeds: SeedRange,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts
+            .process(ctx.program_id, stake_type, seeds, ctx.remaining_accounts)?;
+        check_invariants(&ctx.accounts.lido, None)
     }
 
-    #[allow(unused_variables)]
+    /// Withdraw the rewards from a validator's vote account into the reserve, and pay the fees.
+    ///
+    /// The treasury and developer fees are minted as stSOL, or paid in SOL
+    /// from the rewards if `FeeRecipients` says so, the validation fee is
+    /// credited to the validator, and the rest benefits stSOL holders. This
+    /// can be called once per validator per epoch, after `UpdateExchangeRate`.
+    /// This can be called by anybody, and pays `Config::crank_tip` to `tip_recipient`.
+    /// For the last validator of the epoch, it emits `EpochRewardsCollected`.
     pub fn collect_validator_fee(ctx: Context<CollectValidatorFee>) -> Result<()> {
-        todo!()
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(ctx.program_id)?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
     }
 
-    #[allow(unused_variables)]
+    /// Mint the unclaimed fee credit of a validator to its registered fee address.
+    ///
+    /// This can be called by anybody.
     pub fn claim_validator_fee(ctx: Context<ClaimValidatorFee>) -> Result<()> {
-        todo!()
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Like `ClaimValidatorFee`, but only if the fee credit is at least `threshold`.
+    ///
+    /// Otherwise this does nothing, rather than failing, so a maintainer bot
+    /// can call it for every validator, and validators receive their fees
+    /// without running their own tooling, in claims large enough to be worth
+    /// the transaction. This can be called by anybody.
+    pub fn claim_validator_fee_if_above(
+        ctx: Context<ClaimValidatorFee>,
+        threshold: StLamports,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process_if_above(threshold)?;
+        check_invariants(&ctx.accounts.lido, None)
     }
 
-    #[allow(unused_variables)]
+    /// Mint the unclaimed fee credit of a validator to any stSOL account.
+    ///
+    /// Requires the validator identity (the `node_pubkey` of the vote account)
+    /// to sign. The vote account's withdraw authority is Solido itself, so the
+    /// identity is the key that the validator controls.
+    pub fn claim_validator_fee_to(ctx: Context<ClaimValidatorFeeTo>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Pay the unclaimed fee credit in SOL of a validator from the reserve to any account.
+    ///
+    /// Only instances with `FeeDenomination::Sol` credit fees in SOL. Like
+    /// `ClaimValidatorFeeTo`, this requires the validator identity to sign.
+    pub fn claim_validator_fee_sol(ctx: Context<ClaimValidatorFeeSol>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
+    }
+
+    /// Replace the split of rewards between treasury, validators, developer, and stSOL appreciation.
+    ///
+    /// Requires the manager to sign. Fails with `InvalidRewardDistribution` if
+    /// the parts sum to zero or to more than `MAXIMUM_REWARD_DISTRIBUTION_SUM`.
     pub fn change_reward_distribution(
         ctx: Context<ChangeRewardDistribution>,
         new_reward_distribution: RewardDistribution,
     ) -> Result<()> {
-        Ok(())
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(new_reward_distribution)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Replace the treasury and developer accounts, and whether they are paid in stSOL or SOL.
+    ///
+    /// Requires the manager to sign. A recipient paid in stSOL must be an
+    /// stSOL account, one paid in SOL must be owned by the system program.
+    /// Fees in SOL are paid from the rewards in `CollectValidatorFee`.
+    pub fn change_fee_recipients(
+        ctx: Context<ChangeFeeRecipients>,
+        treasury_fee_denomination: FeeDenomination,
+        developer_fee_denomination: FeeDenomination,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts
+            .process(treasury_fee_denomination, developer_fee_denomination)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Return how `amount` of rewards of a validator would be split into fees.
+    ///
+    /// This does not modify any state. The `ValidatorFees` are set as the
+    /// return data of the transaction, Borsh-serialized. They are what
+    /// `CollectValidatorFee` would book for these rewards with the current
+    /// reward distribution, idle reserve discount, fee denominations and
+    /// exchange rate. This can be called by anybody, typically in a simulated
+    /// transaction.
+    pub fn preview_reward_split(ctx: Context<PreviewRewardSplit>, amount: Lamports) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(amount)
+    }
+
+    /// Return the balances of the validator with vote account `validator_vote`.
+    ///
+    /// This does not modify any state. The `ValidatorStake` is set as the
+    /// return data of the transaction, Borsh-serialized, so dashboards can
+    /// read a single validator without parsing the `Lido` account. This can be
+    /// called by anybody, typically in a simulated transaction.
+    pub fn get_validator_stake(
+        ctx: Context<GetValidatorStake>,
+        validator_vote: Pubkey,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(&validator_vote)
+    }
+
+    /// Schedule the fee address of a validator to be replaced by `new_fee_address`.
+    ///
+    /// Requires the manager to sign.
+    ///
+    /// This is for validators that lost access to their fee account, so their
+    /// unclaimed fee credit is not stranded. The override takes effect only
+    /// after `FEE_ADDRESS_OVERRIDE_DELAY_EPOCHS`, through
+    /// `ApplyValidatorFeeAccountOverride`, which gives the validator and the
+    /// community time to notice it.
+    pub fn override_validator_fee_account(ctx: Context<OverrideValidatorFeeAccount>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Replace the fee address of a validator with the override that the manager scheduled.
+    ///
+    /// This can be called by anybody, once the delay has passed.
+    pub fn apply_validator_fee_account_override(
+        ctx: Context<ApplyValidatorFeeAccountOverride>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Set the tunables in the `Config` account of the instance.
+    ///
+    /// Requires the manager to sign. This replaces all of the minimum deposit,
+    /// the minimum reserve balance, the pause flags, the instant withdrawal
+    /// limit, and the exchange rate change bound at once.
+    pub fn update_config(ctx: Context<UpdateConfig>, update: ConfigUpdate) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(update)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Set the Wormhole core bridge that `PostExchangeRateMessage` posts to.
+    ///
+    /// Requires the manager to sign. The default pubkey disables the integration.
+    pub fn set_wormhole_program(
+        ctx: Context<SetWormholeProgram>,
+        wormhole_program: Pubkey,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(wormhole_program)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Register the program that deposits can invoke after minting, or change it.
+    ///
+    /// Requires the manager to sign, who pays for the `DepositHook` account
+    /// when it is created. The hook is called with `instruction_prefix`
+    /// followed by the amount of stSOL minted, and with `accounts`, in which
+    /// the depositor, the recipient, and the stSOL mint can be filled in per
+    /// deposit. Deposits can only invoke the hook while `enabled` is set.
+    pub fn set_deposit_hook(
+        ctx: Context<SetDepositHook>,
+        enabled: bool,
+        program_id: Pubkey,
+        instruction_prefix: [u8; 8],
+        accounts: Vec<HookAccount>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts
+            .process(enabled, program_id, instruction_prefix, accounts)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Pause or resume staking new deposits with a validator.
+    ///
+    /// Requires the manager or a maintainer to sign.
+    ///
+    /// Unlike `DeactivateValidator`, this does not start the removal process,
+    /// it only prevents `StakeDeposit`, for example while the validator is
+    /// delinquent.
+    pub fn change_validator_stake_paused(
+        ctx: Context<ChangeValidatorStakePaused>,
+        paused: bool,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(paused)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Set the share of the stake that a validator should receive, relative to the other active validators.
+    ///
+    /// Requires the manager or the delegation authority to sign.
+    ///
+    /// `StakeDeposit` stakes with the validator that is furthest below its
+    /// target, and `Unstake` and `Withdraw` start with the one that is
+    /// furthest above it. A weight of 0 stops new stake, like pausing.
+    pub fn set_validator_target_weight(
+        ctx: Context<SetValidatorTargetWeight>,
+        target_weight: u32,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(target_weight)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Replace the delegation authority, or remove it with the default pubkey.
+    ///
+    /// Requires the manager or the current delegation authority to sign.
+    ///
+    /// The delegation authority runs a delegation strategy: it can set target
+    /// weights with `SetValidatorTargetWeight`, and rebalance with `Unstake`,
+    /// but it cannot change fees or the set of validators.
+    pub fn set_delegation_authority(
+        ctx: Context<SetDelegationAuthority>,
+        new_delegation_authority: Pubkey,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(new_delegation_authority)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Move the withdraw authority of a validator's vote account to the validator's own authority.
+    ///
+    /// Requires both the manager and the validator identity to sign. Validators
+    /// added with the rewards withdraw authority of the instance share it, so
+    /// a compromise of it affects all of them. This makes the program-derived
+    /// address with seeds `[lido, validator_vote, REWARDS_WITHDRAW_AUTHORITY]`
+    /// the withdraw authority instead, see
+    /// `Validator::has_own_rewards_withdraw_authority`, which
+    /// `CollectValidatorFee` then signs with.
+    pub fn rotate_rewards_withdraw_authority(
+        ctx: Context<RotateRewardsWithdrawAuthority>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(ctx.program_id)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Set the name, metadata URI, and contact of a validator.
+    ///
+    /// Requires the validator identity or the manager to sign. The information
+    /// is stored in a `ValidatorInfo` account next to the instance, which is
+    /// created on first use, and `payer` pays for it.
+    pub fn set_validator_info(
+        ctx: Context<SetValidatorInfo>,
+        name: String,
+        metadata_uri: String,
+        contact: String,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(name, metadata_uri, contact)
+    }
+
+    /// Update whether a validator is delinquent, based on the last vote in its vote account.
+    ///
+    /// A validator that did not vote for `DELINQUENCY_THRESHOLD_EPOCHS` epochs
+    /// is marked delinquent, and does not receive new stake. Once it votes
+    /// again, calling this clears the mark. This can be called by anybody.
+    pub fn mark_delinquent(ctx: Context<MarkDelinquent>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Deactivate a validator whose vote account was closed, and flag it for removal.
+    ///
+    /// A closed vote account holds no rewards, and can no longer be passed to
+    /// `CollectValidatorFee`, so this takes the validator out of fee collection
+    /// and staking. Its stake can still be unstaked and withdrawn as usual,
+    /// after which `RemoveValidator` removes it. This can be called by anybody,
+    /// and fails with `VoteAccountNotClosed` if the vote account still exists.
+    pub fn deactivate_closed_validator(ctx: Context<DeactivateClosedValidator>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
     }
 
     /// Add a new validator to the validator set.
+    ///
+    /// The withdraw authority of the vote account must be the rewards withdraw
+    /// authority of the instance, or the one derived for this validator alone,
+    /// see `Validator::has_own_rewards_withdraw_authority`.
     pub fn add_validator(ctx: Context<AddValidator>) -> Result<()> {
-        ctx.accounts.process()
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(ctx.program_id)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Add a new validator, and make the rewards withdraw authority the withdraw authority of its vote account.
+    ///
+    /// Requires the manager and the current withdraw authority of the vote
+    /// account to sign. The withdraw authority can be a multisig, that signs
+    /// through its program, so operators can be onboarded atomically, rather
+    /// than handing over the withdraw authority first, and trusting that the
+    /// validator gets added afterwards.
+    pub fn add_validator_and_authorize_withdrawer(
+        ctx: Context<AddValidatorAndAuthorizeWithdrawer>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
     }
 
     /// Set the `active` flag to false for a given validator.
@@ -117,24 +772,272 @@ pub mod asolido {
     /// Once there are no more delegations to this validator, and it has no
     /// unclaimed fee credits, then the validator can be removed.
     pub fn deactivate_validator(ctx: Context<DeactivateValidator>) -> Result<()> {
-        ctx.accounts.process()
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Remove an inactive validator without stake or unclaimed fees.
+    ///
+    /// The remaining accounts are stake and unstake accounts past the end of
+    /// the validator's seed ranges that still exist, which hold no stake, in
+    /// seed order. Their balance moves to the reserve, which closes them.
+    pub fn remove_validator<'info>(
+        ctx: Context<'_, '_, '_, 'info, RemoveValidator<'info>>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts
+            .process(ctx.program_id, ctx.remaining_accounts)?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
+    }
+
+    /// Acknowledge a decrease in the balance of a validator's stake accounts.
+    ///
+    /// Requires the manager to sign.
+    ///
+    /// `WithdrawInactiveStake` refuses to continue when it observes less SOL
+    /// in the stake accounts than we tracked. This writes down the tracked
+    /// balance to the observed balance, lowers the exchange rate by the lost
+    /// amount, and records the loss in the metrics. The stake accounts of the
+    /// validator, followed by its unstake accounts, are passed as remaining
+    /// accounts, in seed order.
+    pub fn acknowledge_loss<'info>(
+        ctx: Context<'_, '_, '_, 'info, AcknowledgeLoss<'info>>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts
+            .process(ctx.program_id, ctx.remaining_accounts)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Absorb a stake account from an SPL stake pool, and mint the equivalent stSOL.
+    ///
+    /// Requires the manager to sign, and the `pool_authority` that is both the
+    /// staker and withdrawer of `source_stake_account`, which is typically a
+    /// stake account withdrawn from the pool with its `WithdrawStake`. The
+    /// source must be fully active and delegated to a member validator. Its
+    /// entire balance is split into the validator's next stake account, and
+    /// stSOL is minted at the current exchange rate to `recipient`, the
+    /// account of the authority that distributes it to the pool token holders.
+    pub fn import_from_stake_pool(ctx: Context<ImportFromStakePool>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(ctx.program_id)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Reconstruct the state of a Solido v1 instance in this instance.
+    ///
+    /// Requires the manager to sign, who must also be the manager of the v1
+    /// instance. This instance must be freshly initialized. Before the
+    /// migration, the v1 instance must have staked its reserve, and handed
+    /// over the mint authority of its stSOL mint to this instance's mint
+    /// authority, and the stake and withdraw authority of its stake accounts
+    /// to this instance's stake authority. v1 has no instructions for that,
+    /// see the `solido_v1` module for the upgrade it takes. This copies the
+    /// exchange rate, reward distribution, fee recipients, metrics,
+    /// validators and maintainers, and switches to the v1 stSOL mint. The SOL
+    /// that is too little to stake stays in the v1 reserve, and is written off
+    /// the exchange rate. The stake accounts are moved afterwards, with
+    /// `MigrateStakeFromSolidoV1`.
+    pub fn migrate_from_solido_v1(ctx: Context<MigrateFromSolidoV1>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Move a stake account of a Solido v1 instance into the validator's next stake account.
+    ///
+    /// Requires the manager to sign. `seed` is the v1 seed of the stake or
+    /// unstake account, and the accounts of a validator should be moved in
+    /// seed order, so they keep their order.
+    pub fn migrate_stake_from_solido_v1(
+        ctx: Context<MigrateStakeFromSolidoV1>,
+        stake_type: StakeType,
+        seed: u64,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(ctx.program_id, stake_type, seed)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Schedule the stSOL mint authority to be transferred to `new_mint_authority`.
+    ///
+    /// Requires the manager to sign.
+    ///
+    /// This is for winding down the instance, or handing stSOL over to a
+    /// successor program. The transfer can be done only after
+    /// `MINT_AUTHORITY_TRANSFER_DELAY_EPOCHS`, through
+    /// `TransferMintAuthorityOut`, which gives stSOL holders time to notice it.
+    pub fn schedule_mint_authority_transfer(
+        ctx: Context<ScheduleMintAuthorityTransfer>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Transfer the stSOL mint authority to the new authority that was scheduled.
+    ///
+    /// Requires the manager to sign, once the delay has passed. This is
+    /// permanent: afterwards this instance cannot mint stSOL any more, so
+    /// `Deposit` is disabled for good, and fees are paid in SOL instead. All
+    /// stSOL fee credit of validators must be claimed before the transfer.
+    pub fn transfer_mint_authority_out(ctx: Context<TransferMintAuthorityOut>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Schedule a change of the highest commission that `AddValidator` accepts.
+    ///
+    /// Requires the manager to sign. The change can be applied with
+    /// `ApplyMaxCommissionChange` after `MAX_COMMISSION_CHANGE_DELAY_EPOCHS`.
+    pub fn schedule_max_commission_change(
+        ctx: Context<ScheduleMaxCommissionChange>,
+        max_commission: u8,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(max_commission)
+    }
+
+    /// Apply the max commission change that was scheduled, once its delay has passed.
+    ///
+    /// This can be called by anybody.
+    pub fn apply_max_commission_change(ctx: Context<ApplyMaxCommissionChange>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Create the protocol treasury, and make it the recipient of the treasury fee.
+    ///
+    /// Requires the manager to sign. The protocol treasury is an stSOL account
+    /// at a program-derived address, so the treasury fee accrues to the
+    /// program rather than to the key of an external wallet. Its stSOL can only
+    /// leave through `WithdrawTreasury`. The manager can still send the
+    /// treasury fee elsewhere with `ChangeFeeRecipients`.
+    pub fn initialize_protocol_treasury(ctx: Context<InitializeProtocolTreasury>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Schedule a transfer of `amount` stSOL from the protocol treasury to `recipient`.
+    ///
+    /// Requires the manager to sign. The transfer can be done only after
+    /// `TREASURY_WITHDRAWAL_DELAY_EPOCHS`, through `WithdrawTreasury`, which
+    /// gives stSOL holders time to notice it. Scheduling a new transfer
+    /// replaces the pending one.
+    pub fn schedule_treasury_withdrawal(
+        ctx: Context<ScheduleTreasuryWithdrawal>,
+        amount: StLamports,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(amount)
+    }
+
+    /// Transfer the stSOL that was scheduled from the protocol treasury to its recipient.
+    ///
+    /// Requires the manager to sign, once the delay has passed.
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(ctx.program_id)?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Start retiring this instance.
+    ///
+    /// Requires the manager to sign. This is permanent: it deactivates all
+    /// validators and disables `Deposit` and `AddValidator`. The stake then
+    /// flows back to the reserve through `Unstake` and `WithdrawInactiveStake`,
+    /// and once no validator holds stake any more, stSOL holders can withdraw
+    /// from the reserve with `WithdrawFromReserve`.
+    pub fn start_wind_down(ctx: Context<StartWindDown>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Withdraw a given amount of stSOL as SOL from the reserve, after a wind-down.
+    ///
+    /// This is possible only once all stake is back in the reserve, so the
+    /// exchange rate is final, or for amounts up to `Config::max_instant_withdrawal`.
+    /// The exchange rate must have been updated in this epoch. The stSOL is
+    /// burned, and the SOL is transferred to `recipient`.
+    pub fn withdraw_from_reserve(
+        ctx: Context<WithdrawFromReserve>,
+        amount: StLamports,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(ctx.program_id, amount)?;
+        check_invariants(&ctx.accounts.lido, Some(ctx.accounts.reserve.lamports()))
     }
 
-    pub fn remove_validator(ctx: Context<RemoveValidator>) -> Result<()> {
+    /// Create the registry of official instances for `authority`.
+    ///
+    /// The registry is a program-derived address of the authority, so every
+    /// authority has at most one, and clients that know the authority can
+    /// find it.
+    pub fn initialize_registry(ctx: Context<InitializeRegistry>, max_instances: u32) -> Result<()> {
+        ctx.accounts.process(max_instances)
+    }
+
+    /// List `lido` in the registry as an official instance.
+    ///
+    /// Requires both the registry authority and the manager of the instance to
+    /// sign. If `canonical` is set, the instance becomes the canonical one.
+    pub fn register_instance(ctx: Context<RegisterInstance>, canonical: bool) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(canonical)
+    }
+
+    /// Remove `lido` from the registry.
+    ///
+    /// Requires the registry authority to sign.
+    pub fn unregister_instance(ctx: Context<UnregisterInstance>) -> Result<()> {
         ctx.accounts.process()
     }
 
     pub fn add_maintainer(ctx: Context<AddMaintainer>) -> Result<()> {
-        ctx.accounts.process()
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
     }
 
     pub fn remove_maintainer(ctx: Context<RemoveMaintainer>) -> Result<()> {
-        ctx.accounts.process()
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process()?;
+        check_invariants(&ctx.accounts.lido, None)
+    }
+
+    /// Replace the maintainers with `maintainers`, adding and removing only the difference.
+    ///
+    /// Takes at most `MAXIMUM_SET_MAINTAINERS` maintainers, and emits a
+    /// `MaintainerAdded` or `MaintainerRemoved` event for every change.
+    /// Requires the manager to sign.
+    pub fn set_maintainers(ctx: Context<SetMaintainers>, maintainers: Vec<Pubkey>) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        ctx.accounts.process(maintainers)?;
+        check_invariants(&ctx.accounts.lido, None)
     }
 
-    #[allow(unused_variables)]
-    pub fn merge_stake(ctx: Context<MergeStake>) -> Result<()> {
-        todo!()
+    /// Merge the validator's first stake account into the second one.
+    ///
+    /// If `expected_from_seed` is set and the validator's begin seed already
+    /// moved past it, the merge happened before, and this does nothing.
+    /// `expected_state_hash` works as for `StakeDeposit`.
+    ///
+    /// Requires the manager or a maintainer to sign.
+    #[access_control(require_maintainer(&ctx.accounts.lido, &ctx.accounts.signer))]
+    pub fn merge_stake(
+        ctx: Context<MergeStake>,
+        expected_from_seed: Option<u64>,
+        expected_state_hash: Option<[u8; 32]>,
+    ) -> Result<()> {
+        Lido::check_account_version(&ctx.accounts.lido)?;
+        check_expected_state_hash(&ctx.accounts.lido, expected_state_hash)?;
+        ctx.accounts.process(ctx.program_id, expected_from_seed)?;
+        check_invariants(&ctx.accounts.lido, None)
     }
 }
 
@@ -146,6 +1049,12 @@ pub const RESERVE_ACCOUNT: [u8; 15] = *b"reserve_account";
 /// Mint authority, mints StSol.
 pub const MINT_AUTHORITY: [u8; 14] = *b"mint_authority";
 
+/// Freeze authority of the stSOL mint, if `FreezeAuthorityPolicy::ComplianceAuthority` is used.
+///
+/// The program never signs with it, the address only designates who may
+/// freeze stSOL.
+pub const COMPLIANCE_AUTHORITY: [u8; 20] = *b"compliance_authority";
+
 /// Seed for managing the stake.
 pub const STAKE_AUTHORITY: [u8; 15] = *b"stake_authority";
 
@@ -157,6 +1066,99 @@ pub const VALIDATOR_UNSTAKE_ACCOUNT: [u8; 25] = *b"validator_unstake_account";
 /// Authority responsible for withdrawing the stake rewards.
 pub const REWARDS_WITHDRAW_AUTHORITY: [u8; 26] = *b"rewards_withdraw_authority";
 
+/// Additional seed for the `ValidatorInfo` account of a validator.
+pub const VALIDATOR_INFO: [u8; 14] = *b"validator_info";
+
+/// Seed for the registry of official instances of a registry authority.
+pub const REGISTRY: [u8; 8] = *b"registry";
+
+/// Additional seed for the `ExchangeRateHistory` account of an instance.
+pub const EXCHANGE_RATE_HISTORY: [u8; 21] = *b"exchange_rate_history";
+
+/// Additional seed for the `ReserveAttestation` account of an instance.
+pub const RESERVE_ATTESTATION: [u8; 19] = *b"reserve_attestation";
+
+/// Additional seed for the emitter of the Wormhole messages of an instance.
+pub const WORMHOLE_EMITTER: [u8; 16] = *b"wormhole_emitter";
+
+/// Additional seed for the `Config` account of an instance.
+pub const CONFIG: [u8; 6] = *b"config";
+
+/// Additional seed for the `DepositHook` account of an instance.
+pub const DEPOSIT_HOOK: [u8; 12] = *b"deposit_hook";
+
+/// Additional seed for the protocol treasury, the stSOL account that the program holds fees in.
+pub const PROTOCOL_TREASURY: [u8; 17] = *b"protocol_treasury";
+
+/// Token authority of the protocol treasury.
+pub const PROTOCOL_TREASURY_AUTHORITY: [u8; 27] = *b"protocol_treasury_authority";
+
+/// The maximum number of unstake accounts that a validator can have at a time.
+///
+/// `WithdrawInactiveStake` needs all stake and unstake accounts of a validator
+/// in a single transaction, so we limit how many of them can exist. Unstake
+/// accounts are closed by `WithdrawInactiveStake` once they are fully
+/// deactivated, which takes an epoch, so this also limits how many times we
+/// can unstake from a validator per epoch.
+pub const MAXIMUM_UNSTAKE_ACCOUNTS: u64 = 3;
+
+/// The number of epochs between the manager overriding a validator's fee address, and the override taking effect.
+pub const FEE_ADDRESS_OVERRIDE_DELAY_EPOCHS: u64 = 2;
+
+/// The number of epochs between scheduling a transfer of the stSOL mint authority, and the transfer.
+pub const MINT_AUTHORITY_TRANSFER_DELAY_EPOCHS: u64 = 5;
+
+/// The highest vote account commission that `AddValidator` accepts, unless the manager changes it.
+///
+/// The program collects the commission through the rewards withdraw authority,
+/// so this was the only commission accepted before it became configurable.
+pub const DEFAULT_MAX_COMMISSION: u8 = 100;
+
+/// The number of epochs between scheduling a change of the max commission, and the change.
+pub const MAX_COMMISSION_CHANGE_DELAY_EPOCHS: u64 = 2;
+
+/// The number of epochs between scheduling a withdrawal from the protocol treasury, and the withdrawal.
+pub const TREASURY_WITHDRAWAL_DELAY_EPOCHS: u64 = 5;
+
+/// The number of epochs without votes after which `MarkDelinquent` pauses staking to a validator.
+pub const DELINQUENCY_THRESHOLD_EPOCHS: u64 = 2;
+
+/// The `Validator::target_weight` of new validators.
+///
+/// All validators start out with the same weight, so unless the manager
+/// changes weights, stake is spread evenly.
+pub const DEFAULT_TARGET_WEIGHT: u32 = 100;
+
+/// The minimum amount to put in a new stake account.
+pub const MINIMUM_STAKE_ACCOUNT_BALANCE: Lamports = Lamports {
+    amount: 1_000_000_000,
+};
+
+/// The share of a stake account, in percent, that a single withdrawal can take on top of `MAXIMUM_WITHDRAW_FLOOR`.
+///
+/// Withdrawals split off the stake account of the validator with the most
+/// stake, so without a cap, one large withdrawal could leave that validator
+/// with almost nothing, or its stake account below the minimum delegation.
+pub const MAXIMUM_WITHDRAW_PERCENTAGE: u64 = 10;
+
+/// The amount that a single withdrawal can take from a stake account regardless of its balance.
+pub const MAXIMUM_WITHDRAW_FLOOR: Lamports = Lamports {
+    amount: 10_000_000_000,
+};
+
+/// The largest sum of the parts of a `RewardDistribution`.
+///
+/// A quarter of `u32::MAX` is far more precision than any split of rewards
+/// needs, so larger sums are almost certainly an encoding mistake, such as
+/// amounts that were meant for another field.
+pub const MAXIMUM_REWARD_DISTRIBUTION_SUM: u64 = u32::MAX as u64 / 4;
+
+/// The sum of the parts of a `RewardDistribution` that is expressed in basis points.
+pub const REWARD_DISTRIBUTION_BASIS_POINTS: u32 = 10_000;
+
+/// The most maintainers that `SetMaintainers` accepts, so the list fits in one transaction.
+pub const MAXIMUM_SET_MAINTAINERS: usize = 16;
+
 // ----------------------------------------------------------------------------
 
 #[derive(Accounts)]
@@ -168,12 +1170,15 @@ pub struct Initialize<'info> {
     /// CHECK: This is not dangerous because we don't read or write from this account
     pub manager: UncheckedAccount<'info>,
 
-    /// Check if the mint program coin supply is zero and the mint authority is set
-    /// to `mint_authority`.
+    /// Check if the mint program coin supply is zero. The mint and freeze
+    /// authorities are checked by `Lido::check_mint` in the processor.
+    ///
+    /// `Account<Mint>` requires the mint to be owned by the classic SPL Token
+    /// program, so Token-2022 mints, and the extensions that would break the
+    /// supply accounting of `Lido::get_st_sol_supply`, are rejected here.
     #[account(
         rent_exempt = enforce,
         constraint = st_sol_mint.supply == 0 @ LidoError::InvalidMint,
-        constraint = st_sol_mint.mint_authority == COption::Some(mint_authority.key()) @ LidoError::InvalidMint,
     )]
     pub st_sol_mint: Account<'info, Mint>,
 
@@ -182,14 +1187,36 @@ pub struct Initialize<'info> {
     #[account(constraint = developer.mint == st_sol_mint.key() @ LidoError::InvalidFeeRecipient)]
     pub developer: Account<'info, TokenAccount>,
 
-    #[account(init, payer = payer, space = 0, seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()], bump)]
-    /// CHECK: Checked above, used only for bump calc and rent_exempt check
+    // The reserve is a rent-exempt system account without data, so that the
+    // system program can transfer SOL out of it when the program signs for it.
+    #[account(
+        init,
+        payer = payer,
+        space = 0,
+        owner = solana_program::system_program::ID,
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+    )]
+    /// CHECK: Created above, used only for bump calc and rent_exempt check
     pub reserve: UncheckedAccount<'info>,
 
+    #[account(
+        init,
+        payer = payer,
+        space = Config::LEN,
+        seeds = [lido.key().as_ref(), CONFIG.as_ref()],
+        bump,
+    )]
+    pub config: Box<Account<'info, Config>>,
+
     #[account(seeds = [lido.key().as_ref(), MINT_AUTHORITY.as_ref()], bump)]
     /// CHECK: Checked above, used only for bump calc
     pub mint_authority: UncheckedAccount<'info>,
 
+    #[account(seeds = [lido.key().as_ref(), COMPLIANCE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, only its address is used
+    pub compliance_authority: UncheckedAccount<'info>,
+
     #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
     /// CHECK: Checked above, used only for bump calc
     pub stake_authority: UncheckedAccount<'info>,
@@ -212,8 +1239,15 @@ pub struct Deposit<'info> {
     #[account(mut)]
     pub lido: Box<Account<'info, Lido>>,
 
-    // Is writable due to transfer (system_instruction::transfer) from user to reserve
-    #[account(mut)]
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    // Is writable due to transfer (system_instruction::transfer) from user to reserve.
+    // This does not have to be the fee payer, any system-owned account that
+    // signs can deposit, including a PDA of another program signing via CPI.
+    #[account(mut,
+        owner = System::id() @ LidoError::InvalidDepositSource,
+    )]
     pub user: Signer<'info>,
 
     // Is writable due to mint to (spl_token::instruction::mint_to) recipient from st_sol_mint
@@ -229,7 +1263,12 @@ pub struct Deposit<'info> {
     pub st_sol_mint: Account<'info, Mint>,
 
     // Is writable due to transfer (system_instruction::transfer) from user to reserve
-    #[account(mut, seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()], bump)]
+    #[account(
+        mut,
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+        owner = solana_program::system_program::ID @ LidoError::InvalidReserveAccount,
+    )]
     /// CHECK: Checked above, used only for bump calc and rent_exempt check
     pub reserve: UncheckedAccount<'info>,
 
@@ -240,90 +1279,1416 @@ pub struct Deposit<'info> {
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    /// CHECK: Checked above, read only if `Config::reject_cpi` is set
+    pub instructions: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
-pub struct Withdraw {}
+pub struct DepositToAssociatedAccount<'info> {
+    // Needs to be writable for us to update the metrics.
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
 
-#[derive(Accounts)]
-pub struct StakeDeposit {}
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
 
-#[derive(Accounts)]
-pub struct Unstake {}
+    // Is writable due to transfer (system_instruction::transfer) from user to reserve,
+    // and because it pays for the recipient account if that does not exist yet.
+    #[account(mut)]
+    pub user: Signer<'info>,
 
-#[derive(Accounts)]
-pub struct UpdateExchangeRate {}
+    /// CHECK: Any account can own stSOL, this is only the owner of `recipient`
+    pub recipient_owner: UncheckedAccount<'info>,
+
+    // Is writable due to mint to (spl_token::instruction::mint_to) recipient from st_sol_mint
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = st_sol_mint,
+        associated_token::authority = recipient_owner,
+    )]
+    pub recipient: Box<Account<'info, TokenAccount>>,
+
+    // Is writable due to mint to (spl_token::instruction::mint_to) recipient from st_sol_mint
+    #[account(mut,
+        address = lido.st_sol_mint @ LidoError::InvalidMint
+    )]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    // Is writable due to transfer (system_instruction::transfer) from user to reserve
+    #[account(
+        mut,
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+        owner = solana_program::system_program::ID @ LidoError::InvalidReserveAccount,
+    )]
+    /// CHECK: Checked above, used only for bump calc and rent_exempt check
+    pub reserve: UncheckedAccount<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), MINT_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only for bump calc
+    pub mint_authority: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    /// CHECK: Checked above, read only if `Config::reject_cpi` is set
+    pub instructions: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+}
 
 #[derive(Accounts)]
-pub struct WithdrawInactiveStake {}
+pub struct Donate<'info> {
+    // Needs to be writable for us to update the metrics.
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Is writable due to transfer (system_instruction::transfer) from donor to reserve
+    #[account(mut)]
+    pub donor: Signer<'info>,
+
+    // Is writable due to transfer (system_instruction::transfer) from donor to reserve
+    #[account(
+        mut,
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+        owner = solana_program::system_program::ID @ LidoError::InvalidReserveAccount,
+    )]
+    /// CHECK: Checked above, used only as the destination of the transfer
+    pub reserve: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
 
 #[derive(Accounts)]
-pub struct CollectValidatorFee {}
+pub struct Withdraw<'info> {
+    // Needs to be writable for us to update the metrics.
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub st_sol_account_owner: Signer<'info>,
+
+    // Is writable due to burn (spl_token::instruction::burn) from st_sol_account
+    #[account(mut,
+        constraint = st_sol_account.mint == st_sol_mint.key() @ LidoError::InvalidStSolAccount,
+        constraint = st_sol_account.owner == st_sol_account_owner.key() @ LidoError::InvalidTokenOwner,
+    )]
+    pub st_sol_account: Account<'info, TokenAccount>,
+
+    // Is writable due to burn (spl_token::instruction::burn) from st_sol_account
+    #[account(mut,
+        address = lido.st_sol_mint @ LidoError::InvalidMint
+    )]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    // Is writable due to split (stake::instruction::split) from source to destination
+    #[account(mut)]
+    /// CHECK: Checked in the processor against the validator's first stake seed
+    pub source_stake_account: UncheckedAccount<'info>,
+
+    // Is writable due to split (stake::instruction::split) from source to destination
+    #[account(mut)]
+    pub destination_stake_account: Signer<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the stake authority of the stake accounts
+    pub stake_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    #[account(address = solana_program::stake::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub stake_program: UncheckedAccount<'info>,
+
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    /// CHECK: Checked above, read only if `Config::reject_cpi` is set
+    pub instructions: UncheckedAccount<'info>,
+}
 
 #[derive(Accounts)]
-pub struct ClaimValidatorFee {}
+pub struct WithdrawAndMerge<'info> {
+    pub withdraw: Withdraw<'info>,
+
+    // Is writable due to merge (stake::instruction::merge) from destination_stake_account
+    #[account(mut)]
+    /// CHECK: Checked in the processor, must be delegated to the validator and owned by the stSOL owner
+    pub user_stake_account: UncheckedAccount<'info>,
+}
 
 #[derive(Accounts)]
-pub struct ChangeRewardDistribution {}
+pub struct WithdrawFromValidators<'info> {
+    // Needs to be writable for us to update the metrics.
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub st_sol_account_owner: Signer<'info>,
+
+    // Is writable due to burn (spl_token::instruction::burn) from st_sol_account
+    #[account(mut,
+        constraint = st_sol_account.mint == st_sol_mint.key() @ LidoError::InvalidStSolAccount,
+        constraint = st_sol_account.owner == st_sol_account_owner.key() @ LidoError::InvalidTokenOwner,
+    )]
+    pub st_sol_account: Account<'info, TokenAccount>,
+
+    // Is writable due to burn (spl_token::instruction::burn) from st_sol_account
+    #[account(mut,
+        address = lido.st_sol_mint @ LidoError::InvalidMint
+    )]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the stake authority of the stake accounts
+    pub stake_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    #[account(address = solana_program::stake::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub stake_program: UncheckedAccount<'info>,
+
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    /// CHECK: Checked above, read only if `Config::reject_cpi` is set
+    pub instructions: UncheckedAccount<'info>,
+}
 
 #[derive(Accounts)]
-pub struct AddValidator<'info> {
-    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+pub struct StakeDeposit<'info> {
+    #[account(mut)]
     pub lido: Box<Account<'info, Lido>>,
 
-    pub manager: Signer<'info>,
+    // Checked by `require_maintainer` to be the manager or a maintainer.
+    pub signer: Signer<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
 
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    // Is writable due to create account (system_instruction::create_account) from reserve to stake account
     #[account(
-        rent_exempt = enforce,
-        constraint = validator_vote.version == 1 @ LidoError::InvalidVoteAccount,
-        constraint = validator_vote.authorized_withdrawer == rewards_withdraw_authority.key() @ LidoError::InvalidVoteAccount,
-        constraint = validator_vote.commission == 100 @ LidoError::InvalidVoteAccount,
+        mut,
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+        owner = solana_program::system_program::ID @ LidoError::InvalidReserveAccount,
     )]
+    /// CHECK: Checked above, used only as the source of the new stake
+    pub reserve: UncheckedAccount<'info>,
+
+    // Is writable due to create account (system_instruction::create_account) from reserve to stake account
+    #[account(mut)]
+    /// CHECK: Checked in the processor against the validator's end stake seed
+    pub stake_account_end: UncheckedAccount<'info>,
+
+    // Is writable due to merge (stake::instruction::merge) from the end stake account
+    #[account(mut)]
+    /// CHECK: Checked in the processor, either the end stake account or the one before
+    pub stake_account_merge_into: UncheckedAccount<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the stake authority of the stake accounts
+    pub stake_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub rent: Sysvar<'info, Rent>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
+
+    #[account(address = solana_program::stake::config::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub stake_config: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(address = solana_program::stake::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub stake_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct Unstake<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Checked by `require_maintainer_or_delegation_authority`.
+    pub signer: Signer<'info>,
+
     pub validator_vote: Account<'info, PartialVoteState>,
 
-    #[account(constraint = validator_fee_st_sol.mint == lido.st_sol_mint @ LidoError::InvalidFeeRecipient)]
-    pub validator_fee_st_sol: Account<'info, TokenAccount>,
+    // Is writable due to split (stake::instruction::split) from source to destination
+    #[account(mut)]
+    /// CHECK: Checked in the processor against the validator's first stake seed
+    pub source_stake_account: UncheckedAccount<'info>,
 
-    #[account(seeds = [lido.key().as_ref(), REWARDS_WITHDRAW_AUTHORITY.as_ref()], bump)]
-    /// CHECK: Checked above, used only for bump calc
-    pub rewards_withdraw_authority: UncheckedAccount<'info>,
+    // Is writable due to split (stake::instruction::split) from source to destination
+    #[account(mut)]
+    /// CHECK: Checked in the processor against the validator's next unstake seed
+    pub destination_unstake_account: UncheckedAccount<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the stake authority of the stake accounts
+    pub stake_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
+    pub system_program: Program<'info, System>,
+
+    #[account(address = solana_program::stake::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub stake_program: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct DeactivateValidator<'info> {
+pub struct UnstakeAllFromValidator<'info> {
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Checked by `require_maintainer` to be the manager or a maintainer.
+    pub signer: Signer<'info>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the stake authority of the stake accounts
+    pub stake_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
+
+    #[account(address = solana_program::stake::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub stake_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CheckSeedRange<'info> {
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the stake authority of the stake accounts
+    pub stake_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
+}
+
+#[derive(Accounts)]
+pub struct RepairSeedRange<'info> {
     #[account(mut, has_one = manager @ LidoError::InvalidManager)]
     pub lido: Box<Account<'info, Lido>>,
 
     pub manager: Signer<'info>,
 
     pub validator_vote: Account<'info, PartialVoteState>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the stake authority of the stake accounts
+    pub stake_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
 }
 
 #[derive(Accounts)]
-pub struct RemoveValidator<'info> {
+pub struct UpdateExchangeRate<'info> {
     #[account(mut)]
     pub lido: Box<Account<'info, Lido>>,
 
-    pub validator_vote: Account<'info, PartialVoteState>,
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    // Is writable due to transfer (system_instruction::transfer) of the crank tip from reserve
+    #[account(
+        mut,
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+        owner = solana_program::system_program::ID @ LidoError::InvalidReserveAccount,
+    )]
+    /// CHECK: Checked above, used only to read the balance and to pay the crank tip
+    pub reserve: UncheckedAccount<'info>,
+
+    #[account(address = lido.st_sol_mint @ LidoError::InvalidMint)]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    // Is writable because the new exchange rate is recorded in it.
+    #[account(mut, seeds = [lido.key().as_ref(), EXCHANGE_RATE_HISTORY.as_ref()], bump)]
+    pub exchange_rate_history: Box<Account<'info, ExchangeRateHistory>>,
+
+    // Is writable due to transfer (system_instruction::transfer) of the crank tip from reserve
+    #[account(mut)]
+    /// CHECK: Any account, used only as the recipient of the crank tip
+    pub tip_recipient: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub rent: Sysvar<'info, Rent>,
+
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct AddMaintainer<'info> {
-    #[account(mut, has_one = manager)]
+pub struct InitializeExchangeRateHistory<'info> {
     pub lido: Box<Account<'info, Lido>>,
-    pub manager: Signer<'info>,
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    pub maintainer: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = ExchangeRateHistory::LEN,
+        seeds = [lido.key().as_ref(), EXCHANGE_RATE_HISTORY.as_ref()],
+        bump,
+    )]
+    pub exchange_rate_history: Box<Account<'info, ExchangeRateHistory>>,
+
+    // Is writable because it pays for the exchange rate history account.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct RemoveMaintainer<'info> {
-    #[account(mut, has_one = manager)]
+pub struct AttestReserves<'info> {
     pub lido: Box<Account<'info, Lido>>,
-    pub manager: Signer<'info>,
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    pub maintainer: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+        owner = solana_program::system_program::ID @ LidoError::InvalidReserveAccount,
+    )]
+    /// CHECK: Checked above, only its balance is read
+    pub reserve: UncheckedAccount<'info>,
+
+    #[account(address = lido.st_sol_mint @ LidoError::InvalidMint)]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = ReserveAttestation::required_bytes(lido.validators.maximum_entries as usize),
+        seeds = [lido.key().as_ref(), RESERVE_ATTESTATION.as_ref()],
+        bump,
+    )]
+    pub reserve_attestation: Box<Account<'info, ReserveAttestation>>,
+
+    // Is writable because it pays for the attestation account if it does not exist yet.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct PostExchangeRateMessage<'info> {
+    pub lido: Box<Account<'info, Lido>>,
+
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    /// CHECK: Checked in the processor against `Config::wormhole_program`
+    pub wormhole_program: UncheckedAccount<'info>,
+
+    // Is writable because the core bridge records the collected fees in it.
+    #[account(mut)]
+    /// CHECK: Checked by the core bridge
+    pub wormhole_bridge: UncheckedAccount<'info>,
+
+    // Is writable because the core bridge creates the message in it.
+    #[account(mut)]
+    pub wormhole_message: Signer<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), WORMHOLE_EMITTER.as_ref()], bump)]
+    /// CHECK: Checked above, only signs for the message
+    pub wormhole_emitter: UncheckedAccount<'info>,
+
+    // Is writable because the core bridge increments the sequence of the emitter in it.
+    #[account(mut)]
+    /// CHECK: Checked by the core bridge
+    pub wormhole_sequence: UncheckedAccount<'info>,
+
+    // Is writable because it pays the message fee and the rent of the message.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    // Is writable because the message fee is paid to it.
+    #[account(mut)]
+    /// CHECK: Checked by the core bridge
+    pub wormhole_fee_collector: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct MergeStake {}
+pub struct BeginUpdateExchangeRate<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawInactiveStake<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    // Is writable due to withdraw (stake::instruction::withdraw) from stake accounts to reserve
+    #[account(
+        mut,
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+        owner = solana_program::system_program::ID @ LidoError::InvalidReserveAccount,
+    )]
+    /// CHECK: Checked above, used only as the recipient of the withdrawals
+    pub reserve: UncheckedAccount<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the withdraw authority of the stake accounts
+    pub stake_authority: UncheckedAccount<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    // Is writable due to transfer (system_instruction::transfer) of the crank tip from reserve
+    #[account(mut)]
+    /// CHECK: Any account, used only as the recipient of the crank tip
+    pub tip_recipient: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(address = solana_program::stake::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub stake_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CollectValidatorFee<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    // Is writable due to withdraw (vote_instruction::withdraw) from vote account to reserve
+    #[account(mut)]
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    /// CHECK: Checked in the processor against the validator's rewards withdraw authority
+    pub rewards_withdraw_authority: UncheckedAccount<'info>,
+
+    // Is writable due to withdraw (vote_instruction::withdraw) from vote account to reserve
+    #[account(
+        mut,
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+        owner = solana_program::system_program::ID @ LidoError::InvalidReserveAccount,
+    )]
+    /// CHECK: Checked above, used only as the destination of the rewards
+    pub reserve: UncheckedAccount<'info>,
+
+    // Is writable due to mint to (spl_token::instruction::mint_to) fee recipients from st_sol_mint
+    #[account(mut,
+        address = lido.st_sol_mint @ LidoError::InvalidMint
+    )]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    #[account(seeds = [lido.key().as_ref(), MINT_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only for bump calc
+    pub mint_authority: UncheckedAccount<'info>,
+
+    // Is writable due to mint to (spl_token::instruction::mint_to) treasury from st_sol_mint,
+    // or due to transfer (system_instruction::transfer) from reserve to treasury
+    #[account(mut,
+        address = lido.fee_recipients.treasury_account @ LidoError::InvalidFeeRecipient
+    )]
+    /// CHECK: Checked above, an stSOL account or a system account, see `FeeRecipients`
+    pub treasury_account: UncheckedAccount<'info>,
+
+    // Is writable due to mint to (spl_token::instruction::mint_to) developer from st_sol_mint,
+    // or due to transfer (system_instruction::transfer) from reserve to developer
+    #[account(mut,
+        address = lido.fee_recipients.developer_account @ LidoError::InvalidFeeRecipient
+    )]
+    /// CHECK: Checked above, an stSOL account or a system account, see `FeeRecipients`
+    pub developer_account: UncheckedAccount<'info>,
+
+    // Is writable due to transfer (system_instruction::transfer) of the crank tip from reserve
+    #[account(mut)]
+    /// CHECK: Any account, used only as the recipient of the crank tip
+    pub tip_recipient: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub rent: Sysvar<'info, Rent>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    #[account(address = solana_program::vote::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub vote_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimValidatorFee<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    // Is writable due to mint to (spl_token::instruction::mint_to) validator_fee_st_sol from st_sol_mint
+    #[account(mut,
+        address = lido.st_sol_mint @ LidoError::InvalidMint
+    )]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    #[account(seeds = [lido.key().as_ref(), MINT_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only for bump calc
+    pub mint_authority: UncheckedAccount<'info>,
+
+    // Is writable due to mint to (spl_token::instruction::mint_to) validator_fee_st_sol from st_sol_mint
+    // Checked in the processor against the validator's `fee_address`.
+    #[account(mut)]
+    pub validator_fee_st_sol: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimValidatorFeeTo<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    #[account(
+        constraint = validator_vote.node_pubkey == validator_identity.key() @ LidoError::InvalidValidatorIdentity,
+    )]
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    pub validator_identity: Signer<'info>,
+
+    // Is writable due to mint to (spl_token::instruction::mint_to) recipient from st_sol_mint
+    #[account(mut,
+        address = lido.st_sol_mint @ LidoError::InvalidMint
+    )]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    #[account(seeds = [lido.key().as_ref(), MINT_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only for bump calc
+    pub mint_authority: UncheckedAccount<'info>,
+
+    // Is writable due to mint to (spl_token::instruction::mint_to) recipient from st_sol_mint
+    #[account(mut,
+        constraint = recipient.mint == st_sol_mint.key() @ LidoError::InvalidStSolAccount,
+    )]
+    pub recipient: Account<'info, TokenAccount>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimValidatorFeeSol<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    #[account(
+        constraint = validator_vote.node_pubkey == validator_identity.key() @ LidoError::InvalidValidatorIdentity,
+    )]
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    pub validator_identity: Signer<'info>,
+
+    // Is writable due to transfer (system_instruction::transfer) from reserve to recipient
+    #[account(
+        mut,
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+        owner = solana_program::system_program::ID @ LidoError::InvalidReserveAccount,
+    )]
+    /// CHECK: Checked above, used only as the source of the SOL
+    pub reserve: UncheckedAccount<'info>,
+
+    // Is writable due to transfer (system_instruction::transfer) from reserve to recipient
+    #[account(mut)]
+    /// CHECK: Any account can receive the SOL
+    pub recipient: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeRewardDistribution<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeFeeRecipients<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    /// CHECK: Checked in the processor against the denomination of the treasury fee
+    pub treasury: UncheckedAccount<'info>,
+
+    /// CHECK: Checked in the processor against the denomination of the developer fee
+    pub developer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PreviewRewardSplit<'info> {
+    pub lido: Box<Account<'info, Lido>>,
+}
+
+#[derive(Accounts)]
+pub struct GetValidatorStake<'info> {
+    pub lido: Box<Account<'info, Lido>>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    // Is writable because the tunables are changed in it.
+    #[account(mut, seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct SetWormholeProgram<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    // Is writable because the Wormhole program is changed in it.
+    #[account(mut, seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+}
+
+#[derive(Accounts)]
+pub struct SetDepositHook<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Is writable because it pays for the deposit hook account if it does not exist yet.
+    #[account(mut)]
+    pub manager: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = manager,
+        space = DepositHook::LEN,
+        seeds = [lido.key().as_ref(), DEPOSIT_HOOK.as_ref()],
+        bump,
+    )]
+    pub deposit_hook: Box<Account<'info, DepositHook>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OverrideValidatorFeeAccount<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    #[account(constraint = new_fee_address.mint == lido.st_sol_mint @ LidoError::InvalidFeeRecipient)]
+    pub new_fee_address: Account<'info, TokenAccount>,
+
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ApplyValidatorFeeAccountOverride<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct AddValidator<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub manager: Signer<'info>,
+
+    // The version and the withdraw authority are checked in the processor.
+    #[account(
+        owner = solana_program::vote::program::ID @ LidoError::ValidatorVoteAccountHasDifferentOwner,
+        constraint = validator_vote.data_len() >= MINIMUM_VOTE_ACCOUNT_SIZE @ LidoError::InvalidVoteAccount,
+        rent_exempt = enforce,
+    )]
+    /// CHECK: Checked above, deserialized as a `PartialVoteState` in the processor
+    pub validator_vote: UncheckedAccount<'info>,
+
+    #[account(constraint = validator_fee_st_sol.mint == lido.st_sol_mint @ LidoError::InvalidFeeRecipient)]
+    pub validator_fee_st_sol: Account<'info, TokenAccount>,
+
+    #[account(seeds = [lido.key().as_ref(), REWARDS_WITHDRAW_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, compared to the withdraw authority of the vote account
+    pub rewards_withdraw_authority: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AddValidatorAndAuthorizeWithdrawer<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub manager: Signer<'info>,
+
+    // Is writable due to authorize (vote_instruction::authorize) of the rewards withdraw authority
+    #[account(
+        mut,
+        rent_exempt = enforce,
+        constraint = validator_vote.version == 1 @ LidoError::InvalidVoteAccount,
+        constraint = validator_vote.authorized_withdrawer == current_withdrawer.key() @ LidoError::InvalidVoteAccount,
+    )]
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    pub current_withdrawer: Signer<'info>,
+
+    #[account(constraint = validator_fee_st_sol.mint == lido.st_sol_mint @ LidoError::InvalidFeeRecipient)]
+    pub validator_fee_st_sol: Account<'info, TokenAccount>,
+
+    #[account(seeds = [lido.key().as_ref(), REWARDS_WITHDRAW_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, only becomes the withdraw authority of the vote account
+    pub rewards_withdraw_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    #[account(address = solana_program::vote::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub vote_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DeactivateValidator<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+}
+
+#[derive(Accounts)]
+pub struct ChangeValidatorStakePaused<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Checked in the processor to be the manager or a maintainer.
+    pub signer: Signer<'info>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+}
+
+#[derive(Accounts)]
+pub struct SetValidatorTargetWeight<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Checked in the processor to be the manager or the delegation authority.
+    pub signer: Signer<'info>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+}
+
+#[derive(Accounts)]
+pub struct SetDelegationAuthority<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Checked in the processor to be the manager or the delegation authority.
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RotateRewardsWithdrawAuthority<'info> {
+    // Is writable because the rewards withdraw authority of the validator is recorded in it.
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    // Is writable due to authorize (vote_instruction::authorize) of the new withdraw authority
+    #[account(
+        mut,
+        constraint = validator_vote.node_pubkey == validator_identity.key() @ LidoError::InvalidValidatorIdentity,
+        constraint = validator_vote.authorized_withdrawer == rewards_withdraw_authority.key() @ LidoError::InvalidVoteAccount,
+    )]
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    pub validator_identity: Signer<'info>,
+
+    /// CHECK: Checked in the processor to be the current rewards withdraw authority of the validator
+    pub rewards_withdraw_authority: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [lido.key().as_ref(), validator_vote.key().as_ref(), REWARDS_WITHDRAW_AUTHORITY.as_ref()],
+        bump,
+    )]
+    /// CHECK: Checked above, it only becomes the withdraw authority of the vote account
+    pub new_rewards_withdraw_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+
+    #[account(address = solana_program::vote::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub vote_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetValidatorInfo<'info> {
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = ValidatorInfo::LEN,
+        seeds = [lido.key().as_ref(), validator_vote.key().as_ref(), VALIDATOR_INFO.as_ref()],
+        bump,
+    )]
+    pub validator_info: Box<Account<'info, ValidatorInfo>>,
+
+    /// Either the validator identity or the manager, checked in the processor.
+    pub signer: Signer<'info>,
+
+    // Is writable because it pays for the validator info account if it does not exist yet.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MarkDelinquent<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub epoch_schedule: Sysvar<'info, EpochSchedule>,
+}
+
+#[derive(Accounts)]
+pub struct DeactivateClosedValidator<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    /// CHECK: Checked in the processor to be closed, so it cannot be deserialized
+    pub validator_vote: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveValidator<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    /// CHECK: Only used as the key of the validator, its vote account may be closed already
+    pub validator_vote: UncheckedAccount<'info>,
+
+    // Is writable because leftover stake accounts are closed into it.
+    #[account(
+        mut,
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+        owner = solana_program::system_program::ID @ LidoError::InvalidReserveAccount,
+    )]
+    /// CHECK: Checked above, used only as the recipient of the leftover balances
+    pub reserve: UncheckedAccount<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the withdraw authority of the stake accounts
+    pub stake_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(address = solana_program::stake::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub stake_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcknowledgeLoss<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+}
+
+#[derive(Accounts)]
+pub struct ImportFromStakePool<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    /// Staker and withdrawer of the source stake account.
+    pub pool_authority: Signer<'info>,
+
+    // Is writable due to split (stake::instruction::split) from source to destination
+    #[account(mut)]
+    /// CHECK: Checked in the processor to be an active stake account owned by `pool_authority`
+    pub source_stake_account: UncheckedAccount<'info>,
+
+    // Is writable due to split (stake::instruction::split) from source to destination
+    #[account(mut)]
+    /// CHECK: Checked in the processor against the validator's end stake seed
+    pub destination_stake_account: UncheckedAccount<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the new stake authority of the destination
+    pub stake_authority: UncheckedAccount<'info>,
+
+    // Is writable due to mint to (spl_token::instruction::mint_to) recipient from st_sol_mint
+    #[account(mut, address = lido.st_sol_mint @ LidoError::InvalidMint)]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    #[account(seeds = [lido.key().as_ref(), MINT_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only for bump calc
+    pub mint_authority: UncheckedAccount<'info>,
+
+    // Is writable due to mint to (spl_token::instruction::mint_to) recipient from st_sol_mint
+    #[account(mut, constraint = recipient.mint == st_sol_mint.key() @ LidoError::InvalidStSolAccount)]
+    pub recipient: Account<'info, TokenAccount>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+
+    #[account(address = solana_program::stake::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub stake_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateFromSolidoV1<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    /// CHECK: Deserialized in the processor, must be owned by the Solido v1 program
+    pub solido_v1: UncheckedAccount<'info>,
+
+    /// CHECK: Checked in the processor to be the reserve of `solido_v1`, only its balance is read
+    pub solido_v1_reserve: UncheckedAccount<'info>,
+
+    /// The stSOL mint that this instance was initialized with, which is replaced.
+    #[account(
+        address = lido.st_sol_mint @ LidoError::InvalidMint,
+        constraint = current_st_sol_mint.supply == 0 @ LidoError::MigrationTargetNotEmpty,
+    )]
+    pub current_st_sol_mint: Account<'info, Mint>,
+
+    /// The stSOL mint of the v1 instance, checked by `Lido::check_mint` in the processor.
+    pub st_sol_mint: Account<'info, Mint>,
+
+    #[account(seeds = [lido.key().as_ref(), MINT_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only for bump calc
+    pub mint_authority: UncheckedAccount<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), COMPLIANCE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, only its address is used
+    pub compliance_authority: UncheckedAccount<'info>,
+
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateStakeFromSolidoV1<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    /// CHECK: Deserialized in the processor, must be owned by the Solido v1 program
+    pub solido_v1: UncheckedAccount<'info>,
+
+    /// CHECK: Checked in the processor to be the reserve of `solido_v1`, only its balance is read
+    pub solido_v1_reserve: UncheckedAccount<'info>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    // Is writable due to split (stake::instruction::split) from source to destination
+    #[account(mut)]
+    /// CHECK: Checked in the processor against the v1 stake account address
+    pub source_stake_account: UncheckedAccount<'info>,
+
+    // Is writable due to split (stake::instruction::split) from source to destination
+    #[account(mut)]
+    /// CHECK: Checked in the processor against the validator's end seed
+    pub destination_stake_account: UncheckedAccount<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the stake authority of the stake accounts
+    pub stake_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
+    pub system_program: Program<'info, System>,
+
+    #[account(address = solana_program::stake::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub stake_program: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ScheduleMintAuthorityTransfer<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    /// CHECK: Can be any account, it only becomes the mint authority of stSOL
+    pub new_mint_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct TransferMintAuthorityOut<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    // Is writable due to the change of the mint authority.
+    #[account(mut, address = lido.st_sol_mint @ LidoError::InvalidMint)]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    #[account(seeds = [lido.key().as_ref(), MINT_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only for bump calc
+    pub mint_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Must be the scheduled new mint authority, checked in the processor
+    pub new_mint_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ScheduleMaxCommissionChange<'info> {
+    #[account(has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Is writable because the tunables are changed in it.
+    #[account(mut, seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub manager: Signer<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ApplyMaxCommissionChange<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Is writable because the tunables are changed in it.
+    #[account(mut, seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeProtocolTreasury<'info> {
+    // Is writable because the treasury fee recipient is changed in it.
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+
+    #[account(address = lido.st_sol_mint @ LidoError::InvalidMint)]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = payer,
+        token::mint = st_sol_mint,
+        token::authority = protocol_treasury_authority,
+        seeds = [lido.key().as_ref(), PROTOCOL_TREASURY.as_ref()],
+        bump,
+    )]
+    pub protocol_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [lido.key().as_ref(), PROTOCOL_TREASURY_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the token authority of the protocol treasury
+    pub protocol_treasury_authority: UncheckedAccount<'info>,
+
+    // Is writable because it pays for the protocol treasury account.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ScheduleTreasuryWithdrawal<'info> {
+    #[account(has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Is writable because the scheduled withdrawal is recorded in it.
+    #[account(mut, seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub manager: Signer<'info>,
+
+    #[account(constraint = recipient.mint == lido.st_sol_mint @ LidoError::InvalidStSolAccount)]
+    pub recipient: Account<'info, TokenAccount>,
+
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Is writable because the scheduled withdrawal is cleared in it.
+    #[account(mut, seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub manager: Signer<'info>,
+
+    // Is writable due to transfer (spl_token::instruction::transfer) from protocol treasury
+    #[account(mut, seeds = [lido.key().as_ref(), PROTOCOL_TREASURY.as_ref()], bump)]
+    pub protocol_treasury: Box<Account<'info, TokenAccount>>,
+
+    #[account(seeds = [lido.key().as_ref(), PROTOCOL_TREASURY_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only to sign the transfer
+    pub protocol_treasury_authority: UncheckedAccount<'info>,
+
+    // Is writable due to transfer (spl_token::instruction::transfer) to recipient.
+    // Checked in the processor against the scheduled recipient.
+    #[account(mut)]
+    pub recipient: Account<'info, TokenAccount>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct StartWindDown<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFromReserve<'info> {
+    // Needs to be writable for us to update the metrics.
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    #[account(seeds = [lido.key().as_ref(), CONFIG.as_ref()], bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    pub st_sol_account_owner: Signer<'info>,
+
+    // Is writable due to burn (spl_token::instruction::burn) from st_sol_account
+    #[account(mut,
+        constraint = st_sol_account.mint == st_sol_mint.key() @ LidoError::InvalidStSolAccount,
+        constraint = st_sol_account.owner == st_sol_account_owner.key() @ LidoError::InvalidTokenOwner,
+    )]
+    pub st_sol_account: Account<'info, TokenAccount>,
+
+    // Is writable due to burn (spl_token::instruction::burn) from st_sol_account
+    #[account(mut,
+        address = lido.st_sol_mint @ LidoError::InvalidMint
+    )]
+    pub st_sol_mint: Account<'info, Mint>,
+
+    // Is writable due to transfer (system_instruction::transfer) from reserve to recipient
+    #[account(
+        mut,
+        seeds = [lido.key().as_ref(), RESERVE_ACCOUNT.as_ref()],
+        bump,
+        owner = solana_program::system_program::ID @ LidoError::InvalidReserveAccount,
+    )]
+    /// CHECK: Checked above, used only as the source of the SOL
+    pub reserve: UncheckedAccount<'info>,
+
+    // Is writable due to transfer (system_instruction::transfer) from reserve to recipient
+    #[account(mut)]
+    /// CHECK: Any account can receive the SOL
+    pub recipient: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub rent: Sysvar<'info, Rent>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+
+    #[account(address = solana_program::sysvar::instructions::ID)]
+    /// CHECK: Checked above, read only if `Config::reject_cpi` is set
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(max_instances: u32)]
+pub struct InitializeRegistry<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = Registry::required_bytes(max_instances),
+        seeds = [REGISTRY.as_ref(), authority.key().as_ref()],
+        bump,
+    )]
+    pub registry: Box<Account<'info, Registry>>,
+
+    // Is writable because it pays for the registry account.
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterInstance<'info> {
+    #[account(
+        mut,
+        seeds = [REGISTRY.as_ref(), authority.key().as_ref()],
+        bump,
+        has_one = authority @ LidoError::InvalidRegistryAuthority,
+    )]
+    pub registry: Box<Account<'info, Registry>>,
+
+    pub authority: Signer<'info>,
+
+    #[account(has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    pub manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UnregisterInstance<'info> {
+    #[account(
+        mut,
+        seeds = [REGISTRY.as_ref(), authority.key().as_ref()],
+        bump,
+        has_one = authority @ LidoError::InvalidRegistryAuthority,
+    )]
+    pub registry: Box<Account<'info, Registry>>,
+
+    pub authority: Signer<'info>,
+
+    /// CHECK: Only the address is used, the instance may not exist any more
+    pub lido: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AddMaintainer<'info> {
+    #[account(mut, has_one = manager)]
+    pub lido: Box<Account<'info, Lido>>,
+    pub manager: Signer<'info>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub maintainer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveMaintainer<'info> {
+    #[account(mut, has_one = manager)]
+    pub lido: Box<Account<'info, Lido>>,
+    pub manager: Signer<'info>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub maintainer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaintainers<'info> {
+    #[account(mut, has_one = manager @ LidoError::InvalidManager)]
+    pub lido: Box<Account<'info, Lido>>,
+    pub manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MergeStake<'info> {
+    #[account(mut)]
+    pub lido: Box<Account<'info, Lido>>,
+
+    // Checked by `require_maintainer` to be the manager or a maintainer.
+    pub signer: Signer<'info>,
+
+    pub validator_vote: Account<'info, PartialVoteState>,
+
+    // Is writable due to merge (stake::instruction::merge) from this account
+    #[account(mut)]
+    /// CHECK: Checked in the processor against the validator's first stake seed
+    pub from_stake: UncheckedAccount<'info>,
+
+    // Is writable due to merge (stake::instruction::merge) into this account
+    #[account(mut)]
+    /// CHECK: Checked in the processor against the validator's second stake seed
+    pub to_stake: UncheckedAccount<'info>,
+
+    #[account(seeds = [lido.key().as_ref(), STAKE_AUTHORITY.as_ref()], bump)]
+    /// CHECK: Checked above, used only as the stake authority of the stake accounts
+    pub stake_authority: UncheckedAccount<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_history: Sysvar<'info, StakeHistory>,
+
+    #[account(address = solana_program::stake::program::ID)]
+    /// CHECK: Checked above, used only for CPI
+    pub stake_program: UncheckedAccount<'info>,
+}
@@ -1,14 +1,264 @@
+use crate::compute_budget::{ComputeBudgetGuard, STAKE_PROGRAM_CPI_COMPUTE_UNITS};
+use crate::config::Config;
+use crate::error::LidoError;
+use crate::events::{CrankTipPaid, MaintenanceSkipped};
+use crate::stake_account::StakeAccount;
+use crate::state::{SeedRange, StakeType, Validator};
+use crate::token;
+use crate::validators::PubkeyAndEntry;
+use crate::{
+    Lamports, Lido, StLamports, MAXIMUM_WITHDRAW_FLOOR, MAXIMUM_WITHDRAW_PERCENTAGE,
+    MINIMUM_STAKE_ACCOUNT_BALANCE, MINT_AUTHORITY, RESERVE_ACCOUNT, STAKE_AUTHORITY,
+};
 use anchor_lang::context::CpiContext;
+use anchor_lang::prelude::{emit, err, error, msg, Account, Result, Signer, Sysvar, ToAccountInfo};
 use anchor_lang::Key;
-use anchor_lang::prelude::{Account, Result};
+use anchor_spl::token::Mint;
 use solana_program::account_info::AccountInfo;
+use solana_program::clock::{Clock, Epoch};
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::program::invoke_signed;
 use solana_program::pubkey::Pubkey;
-use crate::{Lido, MINT_AUTHORITY, StLamports};
+use solana_program::rent::Rent;
+use solana_program::stake::state::StakeAuthorize;
+use solana_program::stake_history::StakeHistory;
+use solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+
+/// Source of the current epoch.
+///
+/// Checks that depend on the epoch take an `EpochProvider` rather than a
+/// `Clock`, so they can be unit tested with synthetic epochs, without a
+/// program-test environment.
+pub trait EpochProvider {
+    /// Return the current epoch.
+    fn epoch(&self) -> Epoch;
+}
+
+impl EpochProvider for Clock {
+    fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+}
+
+impl<'info> EpochProvider for Sysvar<'info, Clock> {
+    fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+}
+
+/// An `EpochProvider` that is always in the given epoch.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FixedEpoch(pub Epoch);
+
+impl EpochProvider for FixedEpoch {
+    fn epoch(&self) -> Epoch {
+        self.0
+    }
+}
+
+/// Check that the exchange rate was not yet updated in the current epoch.
+///
+/// The exchange rate can be updated at most once per epoch.
+pub fn check_exchange_rate_not_updated(lido: &Lido, epochs: &impl EpochProvider) -> Result<()> {
+    if lido.exchange_rate.computed_in_epoch >= epochs.epoch() {
+        msg!(
+            "The exchange rate has already been updated in epoch {}.",
+            lido.exchange_rate.computed_in_epoch
+        );
+        msg!("It can only be done once per epoch, so we are going to abort this transaction.");
+        return err!(LidoError::ExchangeRateAlreadyUpToDate);
+    }
+    Ok(())
+}
+
+/// Check that the fees of the validator can be collected in the current epoch.
+///
+/// The fees are minted at the exchange rate of the current epoch, so it must be
+/// up to date, and they can be collected at most once per epoch.
+pub fn check_can_collect_fee(
+    lido: &Lido,
+    validator_vote: &Pubkey,
+    epochs: &impl EpochProvider,
+) -> Result<()> {
+    lido.check_exchange_rate_last_epoch(epochs, "CollectValidatorFee")?;
+    let validator = lido.validators.get(validator_vote)?;
+    validator.entry.check_fee_not_collected_in(epochs.epoch())
+}
+
+/// Access control for maintenance instructions: `signer` must be the manager or a maintainer.
+///
+/// Used with `#[access_control]`, so the check runs before the handler, and
+/// processors that are reused by other instructions stay ungated.
+pub fn require_maintainer(lido: &Lido, signer: &Signer) -> Result<()> {
+    lido.check_manager_or_maintainer(&signer.key())
+}
+
+/// Access control for rebalancing: like `require_maintainer`, but the delegation authority may sign too.
+pub fn require_maintainer_or_delegation_authority(lido: &Lido, signer: &Signer) -> Result<()> {
+    if lido
+        .check_manager_or_delegation_authority(&signer.key())
+        .is_ok()
+    {
+        return Ok(());
+    }
+    lido.check_manager_or_maintainer(&signer.key())
+}
+
+/// Check that the executing instruction was not invoked through CPI, if `Config::reject_cpi` is set.
+///
+/// The instructions sysvar holds the top-level instructions of the
+/// transaction. If the one that is executing belongs to another program,
+/// that program called us.
+pub fn check_not_cpi(
+    config: &Config,
+    program_id: &Pubkey,
+    instructions: &AccountInfo,
+    method: &str,
+) -> Result<()> {
+    if !config.reject_cpi {
+        return Ok(());
+    }
+    let current_index = load_current_index_checked(instructions)?;
+    let current = load_instruction_at_checked(current_index as usize, instructions)?;
+    check_top_level_program(program_id, &current.program_id, method)
+}
+
+/// Check that the top-level instruction that is executing belongs to this program.
+fn check_top_level_program(
+    program_id: &Pubkey,
+    top_level_program: &Pubkey,
+    method: &str,
+) -> Result<()> {
+    if top_level_program != program_id {
+        msg!(
+            "{} cannot be called through CPI, but it was called by {}.",
+            method,
+            top_level_program
+        );
+        return err!(LidoError::CpiNotAllowed);
+    }
+    Ok(())
+}
+
+/// Check that a withdrawal can split `amount` off a stake account that holds `source_balance`.
+///
+/// A single withdrawal takes at most `MAXIMUM_WITHDRAW_PERCENTAGE` percent of
+/// the stake account, plus `MAXIMUM_WITHDRAW_FLOOR`.
+pub fn check_withdraw_within_limit(source_balance: Lamports, amount: Lamports) -> Result<()> {
+    let percentage = source_balance.checked_mul_div(MAXIMUM_WITHDRAW_PERCENTAGE, 100)?;
+    let maximum = (percentage + MAXIMUM_WITHDRAW_FLOOR)?;
+    if amount > maximum {
+        msg!(
+            "Withdrawing {} from a stake account that holds {} exceeds the maximum of {}.",
+            amount,
+            source_balance,
+            maximum
+        );
+        return err!(LidoError::InvalidAmount);
+    }
+    Ok(())
+}
+
+/// Check that splitting `amount` off a stake account that holds `source_balance` strands no stake.
+///
+/// Both the new stake account and what remains in the source must hold at
+/// least `MINIMUM_STAKE_ACCOUNT_BALANCE`, otherwise the split fails in the
+/// stake program, or leaves an account behind that is too small to use.
+/// Splitting off the entire balance leaves nothing behind, so that is allowed.
+pub fn check_split_leaves_minimum(source_balance: Lamports, amount: Lamports) -> Result<()> {
+    let remainder = (source_balance - amount)?;
+    let remainder_too_small =
+        remainder > Lamports::new(0) && remainder < MINIMUM_STAKE_ACCOUNT_BALANCE;
+    if amount < MINIMUM_STAKE_ACCOUNT_BALANCE || remainder_too_small {
+        msg!(
+            "Splitting {} off a stake account that holds {} leaves a stake account below {}.",
+            amount,
+            source_balance,
+            MINIMUM_STAKE_ACCOUNT_BALANCE
+        );
+        return err!(LidoError::StakeAccountBelowMinimum);
+    }
+    Ok(())
+}
+
+/// Check that `validator` can serve a withdrawal of `amount` from a stake account that holds `source_balance`.
+///
+/// Besides the checks on the split itself, the effective stake of the
+/// validator, which excludes the SOL that is already being unstaked, must
+/// cover the withdrawal. While a rebalance unstakes from a validator, its
+/// stake accounts can still hold the SOL that is on its way out.
+pub fn check_can_withdraw_from(
+    validator: &Validator,
+    source_balance: Lamports,
+    amount: Lamports,
+) -> Result<()> {
+    let effective_stake = validator.effective_stake_balance()?;
+    if amount > effective_stake {
+        msg!(
+            "Withdrawing {} exceeds the effective stake of {}, excluding the {} being unstaked.",
+            amount,
+            effective_stake,
+            validator.unstake_accounts_balance
+        );
+        return err!(LidoError::InsufficientValidatorLiquidity);
+    }
+    require!(amount < source_balance, LidoError::InvalidAmount);
+    check_withdraw_within_limit(source_balance, amount)?;
+    check_split_leaves_minimum(source_balance, amount)
+}
+
+/// Return the rewards in a vote account: its balance above the rent-exempt minimum.
+pub fn get_vote_account_rewards(rent: &Rent, lamports: u64, data_len: usize) -> Lamports {
+    Lamports::new(lamports.saturating_sub(rent.minimum_balance(data_len)))
+}
+
+/// Return whether the `rewards` in a vote account are plausible for the `credits_earned` by its validator.
+///
+/// Rewards are only paid for credits, so rewards without credits came from
+/// elsewhere, such as a transfer to the vote account. Credits without
+/// rewards, while the validator holds `stake` of this instance, mean that the
+/// rewards went elsewhere, for example because the commission was set to zero.
+pub fn is_reward_plausible(rewards: Lamports, credits_earned: u64, stake: Lamports) -> bool {
+    match (rewards > Lamports::new(0), credits_earned > 0) {
+        (true, false) => false,
+        (false, true) => stake == Lamports::new(0),
+        _ => true,
+    }
+}
+
+/// Return whether a vote account was closed, given its owner, balance, and data.
+///
+/// Withdrawing the full balance of a vote account closes it: the runtime
+/// deletes the account, after which it is owned by the system program and
+/// holds no data. Within the transaction that closes it, the vote program
+/// zeroes its data first.
+pub fn is_vote_account_closed(owner: &Pubkey, lamports: u64, data: &[u8]) -> bool {
+    *owner != solana_program::vote::program::ID
+        || lamports == 0
+        || data.iter().all(|byte| *byte == 0)
+}
+
+/// Return the seeds `[solido_address, seed, bump_seed]` to sign as a program address of the instance.
+///
+/// The stSOL and SOL helpers below sign with these, so they all derive the
+/// addresses the same way as `pda`.
+fn signer_seeds<'s>(
+    solido_address: &'s Pubkey,
+    seed: &'s [u8],
+    bump_seed: &'s [u8; 1],
+) -> [&'s [u8]; 3] {
+    [solido_address.as_ref(), seed, bump_seed]
+}
 
 /// Mint the given amount of stSOL and put it in the recipient's account.
 ///
 /// * The stSOL mint must be the one configured in the Solido instance.
 /// * The recipient account must be an stSOL SPL token account.
+///
+/// With the `strict-checks` feature, this also checks that the supply of the
+/// mint grew by exactly `amount`.
 pub fn mint_st_sol_to<'a>(
     solido: &Box<Account<Lido>>,
     spl_token_program: AccountInfo<'a>,
@@ -17,13 +267,16 @@ pub fn mint_st_sol_to<'a>(
     recipient: AccountInfo<'a>,
     amount: StLamports,
 ) -> Result<()> {
+    solido.check_mint_authority_not_transferred()?;
     let pubkey = solido.key();
 
-    let authority_signature_seeds = [
-        pubkey.as_ref(),
-        MINT_AUTHORITY.as_ref(),
-        &[solido.mint_authority_bump_seed],
-    ];
+    #[cfg(feature = "strict-checks")]
+    let mint_info = st_sol_mint.clone();
+    #[cfg(feature = "strict-checks")]
+    let supply_before = get_mint_supply(&mint_info)?;
+
+    let bump_seed = [solido.mint_authority_bump_seed];
+    let authority_signature_seeds = signer_seeds(&pubkey, MINT_AUTHORITY.as_ref(), &bump_seed);
     let signers = [&authority_signature_seeds[..]];
 
     let cpi_accounts = anchor_spl::token::MintTo {
@@ -32,11 +285,1028 @@ pub fn mint_st_sol_to<'a>(
         authority: mint_authority,
     };
 
-    let cpi_context = CpiContext::new_with_signer(
-        spl_token_program,
-        cpi_accounts,
-        &signers,
+    let cpi_context = CpiContext::new_with_signer(spl_token_program, cpi_accounts, &signers);
+
+    anchor_spl::token::mint_to(cpi_context, amount.amount)?;
+
+    #[cfg(feature = "strict-checks")]
+    {
+        let supply_after = get_mint_supply(&mint_info)?;
+        if supply_after.checked_sub(supply_before) != Some(amount.amount) {
+            msg!(
+                "Minted {}, but the stSOL supply went from {} to {}.",
+                amount,
+                supply_before,
+                supply_after
+            );
+            return err!(LidoError::InvariantViolated);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "strict-checks")]
+fn get_mint_supply(st_sol_mint: &AccountInfo) -> Result<u64> {
+    use anchor_lang::AccountDeserialize;
+    Ok(Mint::try_deserialize(&mut &st_sol_mint.try_borrow_data()?[..])?.supply)
+}
+
+/// Burn the given amount of stSOL from the user's account.
+///
+/// * The owner of the account must sign.
+/// * The mint must be the stSOL mint of the Solido instance.
+///
+/// With the `check-burn-supply` feature, this also checks that the supply of
+/// the mint dropped by exactly `amount`, to catch a token program that does
+/// not behave the way we expect before the SOL goes out.
+pub fn burn_st_sol_from<'a>(
+    spl_token_program: AccountInfo<'a>,
+    st_sol_mint: &mut Account<'a, Mint>,
+    user_account: AccountInfo<'a>,
+    owner: AccountInfo<'a>,
+    amount: StLamports,
+) -> Result<()> {
+    #[cfg(feature = "check-burn-supply")]
+    let supply_before = st_sol_mint.supply;
+
+    let cpi_accounts = anchor_spl::token::Burn {
+        mint: st_sol_mint.to_account_info(),
+        to: user_account,
+        authority: owner,
+    };
+    let cpi_context = CpiContext::new(spl_token_program, cpi_accounts);
+    anchor_spl::token::burn(cpi_context, amount.amount)?;
+
+    #[cfg(feature = "check-burn-supply")]
+    {
+        st_sol_mint.reload()?;
+        check_burned_supply(supply_before, st_sol_mint.supply, amount)?;
+    }
+
+    Ok(())
+}
+
+/// Check that the supply of the stSOL mint went from `supply_before` to `supply_after` by burning `amount`.
+#[cfg(any(test, feature = "check-burn-supply"))]
+fn check_burned_supply(supply_before: u64, supply_after: u64, amount: StLamports) -> Result<()> {
+    if supply_before.checked_sub(supply_after) != Some(amount.amount) {
+        msg!(
+            "Burned {}, but the stSOL supply went from {} to {}.",
+            amount,
+            supply_before,
+            supply_after
+        );
+        return err!(LidoError::BurnSupplyMismatch);
+    }
+    Ok(())
+}
+
+/// Transfer the given amount of stSOL out of a token account owned by a program address.
+///
+/// * `authority` must be the program address with seeds `[solido, authority_seed]`,
+///   and `bump_seed` its bump seed, the program can only sign for that address.
+/// * The source and destination must be stSOL SPL token accounts.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_st_sol_from<'a>(
+    solido: &Box<Account<Lido>>,
+    spl_token_program: AccountInfo<'a>,
+    source: AccountInfo<'a>,
+    destination: AccountInfo<'a>,
+    authority: AccountInfo<'a>,
+    authority_seed: &[u8],
+    bump_seed: u8,
+    amount: StLamports,
+) -> Result<()> {
+    let pubkey = solido.key();
+
+    let bump_seed = [bump_seed];
+    let authority_signature_seeds = signer_seeds(&pubkey, authority_seed, &bump_seed);
+    let signers = [&authority_signature_seeds[..]];
+
+    let cpi_accounts = anchor_spl::token::Transfer {
+        from: source,
+        to: destination,
+        authority,
+    };
+
+    let cpi_context = CpiContext::new_with_signer(spl_token_program, cpi_accounts, &signers);
+
+    anchor_spl::token::transfer(cpi_context, amount.amount)
+}
+
+/// Transfer the given amount of SOL from the reserve to the recipient.
+///
+/// * The reserve must be the reserve account of the Solido instance.
+/// * The caller is responsible for keeping the reserve rent-exempt.
+pub fn transfer_sol_from_reserve<'a>(
+    solido: &Box<Account<Lido>>,
+    system_program: AccountInfo<'a>,
+    reserve: AccountInfo<'a>,
+    recipient: AccountInfo<'a>,
+    amount: Lamports,
+) -> Result<()> {
+    let pubkey = solido.key();
+
+    let bump_seed = [solido.sol_reserve_account_bump_seed];
+    let reserve_signature_seeds = signer_seeds(&pubkey, RESERVE_ACCOUNT.as_ref(), &bump_seed);
+    let signers = [&reserve_signature_seeds[..]];
+
+    let cpi_accounts = anchor_lang::system_program::Transfer {
+        from: reserve,
+        to: recipient,
+    };
+
+    let cpi_context = CpiContext::new_with_signer(system_program, cpi_accounts, &signers);
+
+    anchor_lang::system_program::transfer(cpi_context, amount.amount)
+}
+
+/// Pay `Config::crank_tip` from the reserve to `recipient`, if the reserve has it available.
+///
+/// A maintenance step must not fail because the reserve runs low, so if
+/// less than the tip is available, this pays nothing.
+pub fn pay_crank_tip<'a>(
+    solido: &mut Box<Account<Lido>>,
+    config: &Config,
+    rent: &Rent,
+    system_program: AccountInfo<'a>,
+    reserve: AccountInfo<'a>,
+    recipient: AccountInfo<'a>,
+    instruction: &str,
+) -> Result<()> {
+    let tip = config.crank_tip;
+    if tip == Lamports::new(0) {
+        return Ok(());
+    }
+    let available =
+        solido.get_reserve_available_balance(rent, Lamports::new(reserve.lamports()))?;
+    if available < tip {
+        msg!(
+            "The reserve has {} available, less than the crank tip of {}.",
+            available,
+            tip
+        );
+        return Ok(());
+    }
+
+    let recipient_address = *recipient.key;
+    transfer_sol_from_reserve(solido, system_program, reserve, recipient, tip)?;
+    emit!(CrankTipPaid {
+        nonce: solido.next_operation_nonce()?,
+        instruction: instruction.to_string(),
+        recipient: recipient_address,
+        amount: tip.amount,
+    });
+    Ok(())
+}
+
+/// Build the vote program instruction to withdraw `lamports` from a vote account.
+///
+/// solana-program 1.8 does not expose the vote instructions, so we encode it
+/// here: `Withdraw` is variant 3 of `VoteInstruction`, followed by the amount,
+/// both little-endian as bincode does.
+pub fn vote_withdraw_instruction(
+    vote_account: &Pubkey,
+    withdraw_authority: &Pubkey,
+    lamports: u64,
+    to: &Pubkey,
+) -> Instruction {
+    let mut data = Vec::with_capacity(12);
+    data.extend_from_slice(&3u32.to_le_bytes());
+    data.extend_from_slice(&lamports.to_le_bytes());
+
+    Instruction {
+        program_id: solana_program::vote::program::ID,
+        accounts: vec![
+            AccountMeta::new(*vote_account, false),
+            AccountMeta::new(*to, false),
+            AccountMeta::new_readonly(*withdraw_authority, true),
+        ],
+        data,
+    }
+}
+
+/// Build the vote program instruction to make `new_authority` the withdraw authority of a vote account.
+///
+/// Like `vote_withdraw_instruction`, this is encoded here: `Authorize` is
+/// variant 1 of `VoteInstruction`, followed by the new authority, and
+/// `VoteAuthorize::Withdrawer`, which is variant 1.
+pub fn vote_authorize_withdrawer_instruction(
+    vote_account: &Pubkey,
+    withdraw_authority: &Pubkey,
+    new_authority: &Pubkey,
+) -> Instruction {
+    let mut data = Vec::with_capacity(40);
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(new_authority.as_ref());
+    data.extend_from_slice(&1u32.to_le_bytes());
+
+    Instruction {
+        program_id: solana_program::vote::program::ID,
+        accounts: vec![
+            AccountMeta::new(*vote_account, false),
+            AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+            AccountMeta::new_readonly(*withdraw_authority, true),
+        ],
+        data,
+    }
+}
+
+/// Split `amount` off a stake account of the instance into `destination`, owned by `owner`.
+///
+/// * `stake_authority` must be the stake authority of the instance, and
+///   `bump_seed` its bump seed.
+/// * `destination` must sign the transaction, the split allocates and assigns it.
+#[allow(clippy::too_many_arguments)]
+pub fn split_stake_to_owner<'a>(
+    solido_address: &Pubkey,
+    stake_authority: AccountInfo<'a>,
+    bump_seed: u8,
+    source: AccountInfo<'a>,
+    destination: AccountInfo<'a>,
+    owner: &Pubkey,
+    amount: Lamports,
+    clock: AccountInfo<'a>,
+    system_program: AccountInfo<'a>,
+    stake_program: AccountInfo<'a>,
+) -> Result<()> {
+    let authority_signature_seeds = [
+        solido_address.as_ref(),
+        STAKE_AUTHORITY.as_ref(),
+        &[bump_seed],
+    ];
+
+    let split_instructions = solana_program::stake::instruction::split(
+        source.key,
+        stake_authority.key,
+        amount.amount,
+        destination.key,
     );
+    for split_instruction in split_instructions {
+        invoke_signed(
+            &split_instruction,
+            &[
+                source.clone(),
+                destination.clone(),
+                stake_authority.clone(),
+                system_program.clone(),
+                stake_program.clone(),
+            ],
+            &[&authority_signature_seeds[..]],
+        )?;
+    }
+
+    for stake_authorize in [StakeAuthorize::Withdrawer, StakeAuthorize::Staker] {
+        invoke_signed(
+            &solana_program::stake::instruction::authorize(
+                destination.key,
+                stake_authority.key,
+                owner,
+                stake_authorize,
+                None,
+            ),
+            &[
+                destination.clone(),
+                clock.clone(),
+                stake_authority.clone(),
+                stake_program.clone(),
+            ],
+            &[&authority_signature_seeds[..]],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The stake program CPIs that one `WithdrawInactiveStake` call makes for a validator.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct InactiveStakePlan {
+    /// Number of leading unstake accounts to close.
+    pub unstake_to_close: usize,
+    /// Number of leading stake accounts to close, only for an inactive validator.
+    pub stake_to_close: usize,
+    /// Index and amount of the other stake accounts to withdraw from.
+    pub stake_to_withdraw: Vec<(usize, Lamports)>,
+    /// Whether the compute budget ran out before all accounts were handled.
+    pub out_of_budget: bool,
+}
+
+/// Decide which accounts `WithdrawInactiveStake` closes or withdraws from, within `budget`.
+///
+/// Only accounts that need a stake program CPI take from the budget, the
+/// accounts were already read. Unstake accounts go first: they hold the most
+/// SOL that can be reclaimed, and closing them advances the seed range, so a
+/// call that runs out of budget does not start at the same account again.
+/// Stake accounts that were withdrawn from before have nothing withdrawable
+/// left, so the next call skips them for free.
+pub fn plan_withdraw_inactive_stake(
+    stake_accounts: &[StakeAccount],
+    unstake_accounts: &[StakeAccount],
+    validator_active: bool,
+    budget: &mut ComputeBudgetGuard,
+) -> InactiveStakePlan {
+    let mut plan = InactiveStakePlan::default();
+
+    // Unstake accounts are deactivated in seed order, so once we find one
+    // that is not yet fully inactive, the ones after it are not either.
+    for unstake_account in unstake_accounts {
+        if !unstake_account.is_inactive() {
+            break;
+        }
+        if !budget.try_reserve(STAKE_PROGRAM_CPI_COMPUTE_UNITS) {
+            plan.out_of_budget = true;
+            return plan;
+        }
+        plan.unstake_to_close += 1;
+    }
+
+    // For an inactive validator, leading stake accounts that are fully
+    // inactive (after `UnstakeAllFromValidator`) are closed. From the others
+    // we withdraw the balance that is not staked, such as donations; the
+    // rent-exempt reserve has to stay in the account.
+    for (i, stake_account) in stake_accounts.iter().enumerate() {
+        let closes = !validator_active && i == plan.stake_to_close && stake_account.is_inactive();
+        let withdrawable = stake_account.withdrawable();
+        if !closes && withdrawable == Lamports::new(0) {
+            continue;
+        }
+        if !budget.try_reserve(STAKE_PROGRAM_CPI_COMPUTE_UNITS) {
+            plan.out_of_budget = true;
+            return plan;
+        }
+        if closes {
+            plan.stake_to_close += 1;
+        } else {
+            plan.stake_to_withdraw.push((i, withdrawable));
+        }
+    }
+
+    plan
+}
+
+/// Split `amount` into parts in proportion to `weights`, rounding down.
+///
+/// The rounding remainder goes to the first part, so the parts sum to `amount`.
+/// Fails if the weights sum to zero.
+pub fn split_proportionally(amount: StLamports, weights: &[Lamports]) -> Result<Vec<StLamports>> {
+    let total: Lamports = weights.iter().sum::<token::Result<Lamports>>()?;
+    if total == Lamports::new(0) {
+        msg!("There is no stake to split {} over.", amount);
+        return err!(LidoError::InvalidAmount);
+    }
+
+    let mut parts = weights
+        .iter()
+        .map(|weight| amount.checked_mul_div(weight.amount, total.amount))
+        .collect::<token::Result<Vec<StLamports>>>()?;
+    let assigned: StLamports = parts.iter().sum::<token::Result<StLamports>>()?;
+    parts[0] = (parts[0] + (amount - assigned)?)?;
+
+    Ok(parts)
+}
+
+/// Check the invariants of `lido` after an instruction, with the `strict-checks` feature.
+///
+/// `reserve_lamports` is the balance of the reserve, for instructions that
+/// take it. See `Lido::check_invariants` for what is checked. The checks cost
+/// compute units on every instruction, so they are meant for devnet and
+/// testnet deployments, and without the feature this does nothing.
+#[cfg(feature = "strict-checks")]
+pub fn check_invariants(lido: &Lido, reserve_lamports: Option<u64>) -> Result<()> {
+    use solana_program::sysvar::Sysvar as _;
+    lido.check_invariants()?;
+    if let Some(reserve_lamports) = reserve_lamports {
+        lido.check_reserve_invariants(&Rent::get()?, Lamports::new(reserve_lamports))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "strict-checks"))]
+#[inline(always)]
+pub fn check_invariants(_lido: &Lido, _reserve_lamports: Option<u64>) -> Result<()> {
+    Ok(())
+}
+
+/// Check the state hash that the caller of a maintenance instruction computed its arguments against.
+///
+/// Fails with `MaintenanceStateChanged` if `expected` is set and differs from
+/// `Lido::maintenance_state_hash`, which tells maintainers to read the state
+/// again and recompute the instruction. This is cheaper than checking every
+/// seed that the instruction depends on.
+pub fn check_expected_state_hash(lido: &Lido, expected: Option<[u8; 32]>) -> Result<()> {
+    if let Some(expected) = expected {
+        if lido.maintenance_state_hash() != expected {
+            msg!("The state changed since this instruction was computed, recompute it.");
+            return err!(LidoError::MaintenanceStateChanged);
+        }
+    }
+    Ok(())
+}
+
+/// Check the seed or count that the caller of an idempotent maintenance instruction expected.
+///
+/// Returns `true` if the value already moved past `expected`, meaning another
+/// transaction did the work, and emits `MaintenanceSkipped`. Returns `false`
+/// if the value is as expected, and fails if it is behind.
+pub fn is_already_passed(
+    lido: &mut Lido,
+    instruction: &str,
+    expected: u64,
+    actual: u64,
+) -> Result<bool> {
+    if actual > expected {
+        msg!(
+            "{}: expected {}, but it is already {}, nothing to do.",
+            instruction,
+            expected,
+            actual
+        );
+        emit!(MaintenanceSkipped {
+            nonce: lido.next_operation_nonce()?,
+            instruction: instruction.to_string(),
+            expected,
+            actual,
+        });
+        return Ok(true);
+    }
+    if actual < expected {
+        msg!(
+            "{}: expected {}, but it is {}.",
+            instruction,
+            expected,
+            actual
+        );
+        return err!(LidoError::InvalidStakeAccount);
+    }
+    Ok(false)
+}
+
+/// Check that the validator's stake or unstake accounts exist for exactly the seeds in `range`.
+///
+/// `accounts` must hold the account at seed `range.begin - 1` (unless `begin`
+/// is 0), the accounts for every seed in `range`, and the account at seed
+/// `range.end`, in seed order. The accounts in the range must be stake
+/// accounts delegated to the validator with `stake_authority` as authority,
+/// and the two accounts around it must not be stake accounts.
+#[allow(clippy::too_many_arguments)]
+pub fn check_seed_range_accounts(
+    program_id: &Pubkey,
+    solido_address: &Pubkey,
+    validator: &PubkeyAndEntry,
+    stake_type: StakeType,
+    range: &SeedRange,
+    stake_authority: &Pubkey,
+    accounts: &[AccountInfo],
+    clock: &Clock,
+    stake_history: &StakeHistory,
+) -> Result<()> {
+    let num_seeds = range.num_seeds()?;
+    let first_seed = range.begin.saturating_sub(1);
+    let num_expected = (range.end - first_seed)
+        .checked_add(1)
+        .ok_or_else(|| error!(LidoError::InvalidSeedRange))?;
+
+    if (accounts.len() as u64) < num_expected {
+        msg!(
+            "Expected {} accounts for the {} seeds in [{}, {}) and the seeds around it, but got only {}.",
+            num_expected,
+            num_seeds,
+            range.begin,
+            range.end,
+            accounts.len()
+        );
+        return err!(LidoError::InvalidStakeAccount);
+    }
+    if (accounts.len() as u64) > num_expected {
+        return err!(LidoError::TooManyAccountKeys);
+    }
+
+    for (seed, provided) in (first_seed..=range.end).zip(accounts) {
+        let (expected_address, _) =
+            validator.find_stake_account_address(program_id, solido_address, seed, stake_type);
+        if provided.key != &expected_address {
+            msg!(
+                "{:?} account with seed {} should be {}, but {} was provided.",
+                stake_type,
+                seed,
+                expected_address,
+                provided.key
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+
+        let exists = provided.owner == &solana_program::stake::program::ID;
+        let in_range = seed >= range.begin && seed < range.end;
+        if exists != in_range {
+            msg!(
+                "{:?} account with seed {} {}, but the seed range is [{}, {}).",
+                stake_type,
+                seed,
+                if exists { "exists" } else { "does not exist" },
+                range.begin,
+                range.end
+            );
+            return err!(LidoError::SeedRangeMismatch);
+        }
+        if in_range {
+            let stake_account = StakeAccount::from_account_info(provided, clock, stake_history)?;
+            stake_account.check_owned_by(&validator.pubkey, stake_authority)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compute_budget::{COMPUTE_UNITS_BEFORE_LOOP, STAKE_ACCOUNT_READ_COMPUTE_UNITS};
+    use crate::stake_account::StakeBalance;
+    use crate::state::{ExchangeRate, Validator};
+    use solana_program::program_error::ProgramError;
+    use solana_program::stake::state::{Meta, Stake};
+
+    fn lido_with_validator(validator_vote: Pubkey) -> Lido {
+        let mut lido = Lido::default();
+        lido.validators.maximum_entries = 1;
+        lido.validators
+            .add(validator_vote, Validator::new(Pubkey::new_unique()))
+            .unwrap();
+        lido
+    }
+
+    #[test]
+    fn test_exchange_rate_can_be_updated_once_per_epoch() {
+        let mut lido = Lido::default();
+        lido.exchange_rate.computed_in_epoch = 7;
+
+        assert!(check_exchange_rate_not_updated(&lido, &FixedEpoch(6)).is_err());
+        assert!(check_exchange_rate_not_updated(&lido, &FixedEpoch(7)).is_err());
+        assert!(check_exchange_rate_not_updated(&lido, &FixedEpoch(8)).is_ok());
+    }
+
+    #[test]
+    fn test_exchange_rate_of_new_instance_in_epoch_0() {
+        // A new instance starts with a rate computed in epoch 0, so in epoch 0
+        // it counts as up to date, and can only be updated from epoch 1 on.
+        let lido = Lido::default();
+        assert!(check_exchange_rate_not_updated(&lido, &FixedEpoch(0)).is_err());
+        assert!(check_exchange_rate_not_updated(&lido, &FixedEpoch(1)).is_ok());
+        assert!(lido
+            .check_exchange_rate_last_epoch(&FixedEpoch(0), "Test")
+            .is_ok());
+        assert!(lido
+            .check_exchange_rate_last_epoch(&FixedEpoch(1), "Test")
+            .is_err());
+    }
+
+    #[test]
+    fn test_exchange_rate_of_instance_initialized_mid_epoch() {
+        // Fees can be collected in the epoch of initialization without an
+        // update, and the first update is in the next epoch.
+        let validator_vote = Pubkey::new_unique();
+        let mut lido = lido_with_validator(validator_vote);
+        lido.exchange_rate = ExchangeRate::genesis(5);
+        assert!(check_can_collect_fee(&lido, &validator_vote, &FixedEpoch(5)).is_ok());
+        assert!(check_exchange_rate_not_updated(&lido, &FixedEpoch(5)).is_err());
+
+        assert!(check_can_collect_fee(&lido, &validator_vote, &FixedEpoch(6)).is_err());
+        assert!(check_exchange_rate_not_updated(&lido, &FixedEpoch(6)).is_ok());
+
+        // A new validator has never collected fees, but starts at epoch 0,
+        // which does not block a collection in any later epoch.
+        let validator = lido.validators.get(&validator_vote).unwrap();
+        assert_eq!(validator.entry.workstate.fee_collected_epoch, 0);
+        assert!(validator.entry.check_fee_not_collected_in(5).is_ok());
+    }
+
+    #[test]
+    fn test_fee_collection_across_epoch_boundary() {
+        let validator_vote = Pubkey::new_unique();
+        let mut lido = lido_with_validator(validator_vote);
+        lido.exchange_rate.computed_in_epoch = 10;
+        assert!(check_can_collect_fee(&lido, &validator_vote, &FixedEpoch(10)).is_ok());
+
+        let validator = lido.validators.get_mut(&validator_vote).unwrap();
+        validator.entry.workstate.fee_collected_epoch = 10;
+        assert!(check_can_collect_fee(&lido, &validator_vote, &FixedEpoch(10)).is_err());
+
+        // In the next epoch the exchange rate has to be updated first.
+        assert!(check_can_collect_fee(&lido, &validator_vote, &FixedEpoch(11)).is_err());
+        lido.exchange_rate.computed_in_epoch = 11;
+        assert!(check_can_collect_fee(&lido, &validator_vote, &FixedEpoch(11)).is_ok());
+
+        let unknown_vote = Pubkey::new_unique();
+        assert!(check_can_collect_fee(&lido, &unknown_vote, &FixedEpoch(11)).is_err());
+    }
+
+    #[test]
+    fn test_check_seed_range_accounts_without_stake_accounts() {
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let validator_vote = Pubkey::new_unique();
+        let lido = lido_with_validator(validator_vote);
+        let validator = lido.validators.get(&validator_vote).unwrap();
+        let system_program = solana_program::system_program::ID;
+
+        let addresses: Vec<Pubkey> = (0..4)
+            .map(|seed| {
+                let (address, _) = validator.find_stake_account_address(
+                    &program_id,
+                    &solido_address,
+                    seed,
+                    StakeType::Stake,
+                );
+                address
+            })
+            .collect();
+        let mut lamports = vec![0; addresses.len()];
+        let mut data = vec![vec![]; addresses.len()];
+        let accounts: Vec<AccountInfo> = addresses
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|((address, lamports), data)| {
+                AccountInfo::new(
+                    address,
+                    false,
+                    false,
+                    lamports,
+                    data,
+                    &system_program,
+                    false,
+                    0,
+                )
+            })
+            .collect();
+
+        let check = |range: SeedRange, accounts: &[AccountInfo]| {
+            check_seed_range_accounts(
+                &program_id,
+                &solido_address,
+                validator,
+                StakeType::Stake,
+                &range,
+                &Pubkey::new_unique(),
+                accounts,
+                &Clock::default(),
+                &StakeHistory::default(),
+            )
+        };
+
+        // An empty range needs the accounts around it, which do not exist.
+        assert!(check(SeedRange { begin: 0, end: 0 }, &accounts[..1]).is_ok());
+        assert!(check(SeedRange { begin: 2, end: 2 }, &accounts[1..3]).is_ok());
+
+        // Too few, too many, or accounts with the wrong seeds.
+        assert!(check(SeedRange { begin: 2, end: 2 }, &accounts[1..2]).is_err());
+        assert!(check(SeedRange { begin: 2, end: 2 }, &accounts[1..4]).is_err());
+        assert!(check(SeedRange { begin: 2, end: 2 }, &accounts[2..4]).is_err());
+
+        // The accounts in the range do not exist.
+        assert!(check(SeedRange { begin: 1, end: 3 }, &accounts[..4]).is_err());
+
+        // A range that begins after its end, or that wraps around.
+        assert!(check(SeedRange { begin: 3, end: 2 }, &accounts[..4]).is_err());
+        let full = SeedRange {
+            begin: 0,
+            end: u64::MAX,
+        };
+        assert!(check(full, &accounts[..4]).is_err());
+    }
+
+    #[test]
+    fn test_check_withdraw_within_limit() {
+        let sol = |amount: u64| Lamports::new(amount * 1_000_000_000);
+
+        // 10% of 1000 SOL, plus 10 SOL.
+        assert!(check_withdraw_within_limit(sol(1_000), sol(110)).is_ok());
+        assert!(check_withdraw_within_limit(sol(1_000), Lamports::new(110_000_000_001)).is_err());
+
+        // Small stake accounts can always give the floor.
+        assert!(check_withdraw_within_limit(sol(0), sol(10)).is_ok());
+        assert!(check_withdraw_within_limit(sol(0), Lamports::new(10_000_000_001)).is_err());
+    }
+
+    #[test]
+    fn test_check_split_leaves_minimum() {
+        let minimum = MINIMUM_STAKE_ACCOUNT_BALANCE;
+        let below = (minimum - Lamports::new(1)).unwrap();
+        let above = (minimum + Lamports::new(1)).unwrap();
+        let two_minimum = (minimum + minimum).unwrap();
+
+        // Both sides at exactly the minimum.
+        assert!(check_split_leaves_minimum(two_minimum, minimum).is_ok());
+        // The new stake account is one lamport short.
+        assert!(check_split_leaves_minimum(two_minimum, below).is_err());
+        // The remainder is one lamport short.
+        assert!(check_split_leaves_minimum(two_minimum, above).is_err());
+        // Splitting off everything leaves no remainder.
+        assert!(check_split_leaves_minimum(two_minimum, two_minimum).is_ok());
+        assert!(check_split_leaves_minimum(minimum, minimum).is_ok());
+        // But even then the new account must hold the minimum.
+        assert!(check_split_leaves_minimum(below, below).is_err());
+        // More than the balance cannot be split off at all.
+        assert!(check_split_leaves_minimum(minimum, two_minimum).is_err());
+    }
+
+    #[test]
+    fn test_check_can_withdraw_from_excludes_the_unstaking_balance() {
+        let sol = |amount: u64| Lamports::new(amount * 1_000_000_000);
+        let mut validator = Validator::new(Pubkey::new_unique());
+        validator.stake_accounts_balance = sol(1_000);
+        assert!(check_can_withdraw_from(&validator, sol(1_000), sol(100)).is_ok());
+
+        // Most of the stake is being unstaked, what is left cannot cover it.
+        validator.unstake_accounts_balance = sol(950);
+        assert!(check_can_withdraw_from(&validator, sol(1_000), sol(100)).is_err());
+
+        // Too much for the stake account, even though the validator has it.
+        validator.unstake_accounts_balance = sol(0);
+        assert!(check_can_withdraw_from(&validator, sol(100), sol(100)).is_err());
+    }
+
+    /// A stake account with `active` stake, and `inactive` balance including the rent-exempt reserve.
+    fn stake_account(active: u64, inactive: u64) -> StakeAccount {
+        StakeAccount {
+            address: Pubkey::new_unique(),
+            meta: Meta {
+                rent_exempt_reserve: 2_282_880,
+                ..Meta::default()
+            },
+            stake: Stake::default(),
+            balance: StakeBalance {
+                active: Lamports::new(active),
+                inactive: Lamports::new(inactive),
+                ..StakeBalance::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_withdraw_inactive_stake_makes_progress_across_calls() {
+        let rent = 2_282_880;
+        // More accounts with work to do than one call can handle.
+        let mut stake_accounts: Vec<StakeAccount> = (0..20)
+            .map(|_| stake_account(1_000_000_000, rent + 500_000))
+            .collect();
+        let mut unstake_accounts: Vec<StakeAccount> = (0..20)
+            .map(|_| stake_account(0, rent + 1_000_000_000))
+            .collect();
+
+        let mut calls = 0;
+        loop {
+            calls += 1;
+            let num_accounts = (stake_accounts.len() + unstake_accounts.len()) as u64;
+            let mut budget = ComputeBudgetGuard::new(
+                COMPUTE_UNITS_BEFORE_LOOP + num_accounts * STAKE_ACCOUNT_READ_COMPUTE_UNITS,
+            );
+            let plan =
+                plan_withdraw_inactive_stake(&stake_accounts, &unstake_accounts, true, &mut budget);
+            assert!(plan.unstake_to_close + plan.stake_to_withdraw.len() > 0);
+            // An active validator keeps its stake accounts.
+            assert_eq!(plan.stake_to_close, 0);
+
+            // Apply the effects that the CPIs would have.
+            unstake_accounts.drain(..plan.unstake_to_close);
+            for &(i, amount) in &plan.stake_to_withdraw {
+                assert_eq!(amount, Lamports::new(500_000));
+                stake_accounts[i].balance.inactive = Lamports::new(rent);
+            }
+
+            if !plan.out_of_budget {
+                break;
+            }
+            assert!(calls < 10, "WithdrawInactiveStake does not make progress.");
+        }
+
+        assert!(calls > 1);
+        assert!(unstake_accounts.is_empty());
+        assert!(stake_accounts
+            .iter()
+            .all(|account| account.withdrawable() == Lamports::new(0)));
+    }
+
+    #[test]
+    fn test_withdraw_inactive_stake_closes_leading_inactive_accounts() {
+        let rent = 2_282_880;
+        let stake_accounts = [
+            stake_account(0, rent + 1_000),
+            stake_account(0, rent),
+            stake_account(1_000_000_000, rent + 1_000),
+            stake_account(0, rent + 1_000),
+        ];
+        // The second unstake account is still deactivating, it stays.
+        let unstake_accounts = [
+            stake_account(0, rent + 1_000),
+            stake_account(1_000, rent),
+            stake_account(0, rent),
+        ];
+        let mut budget = ComputeBudgetGuard::new(COMPUTE_UNITS_BEFORE_LOOP);
+        let plan =
+            plan_withdraw_inactive_stake(&stake_accounts, &unstake_accounts, false, &mut budget);
+        assert_eq!(
+            plan,
+            InactiveStakePlan {
+                unstake_to_close: 1,
+                stake_to_close: 2,
+                stake_to_withdraw: vec![(2, Lamports::new(1_000)), (3, Lamports::new(1_000))],
+                out_of_budget: false,
+            }
+        );
+
+        // The same accounts of an active validator are not closed.
+        let mut budget = ComputeBudgetGuard::new(COMPUTE_UNITS_BEFORE_LOOP);
+        let plan =
+            plan_withdraw_inactive_stake(&stake_accounts, &unstake_accounts, true, &mut budget);
+        assert_eq!(plan.stake_to_close, 0);
+        assert_eq!(
+            plan.stake_to_withdraw,
+            vec![
+                (0, Lamports::new(1_000)),
+                (2, Lamports::new(1_000)),
+                (3, Lamports::new(1_000))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_proportionally() {
+        let weights = [Lamports::new(300), Lamports::new(200), Lamports::new(100)];
+        let parts = split_proportionally(StLamports::new(60), &weights).unwrap();
+        assert_eq!(
+            parts,
+            vec![
+                StLamports::new(30),
+                StLamports::new(20),
+                StLamports::new(10)
+            ]
+        );
+
+        // The remainder of rounding down goes to the first part.
+        let parts = split_proportionally(StLamports::new(10), &weights).unwrap();
+        assert_eq!(
+            parts,
+            vec![StLamports::new(6), StLamports::new(3), StLamports::new(1)]
+        );
+
+        // A part can be zero if its weight is small enough.
+        let weights = [Lamports::new(1_000), Lamports::new(1)];
+        let parts = split_proportionally(StLamports::new(100), &weights).unwrap();
+        assert_eq!(parts, vec![StLamports::new(100), StLamports::new(0)]);
+
+        assert!(split_proportionally(StLamports::new(10), &[]).is_err());
+        assert!(split_proportionally(StLamports::new(10), &[Lamports::new(0)]).is_err());
+    }
+
+    #[test]
+    fn test_is_already_passed() {
+        let mut lido = Lido::default();
+        assert!(!is_already_passed(&mut lido, "StakeDeposit", 2, 2).unwrap());
+        assert_eq!(lido.operation_nonce, 0);
+
+        // Another maintainer did the work, this emits `MaintenanceSkipped`.
+        assert!(is_already_passed(&mut lido, "StakeDeposit", 2, 3).unwrap());
+        assert_eq!(lido.operation_nonce, 1);
+
+        // The caller expected more than happened so far, its view is invalid.
+        assert!(is_already_passed(&mut lido, "StakeDeposit", 3, 2).is_err());
+    }
+
+    #[test]
+    fn test_check_expected_state_hash() {
+        let mut lido = lido_with_validator(Pubkey::new_unique());
+        let hash = lido.maintenance_state_hash();
+        assert!(check_expected_state_hash(&lido, None).is_ok());
+        assert!(check_expected_state_hash(&lido, Some(hash)).is_ok());
+
+        lido.validators.entries[0].entry.stake_seeds.end = 1;
+        assert!(check_expected_state_hash(&lido, Some(hash)).is_err());
+        assert!(check_expected_state_hash(&lido, None).is_ok());
+    }
+
+    #[test]
+    fn test_is_vote_account_closed() {
+        let vote_program = solana_program::vote::program::ID;
+        let system_program = solana_program::system_program::ID;
+        let data = [1, 0, 0, 0, 7];
+        assert!(!is_vote_account_closed(&vote_program, 1_000, &data));
+
+        assert!(is_vote_account_closed(&system_program, 0, &[]));
+        assert!(is_vote_account_closed(&vote_program, 0, &data));
+        assert!(is_vote_account_closed(&vote_program, 1_000, &[0; 5]));
+        assert!(is_vote_account_closed(&Pubkey::new_unique(), 1_000, &data));
+    }
+
+    #[test]
+    fn test_is_reward_plausible() {
+        let zero = Lamports::new(0);
+        let stake = Lamports::new(1_000_000_000);
+        let rewards = Lamports::new(5_000);
+        assert!(is_reward_plausible(rewards, 100, stake));
+        assert!(is_reward_plausible(zero, 0, stake));
+        // Rewards that were not earned by voting.
+        assert!(!is_reward_plausible(rewards, 0, stake));
+        assert!(!is_reward_plausible(rewards, 0, zero));
+        // Credits that did not pay out, only suspicious if we have stake there.
+        assert!(!is_reward_plausible(zero, 100, stake));
+        assert!(is_reward_plausible(zero, 100, zero));
+    }
+
+    #[test]
+    fn test_clock_provides_its_epoch() {
+        let clock = Clock {
+            epoch: 42,
+            ..Clock::default()
+        };
+        assert_eq!(clock.epoch(), 42);
+        assert_eq!(FixedEpoch(42).epoch(), 42);
+    }
+
+    #[test]
+    fn test_signer_seeds_derive_the_program_addresses() {
+        let program_id = Pubkey::new_unique();
+        let lido_address = Pubkey::new_unique();
+        let addresses = [
+            (
+                &RESERVE_ACCOUNT[..],
+                crate::pda::find_reserve_address(&program_id, &lido_address),
+            ),
+            (
+                &MINT_AUTHORITY[..],
+                crate::pda::find_mint_authority(&program_id, &lido_address),
+            ),
+            (
+                &crate::PROTOCOL_TREASURY_AUTHORITY[..],
+                crate::pda::find_protocol_treasury_authority(&program_id, &lido_address),
+            ),
+        ];
+        for &(seed, (address, bump_seed)) in addresses.iter() {
+            let bump_seed = [bump_seed];
+            let seeds = signer_seeds(&lido_address, seed, &bump_seed);
+            assert_eq!(
+                Pubkey::create_program_address(&seeds, &program_id),
+                Ok(address)
+            );
+        }
+
+        // The program cannot sign for the address with another bump seed.
+        let (reserve, bump_seed) = crate::pda::find_reserve_address(&program_id, &lido_address);
+        let other_bump_seed = [bump_seed.wrapping_sub(1)];
+        let seeds = signer_seeds(&lido_address, RESERVE_ACCOUNT.as_ref(), &other_bump_seed);
+        assert_ne!(
+            Pubkey::create_program_address(&seeds, &program_id).ok(),
+            Some(reserve)
+        );
+    }
+
+    #[test]
+    fn test_check_burned_supply() {
+        let amount = StLamports::new(30);
+        assert!(check_burned_supply(100, 70, amount).is_ok());
+        // A token program that burned less, more, or nothing at all.
+        assert!(check_burned_supply(100, 80, amount).is_err());
+        assert!(check_burned_supply(100, 60, amount).is_err());
+        assert!(check_burned_supply(100, 100, amount).is_err());
+        // The supply cannot grow from a burn.
+        assert!(check_burned_supply(70, 100, amount).is_err());
+    }
+
+    #[test]
+    fn test_check_not_cpi_is_opt_in() {
+        let program_id = Pubkey::new_unique();
+        let mut config = Config::default();
+
+        // Not the instructions sysvar, so it cannot be read.
+        let key = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = Vec::new();
+        let instructions = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+
+        // Without `reject_cpi`, the sysvar is not read at all.
+        assert!(check_not_cpi(&config, &program_id, &instructions, "Deposit").is_ok());
+
+        config.reject_cpi = true;
+        assert!(check_not_cpi(&config, &program_id, &instructions, "Deposit").is_err());
+    }
 
-    anchor_spl::token::mint_to(cpi_context, amount.amount)
-}
\ No newline at end of file
+    #[test]
+    fn test_check_top_level_program() {
+        let program_id = Pubkey::new_unique();
+        assert!(check_top_level_program(&program_id, &program_id, "Deposit").is_ok());
+        let result = check_top_level_program(&program_id, &Pubkey::new_unique(), "Deposit");
+        let expected: ProgramError = error!(LidoError::CpiNotAllowed).into();
+        assert_eq!(ProgramError::from(result.unwrap_err()), expected);
+    }
+}
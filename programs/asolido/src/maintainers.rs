@@ -62,6 +62,37 @@ impl Maintainers {
         Ok(())
     }
 
+    /// Replace the entries with `target`, and return the removed and the added entries.
+    ///
+    /// Entries that are in both keep their position. Fails without changes if
+    /// `target` holds an entry twice, or more entries than fit.
+    pub fn set(&mut self, target: &[Pubkey]) -> Result<(Vec<Pubkey>, Vec<Pubkey>)> {
+        for (i, address) in target.iter().enumerate() {
+            if target[..i].contains(address) {
+                msg!("Maintainer {} is listed twice.", address);
+                return err!(LidoError::DuplicatedEntry);
+            }
+        }
+        if target.len() > self.maximum_entries as usize {
+            return err!(LidoError::MaximumNumberOfAccountsExceeded);
+        }
+
+        let removed: Vec<Pubkey> = self
+            .entries
+            .iter()
+            .filter(|address| !target.contains(*address))
+            .cloned()
+            .collect();
+        let added: Vec<Pubkey> = target
+            .iter()
+            .filter(|address| !self.entries.contains(*address))
+            .cloned()
+            .collect();
+        self.entries.retain(|address| target.contains(address));
+        self.entries.extend(added.iter().cloned());
+        Ok((removed, added))
+    }
+
     pub fn get(&self, address: &Pubkey) -> Result<&Pubkey> {
         self.entries
             .iter()
@@ -129,3 +160,36 @@ impl<'a> std::iter::Iterator for IterEntriesMut<'a> {
         self.iter.next()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_reconciles_the_entries() {
+        let keep = Pubkey::new_unique();
+        let remove = Pubkey::new_unique();
+        let add = Pubkey::new_unique();
+        let mut maintainers = Maintainers::new(2);
+        maintainers.add(remove).unwrap();
+        maintainers.add(keep).unwrap();
+
+        let (removed, added) = maintainers.set(&[keep, add]).unwrap();
+        assert_eq!(removed, vec![remove]);
+        assert_eq!(added, vec![add]);
+        assert_eq!(maintainers.entries, vec![keep, add]);
+
+        // Setting the same list again changes nothing.
+        let (removed, added) = maintainers.set(&[add, keep]).unwrap();
+        assert!(removed.is_empty() && added.is_empty());
+
+        assert!(maintainers.set(&[add, add]).is_err());
+        assert!(maintainers.set(&[keep, add, remove]).is_err());
+        assert_eq!(maintainers.entries, vec![keep, add]);
+
+        let (removed, added) = maintainers.set(&[]).unwrap();
+        assert_eq!(removed, vec![keep, add]);
+        assert!(added.is_empty());
+        assert!(maintainers.is_empty());
+    }
+}
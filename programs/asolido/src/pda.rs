@@ -0,0 +1,181 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Derivation of the program-derived addresses of a Solido instance.
+//!
+//! The program uses these to check the accounts that it is passed, and clients
+//! use them to find the accounts to pass.
+
+use anchor_lang::prelude::Pubkey;
+
+use crate::{
+    COMPLIANCE_AUTHORITY, CONFIG, EXCHANGE_RATE_HISTORY, MINT_AUTHORITY, PROTOCOL_TREASURY,
+    PROTOCOL_TREASURY_AUTHORITY, REGISTRY, RESERVE_ACCOUNT, RESERVE_ATTESTATION,
+    REWARDS_WITHDRAW_AUTHORITY, STAKE_AUTHORITY, VALIDATOR_INFO, VALIDATOR_STAKE_ACCOUNT,
+    VALIDATOR_UNSTAKE_ACCOUNT, WORMHOLE_EMITTER,
+};
+
+fn find_authority(program_id: &Pubkey, lido: &Pubkey, authority: &[u8]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[lido.as_ref(), authority], program_id)
+}
+
+fn find_validator_account(
+    program_id: &Pubkey,
+    lido: &Pubkey,
+    validator_vote: &Pubkey,
+    authority: &[u8],
+    seed: u64,
+) -> (Pubkey, u8) {
+    let seeds = [
+        lido.as_ref(),
+        validator_vote.as_ref(),
+        authority,
+        &seed.to_le_bytes()[..],
+    ];
+    Pubkey::find_program_address(&seeds, program_id)
+}
+
+/// Return the address of the reserve account, that holds deposited SOL.
+pub fn find_reserve_address(program_id: &Pubkey, lido: &Pubkey) -> (Pubkey, u8) {
+    find_authority(program_id, lido, RESERVE_ACCOUNT.as_ref())
+}
+
+/// Return the address of the mint authority of stSOL.
+pub fn find_mint_authority(program_id: &Pubkey, lido: &Pubkey) -> (Pubkey, u8) {
+    find_authority(program_id, lido, MINT_AUTHORITY.as_ref())
+}
+
+/// Return the address of the compliance authority, the freeze authority of
+/// stSOL under `FreezeAuthorityPolicy::ComplianceAuthority`.
+pub fn find_compliance_authority(program_id: &Pubkey, lido: &Pubkey) -> (Pubkey, u8) {
+    find_authority(program_id, lido, COMPLIANCE_AUTHORITY.as_ref())
+}
+
+/// Return the address of the stake and withdraw authority of the stake accounts.
+pub fn find_stake_authority(program_id: &Pubkey, lido: &Pubkey) -> (Pubkey, u8) {
+    find_authority(program_id, lido, STAKE_AUTHORITY.as_ref())
+}
+
+/// Return the address of the withdraw authority of the validators' vote accounts.
+pub fn find_rewards_withdraw_authority(program_id: &Pubkey, lido: &Pubkey) -> (Pubkey, u8) {
+    find_authority(program_id, lido, REWARDS_WITHDRAW_AUTHORITY.as_ref())
+}
+
+/// Return the address of the protocol treasury, the stSOL account that the program holds fees in.
+pub fn find_protocol_treasury(program_id: &Pubkey, lido: &Pubkey) -> (Pubkey, u8) {
+    find_authority(program_id, lido, PROTOCOL_TREASURY.as_ref())
+}
+
+/// Return the address of the token authority of the protocol treasury.
+pub fn find_protocol_treasury_authority(program_id: &Pubkey, lido: &Pubkey) -> (Pubkey, u8) {
+    find_authority(program_id, lido, PROTOCOL_TREASURY_AUTHORITY.as_ref())
+}
+
+/// Return the address of the rewards withdraw authority of a single validator's vote account.
+///
+/// Only used for validators with `Validator::has_own_rewards_withdraw_authority`,
+/// the others use `find_rewards_withdraw_authority`.
+pub fn find_validator_rewards_withdraw_authority(
+    program_id: &Pubkey,
+    lido: &Pubkey,
+    validator_vote: &Pubkey,
+) -> (Pubkey, u8) {
+    let seeds = [
+        lido.as_ref(),
+        validator_vote.as_ref(),
+        REWARDS_WITHDRAW_AUTHORITY.as_ref(),
+    ];
+    Pubkey::find_program_address(&seeds, program_id)
+}
+
+/// Return the address of the validator's stake account with the given seed.
+pub fn find_stake_account(
+    program_id: &Pubkey,
+    lido: &Pubkey,
+    validator_vote: &Pubkey,
+    seed: u64,
+) -> (Pubkey, u8) {
+    find_validator_account(
+        program_id,
+        lido,
+        validator_vote,
+        VALIDATOR_STAKE_ACCOUNT.as_ref(),
+        seed,
+    )
+}
+
+/// Return the address of the validator's unstake account with the given seed.
+pub fn find_unstake_account(
+    program_id: &Pubkey,
+    lido: &Pubkey,
+    validator_vote: &Pubkey,
+    seed: u64,
+) -> (Pubkey, u8) {
+    find_validator_account(
+        program_id,
+        lido,
+        validator_vote,
+        VALIDATOR_UNSTAKE_ACCOUNT.as_ref(),
+        seed,
+    )
+}
+
+/// Return the address of the `ValidatorInfo` account of the validator.
+pub fn find_validator_info(
+    program_id: &Pubkey,
+    lido: &Pubkey,
+    validator_vote: &Pubkey,
+) -> (Pubkey, u8) {
+    let seeds = [
+        lido.as_ref(),
+        validator_vote.as_ref(),
+        VALIDATOR_INFO.as_ref(),
+    ];
+    Pubkey::find_program_address(&seeds, program_id)
+}
+
+/// Return the address of the registry of official instances of `authority`.
+pub fn find_registry_address(program_id: &Pubkey, authority: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REGISTRY.as_ref(), authority.as_ref()], program_id)
+}
+
+/// Return the address of the `Config` account of `lido`.
+pub fn find_config_address(program_id: &Pubkey, lido: &Pubkey) -> (Pubkey, u8) {
+    find_authority(program_id, lido, CONFIG.as_ref())
+}
+
+/// Return the address of the exchange rate history of `lido`.
+pub fn find_exchange_rate_history_address(program_id: &Pubkey, lido: &Pubkey) -> (Pubkey, u8) {
+    find_authority(program_id, lido, EXCHANGE_RATE_HISTORY.as_ref())
+}
+
+/// Return the address that emits the Wormhole messages of `lido`.
+pub fn find_wormhole_emitter_address(program_id: &Pubkey, lido: &Pubkey) -> (Pubkey, u8) {
+    find_authority(program_id, lido, WORMHOLE_EMITTER.as_ref())
+}
+
+/// Return the address of the reserve attestation of `lido`.
+pub fn find_reserve_attestation_address(program_id: &Pubkey, lido: &Pubkey) -> (Pubkey, u8) {
+    find_authority(program_id, lido, RESERVE_ATTESTATION.as_ref())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stake_and_unstake_accounts_do_not_collide() {
+        let program_id = Pubkey::new_unique();
+        let lido = Pubkey::new_unique();
+        let vote = Pubkey::new_unique();
+
+        let (stake_0, _) = find_stake_account(&program_id, &lido, &vote, 0);
+        let (stake_1, _) = find_stake_account(&program_id, &lido, &vote, 1);
+        let (unstake_0, _) = find_unstake_account(&program_id, &lido, &vote, 0);
+        assert_ne!(stake_0, stake_1);
+        assert_ne!(stake_0, unstake_0);
+
+        // The same inputs always derive the same address.
+        assert_eq!(find_stake_account(&program_id, &lido, &vote, 0).0, stake_0);
+    }
+}
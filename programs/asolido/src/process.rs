@@ -1,43 +1,123 @@
 use anchor_lang::prelude::*;
-use std::collections::BTreeMap;
+use solana_program::program::{invoke, invoke_signed, set_return_data};
+use solana_program::stake::state::{Authorized, Lockup, StakeState};
+use solana_program::system_instruction;
 
+use crate::attestation::ValidatorReserves;
+use crate::compute_budget::{
+    ComputeBudgetGuard, COMPUTE_UNITS_BEFORE_LOOP, STAKE_ACCOUNT_COMPUTE_UNITS,
+    STAKE_ACCOUNT_READ_COMPUTE_UNITS,
+};
+use crate::config::Config;
+use crate::deposit_hook::DepositHook;
+use crate::events::{
+    ClosedValidatorDeactivated, Deposited, Donated, EpochRewardsCollected, MaintenanceSkipped,
+    ValidatorRewardsAnomaly, Withdrawn, WithdrawnFromReserve,
+};
+use crate::logic::{
+    burn_st_sol_from, check_can_collect_fee, check_can_withdraw_from,
+    check_exchange_rate_not_updated, check_not_cpi, check_seed_range_accounts,
+    check_split_leaves_minimum, get_vote_account_rewards, is_already_passed, is_reward_plausible,
+    is_vote_account_closed, mint_st_sol_to, pay_crank_tip, plan_withdraw_inactive_stake,
+    split_proportionally, split_stake_to_owner, transfer_sol_from_reserve,
+    vote_withdraw_instruction,
+};
 use crate::maintainers::Maintainers;
 use crate::metrics::Metrics;
-use crate::state::{ExchangeRate, FeeRecipients, LIDO_CONSTANT_SIZE};
-use crate::validators::Validators;
-use crate::{Deposit, Initialize, Lamports, RewardDistribution, LidoError};
-use crate::logic::mint_st_sol_to;
+use crate::pda;
+use crate::remaining_accounts::{
+    parse_seed_range_accounts, parse_stake_accounts_for_all_validators,
+    parse_stake_accounts_for_validator, parse_validator_triples, parse_withdraw_fallbacks,
+};
+use crate::stake_account::StakeAccount;
+use crate::state::{
+    ExchangeRate, FeeDenomination, FeeRecipients, FreezeAuthorityPolicy, StakeType,
+    LIDO_CONSTANT_SIZE,
+};
+use crate::token;
+use crate::validators::{PubkeyAndEntry, Validators};
+use crate::vote_state::PartialVoteState;
+use crate::wormhole::{
+    exchange_rate_payload, post_message_instruction, read_message_fee, PostMessageAccounts,
+};
+use crate::{
+    AttestReserves, BeginUpdateExchangeRate, CheckSeedRange, ClaimValidatorFee,
+    ClaimValidatorFeeSol, ClaimValidatorFeeTo, CollectValidatorFee, DeactivateClosedValidator,
+    Deposit, DepositToAssociatedAccount, Donate, GetValidatorStake, Initialize,
+    InitializeExchangeRateHistory, Lamports, Lido, LidoError, MarkDelinquent, MergeStake,
+    PostExchangeRateMessage, PreviewRewardSplit, RewardDistribution, StLamports, StakeDeposit,
+    Unstake, UnstakeAllFromValidator, UpdateExchangeRate, Withdraw, WithdrawAndMerge,
+    WithdrawFromReserve, WithdrawFromValidators, WithdrawInactiveStake,
+    MINIMUM_STAKE_ACCOUNT_BALANCE, RESERVE_ACCOUNT, STAKE_AUTHORITY, VALIDATOR_STAKE_ACCOUNT,
+    VALIDATOR_UNSTAKE_ACCOUNT, WORMHOLE_EMITTER,
+};
+
+/// Bump seeds of the program-derived addresses that `Initialize` records in `Lido`.
+///
+/// Anchor 0.23 passes bumps only as a map keyed by the name of the account
+/// field, the typed `ctx.bumps` needs a later Anchor. Rather than look them up
+/// by name, we derive them with the same `pda` functions that clients use, so
+/// there is no name to get wrong, and no bump that can be missing.
+struct InitializeBumps {
+    reserve: u8,
+    mint_authority: u8,
+    stake_authority: u8,
+    rewards_withdraw_authority: u8,
+}
+
+impl InitializeBumps {
+    fn derive(program_id: &Pubkey, lido: &Pubkey) -> InitializeBumps {
+        InitializeBumps {
+            reserve: pda::find_reserve_address(program_id, lido).1,
+            mint_authority: pda::find_mint_authority(program_id, lido).1,
+            stake_authority: pda::find_stake_authority(program_id, lido).1,
+            rewards_withdraw_authority: pda::find_rewards_withdraw_authority(program_id, lido).1,
+        }
+    }
+}
 
 impl<'info> Initialize<'info> {
     pub fn process(
         &mut self,
-        bumps: &BTreeMap<String, u8>,
+        program_id: &Pubkey,
         version: u8,
         reward_distribution: RewardDistribution,
         max_validators: u32,
         max_maintainers: u32,
+        freeze_authority_policy: FreezeAuthorityPolicy,
+        fee_denomination: FeeDenomination,
     ) -> Result<()> {
-        let lido = &mut self.lido;
+        let bumps = InitializeBumps::derive(program_id, &self.lido.key());
+        **self.config = Config::new(self.lido.key());
 
+        let lido = &mut self.lido;
         lido.lido_version = version;
         lido.manager = self.manager.key();
         lido.st_sol_mint = self.st_sol_mint.key();
-        lido.exchange_rate = ExchangeRate::default();
-        lido.sol_reserve_account_bump_seed = *bumps.get("reserve").unwrap();
-        lido.mint_authority_bump_seed = *bumps.get("mint_authority").unwrap();
-        lido.stake_authority_bump_seed = *bumps.get("stake_authority").unwrap();
-        lido.rewards_withdraw_authority_bump_seed =
-            *bumps.get("rewards_withdraw_authority").unwrap();
+        lido.exchange_rate = ExchangeRate::genesis(Clock::get()?.epoch);
+        lido.sol_reserve_account_bump_seed = bumps.reserve;
+        lido.mint_authority_bump_seed = bumps.mint_authority;
+        lido.stake_authority_bump_seed = bumps.stake_authority;
+        lido.rewards_withdraw_authority_bump_seed = bumps.rewards_withdraw_authority;
+        reward_distribution.check_valid()?;
         lido.reward_distribution = reward_distribution;
         lido.fee_recipients = FeeRecipients {
             treasury_account: self.treasury.key(),
             developer_account: self.developer.key(),
+            treasury_fee_denomination: FeeDenomination::StSol,
+            developer_fee_denomination: FeeDenomination::StSol,
         };
         lido.metrics = Metrics::new();
         lido.maintainers = Maintainers::new(max_maintainers);
         lido.validators = Validators::new(max_validators);
+        lido.freeze_authority_policy = freeze_authority_policy;
+        lido.fee_denomination = fee_denomination;
 
-        Ok(())
+        lido.check_mint(
+            &self.st_sol_mint,
+            &self.mint_authority.key(),
+            &self.compliance_authority.key(),
+        )
     }
 
     /// Return how many bytes are needed to serialize an Lido for validators and maintainers numbers
@@ -51,33 +131,1992 @@ impl<'info> Initialize<'info> {
     }
 }
 
+/// Check that a deposit of `amount` is possible, and move it from `user` to the reserve.
+fn transfer_deposit<'info>(
+    lido: &Lido,
+    config: &Config,
+    system_program: AccountInfo<'info>,
+    user: AccountInfo<'info>,
+    reserve: AccountInfo<'info>,
+    clock: &Clock,
+    amount: Lamports,
+) -> Result<()> {
+    require!(amount.amount > 0, LidoError::InvalidAmount);
+    config.check_deposit(amount)?;
+    lido.check_not_winding_down()?;
+    lido.check_mint_authority_not_transferred()?;
+    config.check_stale_exchange_rate_guard(lido, clock, "Deposit")?;
+
+    let cpi_accounts = anchor_lang::system_program::Transfer {
+        from: user,
+        to: reserve,
+    };
+    let cpi_context = CpiContext::new(system_program, cpi_accounts);
+    anchor_lang::system_program::transfer(cpi_context, amount.amount)
+}
+
+/// Mint stSOL to `recipient` for a deposit of `amount` that is already in the reserve.
+///
+/// Returns the amount of stSOL minted.
+fn mint_for_deposit<'info>(
+    lido: &mut Box<Account<'info, Lido>>,
+    token_program: AccountInfo<'info>,
+    st_sol_mint: AccountInfo<'info>,
+    mint_authority: AccountInfo<'info>,
+    recipient: AccountInfo<'info>,
+    amount: Lamports,
+    min_st_sol_out: StLamports,
+) -> Result<StLamports> {
+    let st_sol_amount = lido.exchange_rate.exchange_sol(amount)?;
+    if st_sol_amount < min_st_sol_out {
+        msg!(
+            "Depositing {} would mint {}, less than the requested minimum of {}.",
+            amount,
+            st_sol_amount,
+            min_st_sol_out
+        );
+        return err!(LidoError::SlippageExceeded);
+    }
+    mint_st_sol_to(
+        lido,
+        token_program,
+        st_sol_mint,
+        mint_authority,
+        recipient,
+        st_sol_amount,
+    )?;
+    lido.metrics.observe_deposit(amount);
+    Ok(st_sol_amount)
+}
+
+/// Invoke the deposit hook with `st_sol_amount`, if the deposit passes it as first remaining account.
+///
+/// The remaining accounts after the `DepositHook` account are the hook
+/// program and the fixed accounts of its template. The hook is invoked
+/// without any signature of this program, only `user` signs.
+fn invoke_deposit_hook<'info>(
+    lido_address: &Pubkey,
+    user: AccountInfo<'info>,
+    recipient: AccountInfo<'info>,
+    st_sol_mint: AccountInfo<'info>,
+    st_sol_amount: StLamports,
+    accounts: &[AccountInfo<'info>],
+) -> Result<()> {
+    let (hook_account, hook_accounts) = match accounts.split_first() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+    let deposit_hook: Account<DepositHook> = Account::try_from(hook_account)?;
+    if deposit_hook.lido != *lido_address {
+        msg!(
+            "The deposit hook {} belongs to instance {}, not to {}.",
+            hook_account.key,
+            deposit_hook.lido,
+            lido_address
+        );
+        return err!(LidoError::InvalidDepositHook);
+    }
+    require!(deposit_hook.enabled, LidoError::DepositHookDisabled);
+
+    let instruction =
+        deposit_hook.instruction(user.key, recipient.key, st_sol_mint.key, st_sol_amount);
+    let mut account_infos = vec![user, recipient, st_sol_mint];
+    account_infos.extend(hook_accounts.iter().cloned());
+    msg!("Invoking deposit hook {}.", deposit_hook.program_id);
+    invoke(&instruction, &account_infos)?;
+    Ok(())
+}
+
 impl<'info> Deposit<'info> {
+    pub fn process(
+        &mut self,
+        program_id: &Pubkey,
+        amount: Lamports,
+        min_st_sol_out: StLamports,
+        memo: Option<[u8; 32]>,
+        accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        check_not_cpi(&self.config, program_id, &self.instructions, "Deposit")?;
+        transfer_deposit(
+            &self.lido,
+            &self.config,
+            self.system_program.to_account_info(),
+            self.user.to_account_info(),
+            self.reserve.to_account_info(),
+            &self.clock,
+            amount,
+        )?;
+        let st_sol_amount = mint_for_deposit(
+            &mut self.lido,
+            self.token_program.to_account_info(),
+            self.st_sol_mint.to_account_info(),
+            self.mint_authority.to_account_info(),
+            self.recipient.to_account_info(),
+            amount,
+            min_st_sol_out,
+        )?;
+
+        emit!(Deposited {
+            nonce: self.lido.next_operation_nonce()?,
+            user: self.user.key(),
+            recipient: self.recipient.key(),
+            recipient_owner: self.recipient.owner,
+            amount_sol: amount.amount,
+            amount_st_sol: st_sol_amount.amount,
+            memo,
+        });
+
+        invoke_deposit_hook(
+            &self.lido.key(),
+            self.user.to_account_info(),
+            self.recipient.to_account_info(),
+            self.st_sol_mint.to_account_info(),
+            st_sol_amount,
+            accounts,
+        )
+    }
+}
+
+impl<'info> DepositToAssociatedAccount<'info> {
+    pub fn process(
+        &mut self,
+        program_id: &Pubkey,
+        amount: Lamports,
+        min_st_sol_out: StLamports,
+        memo: Option<[u8; 32]>,
+        accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        check_not_cpi(
+            &self.config,
+            program_id,
+            &self.instructions,
+            "DepositToAssociatedAccount",
+        )?;
+        transfer_deposit(
+            &self.lido,
+            &self.config,
+            self.system_program.to_account_info(),
+            self.user.to_account_info(),
+            self.reserve.to_account_info(),
+            &self.clock,
+            amount,
+        )?;
+        let st_sol_amount = mint_for_deposit(
+            &mut self.lido,
+            self.token_program.to_account_info(),
+            self.st_sol_mint.to_account_info(),
+            self.mint_authority.to_account_info(),
+            self.recipient.to_account_info(),
+            amount,
+            min_st_sol_out,
+        )?;
+
+        emit!(Deposited {
+            nonce: self.lido.next_operation_nonce()?,
+            user: self.user.key(),
+            recipient: self.recipient.key(),
+            recipient_owner: self.recipient_owner.key(),
+            amount_sol: amount.amount,
+            amount_st_sol: st_sol_amount.amount,
+            memo,
+        });
+
+        invoke_deposit_hook(
+            &self.lido.key(),
+            self.user.to_account_info(),
+            self.recipient.to_account_info(),
+            self.st_sol_mint.to_account_info(),
+            st_sol_amount,
+            accounts,
+        )
+    }
+}
+
+impl<'info> Donate<'info> {
     pub fn process(&mut self, amount: Lamports) -> Result<()> {
         require!(amount.amount > 0, LidoError::InvalidAmount);
 
         let cpi_accounts = anchor_lang::system_program::Transfer {
-            from: self.user.to_account_info(),
+            from: self.donor.to_account_info(),
             to: self.reserve.to_account_info(),
         };
-        let cpi_context = anchor_lang::context::CpiContext::new(
-            self.system_program.to_account_info(), cpi_accounts
-        );
+        let cpi_context = CpiContext::new(self.system_program.to_account_info(), cpi_accounts);
         anchor_lang::system_program::transfer(cpi_context, amount.amount)?;
 
-        let st_sol_amount = self.lido.exchange_rate.exchange_sol(amount)?;
+        self.lido.metrics.observe_donation(amount);
+
+        msg!("Received a donation of {}.", amount);
+        emit!(Donated {
+            nonce: self.lido.next_operation_nonce()?,
+            donor: self.donor.key(),
+            amount_sol: amount.amount,
+        });
+
+        Ok(())
+    }
+}
+
+impl<'info> WithdrawInactiveStake<'info> {
+    pub fn process(&mut self, program_id: &Pubkey, accounts: &[AccountInfo<'info>]) -> Result<()> {
+        let lido_address = self.lido.key();
+        let validator_vote = self.validator_vote.key();
+        let validator = self.lido.validators.get(&validator_vote)?;
+        let validator_active = validator.entry.active;
+        let (stake_account_infos, unstake_account_infos) =
+            parse_stake_accounts_for_validator(program_id, &lido_address, validator, accounts)?;
+
+        let stake_observed: Lamports = stake_account_infos
+            .iter()
+            .map(|account| Lamports::new(account.lamports()))
+            .sum::<token::Result<Lamports>>()?;
+        let unstake_observed: Lamports = unstake_account_infos
+            .iter()
+            .map(|account| Lamports::new(account.lamports()))
+            .sum::<token::Result<Lamports>>()?;
+        let observed_total = (stake_observed + unstake_observed)?;
+        validator.entry.check_observed_balance(observed_total)?;
+
+        let stake_accounts: Vec<StakeAccount> = stake_account_infos
+            .iter()
+            .map(|info| self.read_stake_account(info, &validator_vote))
+            .collect::<Result<_>>()?;
+        let unstake_accounts: Vec<StakeAccount> = unstake_account_infos
+            .iter()
+            .map(|info| self.read_stake_account(info, &validator_vote))
+            .collect::<Result<_>>()?;
+
+        // Reading the accounts costs the same on every call, only the CPIs
+        // are limited by the budget. If it runs low, we stop early and record
+        // what was done so far. Closed accounts leave the seed ranges and
+        // withdrawn ones have nothing left to withdraw, so the next call
+        // continues with the remaining accounts.
+        let num_accounts = (stake_accounts.len() + unstake_accounts.len()) as u64;
+        let mut budget = ComputeBudgetGuard::new(
+            COMPUTE_UNITS_BEFORE_LOOP
+                .saturating_add(num_accounts.saturating_mul(STAKE_ACCOUNT_READ_COMPUTE_UNITS)),
+        );
+        let plan = plan_withdraw_inactive_stake(
+            &stake_accounts,
+            &unstake_accounts,
+            validator_active,
+            &mut budget,
+        );
+
+        let mut withdrawn_unstake = Lamports::new(0);
+        let mut reclaimed_rent = Lamports::new(0);
+        for (info, unstake_account) in unstake_account_infos
+            .iter()
+            .zip(&unstake_accounts)
+            .take(plan.unstake_to_close)
+        {
+            let balance = self.close_to_reserve(info)?;
+            withdrawn_unstake = (withdrawn_unstake + balance)?;
+            reclaimed_rent = (reclaimed_rent + unstake_account.rent_exempt_reserve())?;
+        }
+
+        let mut withdrawn_stake = Lamports::new(0);
+        for (info, stake_account) in stake_account_infos
+            .iter()
+            .zip(&stake_accounts)
+            .take(plan.stake_to_close)
+        {
+            let balance = self.close_to_reserve(info)?;
+            withdrawn_stake = (withdrawn_stake + balance)?;
+            reclaimed_rent = (reclaimed_rent + stake_account.rent_exempt_reserve())?;
+        }
+        for &(i, amount) in &plan.stake_to_withdraw {
+            self.withdraw_to_reserve(&stake_account_infos[i], amount)?;
+            withdrawn_stake = (withdrawn_stake + amount)?;
+        }
+
+        if plan.out_of_budget {
+            msg!("Compute budget is running low, call WithdrawInactiveStake again to continue.");
+        }
+
+        // The rent-exempt reserves of the closed accounts are back in the
+        // reserve, where they are no longer locked, we count them as a donation.
+        if reclaimed_rent > Lamports::new(0) {
+            msg!(
+                "Reclaimed {} of rent from closed stake accounts.",
+                reclaimed_rent
+            );
+            self.lido.metrics.observe_donation(reclaimed_rent);
+        }
+
+        let validator = self.lido.validators.get_mut(&validator_vote)?;
+        validator.entry.stake_seeds.begin += plan.stake_to_close as u64;
+        validator.entry.unstake_seeds.begin += plan.unstake_to_close as u64;
+        // This can be called repeatedly, so the tip is only paid for the
+        // first call per validator per epoch that withdrew anything.
+        let withdrew_anything =
+            withdrawn_stake > Lamports::new(0) || withdrawn_unstake > Lamports::new(0);
+        let pays_tip = withdrew_anything
+            && validator.entry.workstate.withdraw_tip_paid_epoch != self.clock.epoch;
+        if pays_tip {
+            validator.entry.workstate.withdraw_tip_paid_epoch = self.clock.epoch;
+        }
+        self.lido.reconcile_validator_balance(
+            &validator_vote,
+            (stake_observed - withdrawn_stake)?,
+            (unstake_observed - withdrawn_unstake)?,
+        )?;
+
+        if pays_tip {
+            pay_crank_tip(
+                &mut self.lido,
+                &self.config,
+                &Rent::get()?,
+                self.system_program.to_account_info(),
+                self.reserve.to_account_info(),
+                self.tip_recipient.to_account_info(),
+                "WithdrawInactiveStake",
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Read a stake account of the validator, and check that Solido controls it.
+    fn read_stake_account(
+        &self,
+        stake_account: &AccountInfo<'info>,
+        validator_vote: &Pubkey,
+    ) -> Result<StakeAccount> {
+        let stake_account =
+            StakeAccount::from_account_info(stake_account, &self.clock, &self.stake_history)?;
+        stake_account.check_owned_by(validator_vote, &self.stake_authority.key())?;
+        Ok(stake_account)
+    }
+
+    /// Withdraw the full balance of a stake account into the reserve, which closes it.
+    ///
+    /// Returns the balance, including the rent-exempt reserve.
+    fn close_to_reserve(&self, stake_account: &AccountInfo<'info>) -> Result<Lamports> {
+        let balance = Lamports::new(stake_account.lamports());
+        self.withdraw_to_reserve(stake_account, balance)?;
+
+        // The runtime garbage-collects accounts without lamports at the end of
+        // the transaction, anything left would keep the account alive.
+        if stake_account.lamports() != 0 {
+            msg!(
+                "Stake account {} still holds {} Lamports after closing.",
+                stake_account.key,
+                stake_account.lamports()
+            );
+            return err!(LidoError::WrongStakeState);
+        }
+
+        Ok(balance)
+    }
+
+    /// Withdraw `amount` from the given stake account into the reserve.
+    fn withdraw_to_reserve(
+        &self,
+        stake_account: &AccountInfo<'info>,
+        amount: Lamports,
+    ) -> Result<()> {
+        let lido_address = self.lido.key();
+        let authority_signature_seeds = [
+            lido_address.as_ref(),
+            STAKE_AUTHORITY.as_ref(),
+            &[self.lido.stake_authority_bump_seed],
+        ];
+
+        invoke_signed(
+            &solana_program::stake::instruction::withdraw(
+                stake_account.key,
+                self.stake_authority.key,
+                self.reserve.key,
+                amount.amount,
+                None,
+            ),
+            &[
+                stake_account.clone(),
+                self.reserve.to_account_info(),
+                self.clock.to_account_info(),
+                self.stake_history.to_account_info(),
+                self.stake_authority.to_account_info(),
+                self.stake_program.to_account_info(),
+            ],
+            &[&authority_signature_seeds[..]],
+        )?;
+
+        Ok(())
+    }
+}
+
+impl<'info> Unstake<'info> {
+    pub fn process(&mut self, program_id: &Pubkey, amount: Lamports) -> Result<()> {
+        let lido_address = self.lido.key();
+        let validator_vote = self.validator_vote.key();
+        let validator = self.lido.validators.get(&validator_vote)?;
+        validator.entry.check_can_unstake()?;
+        // Inactive validators are unstaked completely, active validators only
+        // to rebalance, which starts with the one with the most stake.
+        if validator.entry.active {
+            self.lido
+                .validators
+                .check_has_most_stake_among_active(validator)?;
+        }
+
+        let stake_seed = validator.entry.stake_seeds.begin;
+        let (source_address, _) = validator.find_stake_account_address(
+            program_id,
+            &lido_address,
+            stake_seed,
+            StakeType::Stake,
+        );
+        if self.source_stake_account.key() != source_address {
+            msg!(
+                "Source stake account should be {}, but {} was provided.",
+                source_address,
+                self.source_stake_account.key()
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+        let source = StakeAccount::from_account_info(
+            &self.source_stake_account,
+            &self.clock,
+            &self.stake_history,
+        )?;
+        source.check_owned_by(&validator_vote, &self.stake_authority.key())?;
+        source.check_not_deactivated()?;
+
+        let unstake_seed = validator.entry.unstake_seeds.end;
+        let (destination_address, destination_bump_seed) = validator.find_stake_account_address(
+            program_id,
+            &lido_address,
+            unstake_seed,
+            StakeType::Unstake,
+        );
+        if self.destination_unstake_account.key() != destination_address {
+            msg!(
+                "Destination unstake account should be {}, but {} was provided.",
+                destination_address,
+                self.destination_unstake_account.key()
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+
+        let source_balance = Lamports::new(self.source_stake_account.lamports());
+        require!(
+            amount > Lamports::new(0) && amount <= source_balance,
+            LidoError::InvalidAmount
+        );
+        // Rather than leave a stake account behind that is too small to use,
+        // unstake all of it.
+        let remainder = (source_balance - amount)?;
+        let amount = if remainder > Lamports::new(0) && remainder < MINIMUM_STAKE_ACCOUNT_BALANCE {
+            msg!(
+                "Unstaking all of {} rather than {}, the rest would be below {}.",
+                source_balance,
+                amount,
+                MINIMUM_STAKE_ACCOUNT_BALANCE
+            );
+            source_balance
+        } else {
+            amount
+        };
+        check_split_leaves_minimum(source_balance, amount)?;
+
+        let unstake_seed_bytes = unstake_seed.to_le_bytes();
+        let destination_signature_seeds = [
+            lido_address.as_ref(),
+            validator_vote.as_ref(),
+            VALIDATOR_UNSTAKE_ACCOUNT.as_ref(),
+            &unstake_seed_bytes[..],
+            &[destination_bump_seed],
+        ];
+        let authority_signature_seeds = [
+            lido_address.as_ref(),
+            STAKE_AUTHORITY.as_ref(),
+            &[self.lido.stake_authority_bump_seed],
+        ];
+
+        // The split instructions also allocate and assign the destination
+        // account, which needs its signature, so we sign for both.
+        let split_instructions = solana_program::stake::instruction::split(
+            self.source_stake_account.key,
+            self.stake_authority.key,
+            amount.amount,
+            self.destination_unstake_account.key,
+        );
+        for split_instruction in split_instructions {
+            invoke_signed(
+                &split_instruction,
+                &[
+                    self.source_stake_account.to_account_info(),
+                    self.destination_unstake_account.to_account_info(),
+                    self.stake_authority.to_account_info(),
+                    self.system_program.to_account_info(),
+                    self.stake_program.to_account_info(),
+                ],
+                &[
+                    &destination_signature_seeds[..],
+                    &authority_signature_seeds[..],
+                ],
+            )?;
+        }
+
+        invoke_signed(
+            &solana_program::stake::instruction::deactivate_stake(
+                self.destination_unstake_account.key,
+                self.stake_authority.key,
+            ),
+            &[
+                self.destination_unstake_account.to_account_info(),
+                self.clock.to_account_info(),
+                self.stake_authority.to_account_info(),
+                self.stake_program.to_account_info(),
+            ],
+            &[&authority_signature_seeds[..]],
+        )?;
+
+        let validator = self.lido.validators.get_mut(&validator_vote)?;
+        validator.entry.observe_unstake(amount)?;
+        validator.entry.unstake_seeds.end += 1;
+        validator
+            .entry
+            .unbonding
+            .record(unstake_seed, self.clock.epoch);
+
+        // If we unstaked the full balance, the source account is gone, and the
+        // next stake account becomes the first one.
+        if amount == source_balance {
+            validator.entry.stake_seeds.begin += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'info> UnstakeAllFromValidator<'info> {
+    pub fn process(&mut self, program_id: &Pubkey, accounts: &[AccountInfo<'info>]) -> Result<()> {
+        let lido_address = self.lido.key();
+        let validator_vote = self.validator_vote.key();
+        let validator = self.lido.validators.get(&validator_vote)?;
+        if validator.entry.active {
+            msg!(
+                "Validator {} is still active, deactivate it first.",
+                validator_vote
+            );
+            return err!(LidoError::ValidatorIsStillActive);
+        }
+
+        let stake_accounts = parse_seed_range_accounts(
+            program_id,
+            &lido_address,
+            validator,
+            StakeType::Stake,
+            accounts,
+        )?;
+
+        let authority_signature_seeds = [
+            lido_address.as_ref(),
+            STAKE_AUTHORITY.as_ref(),
+            &[self.lido.stake_authority_bump_seed],
+        ];
+
+        // Deactivating is idempotent, so if the compute budget runs low, we
+        // stop early, and the next call skips the accounts that are done.
+        let mut budget = ComputeBudgetGuard::new(COMPUTE_UNITS_BEFORE_LOOP);
+        let seeds = validator.entry.stake_seeds.into_iter();
+        for (seed, stake_account_info) in seeds.zip(stake_accounts) {
+            if !budget.try_reserve(STAKE_ACCOUNT_COMPUTE_UNITS) {
+                msg!(
+                    "Compute budget is running low at seed {}, call UnstakeAllFromValidator again.",
+                    seed
+                );
+                break;
+            }
+            let stake_account = StakeAccount::from_account_info(
+                stake_account_info,
+                &self.clock,
+                &self.stake_history,
+            )?;
+            stake_account.check_owned_by(&validator_vote, &self.stake_authority.key())?;
+            if stake_account.is_deactivated() {
+                continue;
+            }
+
+            invoke_signed(
+                &solana_program::stake::instruction::deactivate_stake(
+                    stake_account_info.key,
+                    self.stake_authority.key,
+                ),
+                &[
+                    stake_account_info.clone(),
+                    self.clock.to_account_info(),
+                    self.stake_authority.to_account_info(),
+                    self.stake_program.to_account_info(),
+                ],
+                &[&authority_signature_seeds[..]],
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'info> StakeDeposit<'info> {
+    /// Stake everything in the reserve above the `Config::min_reserve_balance` buffer.
+    ///
+    /// If that is more than the remaining staking budget of this epoch, only
+    /// the budget is staked.
+    pub fn process_sweep(&mut self, program_id: &Pubkey) -> Result<()> {
+        let mut amount = self.lido.get_reserve_stakeable_balance(
+            &self.rent,
+            Lamports::new(self.reserve.lamports()),
+            self.config.min_reserve_balance,
+        )?;
+        if let Some(budget) = self
+            .config
+            .get_stake_budget_remaining(self.lido.staked_this_epoch)
+        {
+            amount = amount.min(budget);
+        }
+        self.process(program_id, amount, None)
+    }
+
+    pub fn process(
+        &mut self,
+        program_id: &Pubkey,
+        amount: Lamports,
+        expected_stake_deposits: Option<u64>,
+    ) -> Result<()> {
+        // Check this first, if another maintainer staked already, the reserve
+        // may no longer hold `amount`.
+        if let Some(expected_stake_deposits) = expected_stake_deposits {
+            let validator = self.lido.validators.get(&self.validator_vote.key())?;
+            let stake_deposits = validator.entry.stake_deposits;
+            if is_already_passed(
+                &mut self.lido,
+                "StakeDeposit",
+                expected_stake_deposits,
+                stake_deposits,
+            )? {
+                return Ok(());
+            }
+        }
+
+        require!(
+            amount >= MINIMUM_STAKE_ACCOUNT_BALANCE,
+            LidoError::InvalidAmount
+        );
+        let reserve_stakeable = self.lido.get_reserve_stakeable_balance(
+            &self.rent,
+            Lamports::new(self.reserve.lamports()),
+            self.config.min_reserve_balance,
+        )?;
+        if amount > reserve_stakeable {
+            msg!(
+                "Can stake at most {}, the reserve keeps a buffer of {}.",
+                reserve_stakeable,
+                self.config.min_reserve_balance
+            );
+            return err!(LidoError::AmountExceedsReserve);
+        }
+        if let Some(budget) = self
+            .config
+            .get_stake_budget_remaining(self.lido.staked_this_epoch)
+        {
+            if amount > budget {
+                msg!(
+                    "Can stake at most {} more in this epoch, {} was staked already.",
+                    budget,
+                    self.lido.staked_this_epoch
+                );
+                return err!(LidoError::StakeBudgetExceeded);
+            }
+        }
+
+        let lido_address = self.lido.key();
+        let validator_vote = self.validator_vote.key();
+        let stake_authority = self.stake_authority.key();
+        let validator = self.lido.validators.get(&validator_vote)?;
+        let epoch = self.clock.epoch;
+        let min_epochs_before_stake = self.config.min_epochs_before_stake;
+        validator.entry.check_can_stake()?;
+        validator
+            .entry
+            .check_stake_delay_over(epoch, min_epochs_before_stake)?;
+        self.lido
+            .validators
+            .check_has_least_stake(validator, epoch, min_epochs_before_stake)?;
+
+        let end_seed = validator.entry.stake_seeds.end;
+        let (end_address, end_bump_seed) = validator.find_stake_account_address(
+            program_id,
+            &lido_address,
+            end_seed,
+            StakeType::Stake,
+        );
+        if self.stake_account_end.key() != end_address {
+            msg!(
+                "Stake account should be {}, but {} was provided.",
+                end_address,
+                self.stake_account_end.key()
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+
+        // If the last stake account was activated in this epoch, we merge the
+        // new stake into it, rather than creating yet another stake account.
+        let merge = self.stake_account_merge_into.key() != end_address;
+        if merge {
+            require!(
+                validator.entry.has_stake_accounts(),
+                LidoError::InvalidStakeAccount
+            );
+            let (merge_address, _) = validator.find_stake_account_address(
+                program_id,
+                &lido_address,
+                end_seed - 1,
+                StakeType::Stake,
+            );
+            if self.stake_account_merge_into.key() != merge_address {
+                msg!(
+                    "Stake account to merge into should be {}, but {} was provided.",
+                    merge_address,
+                    self.stake_account_merge_into.key()
+                );
+                return err!(LidoError::InvalidStakeAccount);
+            }
+            let merge_into = StakeAccount::from_account_info(
+                &self.stake_account_merge_into,
+                &self.clock,
+                &self.stake_history,
+            )?;
+            merge_into.check_owned_by(&validator_vote, &stake_authority)?;
+            merge_into.check_activated_in(self.clock.epoch)?;
+        }
+
+        let end_seed_bytes = end_seed.to_le_bytes();
+        let stake_account_signature_seeds = [
+            lido_address.as_ref(),
+            validator_vote.as_ref(),
+            VALIDATOR_STAKE_ACCOUNT.as_ref(),
+            &end_seed_bytes[..],
+            &[end_bump_seed],
+        ];
+        let reserve_signature_seeds = [
+            lido_address.as_ref(),
+            RESERVE_ACCOUNT.as_ref(),
+            &[self.lido.sol_reserve_account_bump_seed],
+        ];
+        let authority_signature_seeds = [
+            lido_address.as_ref(),
+            STAKE_AUTHORITY.as_ref(),
+            &[self.lido.stake_authority_bump_seed],
+        ];
+
+        invoke_signed(
+            &system_instruction::create_account(
+                self.reserve.key,
+                self.stake_account_end.key,
+                amount.amount,
+                StakeState::size_of() as u64,
+                &solana_program::stake::program::ID,
+            ),
+            &[
+                self.reserve.to_account_info(),
+                self.stake_account_end.to_account_info(),
+                self.system_program.to_account_info(),
+            ],
+            &[
+                &reserve_signature_seeds[..],
+                &stake_account_signature_seeds[..],
+            ],
+        )?;
 
-        mint_st_sol_to(&self.lido,
-        self.token_program.to_account_info(),
+        invoke(
+            &solana_program::stake::instruction::initialize(
+                self.stake_account_end.key,
+                &Authorized {
+                    staker: stake_authority,
+                    withdrawer: stake_authority,
+                },
+                &Lockup::default(),
+            ),
+            &[
+                self.stake_account_end.to_account_info(),
+                self.rent.to_account_info(),
+                self.stake_program.to_account_info(),
+            ],
+        )?;
+
+        invoke_signed(
+            &solana_program::stake::instruction::delegate_stake(
+                self.stake_account_end.key,
+                self.stake_authority.key,
+                &validator_vote,
+            ),
+            &[
+                self.stake_account_end.to_account_info(),
+                self.validator_vote.to_account_info(),
+                self.clock.to_account_info(),
+                self.stake_history.to_account_info(),
+                self.stake_config.to_account_info(),
+                self.stake_authority.to_account_info(),
+                self.stake_program.to_account_info(),
+            ],
+            &[&authority_signature_seeds[..]],
+        )?;
+
+        if merge {
+            let merge_instructions = solana_program::stake::instruction::merge(
+                self.stake_account_merge_into.key,
+                self.stake_account_end.key,
+                self.stake_authority.key,
+            );
+            for merge_instruction in merge_instructions {
+                invoke_signed(
+                    &merge_instruction,
+                    &[
+                        self.stake_account_merge_into.to_account_info(),
+                        self.stake_account_end.to_account_info(),
+                        self.clock.to_account_info(),
+                        self.stake_history.to_account_info(),
+                        self.stake_authority.to_account_info(),
+                        self.stake_program.to_account_info(),
+                    ],
+                    &[&authority_signature_seeds[..]],
+                )?;
+            }
+        }
+
+        self.lido
+            .update_validator_balances(&validator_vote, |validator| {
+                validator.observe_stake_deposit(amount, merge)
+            })?;
+        self.lido.staked_this_epoch = (self.lido.staked_this_epoch + amount)?;
+
+        Ok(())
+    }
+}
+
+impl<'info> MergeStake<'info> {
+    pub fn process(&mut self, program_id: &Pubkey, expected_from_seed: Option<u64>) -> Result<()> {
+        let lido_address = self.lido.key();
+        let validator_vote = self.validator_vote.key();
+        let stake_authority = self.stake_authority.key();
+
+        let from_seed = self
+            .lido
+            .validators
+            .get(&validator_vote)?
+            .entry
+            .stake_seeds
+            .begin;
+        if let Some(expected_from_seed) = expected_from_seed {
+            if is_already_passed(&mut self.lido, "MergeStake", expected_from_seed, from_seed)? {
+                return Ok(());
+            }
+        }
+        let validator = self.lido.validators.get(&validator_vote)?;
+        let to_seed = from_seed + 1;
+        if to_seed >= validator.entry.stake_seeds.end {
+            msg!("The validator needs at least two stake accounts to merge.");
+            return err!(LidoError::InvalidStakeAccount);
+        }
+
+        let (from_address, _) = validator.find_stake_account_address(
+            program_id,
+            &lido_address,
+            from_seed,
+            StakeType::Stake,
+        );
+        let (to_address, _) = validator.find_stake_account_address(
+            program_id,
+            &lido_address,
+            to_seed,
+            StakeType::Stake,
+        );
+        if self.from_stake.key() != from_address || self.to_stake.key() != to_address {
+            msg!(
+                "Expected to merge {} into {}, but got {} and {}.",
+                from_address,
+                to_address,
+                self.from_stake.key(),
+                self.to_stake.key()
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+
+        for stake_account_info in [&self.from_stake, &self.to_stake] {
+            let stake_account = StakeAccount::from_account_info(
+                stake_account_info,
+                &self.clock,
+                &self.stake_history,
+            )?;
+            stake_account.check_owned_by(&validator_vote, &stake_authority)?;
+        }
+
+        let authority_signature_seeds = [
+            lido_address.as_ref(),
+            STAKE_AUTHORITY.as_ref(),
+            &[self.lido.stake_authority_bump_seed],
+        ];
+        let merge_instructions = solana_program::stake::instruction::merge(
+            self.to_stake.key,
+            self.from_stake.key,
+            self.stake_authority.key,
+        );
+        for merge_instruction in merge_instructions {
+            invoke_signed(
+                &merge_instruction,
+                &[
+                    self.to_stake.to_account_info(),
+                    self.from_stake.to_account_info(),
+                    self.clock.to_account_info(),
+                    self.stake_history.to_account_info(),
+                    self.stake_authority.to_account_info(),
+                    self.stake_program.to_account_info(),
+                ],
+                &[&authority_signature_seeds[..]],
+            )?;
+        }
+
+        // The merge moved the full balance, so the tracked balance does not
+        // change, only the first stake account is gone.
+        let validator = self.lido.validators.get_mut(&validator_vote)?;
+        validator.entry.stake_seeds.begin += 1;
+
+        Ok(())
+    }
+}
+
+impl<'info> Withdraw<'info> {
+    pub fn process(
+        &mut self,
+        program_id: &Pubkey,
+        amount: StLamports,
+        min_sol_out: Lamports,
+        fallback_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        require!(amount > StLamports::new(0), LidoError::InvalidAmount);
+        self.config.check_withdrawals_not_paused()?;
+        check_not_cpi(&self.config, program_id, &self.instructions, "Withdraw")?;
+        self.config
+            .check_stale_exchange_rate_guard(&self.lido, &self.clock, "Withdraw")?;
+
+        let lido_address = self.lido.key();
+        let sol_to_withdraw = self.lido.exchange_rate.exchange_st_sol(amount)?;
+
+        // The validator with the most stake serves the withdrawal. If it cannot,
+        // because its stake is being unstaked during a rebalance, or its first
+        // stake account is too small, the next-heaviest of the fallbacks that
+        // the caller passed serves it instead. Only a validator whose accounts
+        // prove that it cannot serve the withdrawal is skipped.
+        let mut candidates = vec![(
+            self.validator_vote.key(),
+            self.source_stake_account.to_account_info(),
+        )];
+        for fallback in parse_withdraw_fallbacks(fallback_accounts)? {
+            candidates.push((fallback.validator_vote, fallback.stake_account.clone()));
+        }
+        let mut candidates = candidates.into_iter().peekable();
+        let mut excluded: Vec<Pubkey> = Vec::new();
+        let (validator_vote, source_stake_account) = loop {
+            let (validator_vote, source_stake_account) = match candidates.next() {
+                Some(candidate) => candidate,
+                // There is at least one candidate, and the last one returns its error below.
+                None => return err!(LidoError::InvalidStakeAccount),
+            };
+            if excluded.contains(&validator_vote) {
+                msg!("Validator {} is passed more than once.", validator_vote);
+                return err!(LidoError::DuplicatedEntry);
+            }
+            let validator = self.lido.validators.get(&validator_vote)?;
+            self.lido
+                .validators
+                .check_has_most_stake_excluding(validator, &excluded)?;
+            self.check_source_stake_account(program_id, validator, &source_stake_account)?;
+
+            let source_balance = Lamports::new(source_stake_account.lamports());
+            let is_last = candidates.peek().is_none();
+            match check_can_withdraw_from(&validator.entry, source_balance, sol_to_withdraw) {
+                Ok(()) => break (validator_vote, source_stake_account),
+                Err(err) if is_last => return Err(err),
+                Err(_) => {
+                    msg!(
+                        "Validator {} cannot serve the withdrawal, falling back to the next validator.",
+                        validator_vote
+                    );
+                    excluded.push(validator_vote);
+                }
+            }
+        };
+
+        if sol_to_withdraw < min_sol_out {
+            msg!(
+                "Withdrawing {} would yield {}, less than the requested minimum of {}.",
+                amount,
+                sol_to_withdraw,
+                min_sol_out
+            );
+            return err!(LidoError::SlippageExceeded);
+        }
+
+        burn_st_sol_from(
+            self.token_program.to_account_info(),
+            &mut self.st_sol_mint,
+            self.st_sol_account.to_account_info(),
+            self.st_sol_account_owner.to_account_info(),
+            amount,
+        )?;
+
+        // Hand the new stake account over to the owner of the stSOL.
+        split_stake_to_owner(
+            &lido_address,
+            self.stake_authority.to_account_info(),
+            self.lido.stake_authority_bump_seed,
+            source_stake_account,
+            self.destination_stake_account.to_account_info(),
+            self.st_sol_account_owner.key,
+            sol_to_withdraw,
+            self.clock.to_account_info(),
+            self.system_program.to_account_info(),
+            self.stake_program.to_account_info(),
+        )?;
+
+        self.lido
+            .update_validator_balances(&validator_vote, |validator| {
+                validator.observe_stake_decrease(sol_to_withdraw)
+            })?;
+
+        self.lido
+            .metrics
+            .observe_withdrawal(amount, sol_to_withdraw);
+
+        emit!(Withdrawn {
+            nonce: self.lido.next_operation_nonce()?,
+            owner: self.st_sol_account_owner.key(),
+            stake_account: self.destination_stake_account.key(),
+            amount_st_sol: amount.amount,
+            amount_sol: sol_to_withdraw.amount,
+        });
+
+        Ok(())
+    }
+
+    /// Check that `source_stake_account` is the first stake account of `validator`.
+    fn check_source_stake_account(
+        &self,
+        program_id: &Pubkey,
+        validator: &PubkeyAndEntry,
+        source_stake_account: &AccountInfo<'info>,
+    ) -> Result<()> {
+        let (source_address, _) = validator.find_stake_account_address(
+            program_id,
+            &self.lido.key(),
+            validator.entry.stake_seeds.begin,
+            StakeType::Stake,
+        );
+        if source_stake_account.key() != source_address {
+            msg!(
+                "Source stake account of {} should be {}, but {} was provided.",
+                validator.pubkey,
+                source_address,
+                source_stake_account.key()
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+        let source = StakeAccount::from_account_info(
+            source_stake_account,
+            &self.clock,
+            &self.stake_history,
+        )?;
+        source.check_owned_by(&validator.pubkey, &self.stake_authority.key())
+    }
+}
+
+impl<'info> WithdrawFromValidators<'info> {
+    pub fn process(
+        &mut self,
+        program_id: &Pubkey,
+        amount: StLamports,
+        min_sol_out: Lamports,
+        accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        require!(amount > StLamports::new(0), LidoError::InvalidAmount);
+        self.config.check_withdrawals_not_paused()?;
+        check_not_cpi(
+            &self.config,
+            program_id,
+            &self.instructions,
+            "WithdrawFromValidators",
+        )?;
+        self.config.check_stale_exchange_rate_guard(
+            &self.lido,
+            &self.clock,
+            "WithdrawFromValidators",
+        )?;
+
+        let lido_address = self.lido.key();
+        let triples =
+            parse_validator_triples(program_id, &lido_address, &self.lido.validators, accounts)?;
+        let mut stake_balances = Vec::with_capacity(triples.len());
+        for triple in triples.iter() {
+            let validator = self.lido.validators.get(&triple.validator_vote)?;
+            stake_balances.push(validator.entry.effective_stake_balance()?);
+        }
+        // Like for `Withdraw`, the validator with the most stake goes first,
+        // and it gets the rounding remainder of the split.
+        let first_validator = self.lido.validators.get(&triples[0].validator_vote)?;
+        self.lido.validators.check_has_most_stake(first_validator)?;
+
+        let st_sol_parts = split_proportionally(amount, &stake_balances)?;
+        let mut sol_parts = Vec::with_capacity(st_sol_parts.len());
+        for (triple, st_sol_part) in triples.iter().zip(&st_sol_parts) {
+            let source = StakeAccount::from_account_info(
+                triple.stake_account,
+                &self.clock,
+                &self.stake_history,
+            )?;
+            source.check_owned_by(&triple.validator_vote, &self.stake_authority.key())?;
+
+            let sol_part = self.lido.exchange_rate.exchange_st_sol(*st_sol_part)?;
+            if sol_part == Lamports::new(0) {
+                msg!(
+                    "Withdrawing {} is too little to split over {} validators.",
+                    amount,
+                    triples.len()
+                );
+                return err!(LidoError::InvalidAmount);
+            }
+            let source_balance = Lamports::new(triple.stake_account.lamports());
+            let validator = self.lido.validators.get(&triple.validator_vote)?;
+            check_can_withdraw_from(&validator.entry, source_balance, sol_part)?;
+            sol_parts.push(sol_part);
+        }
+
+        let sol_to_withdraw: Lamports = sol_parts.iter().sum::<token::Result<Lamports>>()?;
+        if sol_to_withdraw < min_sol_out {
+            msg!(
+                "Withdrawing {} would yield {}, less than the requested minimum of {}.",
+                amount,
+                sol_to_withdraw,
+                min_sol_out
+            );
+            return err!(LidoError::SlippageExceeded);
+        }
+
+        burn_st_sol_from(
+            self.token_program.to_account_info(),
+            &mut self.st_sol_mint,
+            self.st_sol_account.to_account_info(),
+            self.st_sol_account_owner.to_account_info(),
+            amount,
+        )?;
+
+        let parts = triples.iter().zip(st_sol_parts.iter().zip(&sol_parts));
+        for (triple, (st_sol_part, sol_part)) in parts {
+            let validator_vote = &triple.validator_vote;
+            let destination_stake_account = triple.new_stake_account;
+            split_stake_to_owner(
+                &lido_address,
+                self.stake_authority.to_account_info(),
+                self.lido.stake_authority_bump_seed,
+                triple.stake_account.clone(),
+                destination_stake_account.clone(),
+                self.st_sol_account_owner.key,
+                *sol_part,
+                self.clock.to_account_info(),
+                self.system_program.to_account_info(),
+                self.stake_program.to_account_info(),
+            )?;
+
+            self.lido
+                .update_validator_balances(validator_vote, |validator| {
+                    validator.observe_stake_decrease(*sol_part)
+                })?;
+
+            emit!(Withdrawn {
+                nonce: self.lido.next_operation_nonce()?,
+                owner: self.st_sol_account_owner.key(),
+                stake_account: destination_stake_account.key(),
+                amount_st_sol: st_sol_part.amount,
+                amount_sol: sol_part.amount,
+            });
+        }
+
+        self.lido
+            .metrics
+            .observe_withdrawal(amount, sol_to_withdraw);
+
+        Ok(())
+    }
+}
+
+impl<'info> WithdrawAndMerge<'info> {
+    pub fn process(
+        &mut self,
+        program_id: &Pubkey,
+        amount: StLamports,
+        min_sol_out: Lamports,
+    ) -> Result<()> {
+        let withdraw = &mut self.withdraw;
+        let owner = withdraw.st_sol_account_owner.key();
+        let user_stake = StakeAccount::from_account_info(
+            &self.user_stake_account,
+            &withdraw.clock,
+            &withdraw.stake_history,
+        )?;
+        user_stake.check_can_merge_withdrawal(&withdraw.validator_vote.key(), &owner)?;
+
+        // The stake must come from the validator of `user_stake_account`, so
+        // there are no fallbacks.
+        withdraw.process(program_id, amount, min_sol_out, &[])?;
+
+        // `Withdraw` handed the new stake account over to the owner, who
+        // signed this transaction, so the owner can authorize the merge.
+        let merge_instructions = solana_program::stake::instruction::merge(
+            self.user_stake_account.key,
+            withdraw.destination_stake_account.key,
+            &owner,
+        );
+        for merge_instruction in merge_instructions {
+            invoke(
+                &merge_instruction,
+                &[
+                    self.user_stake_account.to_account_info(),
+                    withdraw.destination_stake_account.to_account_info(),
+                    withdraw.clock.to_account_info(),
+                    withdraw.stake_history.to_account_info(),
+                    withdraw.st_sol_account_owner.to_account_info(),
+                    withdraw.stake_program.to_account_info(),
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'info> WithdrawFromReserve<'info> {
+    pub fn process(&mut self, program_id: &Pubkey, amount: StLamports) -> Result<()> {
+        require!(amount > StLamports::new(0), LidoError::InvalidAmount);
+        self.config.check_withdrawals_not_paused()?;
+        check_not_cpi(
+            &self.config,
+            program_id,
+            &self.instructions,
+            "WithdrawFromReserve",
+        )?;
+        let is_instant = self
+            .config
+            .is_instant_withdrawal(amount, self.lido.instant_withdrawn_this_epoch);
+        self.lido
+            .check_withdraw_from_reserve(&self.clock, is_instant)?;
+
+        // Instant withdrawals leave the `min_reserve_balance` buffer in place.
+        // Once the wind-down is complete, all SOL in the reserve belongs to
+        // the stSOL holders who withdraw.
+        let sol_to_withdraw = self.lido.exchange_rate.exchange_st_sol(amount)?;
+        let reserve_balance = Lamports::new(self.reserve.lamports());
+        let reserve_available = if is_instant {
+            self.lido.get_reserve_stakeable_balance(
+                &self.rent,
+                reserve_balance,
+                self.config.min_reserve_balance,
+            )?
+        } else {
+            self.lido
+                .get_reserve_available_balance(&self.rent, reserve_balance)?
+        };
+        if sol_to_withdraw > reserve_available {
+            msg!(
+                "The reserve holds {}, which is not enough to withdraw {}.",
+                reserve_available,
+                sol_to_withdraw
+            );
+            return err!(LidoError::InvalidAmount);
+        }
+
+        burn_st_sol_from(
+            self.token_program.to_account_info(),
+            &mut self.st_sol_mint,
+            self.st_sol_account.to_account_info(),
+            self.st_sol_account_owner.to_account_info(),
+            amount,
+        )?;
+
+        transfer_sol_from_reserve(
+            &self.lido,
+            self.system_program.to_account_info(),
+            self.reserve.to_account_info(),
+            self.recipient.to_account_info(),
+            sol_to_withdraw,
+        )?;
+
+        if is_instant {
+            self.lido.instant_withdrawn_this_epoch =
+                (self.lido.instant_withdrawn_this_epoch + amount)?;
+        }
+        self.lido
+            .metrics
+            .observe_withdrawal(amount, sol_to_withdraw);
+
+        emit!(WithdrawnFromReserve {
+            nonce: self.lido.next_operation_nonce()?,
+            owner: self.st_sol_account_owner.key(),
+            recipient: self.recipient.key(),
+            amount_st_sol: amount.amount,
+            amount_sol: sol_to_withdraw.amount,
+        });
+
+        Ok(())
+    }
+}
+
+impl<'info> PreviewRewardSplit<'info> {
+    pub fn process(&self, amount: Lamports) -> Result<()> {
+        let fees = self.lido.split_validator_rewards(amount)?;
+        let data = fees
+            .try_to_vec()
+            .map_err(|_| error!(ErrorCode::AccountDidNotSerialize))?;
+        set_return_data(&data);
+        Ok(())
+    }
+}
+
+impl<'info> GetValidatorStake<'info> {
+    pub fn process(&self, validator_vote: &Pubkey) -> Result<()> {
+        let validator = self.lido.validators.get(validator_vote)?;
+        let data = validator
+            .entry
+            .get_stake()?
+            .try_to_vec()
+            .map_err(|_| error!(ErrorCode::AccountDidNotSerialize))?;
+        set_return_data(&data);
+        Ok(())
+    }
+}
+
+impl<'info> CollectValidatorFee<'info> {
+    pub fn process(&mut self, program_id: &Pubkey) -> Result<()> {
+        let lido_address = self.lido.key();
+        let validator_vote = self.validator_vote.key();
+        check_can_collect_fee(&self.lido, &validator_vote, &self.clock)?;
+
+        let seeds = self
+            .lido
+            .rewards_withdraw_authority_seeds(&lido_address, &validator_vote)?;
+        let authority_signature_seeds: Vec<&[u8]> = seeds.iter().map(Vec::as_slice).collect();
+        let rewards_withdraw_authority =
+            Pubkey::create_program_address(&authority_signature_seeds, program_id)
+                .map_err(|_| error!(LidoError::InvalidVoteAccount))?;
+        if self.rewards_withdraw_authority.key() != rewards_withdraw_authority {
+            msg!(
+                "Rewards withdraw authority should be {}, but {} was provided.",
+                rewards_withdraw_authority,
+                self.rewards_withdraw_authority.key()
+            );
+            return err!(LidoError::InvalidVoteAccount);
+        }
+
+        let vote_account = self.validator_vote.to_account_info();
+        let rewards =
+            get_vote_account_rewards(&self.rent, vote_account.lamports(), vote_account.data_len());
+        self.check_rewards_plausible(&validator_vote, rewards)?;
+
+        if rewards > Lamports::new(0) {
+            invoke_signed(
+                &vote_withdraw_instruction(
+                    &validator_vote,
+                    self.rewards_withdraw_authority.key,
+                    rewards.amount,
+                    self.reserve.key,
+                ),
+                &[
+                    vote_account,
+                    self.reserve.to_account_info(),
+                    self.rewards_withdraw_authority.to_account_info(),
+                    self.vote_program.to_account_info(),
+                ],
+                &[&authority_signature_seeds[..]],
+            )?;
+
+            self.distribute_fees(&validator_vote, rewards)?;
+        }
+
+        let validator = self.lido.validators.get_mut(&validator_vote)?;
+        validator.entry.workstate.fee_collected_epoch = self.clock.epoch;
+        if self.lido.is_fee_collection_complete(self.clock.epoch) {
+            self.emit_epoch_rewards()?;
+        }
+
+        // Fees are collected once per validator per epoch, and so is the tip.
+        pay_crank_tip(
+            &mut self.lido,
+            &self.config,
+            &self.rent,
+            self.system_program.to_account_info(),
+            self.reserve.to_account_info(),
+            self.tip_recipient.to_account_info(),
+            "CollectValidatorFee",
+        )
+    }
+
+    /// Emit `EpochRewardsCollected` with the rewards and fees of the epoch so far.
+    fn emit_epoch_rewards(&mut self) -> Result<()> {
+        let this_epoch = self.lido.metrics.this_epoch.clone();
+        let exchange_rate = self.lido.exchange_rate.clone();
+        let sol_balance_after = (exchange_rate.sol_balance + this_epoch.st_sol_appreciation_sol)?;
+        emit!(EpochRewardsCollected {
+            nonce: self.lido.next_operation_nonce()?,
+            epoch: exchange_rate.computed_in_epoch,
+            rewards_sol: this_epoch.rewards_sol.amount,
+            fee_treasury_sol: this_epoch.fee_treasury_sol.amount,
+            fee_validation_sol: this_epoch.fee_validation_sol.amount,
+            fee_developer_sol: this_epoch.fee_developer_sol.amount,
+            st_sol_appreciation_sol: this_epoch.st_sol_appreciation_sol.amount,
+            st_sol_supply: exchange_rate.st_sol_supply.amount,
+            sol_balance: exchange_rate.sol_balance.amount,
+            sol_balance_after: sol_balance_after.amount,
+        });
+        Ok(())
+    }
+
+    /// Emit `ValidatorRewardsAnomaly` if `rewards` do not match the credits earned since the last collection.
+    ///
+    /// This reads the full vote state. If that fails, for example for an older
+    /// layout, the check is skipped, fees are collected either way.
+    fn check_rewards_plausible(
+        &mut self,
+        validator_vote: &Pubkey,
+        rewards: Lamports,
+    ) -> Result<()> {
+        let vote_state = {
+            let vote_account = self.validator_vote.to_account_info();
+            let data = vote_account.try_borrow_data()?;
+            PartialVoteState::try_deserialize_with_epoch_credits(&data)
+        };
+        let validator = self.lido.validators.get(validator_vote)?;
+        let since_epoch = validator.entry.workstate.fee_collected_epoch;
+        let credits_earned = match vote_state
+            .ok()
+            .and_then(|vote_state| vote_state.credits_earned(since_epoch, self.clock.epoch))
+        {
+            Some(credits_earned) => credits_earned,
+            None => {
+                msg!(
+                    "Could not read the epoch credits of {}, skipping the check.",
+                    validator_vote
+                );
+                return Ok(());
+            }
+        };
+
+        let stake = validator.entry.stake_accounts_balance;
+        if !is_reward_plausible(rewards, credits_earned, stake) {
+            msg!(
+                "Validator {} has {} of rewards for {} credits since epoch {}.",
+                validator_vote,
+                rewards,
+                credits_earned,
+                since_epoch
+            );
+            emit!(ValidatorRewardsAnomaly {
+                nonce: self.lido.next_operation_nonce()?,
+                validator_vote: *validator_vote,
+                rewards: rewards.amount,
+                credits_earned,
+                since_epoch,
+            });
+        }
+        Ok(())
+    }
+
+    /// Pay the fees over `rewards`, which were just added to the reserve.
+    ///
+    /// Fees in SOL are paid from the reserve right away, so they never count
+    /// towards the SOL of stSOL holders. Fees in stSOL stay in the reserve,
+    /// as if the recipient deposited them.
+    fn distribute_fees(&mut self, validator_vote: &Pubkey, rewards: Lamports) -> Result<()> {
+        let fees = self.lido.credit_validator_fees(validator_vote, rewards)?;
+
+        let recipients = [
+            (
+                self.treasury_account.to_account_info(),
+                fees.treasury_st_sol,
+                fees.treasury_sol,
+            ),
+            (
+                self.developer_account.to_account_info(),
+                fees.developer_st_sol,
+                fees.developer_sol,
+            ),
+        ];
+        for (recipient, st_sol_amount, sol_amount) in recipients {
+            if st_sol_amount > StLamports::new(0) {
+                mint_st_sol_to(
+                    &self.lido,
+                    self.token_program.to_account_info(),
+                    self.st_sol_mint.to_account_info(),
+                    self.mint_authority.to_account_info(),
+                    recipient.clone(),
+                    st_sol_amount,
+                )?;
+            }
+            if sol_amount > Lamports::new(0) {
+                transfer_sol_from_reserve(
+                    &self.lido,
+                    self.system_program.to_account_info(),
+                    self.reserve.to_account_info(),
+                    recipient,
+                    sol_amount,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'info> MarkDelinquent<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        let last_vote_epoch = self
+            .validator_vote
+            .last_vote_slot
+            .map(|slot| self.epoch_schedule.get_epoch(slot));
+
+        let validator = self.lido.validators.get_mut(&self.validator_vote.key())?;
+        let was_delinquent = validator.entry.delinquent;
+        validator
+            .entry
+            .update_delinquency(last_vote_epoch, self.clock.epoch);
+
+        if validator.entry.delinquent != was_delinquent {
+            msg!(
+                "Validator {} is now {}, its last vote was in epoch {:?}.",
+                validator.pubkey,
+                if validator.entry.delinquent {
+                    "delinquent"
+                } else {
+                    "voting again"
+                },
+                last_vote_epoch
+            );
+        }
+        Ok(())
+    }
+}
+
+impl<'info> DeactivateClosedValidator<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        let vote_account = self.validator_vote.to_account_info();
+        let closed = is_vote_account_closed(
+            vote_account.owner,
+            vote_account.lamports(),
+            &vote_account.try_borrow_data()?,
+        );
+        if !closed {
+            msg!("Vote account {} still exists.", vote_account.key);
+            return err!(LidoError::VoteAccountNotClosed);
+        }
+
+        let validator = self.lido.validators.get_mut(vote_account.key)?;
+        if validator.entry.vote_account_closed {
+            msg!("Validator {} was already deactivated.", validator.pubkey);
+            return Ok(());
+        }
+        validator.entry.mark_vote_account_closed();
+        msg!(
+            "Vote account of validator {} was closed, it is now deactivated.",
+            validator.pubkey
+        );
+
+        emit!(ClosedValidatorDeactivated {
+            nonce: self.lido.next_operation_nonce()?,
+            validator_vote: vote_account.key(),
+        });
+        Ok(())
+    }
+}
+
+/// Mint the unclaimed fee credit of a validator to `recipient`, and reset the credit.
+///
+/// The stSOL supply that we use for the exchange rate already includes the
+/// fee credit, so minting it does not change the exchange rate.
+fn claim_fee_credit<'info>(
+    lido: &mut Box<Account<'info, Lido>>,
+    validator_vote: &Pubkey,
+    token_program: AccountInfo<'info>,
+    st_sol_mint: AccountInfo<'info>,
+    mint_authority: AccountInfo<'info>,
+    recipient: AccountInfo<'info>,
+) -> Result<()> {
+    let fee_credit = lido.validators.get(validator_vote)?.entry.fee_credit;
+    if fee_credit == StLamports::new(0) {
+        msg!("Validator {} has no fee credit to claim.", validator_vote);
+        return Ok(());
+    }
+
+    mint_st_sol_to(
+        lido,
+        token_program,
+        st_sol_mint,
+        mint_authority,
+        recipient,
+        fee_credit,
+    )?;
+
+    let validator = lido.validators.get_mut(validator_vote)?;
+    validator.entry.fee_credit = StLamports::new(0);
+
+    Ok(())
+}
+
+impl<'info> ClaimValidatorFee<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        self.process_if_above(StLamports::new(0))
+    }
+
+    pub fn process_if_above(&mut self, threshold: StLamports) -> Result<()> {
+        let validator_vote = self.validator_vote.key();
+        let validator = self.lido.validators.get(&validator_vote)?;
+        let fee_address = validator.entry.fee_address;
+        if self.validator_fee_st_sol.key() != fee_address {
+            msg!(
+                "Validator fee account should be {}, but {} was provided.",
+                fee_address,
+                self.validator_fee_st_sol.key()
+            );
+            return err!(LidoError::InvalidFeeRecipient);
+        }
+
+        let fee_credit = validator.entry.fee_credit;
+        if fee_credit < threshold {
+            msg!(
+                "Validator {} has a fee credit of {}, below the threshold of {}, nothing to do.",
+                validator_vote,
+                fee_credit,
+                threshold
+            );
+            return Ok(());
+        }
+
+        claim_fee_credit(
+            &mut self.lido,
+            &validator_vote,
+            self.token_program.to_account_info(),
+            self.st_sol_mint.to_account_info(),
+            self.mint_authority.to_account_info(),
+            self.validator_fee_st_sol.to_account_info(),
+        )
+    }
+}
+
+impl<'info> ClaimValidatorFeeTo<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        claim_fee_credit(
+            &mut self.lido,
+            &self.validator_vote.key(),
+            self.token_program.to_account_info(),
             self.st_sol_mint.to_account_info(),
             self.mint_authority.to_account_info(),
             self.recipient.to_account_info(),
-            st_sol_amount
+        )
+    }
+}
+
+impl<'info> ClaimValidatorFeeSol<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        let validator_vote = self.validator_vote.key();
+        let fee_credit = self
+            .lido
+            .validators
+            .get(&validator_vote)?
+            .entry
+            .fee_credit_sol;
+        if fee_credit == Lamports::new(0) {
+            msg!(
+                "Validator {} has no fee credit in SOL to claim.",
+                validator_vote
+            );
+            return Ok(());
+        }
+
+        transfer_sol_from_reserve(
+            &self.lido,
+            self.system_program.to_account_info(),
+            self.reserve.to_account_info(),
+            self.recipient.to_account_info(),
+            fee_credit,
+        )?;
+
+        let validator = self.lido.validators.get_mut(&validator_vote)?;
+        validator.entry.fee_credit_sol = Lamports::new(0);
+
+        Ok(())
+    }
+}
+
+impl<'info> CheckSeedRange<'info> {
+    pub fn process(
+        &self,
+        program_id: &Pubkey,
+        stake_type: StakeType,
+        accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        let validator = self.lido.validators.get(&self.validator_vote.key())?;
+        check_seed_range_accounts(
+            program_id,
+            &self.lido.key(),
+            validator,
+            stake_type,
+            validator.entry.seeds(stake_type),
+            &self.stake_authority.key(),
+            accounts,
+            &self.clock,
+            &self.stake_history,
+        )
+    }
+}
+
+impl<'info> UpdateExchangeRate<'info> {
+    pub fn process(&mut self, expected_epoch: Option<u64>) -> Result<()> {
+        let computed_in_epoch = self.lido.exchange_rate.computed_in_epoch;
+        if let Some(expected_epoch) = expected_epoch {
+            if computed_in_epoch >= expected_epoch {
+                msg!(
+                    "The exchange rate was already updated in epoch {}, nothing to do.",
+                    computed_in_epoch
+                );
+                emit!(MaintenanceSkipped {
+                    nonce: self.lido.next_operation_nonce()?,
+                    instruction: "UpdateExchangeRate".to_string(),
+                    expected: expected_epoch,
+                    actual: computed_in_epoch,
+                });
+                return Ok(());
+            }
+        }
+
+        check_exchange_rate_not_updated(&self.lido, &self.clock)?;
+        self.pay_crank_tip("UpdateExchangeRate")?;
+
+        let reserve_available = self
+            .lido
+            .get_reserve_available_balance(&self.rent, Lamports::new(self.reserve.lamports()))?;
+        let sol_balance = self.lido.get_sol_balance_with_reserve(reserve_available)?;
+        self.commit_exchange_rate(sol_balance, reserve_available)
+    }
+
+    pub fn process_finish(&mut self) -> Result<()> {
+        check_exchange_rate_not_updated(&self.lido, &self.clock)?;
+        self.pay_crank_tip("FinishUpdateExchangeRate")?;
+
+        let reserve_available = self
+            .lido
+            .get_reserve_available_balance(&self.rent, Lamports::new(self.reserve.lamports()))?;
+        let sol_balance = self
+            .lido
+            .finish_exchange_rate_update(self.clock.epoch, reserve_available)?;
+        self.commit_exchange_rate(sol_balance, reserve_available)
+    }
+
+    /// Pay the crank tip before the new exchange rate is computed, so that it accounts for it.
+    ///
+    /// The exchange rate can be updated once per epoch, so this pays once per epoch too.
+    fn pay_crank_tip(&mut self, instruction: &str) -> Result<()> {
+        pay_crank_tip(
+            &mut self.lido,
+            &self.config,
+            &self.rent,
+            self.system_program.to_account_info(),
+            self.reserve.to_account_info(),
+            self.tip_recipient.to_account_info(),
+            instruction,
+        )
+    }
+
+    /// Set the exchange rate for the current epoch, and record it in the history.
+    ///
+    /// `reserve_available` is the part of `sol_balance` that is idle in the reserve.
+    fn commit_exchange_rate(
+        &mut self,
+        sol_balance: Lamports,
+        reserve_available: Lamports,
+    ) -> Result<()> {
+        let exchange_rate =
+            self.lido
+                .compute_exchange_rate(self.clock.epoch, sol_balance, &self.st_sol_mint)?;
+        self.config
+            .check_exchange_rate_change(&self.lido.exchange_rate, &exchange_rate)?;
+        self.lido.set_exchange_rate(exchange_rate);
+        self.lido.metrics.observe_reserve_idle(reserve_available);
+        self.lido.idle_reserve_balance = if self.config.idle_reserve_fee_adjustment {
+            reserve_available
+        } else {
+            Lamports::new(0)
+        };
+        self.exchange_rate_history
+            .record(self.lido.exchange_rate.clone());
+
+        Ok(())
+    }
+}
+
+impl<'info> InitializeExchangeRateHistory<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        self.exchange_rate_history.lido = self.lido.key();
+        Ok(())
+    }
+}
+
+impl<'info> AttestReserves<'info> {
+    pub fn process(&mut self, program_id: &Pubkey, accounts: &[AccountInfo<'info>]) -> Result<()> {
+        let validator_accounts = parse_stake_accounts_for_all_validators(
+            program_id,
+            &self.lido.key(),
+            &self.lido.validators,
+            accounts,
+        )?;
+
+        let mut validators = Vec::with_capacity(validator_accounts.len());
+        for (validator_vote, stake_accounts) in validator_accounts {
+            let balance = stake_accounts
+                .iter()
+                .map(|account| Lamports::new(account.lamports()))
+                .sum::<token::Result<Lamports>>()?;
+            validators.push(ValidatorReserves {
+                validator_vote,
+                balance,
+            });
+        }
+
+        let reserve_balance = Lamports::new(self.reserve.lamports());
+        let st_sol_supply = StLamports::new(self.st_sol_mint.supply);
+        self.reserve_attestation.lido = self.lido.key();
+        self.reserve_attestation
+            .set(&self.clock, reserve_balance, st_sol_supply, validators)?;
+        msg!(
+            "Attested {} in the reserve and stake accounts, backing {}.",
+            self.reserve_attestation.total_sol,
+            st_sol_supply
+        );
+        Ok(())
+    }
+}
+
+impl<'info> PostExchangeRateMessage<'info> {
+    pub fn process(&mut self, program_id: &Pubkey, nonce: u32) -> Result<()> {
+        self.config
+            .check_wormhole_program(self.wormhole_program.key)?;
+        // Only the rate of the current epoch is posted, so that a message
+        // never announces a rate that is already outdated.
+        if self.lido.exchange_rate.computed_in_epoch != self.clock.epoch {
+            msg!(
+                "The exchange rate was last updated in epoch {}, but the current epoch is {}.",
+                self.lido.exchange_rate.computed_in_epoch,
+                self.clock.epoch
+            );
+            return err!(LidoError::ExchangeRateNotUpdatedInThisEpoch);
+        }
+
+        let fee = read_message_fee(&self.wormhole_bridge.try_borrow_data()?)?;
+        if fee > 0 {
+            invoke(
+                &system_instruction::transfer(self.payer.key, self.wormhole_fee_collector.key, fee),
+                &[
+                    self.payer.to_account_info(),
+                    self.wormhole_fee_collector.to_account_info(),
+                    self.system_program.to_account_info(),
+                ],
+            )?;
+        }
+
+        let lido_address = self.lido.key();
+        let payload = exchange_rate_payload(&lido_address, &self.lido.exchange_rate);
+        let accounts = PostMessageAccounts {
+            bridge: self.wormhole_bridge.key(),
+            message: self.wormhole_message.key(),
+            emitter: self.wormhole_emitter.key(),
+            sequence: self.wormhole_sequence.key(),
+            payer: self.payer.key(),
+            fee_collector: self.wormhole_fee_collector.key(),
+        };
+        let (_, bump_seed) = pda::find_wormhole_emitter_address(program_id, &lido_address);
+        let emitter_signature_seeds = [
+            lido_address.as_ref(),
+            WORMHOLE_EMITTER.as_ref(),
+            &[bump_seed],
+        ];
+        invoke_signed(
+            &post_message_instruction(self.wormhole_program.key, &accounts, nonce, &payload),
+            &[
+                self.wormhole_bridge.to_account_info(),
+                self.wormhole_message.to_account_info(),
+                self.wormhole_emitter.to_account_info(),
+                self.wormhole_sequence.to_account_info(),
+                self.payer.to_account_info(),
+                self.wormhole_fee_collector.to_account_info(),
+                self.clock.to_account_info(),
+                self.rent.to_account_info(),
+                self.system_program.to_account_info(),
+                self.wormhole_program.to_account_info(),
+            ],
+            &[&emitter_signature_seeds[..]],
         )?;
+        msg!(
+            "Posted the exchange rate of epoch {} to Wormhole with nonce {}.",
+            self.clock.epoch,
+            nonce
+        );
+        Ok(())
+    }
+}
 
-        // TODO: emit event about deposit
+impl<'info> BeginUpdateExchangeRate<'info> {
+    pub fn process(&mut self, max_validators: u32) -> Result<()> {
+        check_exchange_rate_not_updated(&self.lido, &self.clock)?;
 
-        self.lido.metrics.observe_deposit(amount)?;
+        let num_remaining = self
+            .lido
+            .accumulate_exchange_rate_update(self.clock.epoch, max_validators)?;
+        msg!(
+            "{} validators remaining before the exchange rate update can be finished.",
+            num_remaining
+        );
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_initialize_bumps_derive_the_authorities() {
+        use crate::{MINT_AUTHORITY, REWARDS_WITHDRAW_AUTHORITY};
+
+        let lido = Pubkey::new_unique();
+        let bumps = InitializeBumps::derive(&crate::ID, &lido);
+        let address = |seed: &[u8], bump: u8| {
+            Pubkey::create_program_address(&[lido.as_ref(), seed, &[bump]], &crate::ID).unwrap()
+        };
+        assert_eq!(
+            address(&RESERVE_ACCOUNT, bumps.reserve),
+            pda::find_reserve_address(&crate::ID, &lido).0
+        );
+        assert_eq!(
+            address(&MINT_AUTHORITY, bumps.mint_authority),
+            pda::find_mint_authority(&crate::ID, &lido).0
+        );
+        assert_eq!(
+            address(&STAKE_AUTHORITY, bumps.stake_authority),
+            pda::find_stake_authority(&crate::ID, &lido).0
+        );
+        assert_eq!(
+            address(
+                &REWARDS_WITHDRAW_AUTHORITY,
+                bumps.rewards_withdraw_authority
+            ),
+            pda::find_rewards_withdraw_authority(&crate::ID, &lido).0
+        );
+    }
+}
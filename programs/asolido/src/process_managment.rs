@@ -1,16 +1,135 @@
-use crate::state::Validator;
-use crate::{AddMaintainer, AddValidator, DeactivateValidator, RemoveMaintainer, RemoveValidator};
+use crate::config::{Config, ConfigUpdate};
+use crate::deposit_hook::HookAccount;
+use crate::error::LidoError;
+use crate::events::{
+    ConfigUpdated, DelegationAuthorityChanged, DepositHookSet, FeeRecipientsChanged,
+    MaintainerAdded, MaintainerRemoved, MaxCommissionChanged, MintAuthorityTransferScheduled,
+    MintAuthorityTransferredOut, RewardDistributionChanged, RewardsWithdrawAuthorityRotated,
+    SeedRangeRepaired, StakePoolStakeImported, TreasuryWithdrawn,
+    ValidatorFeeAccountOverrideScheduled, ValidatorTargetWeightChanged, WindDownStarted,
+    WormholeProgramChanged,
+};
+use crate::logic::{
+    check_seed_range_accounts, mint_st_sol_to, vote_authorize_withdrawer_instruction,
+};
+use crate::maintainers::Maintainers;
+use crate::pda;
+use crate::remaining_accounts::parse_stake_accounts_for_validator;
+use crate::solido_v1::{self, LidoV1};
+use crate::stake_account::StakeAccount;
+use crate::state::{
+    FeeDenomination, FeeRecipients, Lido, RewardDistribution, SeedRange, StakeType, Validator,
+};
+use crate::token::{self, Lamports, StLamports};
+use crate::vote_state::PartialVoteState;
+use crate::{
+    AcknowledgeLoss, AddMaintainer, AddValidator, AddValidatorAndAuthorizeWithdrawer,
+    ApplyMaxCommissionChange, ApplyValidatorFeeAccountOverride, ChangeFeeRecipients,
+    ChangeRewardDistribution, ChangeValidatorStakePaused, DeactivateValidator, ImportFromStakePool,
+    InitializeProtocolTreasury, InitializeRegistry, MigrateFromSolidoV1, MigrateStakeFromSolidoV1,
+    OverrideValidatorFeeAccount, RegisterInstance, RemoveMaintainer, RemoveValidator,
+    RepairSeedRange, RotateRewardsWithdrawAuthority, ScheduleMaxCommissionChange,
+    ScheduleMintAuthorityTransfer, ScheduleTreasuryWithdrawal, SetDelegationAuthority,
+    SetDepositHook, SetMaintainers, SetValidatorInfo, SetValidatorTargetWeight, SetWormholeProgram,
+    StartWindDown, TransferMintAuthorityOut, UnregisterInstance, UpdateConfig, WithdrawTreasury,
+    MAXIMUM_SET_MAINTAINERS, MINT_AUTHORITY, PROTOCOL_TREASURY_AUTHORITY, RESERVE_ACCOUNT,
+    STAKE_AUTHORITY, VALIDATOR_STAKE_ACCOUNT, VALIDATOR_UNSTAKE_ACCOUNT,
+};
 use anchor_lang::prelude::*;
+use anchor_spl::token::TokenAccount;
+use solana_program::program::{invoke, invoke_signed};
+use solana_program::stake::state::StakeAuthorize;
+use solana_program::system_instruction;
+
+/// Add `validator` with the given vote account to the validator set, as added in the current epoch.
+fn add_validator(
+    lido: &mut Lido,
+    config: &Config,
+    validator_vote: Pubkey,
+    vote_state: &PartialVoteState,
+    mut validator: Validator,
+) -> Result<()> {
+    lido.check_not_winding_down()?;
+    config.check_commission(vote_state.commission)?;
+    validator.added_epoch = Clock::get()?.epoch;
+    lido.validators
+        .add(validator_vote, validator)
+        .map_err(|err| error!(err))
+}
 
 impl<'info> AddValidator<'info> {
+    pub fn process(&mut self, program_id: &Pubkey) -> Result<()> {
+        let mut validator = Validator::new(self.validator_fee_st_sol.key());
+
+        // The owner and the size of the vote account were checked by the
+        // accounts constraints, so it holds a vote state.
+        let vote_state =
+            PartialVoteState::try_deserialize(&mut &self.validator_vote.try_borrow_data()?[..])?;
+        require!(vote_state.version == 1, LidoError::InvalidVoteAccount);
+
+        // The vote account is withdrawn from by either the instance-wide
+        // rewards withdraw authority, or by one derived for this validator.
+        let withdrawer = vote_state.authorized_withdrawer;
+        if withdrawer != self.rewards_withdraw_authority.key() {
+            let (own_authority, bump_seed) = pda::find_validator_rewards_withdraw_authority(
+                program_id,
+                &self.lido.key(),
+                &self.validator_vote.key(),
+            );
+            if withdrawer != own_authority {
+                msg!(
+                    "The withdraw authority of the vote account is {}, expected {} or {}.",
+                    withdrawer,
+                    self.rewards_withdraw_authority.key(),
+                    own_authority
+                );
+                return err!(LidoError::InvalidVoteAccount);
+            }
+            validator.has_own_rewards_withdraw_authority = true;
+            validator.rewards_withdraw_authority_bump_seed = bump_seed;
+        }
+
+        add_validator(
+            &mut self.lido,
+            &self.config,
+            self.validator_vote.key(),
+            &vote_state,
+            validator,
+        )
+    }
+}
+
+impl<'info> AddValidatorAndAuthorizeWithdrawer<'info> {
     pub fn process(&mut self) -> Result<()> {
-        let lido = &mut self.lido;
-        lido.validators
-            .add(
-                self.validator_vote.key(),
-                Validator::new(self.validator_fee_st_sol.key()),
-            )
-            .map_err(|err| error!(err))
+        add_validator(
+            &mut self.lido,
+            &self.config,
+            self.validator_vote.key(),
+            &self.validator_vote,
+            Validator::new(self.validator_fee_st_sol.key()),
+        )?;
+
+        // The current withdraw authority signed this instruction, and the
+        // signature carries over to the CPI.
+        invoke(
+            &vote_authorize_withdrawer_instruction(
+                &self.validator_vote.key(),
+                self.current_withdrawer.key,
+                self.rewards_withdraw_authority.key,
+            ),
+            &[
+                self.validator_vote.to_account_info(),
+                self.clock.to_account_info(),
+                self.current_withdrawer.to_account_info(),
+                self.vote_program.to_account_info(),
+            ],
+        )?;
+        msg!(
+            "Withdraw authority of vote account {} is now {}.",
+            self.validator_vote.key(),
+            self.rewards_withdraw_authority.key()
+        );
+        Ok(())
     }
 }
 
@@ -21,11 +140,164 @@ impl<'info> RemoveValidator<'info> {
     /// and it is callable by anybody. Initiation of the removal (`DeactivateValidator`)
     /// is restricted to the manager, but once a validator is inactive, and there is
     /// no more stake delegated to it, removing it from the list can be done by anybody.
-    pub fn process(&mut self) -> Result<()> {
-        let removed_validator = self.lido.validators.remove(&self.validator_vote.key())?;
+    ///
+    /// `accounts` are stake and unstake accounts of the validator past the end
+    /// of its seed ranges that still exist, for example because somebody sent
+    /// SOL to their address. They must be passed in seed order, the stake
+    /// accounts and unstake accounts in any interleaving, and they must hold
+    /// no stake. Their balance moves to the reserve, which closes them, so the
+    /// validator leaves no accounts behind.
+    pub fn process(&mut self, program_id: &Pubkey, accounts: &[AccountInfo<'info>]) -> Result<()> {
+        let lido_address = self.lido.key();
+        let validator_vote = self.validator_vote.key();
+        let removed_validator = self.lido.validators.remove(&validator_vote)?;
         removed_validator.check_can_be_removed()?;
+
+        let find_address = |stake_type: StakeType, seed: u64| match stake_type {
+            StakeType::Stake => {
+                pda::find_stake_account(program_id, &lido_address, &validator_vote, seed)
+            }
+            StakeType::Unstake => {
+                pda::find_unstake_account(program_id, &lido_address, &validator_vote, seed)
+            }
+        };
+        let mut next_seeds = [
+            (StakeType::Stake, removed_validator.stake_seeds.end),
+            (StakeType::Unstake, removed_validator.unstake_seeds.end),
+        ];
+        let mut reclaimed = Lamports::new(0);
+        for account in accounts {
+            let mut found = None;
+            for (stake_type, seed) in next_seeds.iter_mut() {
+                let (address, bump_seed) = find_address(*stake_type, *seed);
+                if account.key == &address {
+                    found = Some((*stake_type, *seed, bump_seed));
+                    *seed += 1;
+                    break;
+                }
+            }
+            let (stake_type, seed, bump_seed) = match found {
+                Some(found) => found,
+                None => {
+                    msg!(
+                        "Account {} is not the next stake or unstake account of validator {}.",
+                        account.key,
+                        validator_vote
+                    );
+                    return err!(LidoError::InvalidStakeAccount);
+                }
+            };
+            let balance = self.close_residual_account(account, stake_type, seed, bump_seed)?;
+            reclaimed = (reclaimed + balance)?;
+        }
+
+        // Like the rent of stake accounts that `WithdrawInactiveStake` closes,
+        // this SOL was not tracked before, so we count it as a donation.
+        if reclaimed > Lamports::new(0) {
+            msg!("Reclaimed {} from leftover stake accounts.", reclaimed);
+            self.lido.metrics.observe_donation(reclaimed);
+        }
         Ok(())
     }
+
+    /// Move the balance of a leftover stake or unstake account into the reserve, which closes it.
+    ///
+    /// Returns the balance that was moved.
+    fn close_residual_account(
+        &self,
+        account: &AccountInfo<'info>,
+        stake_type: StakeType,
+        seed: u64,
+        bump_seed: u8,
+    ) -> Result<Lamports> {
+        let balance = Lamports::new(account.lamports());
+        if balance == Lamports::new(0) {
+            return Ok(balance);
+        }
+
+        let lido_address = self.lido.key();
+        let validator_vote = self.validator_vote.key();
+        if account.owner == &solana_program::stake::program::ID {
+            let stake_account =
+                StakeAccount::from_account_info(account, &self.clock, &self.stake_history)?;
+            stake_account.check_owned_by(&validator_vote, &self.stake_authority.key())?;
+            if !stake_account.is_inactive() || balance > stake_account.rent_exempt_reserve() {
+                msg!(
+                    "{:?} account {} holds {}, more than its rent-exempt reserve of {}.",
+                    stake_type,
+                    account.key,
+                    balance,
+                    stake_account.rent_exempt_reserve()
+                );
+                return err!(LidoError::WrongStakeState);
+            }
+
+            let authority_signature_seeds = [
+                lido_address.as_ref(),
+                STAKE_AUTHORITY.as_ref(),
+                &[self.lido.stake_authority_bump_seed],
+            ];
+            invoke_signed(
+                &solana_program::stake::instruction::withdraw(
+                    account.key,
+                    self.stake_authority.key,
+                    self.reserve.key,
+                    balance.amount,
+                    None,
+                ),
+                &[
+                    account.clone(),
+                    self.reserve.to_account_info(),
+                    self.clock.to_account_info(),
+                    self.stake_history.to_account_info(),
+                    self.stake_authority.to_account_info(),
+                    self.stake_program.to_account_info(),
+                ],
+                &[&authority_signature_seeds[..]],
+            )?;
+        } else if account.owner == &System::id() && account.data_is_empty() {
+            // SOL that was sent to the address before a stake account was
+            // created there. The program signs for the address itself.
+            let authority = match stake_type {
+                StakeType::Stake => VALIDATOR_STAKE_ACCOUNT.as_ref(),
+                StakeType::Unstake => VALIDATOR_UNSTAKE_ACCOUNT.as_ref(),
+            };
+            let seed_bytes = seed.to_le_bytes();
+            let account_signature_seeds = [
+                lido_address.as_ref(),
+                validator_vote.as_ref(),
+                authority,
+                &seed_bytes[..],
+                &[bump_seed],
+            ];
+            invoke_signed(
+                &system_instruction::transfer(account.key, self.reserve.key, balance.amount),
+                &[
+                    account.clone(),
+                    self.reserve.to_account_info(),
+                    self.system_program.to_account_info(),
+                ],
+                &[&account_signature_seeds[..]],
+            )?;
+        } else {
+            msg!(
+                "{:?} account {} is owned by {}, it cannot be closed.",
+                stake_type,
+                account.key,
+                account.owner
+            );
+            return err!(LidoError::WrongStakeState);
+        }
+
+        msg!(
+            "Closed {:?} account {} with seed {}, which held {}.",
+            stake_type,
+            account.key,
+            seed,
+            balance
+        );
+        Ok(balance)
+    }
 }
 
 /// Set the `active` flag to false for a given validator.
@@ -42,14 +314,918 @@ impl<'info> DeactivateValidator<'info> {
     }
 }
 
+impl<'info> OverrideValidatorFeeAccount<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        let validator_vote = self.validator_vote.key();
+        let validator = self.lido.validators.get_mut(&validator_vote)?;
+        validator
+            .entry
+            .schedule_fee_address_override(self.new_fee_address.key(), self.clock.epoch);
+        msg!(
+            "Fee address of validator {} will change from {} to {} in epoch {}.",
+            validator.pubkey,
+            validator.entry.fee_address,
+            validator.entry.fee_address_override,
+            validator.entry.fee_address_override_epoch
+        );
+        let effective_epoch = validator.entry.fee_address_override_epoch;
+        emit!(ValidatorFeeAccountOverrideScheduled {
+            nonce: self.lido.next_operation_nonce()?,
+            validator_vote,
+            fee_address: self.new_fee_address.key(),
+            effective_epoch,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> ApplyValidatorFeeAccountOverride<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        let validator = self.lido.validators.get_mut(&self.validator_vote.key())?;
+        validator.entry.apply_fee_address_override(self.clock.epoch)
+    }
+}
+
+impl<'info> ScheduleMaxCommissionChange<'info> {
+    pub fn process(&mut self, max_commission: u8) -> Result<()> {
+        self.config
+            .schedule_max_commission_change(max_commission, self.clock.epoch)?;
+        msg!(
+            "The max commission can be changed to {}% from epoch {}.",
+            self.config.max_commission_scheduled,
+            self.config.max_commission_change_epoch
+        );
+        Ok(())
+    }
+}
+
+impl<'info> ApplyMaxCommissionChange<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        self.config.apply_max_commission_change(self.clock.epoch)?;
+        emit!(MaxCommissionChanged {
+            nonce: self.lido.next_operation_nonce()?,
+            max_commission: self.config.max_commission,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> ScheduleMintAuthorityTransfer<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        self.lido
+            .schedule_mint_authority_transfer(self.new_mint_authority.key(), self.clock.epoch)?;
+        msg!(
+            "The stSOL mint authority can be transferred to {} from epoch {}.",
+            self.lido.mint_authority_transfer_to,
+            self.lido.mint_authority_transfer_epoch
+        );
+        emit!(MintAuthorityTransferScheduled {
+            nonce: self.lido.next_operation_nonce()?,
+            new_mint_authority: self.lido.mint_authority_transfer_to,
+            effective_epoch: self.lido.mint_authority_transfer_epoch,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> TransferMintAuthorityOut<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        let new_mint_authority = self
+            .lido
+            .complete_mint_authority_transfer(self.clock.epoch)?;
+        require!(
+            self.new_mint_authority.key() == new_mint_authority,
+            LidoError::InvalidMintAuthorityTransfer
+        );
+
+        let lido_key = self.lido.key();
+        let authority_signature_seeds = [
+            lido_key.as_ref(),
+            MINT_AUTHORITY.as_ref(),
+            &[self.lido.mint_authority_bump_seed],
+        ];
+        let signers = [&authority_signature_seeds[..]];
+        let cpi_accounts = anchor_spl::token::SetAuthority {
+            current_authority: self.mint_authority.to_account_info(),
+            account_or_mint: self.st_sol_mint.to_account_info(),
+        };
+        let cpi_context = CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+            &signers,
+        );
+        anchor_spl::token::set_authority(
+            cpi_context,
+            spl_token::instruction::AuthorityType::MintTokens,
+            Some(new_mint_authority),
+        )?;
+
+        emit!(MintAuthorityTransferredOut {
+            nonce: self.lido.next_operation_nonce()?,
+            new_mint_authority,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> InitializeProtocolTreasury<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        let treasury_account = self.protocol_treasury.key();
+        let developer_account = self.lido.fee_recipients.developer_account;
+        let developer_fee_denomination = self.lido.fee_recipients.developer_fee_denomination;
+        self.lido.fee_recipients = FeeRecipients {
+            treasury_account,
+            developer_account,
+            treasury_fee_denomination: FeeDenomination::StSol,
+            developer_fee_denomination,
+        };
+        emit!(FeeRecipientsChanged {
+            nonce: self.lido.next_operation_nonce()?,
+            treasury_account,
+            developer_account,
+            treasury_fee_denomination: FeeDenomination::StSol,
+            developer_fee_denomination,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> ScheduleTreasuryWithdrawal<'info> {
+    pub fn process(&mut self, amount: StLamports) -> Result<()> {
+        self.config
+            .schedule_treasury_withdrawal(self.recipient.key(), amount, self.clock.epoch)?;
+        msg!(
+            "{} can be withdrawn from the protocol treasury to {} from epoch {}.",
+            amount,
+            self.config.treasury_withdrawal_recipient,
+            self.config.treasury_withdrawal_epoch
+        );
+        Ok(())
+    }
+}
+
+impl<'info> WithdrawTreasury<'info> {
+    pub fn process(&mut self, program_id: &Pubkey) -> Result<()> {
+        let amount = self
+            .config
+            .complete_treasury_withdrawal(&self.recipient.key(), self.clock.epoch)?;
+
+        let lido_key = self.lido.key();
+        let (_, bump_seed) = pda::find_protocol_treasury_authority(program_id, &lido_key);
+        let authority_signature_seeds = [
+            lido_key.as_ref(),
+            PROTOCOL_TREASURY_AUTHORITY.as_ref(),
+            &[bump_seed],
+        ];
+        let signers = [&authority_signature_seeds[..]];
+        let cpi_accounts = anchor_spl::token::Transfer {
+            from: self.protocol_treasury.to_account_info(),
+            to: self.recipient.to_account_info(),
+            authority: self.protocol_treasury_authority.to_account_info(),
+        };
+        let cpi_context = CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+            &signers,
+        );
+        anchor_spl::token::transfer(cpi_context, amount.amount)?;
+
+        emit!(TreasuryWithdrawn {
+            nonce: self.lido.next_operation_nonce()?,
+            recipient: self.recipient.key(),
+            amount_st_sol: amount.amount,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> StartWindDown<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        self.lido.start_wind_down()?;
+        msg!(
+            "Winding down, {} validators were deactivated.",
+            self.lido.validators.len()
+        );
+        emit!(WindDownStarted {
+            nonce: self.lido.next_operation_nonce()?,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> ChangeValidatorStakePaused<'info> {
+    pub fn process(&mut self, paused: bool) -> Result<()> {
+        self.lido.check_manager_or_maintainer(&self.signer.key())?;
+        let validator = self.lido.validators.get_mut(&self.validator_vote.key())?;
+        validator.entry.stake_paused = paused;
+        Ok(())
+    }
+}
+
+impl<'info> SetValidatorTargetWeight<'info> {
+    pub fn process(&mut self, target_weight: u32) -> Result<()> {
+        self.lido
+            .check_manager_or_delegation_authority(&self.signer.key())?;
+        let validator_vote = self.validator_vote.key();
+        let validator = self.lido.validators.get_mut(&validator_vote)?;
+        validator.entry.target_weight = target_weight;
+        emit!(ValidatorTargetWeightChanged {
+            nonce: self.lido.next_operation_nonce()?,
+            validator_vote,
+            target_weight,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> SetDelegationAuthority<'info> {
+    pub fn process(&mut self, new_delegation_authority: Pubkey) -> Result<()> {
+        self.lido
+            .check_manager_or_delegation_authority(&self.signer.key())?;
+        self.lido.delegation_authority = new_delegation_authority;
+        emit!(DelegationAuthorityChanged {
+            nonce: self.lido.next_operation_nonce()?,
+            delegation_authority: new_delegation_authority,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> RotateRewardsWithdrawAuthority<'info> {
+    pub fn process(&mut self, program_id: &Pubkey) -> Result<()> {
+        let lido_address = self.lido.key();
+        let validator_vote = self.validator_vote.key();
+        let validator = self.lido.validators.get(&validator_vote)?;
+        if validator.entry.has_own_rewards_withdraw_authority {
+            msg!(
+                "Validator {} already has its own rewards withdraw authority.",
+                validator_vote
+            );
+            return err!(LidoError::InvalidRewardsWithdrawAuthority);
+        }
+        let (_, bump_seed) = pda::find_validator_rewards_withdraw_authority(
+            program_id,
+            &lido_address,
+            &validator_vote,
+        );
+
+        // Sign with the authority that the vote account has now.
+        let seeds = self
+            .lido
+            .rewards_withdraw_authority_seeds(&lido_address, &validator_vote)?;
+        let authority_signature_seeds: Vec<&[u8]> = seeds.iter().map(Vec::as_slice).collect();
+        let rewards_withdraw_authority =
+            Pubkey::create_program_address(&authority_signature_seeds, program_id)
+                .map_err(|_| error!(LidoError::InvalidRewardsWithdrawAuthority))?;
+        if self.rewards_withdraw_authority.key() != rewards_withdraw_authority {
+            msg!(
+                "Rewards withdraw authority should be {}, but {} was provided.",
+                rewards_withdraw_authority,
+                self.rewards_withdraw_authority.key()
+            );
+            return err!(LidoError::InvalidRewardsWithdrawAuthority);
+        }
+
+        invoke_signed(
+            &vote_authorize_withdrawer_instruction(
+                &validator_vote,
+                self.rewards_withdraw_authority.key,
+                self.new_rewards_withdraw_authority.key,
+            ),
+            &[
+                self.validator_vote.to_account_info(),
+                self.clock.to_account_info(),
+                self.rewards_withdraw_authority.to_account_info(),
+                self.vote_program.to_account_info(),
+            ],
+            &[&authority_signature_seeds[..]],
+        )?;
+
+        let validator = self.lido.validators.get_mut(&validator_vote)?;
+        validator.entry.has_own_rewards_withdraw_authority = true;
+        validator.entry.rewards_withdraw_authority_bump_seed = bump_seed;
+
+        emit!(RewardsWithdrawAuthorityRotated {
+            nonce: self.lido.next_operation_nonce()?,
+            validator_vote,
+            rewards_withdraw_authority: self.new_rewards_withdraw_authority.key(),
+        });
+        Ok(())
+    }
+}
+
+impl<'info> SetValidatorInfo<'info> {
+    pub fn process(&mut self, name: String, metadata_uri: String, contact: String) -> Result<()> {
+        // The validator must be part of the instance, so the account is not
+        // created for arbitrary vote accounts.
+        self.lido.validators.get(&self.validator_vote.key())?;
+
+        let signer = self.signer.key();
+        if signer != self.validator_vote.node_pubkey && signer != self.lido.manager {
+            msg!(
+                "{} is neither the validator identity {} nor the manager.",
+                signer,
+                self.validator_vote.node_pubkey
+            );
+            return err!(LidoError::InvalidValidatorInfoAuthority);
+        }
+
+        self.validator_info.lido = self.lido.key();
+        self.validator_info.validator_vote = self.validator_vote.key();
+        self.validator_info.set(name, metadata_uri, contact)
+    }
+}
+
+impl<'info> AcknowledgeLoss<'info> {
+    /// Write down the tracked balance of a validator's stake accounts to the observed balance.
+    ///
+    /// The loss is borne by all stSOL holders: we lower the SOL balance of the
+    /// exchange rate right away, rather than waiting for the next update, so
+    /// nobody can withdraw at a rate that still includes the lost SOL.
+    pub fn process(&mut self, program_id: &Pubkey, accounts: &[AccountInfo<'info>]) -> Result<()> {
+        let lido_address = self.lido.key();
+        let validator_vote = self.validator_vote.key();
+        let validator = self.lido.validators.get(&validator_vote)?;
+        let (stake_accounts, unstake_accounts) =
+            parse_stake_accounts_for_validator(program_id, &lido_address, validator, accounts)?;
+
+        let stake_observed: Lamports = stake_accounts
+            .iter()
+            .map(|account| Lamports::new(account.lamports()))
+            .sum::<token::Result<Lamports>>()?;
+        let unstake_observed: Lamports = unstake_accounts
+            .iter()
+            .map(|account| Lamports::new(account.lamports()))
+            .sum::<token::Result<Lamports>>()?;
+
+        let loss = self.lido.acknowledge_validator_loss(
+            &validator_vote,
+            stake_observed,
+            unstake_observed,
+        )?;
+        msg!(
+            "Acknowledging a loss of {} for validator {}.",
+            loss,
+            validator_vote
+        );
+
+        Ok(())
+    }
+}
+
+impl<'info> ChangeRewardDistribution<'info> {
+    pub fn process(&mut self, new_reward_distribution: RewardDistribution) -> Result<()> {
+        new_reward_distribution.check_valid()?;
+        emit!(RewardDistributionChanged {
+            nonce: self.lido.next_operation_nonce()?,
+            treasury_fee: new_reward_distribution.treasury_fee,
+            validation_fee: new_reward_distribution.validation_fee,
+            developer_fee: new_reward_distribution.developer_fee,
+            st_sol_appreciation: new_reward_distribution.st_sol_appreciation,
+        });
+        self.lido.reward_distribution = new_reward_distribution;
+        Ok(())
+    }
+}
+
+/// Check that `account` can receive fees in `denomination`.
+fn check_fee_recipient(
+    lido: &Lido,
+    account: &AccountInfo,
+    denomination: FeeDenomination,
+) -> Result<()> {
+    let is_valid = match denomination {
+        FeeDenomination::StSol => Account::<TokenAccount>::try_from(account)
+            .map_or(false, |token_account| {
+                token_account.mint == lido.st_sol_mint
+            }),
+        FeeDenomination::Sol => account.owner == &System::id(),
+    };
+    if !is_valid {
+        msg!("{} cannot receive fees in {:?}.", account.key, denomination);
+        return err!(LidoError::InvalidFeeRecipient);
+    }
+    Ok(())
+}
+
+impl<'info> ChangeFeeRecipients<'info> {
+    pub fn process(
+        &mut self,
+        treasury_fee_denomination: FeeDenomination,
+        developer_fee_denomination: FeeDenomination,
+    ) -> Result<()> {
+        let treasury = self.treasury.to_account_info();
+        let developer = self.developer.to_account_info();
+        check_fee_recipient(&self.lido, &treasury, treasury_fee_denomination)?;
+        check_fee_recipient(&self.lido, &developer, developer_fee_denomination)?;
+
+        self.lido.fee_recipients = FeeRecipients {
+            treasury_account: treasury.key(),
+            developer_account: developer.key(),
+            treasury_fee_denomination,
+            developer_fee_denomination,
+        };
+        emit!(FeeRecipientsChanged {
+            nonce: self.lido.next_operation_nonce()?,
+            treasury_account: treasury.key(),
+            developer_account: developer.key(),
+            treasury_fee_denomination,
+            developer_fee_denomination,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> UpdateConfig<'info> {
+    pub fn process(&mut self, update: ConfigUpdate) -> Result<()> {
+        self.config.update(update)?;
+        emit!(ConfigUpdated {
+            nonce: self.lido.next_operation_nonce()?,
+            min_deposit: self.config.min_deposit.amount,
+            min_reserve_balance: self.config.min_reserve_balance.amount,
+            deposits_paused: self.config.deposits_paused,
+            withdrawals_paused: self.config.withdrawals_paused,
+            max_instant_withdrawal: self.config.max_instant_withdrawal.amount,
+            max_stake_per_epoch: self.config.max_stake_per_epoch.amount,
+            max_exchange_rate_change_bps: self.config.max_exchange_rate_change_bps,
+            idle_reserve_fee_adjustment: self.config.idle_reserve_fee_adjustment,
+            reject_cpi: self.config.reject_cpi,
+            stale_exchange_rate_guard: self.config.stale_exchange_rate_guard,
+            crank_tip: self.config.crank_tip.amount,
+            min_epochs_before_stake: self.config.min_epochs_before_stake,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> SetWormholeProgram<'info> {
+    pub fn process(&mut self, wormhole_program: Pubkey) -> Result<()> {
+        self.config.wormhole_program = wormhole_program;
+        emit!(WormholeProgramChanged {
+            nonce: self.lido.next_operation_nonce()?,
+            wormhole_program,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> SetDepositHook<'info> {
+    pub fn process(
+        &mut self,
+        enabled: bool,
+        program_id: Pubkey,
+        instruction_prefix: [u8; 8],
+        accounts: Vec<HookAccount>,
+    ) -> Result<()> {
+        self.deposit_hook.lido = self.lido.key();
+        self.deposit_hook
+            .set(enabled, program_id, instruction_prefix, accounts)?;
+        emit!(DepositHookSet {
+            nonce: self.lido.next_operation_nonce()?,
+            enabled,
+            program_id,
+            num_accounts: self.deposit_hook.accounts.len() as u8,
+        });
+        Ok(())
+    }
+}
+
+impl<'info> ImportFromStakePool<'info> {
+    pub fn process(&mut self, program_id: &Pubkey) -> Result<()> {
+        // The stSOL is minted at the current exchange rate, so it must be up to date.
+        self.lido
+            .check_exchange_rate_last_epoch(&self.clock, "ImportFromStakePool")?;
+
+        let lido_address = self.lido.key();
+        let validator_vote = self.validator_vote.key();
+        let validator = self.lido.validators.get(&validator_vote)?;
+        validator.entry.check_can_stake()?;
+
+        let source = StakeAccount::from_account_info(
+            &self.source_stake_account,
+            &self.clock,
+            &self.stake_history,
+        )?;
+        source.check_importable(&validator_vote, &self.pool_authority.key(), &self.clock)?;
+
+        let end_seed = validator.entry.stake_seeds.end;
+        let (destination_address, destination_bump_seed) = validator.find_stake_account_address(
+            program_id,
+            &lido_address,
+            end_seed,
+            StakeType::Stake,
+        );
+        if self.destination_stake_account.key() != destination_address {
+            msg!(
+                "Destination stake account should be {}, but {} was provided.",
+                destination_address,
+                self.destination_stake_account.key()
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+
+        let amount = Lamports::new(self.source_stake_account.lamports());
+
+        let end_seed_bytes = end_seed.to_le_bytes();
+        let destination_signature_seeds = [
+            lido_address.as_ref(),
+            validator_vote.as_ref(),
+            VALIDATOR_STAKE_ACCOUNT.as_ref(),
+            &end_seed_bytes[..],
+            &[destination_bump_seed],
+        ];
+
+        // Move the entire balance into our next stake account. The pool
+        // authority signs the transaction, the destination needs our signature
+        // for the allocate and assign parts of the split.
+        let split_instructions = solana_program::stake::instruction::split(
+            self.source_stake_account.key,
+            self.pool_authority.key,
+            amount.amount,
+            self.destination_stake_account.key,
+        );
+        for split_instruction in split_instructions {
+            invoke_signed(
+                &split_instruction,
+                &[
+                    self.source_stake_account.to_account_info(),
+                    self.destination_stake_account.to_account_info(),
+                    self.pool_authority.to_account_info(),
+                    self.system_program.to_account_info(),
+                    self.stake_program.to_account_info(),
+                ],
+                &[&destination_signature_seeds[..]],
+            )?;
+        }
+
+        // The split copies the authorities of the source, hand them over to Solido.
+        for stake_authorize in [StakeAuthorize::Staker, StakeAuthorize::Withdrawer] {
+            invoke(
+                &solana_program::stake::instruction::authorize(
+                    self.destination_stake_account.key,
+                    self.pool_authority.key,
+                    self.stake_authority.key,
+                    stake_authorize,
+                    None,
+                ),
+                &[
+                    self.destination_stake_account.to_account_info(),
+                    self.clock.to_account_info(),
+                    self.pool_authority.to_account_info(),
+                    self.stake_program.to_account_info(),
+                ],
+            )?;
+        }
+
+        let st_sol_amount = self.lido.import_stake(&validator_vote, amount)?;
+        mint_st_sol_to(
+            &self.lido,
+            self.token_program.to_account_info(),
+            self.st_sol_mint.to_account_info(),
+            self.mint_authority.to_account_info(),
+            self.recipient.to_account_info(),
+            st_sol_amount,
+        )?;
+
+        msg!(
+            "Imported {} from stake account {}, minted {}.",
+            amount,
+            source.address,
+            st_sol_amount
+        );
+        emit!(StakePoolStakeImported {
+            nonce: self.lido.next_operation_nonce()?,
+            validator_vote,
+            source_stake_account: source.address,
+            amount_sol: amount.amount,
+            recipient: self.recipient.key(),
+            amount_st_sol: st_sol_amount.amount,
+        });
+
+        Ok(())
+    }
+}
+
+impl<'info> RepairSeedRange<'info> {
+    pub fn process(
+        &mut self,
+        program_id: &Pubkey,
+        stake_type: StakeType,
+        seeds: SeedRange,
+        accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        let validator_vote = self.validator_vote.key();
+        let validator = self.lido.validators.get(&validator_vote)?;
+        check_seed_range_accounts(
+            program_id,
+            &self.lido.key(),
+            validator,
+            stake_type,
+            &seeds,
+            &self.stake_authority.key(),
+            accounts,
+            &self.clock,
+            &self.stake_history,
+        )?;
+
+        let validator = self.lido.validators.get_mut(&validator_vote)?;
+        let old_seeds = match stake_type {
+            StakeType::Stake => std::mem::replace(&mut validator.entry.stake_seeds, seeds.clone()),
+            StakeType::Unstake => {
+                std::mem::replace(&mut validator.entry.unstake_seeds, seeds.clone())
+            }
+        };
+        msg!(
+            "Replaced the {:?} seeds [{}, {}) of validator {} with [{}, {}).",
+            stake_type,
+            old_seeds.begin,
+            old_seeds.end,
+            validator_vote,
+            seeds.begin,
+            seeds.end
+        );
+
+        emit!(SeedRangeRepaired {
+            nonce: self.lido.next_operation_nonce()?,
+            validator_vote,
+            stake_type,
+            old_begin: old_seeds.begin,
+            old_end: old_seeds.end,
+            new_begin: seeds.begin,
+            new_end: seeds.end,
+        });
+
+        Ok(())
+    }
+}
+
+impl<'info> MigrateFromSolidoV1<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        let v1 = LidoV1::from_account_info(&self.solido_v1)?;
+        if v1.manager != self.manager.key() {
+            msg!(
+                "The manager of Solido v1 instance {} is {}, not the signer.",
+                self.solido_v1.key(),
+                v1.manager
+            );
+            return err!(LidoError::InvalidManager);
+        }
+        if v1.st_sol_mint != self.st_sol_mint.key() {
+            msg!(
+                "The stSOL mint of Solido v1 instance {} is {}, not {}.",
+                self.solido_v1.key(),
+                v1.st_sol_mint,
+                self.st_sol_mint.key()
+            );
+            return err!(LidoError::InvalidMint);
+        }
+        self.lido.check_mint(
+            &self.st_sol_mint,
+            &self.mint_authority.key(),
+            &self.compliance_authority.key(),
+        )?;
+
+        let v1_reserve_address = Pubkey::create_program_address(
+            &[
+                self.solido_v1.key.as_ref(),
+                RESERVE_ACCOUNT.as_ref(),
+                &[v1.sol_reserve_account_bump_seed],
+            ],
+            &solido_v1::program::ID,
+        )
+        .map_err(|_| error!(LidoError::InvalidSolidoV1Account))?;
+        if self.solido_v1_reserve.key() != v1_reserve_address {
+            msg!(
+                "The reserve of Solido v1 instance {} is {}, not {}.",
+                self.solido_v1.key(),
+                v1_reserve_address,
+                self.solido_v1_reserve.key()
+            );
+            return err!(LidoError::InvalidReserveAccount);
+        }
+        let reserve_left_behind = Lamports::new(
+            self.solido_v1_reserve
+                .lamports()
+                .saturating_sub(self.rent.minimum_balance(0)),
+        );
+
+        let num_validators = v1.validators.entries.len();
+        v1.migrate_into(&mut self.lido, reserve_left_behind)?;
+
+        let lido = &self.lido;
+        msg!(
+            "Migrated {} validators and the exchange rate of {} SOL for {} stSOL from Solido v1 instance {}.",
+            num_validators,
+            lido.exchange_rate.sol_balance,
+            lido.exchange_rate.st_sol_supply,
+            self.solido_v1.key()
+        );
+        if reserve_left_behind > Lamports::new(0) {
+            msg!(
+                "Left {} behind in the Solido v1 reserve, too little to stake.",
+                reserve_left_behind
+            );
+        }
+        Ok(())
+    }
+}
+
+impl<'info> MigrateStakeFromSolidoV1<'info> {
+    pub fn process(&mut self, program_id: &Pubkey, stake_type: StakeType, seed: u64) -> Result<()> {
+        let v1 = LidoV1::from_account_info(&self.solido_v1)?;
+        // `MigrateFromSolidoV1` switched to the v1 mint.
+        require!(
+            v1.st_sol_mint == self.lido.st_sol_mint,
+            LidoError::InvalidSolidoV1Account
+        );
+
+        let lido_address = self.lido.key();
+        let v1_address = self.solido_v1.key();
+        let validator_vote = self.validator_vote.key();
+        let stake_authority = self.stake_authority.key();
+
+        let v1_seeds = v1.get_validator(&validator_vote)?.seeds(stake_type);
+        if seed < v1_seeds.begin || seed >= v1_seeds.end {
+            msg!(
+                "Seed {} is outside of the v1 seed range {} to {}.",
+                seed,
+                v1_seeds.begin,
+                v1_seeds.end
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+        let find_v1_account = match stake_type {
+            StakeType::Stake => pda::find_stake_account,
+            StakeType::Unstake => pda::find_unstake_account,
+        };
+        let (source_address, _) =
+            find_v1_account(&solido_v1::program::ID, &v1_address, &validator_vote, seed);
+        if self.source_stake_account.key() != source_address {
+            msg!(
+                "Source stake account should be {}, but {} was provided.",
+                source_address,
+                self.source_stake_account.key()
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+        let source = StakeAccount::from_account_info(
+            &self.source_stake_account,
+            &self.clock,
+            &self.stake_history,
+        )?;
+        // The v1 instance must have handed the stake account over already.
+        source.check_owned_by(&validator_vote, &stake_authority)?;
+
+        let validator = self.lido.validators.get(&validator_vote)?;
+        let (end_seed, destination_seed): (u64, &[u8]) = match stake_type {
+            StakeType::Stake => (validator.entry.stake_seeds.end, &VALIDATOR_STAKE_ACCOUNT),
+            StakeType::Unstake => (
+                validator.entry.unstake_seeds.end,
+                &VALIDATOR_UNSTAKE_ACCOUNT,
+            ),
+        };
+        let (destination_address, destination_bump_seed) =
+            validator.find_stake_account_address(program_id, &lido_address, end_seed, stake_type);
+        if self.destination_stake_account.key() != destination_address {
+            msg!(
+                "Destination stake account should be {}, but {} was provided.",
+                destination_address,
+                self.destination_stake_account.key()
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+
+        let amount = Lamports::new(self.source_stake_account.lamports());
+        let end_seed_bytes = end_seed.to_le_bytes();
+        let destination_signature_seeds = [
+            lido_address.as_ref(),
+            validator_vote.as_ref(),
+            destination_seed,
+            &end_seed_bytes[..],
+            &[destination_bump_seed],
+        ];
+        let authority_signature_seeds = [
+            lido_address.as_ref(),
+            STAKE_AUTHORITY.as_ref(),
+            &[self.lido.stake_authority_bump_seed],
+        ];
+
+        let split_instructions = solana_program::stake::instruction::split(
+            self.source_stake_account.key,
+            self.stake_authority.key,
+            amount.amount,
+            self.destination_stake_account.key,
+        );
+        for split_instruction in split_instructions {
+            invoke_signed(
+                &split_instruction,
+                &[
+                    self.source_stake_account.to_account_info(),
+                    self.destination_stake_account.to_account_info(),
+                    self.stake_authority.to_account_info(),
+                    self.system_program.to_account_info(),
+                    self.stake_program.to_account_info(),
+                ],
+                &[
+                    &destination_signature_seeds[..],
+                    &authority_signature_seeds[..],
+                ],
+            )?;
+        }
+
+        let deactivation_epoch = source.stake.delegation.deactivation_epoch;
+        self.lido
+            .update_validator_balances(&validator_vote, |validator| {
+                // The stake accounts balance includes the unstake accounts.
+                validator.observe_stake_increase(amount)?;
+                match stake_type {
+                    StakeType::Stake => validator.stake_seeds.end += 1,
+                    StakeType::Unstake => {
+                        validator.observe_unstake(amount)?;
+                        validator.unstake_seeds.end += 1;
+                        validator.unbonding.record(end_seed, deactivation_epoch);
+                    }
+                }
+                Ok(())
+            })?;
+
+        msg!(
+            "Moved {} from v1 stake account {} to {}.",
+            amount,
+            source_address,
+            destination_address
+        );
+        Ok(())
+    }
+}
+
+impl<'info> InitializeRegistry<'info> {
+    pub fn process(&mut self, max_instances: u32) -> Result<()> {
+        self.registry.authority = self.authority.key();
+        self.registry.canonical_instance = Pubkey::default();
+        self.registry.instances = Maintainers::new(max_instances);
+        Ok(())
+    }
+}
+
+impl<'info> RegisterInstance<'info> {
+    pub fn process(&mut self, canonical: bool) -> Result<()> {
+        self.registry.register(self.lido.key(), canonical)
+    }
+}
+
+impl<'info> UnregisterInstance<'info> {
+    pub fn process(&mut self) -> Result<()> {
+        self.registry.unregister(&self.lido.key())
+    }
+}
+
 impl<'info> AddMaintainer<'info> {
     pub fn process(&mut self) -> Result<()> {
-        self.lido.maintainers.add(self.maintainer.key())
+        self.lido.maintainers.add(self.maintainer.key())?;
+        emit!(MaintainerAdded {
+            nonce: self.lido.next_operation_nonce()?,
+            maintainer: self.maintainer.key(),
+        });
+        Ok(())
     }
 }
 
 impl<'info> RemoveMaintainer<'info> {
     pub fn process(&mut self) -> Result<()> {
-        self.lido.maintainers.remove(&self.maintainer.key())
+        self.lido.maintainers.remove(&self.maintainer.key())?;
+        emit!(MaintainerRemoved {
+            nonce: self.lido.next_operation_nonce()?,
+            maintainer: self.maintainer.key(),
+        });
+        Ok(())
+    }
+}
+
+impl<'info> SetMaintainers<'info> {
+    pub fn process(&mut self, maintainers: Vec<Pubkey>) -> Result<()> {
+        if maintainers.len() > MAXIMUM_SET_MAINTAINERS {
+            msg!(
+                "At most {} maintainers can be set at once, got {}.",
+                MAXIMUM_SET_MAINTAINERS,
+                maintainers.len()
+            );
+            return err!(LidoError::MaximumNumberOfAccountsExceeded);
+        }
+
+        let (removed, added) = self.lido.maintainers.set(&maintainers)?;
+        for maintainer in removed {
+            emit!(MaintainerRemoved {
+                nonce: self.lido.next_operation_nonce()?,
+                maintainer,
+            });
+        }
+        for maintainer in added {
+            emit!(MaintainerAdded {
+                nonce: self.lido.next_operation_nonce()?,
+                maintainer,
+            });
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,83 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Registry of the official Solido instances of this program.
+//!
+//! Nothing prevents anybody from initializing a `Lido` account with this
+//! program, and a look-alike instance cannot be told apart from the real one
+//! on chain. A registry is a program-derived account of a registry authority,
+//! that lists the instances that both the authority and their managers vouch
+//! for, and marks one of them as canonical. Clients that know the program id
+//! and the registry authority can resolve the instance to use from that.
+
+use anchor_lang::prelude::*;
+
+use crate::maintainers::Maintainers;
+
+#[account]
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Registry {
+    /// Account that approves which instances are listed.
+    pub authority: Pubkey,
+
+    /// Instance that clients should use by default, or the default pubkey if there is none.
+    pub canonical_instance: Pubkey,
+
+    /// The registered instances.
+    pub instances: Maintainers,
+}
+
+impl Registry {
+    /// Return how many bytes are needed for a registry of at most `max_instances`.
+    pub fn required_bytes(max_instances: u32) -> usize {
+        // 8 bytes for the discriminator, then the authority and the canonical instance.
+        8 + 32 + 32 + Maintainers::required_bytes(max_instances as usize)
+    }
+
+    /// Add `instance` to the registry, and make it the canonical instance if `canonical` is set.
+    pub fn register(&mut self, instance: Pubkey, canonical: bool) -> Result<()> {
+        self.instances.add(instance)?;
+        if canonical {
+            self.canonical_instance = instance;
+        }
+        Ok(())
+    }
+
+    /// Remove `instance` from the registry.
+    ///
+    /// If it was the canonical instance, there is no canonical instance afterwards.
+    pub fn unregister(&mut self, instance: &Pubkey) -> Result<()> {
+        self.instances.remove(instance)?;
+        if self.canonical_instance == *instance {
+            self.canonical_instance = Pubkey::default();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_register_and_unregister_canonical_instance() {
+        let mut registry = Registry {
+            instances: Maintainers::new(2),
+            ..Registry::default()
+        };
+        let instance_a = Pubkey::new_unique();
+        let instance_b = Pubkey::new_unique();
+
+        registry.register(instance_a, true).unwrap();
+        registry.register(instance_b, false).unwrap();
+        assert_eq!(registry.canonical_instance, instance_a);
+        assert!(registry.register(instance_b, true).is_err());
+        assert!(registry.register(Pubkey::new_unique(), false).is_err());
+
+        registry.unregister(&instance_b).unwrap();
+        assert_eq!(registry.canonical_instance, instance_a);
+        registry.unregister(&instance_a).unwrap();
+        assert_eq!(registry.canonical_instance, Pubkey::default());
+        assert!(registry.instances.is_empty());
+    }
+}
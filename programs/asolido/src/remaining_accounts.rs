@@ -0,0 +1,535 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Parsers for the remaining accounts of instructions that take a variable number of them.
+//!
+//! Instructions like `WithdrawInactiveStake` and `WithdrawFromValidators` get
+//! their stake accounts as remaining accounts, in an order that the
+//! instruction documents. The parsers here check the number of accounts,
+//! their addresses, and their owners once, and return them in typed form, so
+//! the processors do not index into the account list themselves.
+
+use anchor_lang::prelude::*;
+
+use crate::error::LidoError;
+use crate::state::StakeType;
+use crate::validators::{PubkeyAndEntry, Validators};
+
+/// Check that `accounts` are the validator's stake or unstake accounts for every seed in its range.
+///
+/// The accounts must be in seed order, and owned by the stake program.
+pub fn parse_seed_range_accounts<'a, 'info>(
+    program_id: &Pubkey,
+    solido_address: &Pubkey,
+    validator: &PubkeyAndEntry,
+    stake_type: StakeType,
+    accounts: &'a [AccountInfo<'info>],
+) -> Result<&'a [AccountInfo<'info>]> {
+    let range = validator.entry.seeds(stake_type);
+    let num_expected = range.num_seeds()? as usize;
+    if accounts.len() < num_expected {
+        msg!(
+            "Expected {} {:?} accounts, but got only {} accounts.",
+            num_expected,
+            stake_type,
+            accounts.len()
+        );
+        return err!(LidoError::InvalidStakeAccount);
+    }
+    if accounts.len() > num_expected {
+        return err!(LidoError::TooManyAccountKeys);
+    }
+
+    for (seed, provided) in range.into_iter().zip(accounts) {
+        let (expected_address, _) =
+            validator.find_stake_account_address(program_id, solido_address, seed, stake_type);
+        if provided.key != &expected_address {
+            msg!(
+                "{:?} account with seed {} should be {}, but {} was provided.",
+                stake_type,
+                seed,
+                expected_address,
+                provided.key
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+        if provided.owner != &solana_program::stake::program::ID {
+            msg!(
+                "{:?} account {} is owned by {}, expected the stake program.",
+                stake_type,
+                provided.key,
+                provided.owner
+            );
+            return err!(LidoError::WrongStakeState);
+        }
+    }
+
+    Ok(accounts)
+}
+
+/// Split `accounts` into the validator's stake accounts and unstake accounts.
+///
+/// `accounts` must hold the stake accounts for every seed in the validator's
+/// `stake_seeds`, followed by the unstake accounts for every seed in its
+/// `unstake_seeds`, both in seed order, see `parse_seed_range_accounts`.
+pub fn parse_stake_accounts_for_validator<'a, 'info>(
+    program_id: &Pubkey,
+    solido_address: &Pubkey,
+    validator: &PubkeyAndEntry,
+    accounts: &'a [AccountInfo<'info>],
+) -> Result<(&'a [AccountInfo<'info>], &'a [AccountInfo<'info>])> {
+    let num_stake_accounts = validator.entry.stake_seeds.num_seeds()? as usize;
+    let num_unstake_accounts = validator.entry.unstake_seeds.num_seeds()? as usize;
+    if accounts.len() < num_stake_accounts + num_unstake_accounts {
+        msg!(
+            "Expected {} stake accounts and {} unstake accounts, but got only {} accounts.",
+            num_stake_accounts,
+            num_unstake_accounts,
+            accounts.len()
+        );
+        return err!(LidoError::InvalidStakeAccount);
+    }
+
+    let (stake_accounts, unstake_accounts) = accounts.split_at(num_stake_accounts);
+    let stake_accounts = parse_seed_range_accounts(
+        program_id,
+        solido_address,
+        validator,
+        StakeType::Stake,
+        stake_accounts,
+    )?;
+    let unstake_accounts = parse_seed_range_accounts(
+        program_id,
+        solido_address,
+        validator,
+        StakeType::Unstake,
+        unstake_accounts,
+    )?;
+    Ok((stake_accounts, unstake_accounts))
+}
+
+/// Split `accounts` into the stake and unstake accounts of every validator.
+///
+/// `accounts` must hold the accounts of every validator in the order of
+/// `validators`, each as described in `parse_stake_accounts_for_validator`.
+/// Returns the vote account of every validator with all of its accounts.
+pub fn parse_stake_accounts_for_all_validators<'a, 'info>(
+    program_id: &Pubkey,
+    solido_address: &Pubkey,
+    validators: &Validators,
+    accounts: &'a [AccountInfo<'info>],
+) -> Result<Vec<(Pubkey, &'a [AccountInfo<'info>])>> {
+    let mut remaining = accounts;
+    let mut result = Vec::with_capacity(validators.entries.len());
+    for validator in validators.entries.iter() {
+        let num_accounts = (validator.entry.stake_seeds.num_seeds()?
+            + validator.entry.unstake_seeds.num_seeds()?) as usize;
+        if remaining.len() < num_accounts {
+            msg!(
+                "Expected {} stake and unstake accounts for validator {}, but only {} are left.",
+                num_accounts,
+                validator.pubkey,
+                remaining.len()
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+        let (validator_accounts, rest) = remaining.split_at(num_accounts);
+        parse_stake_accounts_for_validator(
+            program_id,
+            solido_address,
+            validator,
+            validator_accounts,
+        )?;
+        result.push((validator.pubkey, validator_accounts));
+        remaining = rest;
+    }
+    if !remaining.is_empty() {
+        msg!(
+            "Got {} accounts more than the validators have.",
+            remaining.len()
+        );
+        return err!(LidoError::TooManyAccountKeys);
+    }
+    Ok(result)
+}
+
+/// The three accounts that `WithdrawFromValidators` takes per validator.
+pub struct ValidatorTriple<'a, 'info> {
+    /// Vote account of the validator, which is part of the instance.
+    pub validator_vote: Pubkey,
+
+    /// Stake account of the validator at the beginning of its `stake_seeds`.
+    pub stake_account: &'a AccountInfo<'info>,
+
+    /// New stake account, which signs.
+    pub new_stake_account: &'a AccountInfo<'info>,
+}
+
+/// Parse `accounts` as triples of a vote account, its first stake account, and a new stake account.
+///
+/// Every validator must be part of the instance and occur only once, and
+/// there must be at least one.
+pub fn parse_validator_triples<'a, 'info>(
+    program_id: &Pubkey,
+    solido_address: &Pubkey,
+    validators: &Validators,
+    accounts: &'a [AccountInfo<'info>],
+) -> Result<Vec<ValidatorTriple<'a, 'info>>> {
+    if accounts.is_empty() || accounts.len() % 3 != 0 {
+        msg!(
+            "Expected three accounts per validator, but got {} accounts.",
+            accounts.len()
+        );
+        return err!(LidoError::InvalidAccountInfo);
+    }
+
+    let mut triples: Vec<ValidatorTriple> = Vec::with_capacity(accounts.len() / 3);
+    for validator_accounts in accounts.chunks(3) {
+        let validator_vote = validator_accounts[0].key();
+        if triples
+            .iter()
+            .any(|triple| triple.validator_vote == validator_vote)
+        {
+            msg!("Validator {} is passed more than once.", validator_vote);
+            return err!(LidoError::DuplicatedEntry);
+        }
+        let validator = validators.get(&validator_vote)?;
+
+        let stake_account = &validator_accounts[1];
+        let (stake_address, _) = validator.find_stake_account_address(
+            program_id,
+            solido_address,
+            validator.entry.stake_seeds.begin,
+            StakeType::Stake,
+        );
+        if stake_account.key() != stake_address {
+            msg!(
+                "Source stake account of {} should be {}, but {} was provided.",
+                validator_vote,
+                stake_address,
+                stake_account.key()
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+
+        let new_stake_account = &validator_accounts[2];
+        if !new_stake_account.is_signer {
+            msg!("New stake account {} must sign.", new_stake_account.key());
+            return err!(LidoError::SignatureMissing);
+        }
+
+        triples.push(ValidatorTriple {
+            validator_vote,
+            stake_account,
+            new_stake_account,
+        });
+    }
+    Ok(triples)
+}
+
+/// The two accounts that `Withdraw` takes per validator that it can fall back to.
+pub struct WithdrawFallback<'a, 'info> {
+    /// Vote account of the validator, checked by `Withdraw` to be part of the instance.
+    pub validator_vote: Pubkey,
+
+    /// Stake account of the validator at the beginning of its `stake_seeds`.
+    pub stake_account: &'a AccountInfo<'info>,
+}
+
+/// Parse `accounts` as pairs of a vote account and the first stake account of its validator.
+///
+/// There can be no pairs at all. `Withdraw` checks the addresses, because it
+/// checks the validator that the caller passes first in the same way.
+pub fn parse_withdraw_fallbacks<'a, 'info>(
+    accounts: &'a [AccountInfo<'info>],
+) -> Result<Vec<WithdrawFallback<'a, 'info>>> {
+    if accounts.len() % 2 != 0 {
+        msg!(
+            "Expected two accounts per validator, but got {} accounts.",
+            accounts.len()
+        );
+        return err!(LidoError::InvalidAccountInfo);
+    }
+    Ok(accounts
+        .chunks(2)
+        .map(|pair| WithdrawFallback {
+            validator_vote: pair[0].key(),
+            stake_account: &pair[1],
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::{SeedRange, Validator};
+
+    /// Owned data for an `AccountInfo`, which only borrows it.
+    struct TestAccount {
+        key: Pubkey,
+        owner: Pubkey,
+        is_signer: bool,
+        lamports: u64,
+        data: Vec<u8>,
+    }
+
+    impl TestAccount {
+        fn new(key: Pubkey, owner: Pubkey, is_signer: bool) -> TestAccount {
+            TestAccount {
+                key,
+                owner,
+                is_signer,
+                lamports: 0,
+                data: Vec::new(),
+            }
+        }
+
+        fn info(&mut self) -> AccountInfo {
+            AccountInfo::new(
+                &self.key,
+                self.is_signer,
+                true,
+                &mut self.lamports,
+                &mut self.data,
+                &self.owner,
+                false,
+                0,
+            )
+        }
+    }
+
+    fn validators_with(votes: &[Pubkey]) -> Validators {
+        let mut validators = Validators::new(votes.len() as u32);
+        for vote in votes {
+            validators
+                .add(*vote, Validator::new(Pubkey::new_unique()))
+                .unwrap();
+        }
+        validators
+    }
+
+    #[test]
+    fn test_parse_stake_accounts_for_validator() {
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let vote = Pubkey::new_unique();
+        let mut validators = validators_with(&[vote]);
+        let entry = &mut validators.get_mut(&vote).unwrap().entry;
+        entry.stake_seeds = SeedRange { begin: 1, end: 3 };
+        entry.unstake_seeds = SeedRange { begin: 0, end: 1 };
+        let validator = validators.get(&vote).unwrap();
+
+        let stake_program = solana_program::stake::program::ID;
+        let address = |seed, stake_type| {
+            validator
+                .find_stake_account_address(&program_id, &solido_address, seed, stake_type)
+                .0
+        };
+        let mut accounts = vec![
+            TestAccount::new(address(1, StakeType::Stake), stake_program, false),
+            TestAccount::new(address(2, StakeType::Stake), stake_program, false),
+            TestAccount::new(address(0, StakeType::Unstake), stake_program, false),
+        ];
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(|a| a.info()).collect();
+        let parse = |infos: &[AccountInfo]| {
+            parse_stake_accounts_for_validator(&program_id, &solido_address, validator, infos)
+                .map(|_| ())
+        };
+
+        let (stake_accounts, unstake_accounts) =
+            parse_stake_accounts_for_validator(&program_id, &solido_address, validator, &infos)
+                .unwrap();
+        assert_eq!(stake_accounts.len(), 2);
+        assert_eq!(unstake_accounts[0].key, infos[2].key);
+
+        // Too few, too many, or out of order.
+        assert!(parse(&infos[..2]).is_err());
+        let mut extra = infos.clone();
+        extra.push(infos[0].clone());
+        assert!(parse(&extra[..]).is_err());
+        let swapped = vec![infos[1].clone(), infos[0].clone(), infos[2].clone()];
+        assert!(parse(&swapped[..]).is_err());
+
+        // The right address, but not a stake account.
+        let mut closed = TestAccount::new(address(0, StakeType::Unstake), Pubkey::default(), false);
+        let not_stake = vec![infos[0].clone(), infos[1].clone(), closed.info()];
+        assert!(parse(&not_stake[..]).is_err());
+    }
+
+    #[test]
+    fn test_parse_seed_range_accounts() {
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let vote = Pubkey::new_unique();
+        let mut validators = validators_with(&[vote]);
+        let entry = &mut validators.get_mut(&vote).unwrap().entry;
+        entry.stake_seeds = SeedRange { begin: 2, end: 4 };
+        let validator = validators.get(&vote).unwrap();
+
+        let stake_program = solana_program::stake::program::ID;
+        let address = |seed| {
+            validator
+                .find_stake_account_address(&program_id, &solido_address, seed, StakeType::Stake)
+                .0
+        };
+        let mut accounts = vec![
+            TestAccount::new(address(2), stake_program, false),
+            TestAccount::new(address(3), stake_program, false),
+        ];
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(|a| a.info()).collect();
+        let parse = |infos: &[AccountInfo]| {
+            parse_seed_range_accounts(
+                &program_id,
+                &solido_address,
+                validator,
+                StakeType::Stake,
+                infos,
+            )
+            .map(|parsed| parsed.len())
+        };
+
+        assert_eq!(parse(&infos).unwrap(), 2);
+
+        // All accounts of the seed range are needed, in seed order.
+        assert!(parse(&infos[..1]).is_err());
+        let swapped = vec![infos[1].clone(), infos[0].clone()];
+        assert!(parse(&swapped[..]).is_err());
+        let mut extra = infos.clone();
+        extra.push(infos[0].clone());
+        assert!(parse(&extra[..]).is_err());
+
+        // The validator has no unstake accounts, so none are expected.
+        let parse_unstake = parse_seed_range_accounts(
+            &program_id,
+            &solido_address,
+            validator,
+            StakeType::Unstake,
+            &[],
+        );
+        assert_eq!(parse_unstake.unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_parse_stake_accounts_for_all_validators() {
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let votes = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut validators = validators_with(&votes);
+        validators.get_mut(&votes[0]).unwrap().entry.stake_seeds = SeedRange { begin: 0, end: 2 };
+        validators.get_mut(&votes[1]).unwrap().entry.unstake_seeds = SeedRange { begin: 4, end: 5 };
+
+        let stake_program = solana_program::stake::program::ID;
+        let mut accounts = Vec::new();
+        for &(vote, seed, stake_type) in [
+            (votes[0], 0, StakeType::Stake),
+            (votes[0], 1, StakeType::Stake),
+            (votes[1], 4, StakeType::Unstake),
+        ]
+        .iter()
+        {
+            let validator = validators.get(&vote).unwrap();
+            let (address, _) = validator.find_stake_account_address(
+                &program_id,
+                &solido_address,
+                seed,
+                stake_type,
+            );
+            accounts.push(TestAccount::new(address, stake_program, false));
+        }
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(|a| a.info()).collect();
+        let parse = |infos: &[AccountInfo]| {
+            parse_stake_accounts_for_all_validators(
+                &program_id,
+                &solido_address,
+                &validators,
+                infos,
+            )
+            .map(|_| ())
+        };
+
+        let parsed = parse_stake_accounts_for_all_validators(
+            &program_id,
+            &solido_address,
+            &validators,
+            &infos,
+        )
+        .unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].0, votes[0]);
+        assert_eq!(parsed[0].1.len(), 2);
+        assert_eq!(parsed[1].1[0].key, infos[2].key);
+
+        // The accounts of the last validator are missing, or there is one too many.
+        assert!(parse(&infos[..2]).is_err());
+        let mut extra = infos.clone();
+        extra.push(infos[0].clone());
+        assert!(parse(&extra[..]).is_err());
+    }
+
+    #[test]
+    fn test_parse_validator_triples() {
+        let program_id = Pubkey::new_unique();
+        let solido_address = Pubkey::new_unique();
+        let votes = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let validators = validators_with(&votes);
+
+        let stake_program = solana_program::stake::program::ID;
+        let system_program = solana_program::system_program::ID;
+        let mut accounts = Vec::new();
+        for vote in votes.iter() {
+            let validator = validators.get(vote).unwrap();
+            let (stake_address, _) = validator.find_stake_account_address(
+                &program_id,
+                &solido_address,
+                0,
+                StakeType::Stake,
+            );
+            accounts.push(TestAccount::new(*vote, Pubkey::default(), false));
+            accounts.push(TestAccount::new(stake_address, stake_program, false));
+            accounts.push(TestAccount::new(Pubkey::new_unique(), system_program, true));
+        }
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(|a| a.info()).collect();
+        let parse = |infos: &[AccountInfo]| {
+            parse_validator_triples(&program_id, &solido_address, &validators, infos).map(|_| ())
+        };
+
+        let triples =
+            parse_validator_triples(&program_id, &solido_address, &validators, &infos).unwrap();
+        assert_eq!(triples.len(), 2);
+        assert_eq!(triples[1].validator_vote, votes[1]);
+        assert_eq!(triples[1].new_stake_account.key, infos[5].key);
+
+        // No validators, or an incomplete triple.
+        assert!(parse(&infos[..0]).is_err());
+        assert!(parse(&infos[..4]).is_err());
+
+        // The same validator twice.
+        let mut duplicated = infos[..3].to_vec();
+        duplicated.extend_from_slice(&infos[..3]);
+        assert!(parse(&duplicated[..]).is_err());
+
+        // The stake account of the other validator.
+        let wrong_stake = vec![infos[0].clone(), infos[4].clone(), infos[2].clone()];
+        assert!(parse(&wrong_stake[..]).is_err());
+
+        // A new stake account that does not sign.
+        let not_signed = vec![infos[0].clone(), infos[1].clone(), infos[1].clone()];
+        assert!(parse(&not_signed[..]).is_err());
+    }
+    #[test]
+    fn test_parse_withdraw_fallbacks() {
+        let mut accounts: Vec<TestAccount> = (0..4)
+            .map(|_| TestAccount::new(Pubkey::new_unique(), Pubkey::default(), false))
+            .collect();
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(|a| a.info()).collect();
+
+        assert!(parse_withdraw_fallbacks(&infos[..0]).unwrap().is_empty());
+        let fallbacks = parse_withdraw_fallbacks(&infos).unwrap();
+        assert_eq!(fallbacks.len(), 2);
+        assert_eq!(fallbacks[1].validator_vote, *infos[2].key);
+        assert_eq!(fallbacks[1].stake_account.key, infos[3].key);
+
+        // An incomplete pair.
+        assert!(parse_withdraw_fallbacks(&infos[..3]).is_err());
+    }
+}
@@ -0,0 +1,634 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! A consistent view of a Solido instance, for use off-chain.
+//!
+//! The on-chain state is spread over the `Lido` account, the reserve, and the
+//! stake accounts of every validator. The CLI and the maintainer bot fetch
+//! those accounts over RPC, and this module turns them into a single
+//! `SolidoSnapshot`, with the derived quantities that they are interested in.
+//! Fetching is left to the caller, [`SolidoSnapshot::required_accounts`]
+//! lists the addresses to fetch.
+//!
+//! [`SolidoSnapshot::replay`] runs the bookkeeping of `UpdateExchangeRate` and
+//! `CollectValidatorFee` against a snapshot, with the same functions that the
+//! program uses, so we can preview their effect on a live instance.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use anchor_lang::prelude::*;
+use solana_program::clock::Epoch;
+use solana_program::program_pack::Pack;
+
+use crate::logic::get_vote_account_rewards;
+use crate::pda;
+use crate::state::{ExchangeRate, Lido, StakeType, Validator, ValidatorFees};
+use crate::token::{self, Lamports, Rational};
+use crate::validators::PubkeyAndEntry;
+
+/// The parts of an account that we need, as returned by RPC.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AccountSnapshot {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum SnapshotError {
+    /// The `Lido` account could not be deserialized.
+    InvalidLidoAccount,
+
+    /// An account that the snapshot needs was not provided.
+    MissingAccount(Pubkey),
+
+    /// The stSOL mint could not be deserialized.
+    InvalidMintAccount,
+
+    /// A sum of balances overflowed.
+    CalculationFailure,
+}
+
+impl From<token::ArithmeticError> for SnapshotError {
+    fn from(_: token::ArithmeticError) -> Self {
+        SnapshotError::CalculationFailure
+    }
+}
+
+/// A stake account of a validator, and its balance.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StakeAccountSnapshot {
+    pub address: Pubkey,
+    pub seed: u64,
+    pub balance: Lamports,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidatorSnapshot {
+    pub vote_account: Pubkey,
+    pub entry: Validator,
+    pub stake_accounts: Vec<StakeAccountSnapshot>,
+    pub unstake_accounts: Vec<StakeAccountSnapshot>,
+
+    /// Observed balance of the stake accounts, excluding the unstake accounts.
+    pub effective_stake: Lamports,
+
+    /// Observed balance of the unstake accounts.
+    pub unstaking: Lamports,
+}
+
+impl ValidatorSnapshot {
+    /// Return the epoch from which all SOL in the unstake accounts can be withdrawn.
+    ///
+    /// Returns `None` if there are no unstake accounts, or if the deactivation
+    /// epoch of one of them was not recorded, see `UnbondingLedger`.
+    pub fn unstaking_available_epoch(&self) -> Option<Epoch> {
+        let mut result = None;
+        for account in self.unstake_accounts.iter() {
+            let entry = self.entry.unbonding.get(account.seed)?;
+            result = result.max(Some(entry.expected_available_epoch()));
+        }
+        result
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SolidoSnapshot {
+    pub lido_address: Pubkey,
+    pub lido: Lido,
+    pub reserve_address: Pubkey,
+
+    /// Balance of the reserve, excluding the rent-exempt minimum and SOL owed to validators.
+    pub reserve_available: Lamports,
+
+    pub validators: Vec<ValidatorSnapshot>,
+
+    /// Total SOL under management: the available reserve, plus the observed
+    /// balance of all stake and unstake accounts.
+    ///
+    /// Unlike the exchange rate, this includes rewards that have not yet been
+    /// observed on-chain.
+    pub total_sol: Lamports,
+}
+
+impl SolidoSnapshot {
+    /// Return the addresses of the accounts needed to build a snapshot of `lido`, besides the `Lido` account itself.
+    ///
+    /// The reserve comes first, followed by the stake accounts and then the
+    /// unstake accounts of every validator, in seed order.
+    pub fn required_accounts(
+        program_id: &Pubkey,
+        lido_address: &Pubkey,
+        lido: &Lido,
+    ) -> Vec<Pubkey> {
+        let (reserve_address, _) = pda::find_reserve_address(program_id, lido_address);
+        let mut result = vec![reserve_address];
+        for validator in lido.validators.entries.iter() {
+            for (seed, stake_type) in iter_seeds(&validator.entry) {
+                let (address, _) = validator.find_stake_account_address(
+                    program_id,
+                    lido_address,
+                    seed,
+                    stake_type,
+                );
+                result.push(address);
+            }
+        }
+        result
+    }
+
+    /// Build a snapshot from the raw `Lido` account data and the accounts listed by `required_accounts`.
+    pub fn new(
+        program_id: &Pubkey,
+        lido_address: &Pubkey,
+        lido_data: &[u8],
+        accounts: &HashMap<Pubkey, AccountSnapshot>,
+        rent: &Rent,
+    ) -> std::result::Result<SolidoSnapshot, SnapshotError> {
+        let (reserve_address, _) = pda::find_reserve_address(program_id, lido_address);
+        SolidoSnapshot::new_with_addresses(
+            lido_address,
+            &reserve_address,
+            lido_data,
+            accounts,
+            rent,
+            |validator, seed, stake_type| {
+                validator
+                    .find_stake_account_address(program_id, lido_address, seed, stake_type)
+                    .0
+            },
+        )
+    }
+
+    /// Build a snapshot like [`SolidoSnapshot::new`], with addresses that the caller already derived.
+    ///
+    /// Deriving program addresses is slow, so clients that build snapshots
+    /// repeatedly can cache them. `stake_address` returns the address of the
+    /// stake or unstake account of a validator with the given seed.
+    pub fn new_with_addresses<F>(
+        lido_address: &Pubkey,
+        reserve_address: &Pubkey,
+        lido_data: &[u8],
+        accounts: &HashMap<Pubkey, AccountSnapshot>,
+        rent: &Rent,
+        stake_address: F,
+    ) -> std::result::Result<SolidoSnapshot, SnapshotError>
+    where
+        F: Fn(&PubkeyAndEntry, u64, StakeType) -> Pubkey,
+    {
+        Lido::check_data_version(lido_data).map_err(|_| SnapshotError::InvalidLidoAccount)?;
+        let lido = Lido::try_deserialize(&mut &lido_data[..])
+            .map_err(|_| SnapshotError::InvalidLidoAccount)?;
+
+        let get_account = |address: &Pubkey| {
+            accounts
+                .get(address)
+                .ok_or(SnapshotError::MissingAccount(*address))
+        };
+
+        let reserve = get_account(reserve_address)?;
+        // SOL owed to validators as fees sits in the reserve, but is not ours.
+        let reserve_available =
+            Lamports::new(reserve.lamports.saturating_sub(rent.minimum_balance(0)))
+                .saturating_sub(lido.get_fee_credit_sol()?);
+
+        let mut validators = Vec::with_capacity(lido.validators.len());
+        for validator in lido.validators.entries.iter() {
+            let mut stake_accounts = Vec::new();
+            let mut unstake_accounts = Vec::new();
+            for (seed, stake_type) in iter_seeds(&validator.entry) {
+                let address = stake_address(validator, seed, stake_type);
+                let account = StakeAccountSnapshot {
+                    address,
+                    seed,
+                    balance: Lamports::new(get_account(&address)?.lamports),
+                };
+                match stake_type {
+                    StakeType::Stake => stake_accounts.push(account),
+                    StakeType::Unstake => unstake_accounts.push(account),
+                }
+            }
+
+            let effective_stake: Lamports = stake_accounts
+                .iter()
+                .map(|account| account.balance)
+                .sum::<token::Result<Lamports>>()?;
+            let unstaking: Lamports = unstake_accounts
+                .iter()
+                .map(|account| account.balance)
+                .sum::<token::Result<Lamports>>()?;
+
+            validators.push(ValidatorSnapshot {
+                vote_account: validator.pubkey,
+                entry: validator.entry.clone(),
+                stake_accounts,
+                unstake_accounts,
+                effective_stake,
+                unstaking,
+            });
+        }
+
+        let staked = Lamports::try_sum(validators.iter().map(|v| v.effective_stake + v.unstaking))?;
+        let total_sol = (staked + reserve_available)?;
+
+        Ok(SolidoSnapshot {
+            lido_address: *lido_address,
+            lido,
+            reserve_address: *reserve_address,
+            reserve_available,
+            validators,
+            total_sol,
+        })
+    }
+
+    /// Return the annual percentage yield implied by the change from `previous` to the current exchange rate.
+    ///
+    /// Returns `None` if either rate is undefined, or if no epochs passed in between.
+    pub fn apy(&self, previous: &ExchangeRate, epochs_per_year: f64) -> Option<f64> {
+        let current = &self.lido.exchange_rate;
+        if current.computed_in_epoch <= previous.computed_in_epoch {
+            return None;
+        }
+
+        // The SOL value of one stSOL, at both points in time.
+        let sol_per_st_sol = |rate: &ExchangeRate| Rational {
+            numerator: rate.sol_balance.amount,
+            denominator: rate.st_sol_supply.amount,
+        };
+        token::compute_growth(
+            sol_per_st_sol(previous),
+            sol_per_st_sol(current),
+            current.computed_in_epoch - previous.computed_in_epoch,
+            epochs_per_year,
+        )
+        .map(|growth| growth.apy)
+    }
+}
+
+/// Fees that a replayed `CollectValidatorFee` booked for one validator.
+#[derive(Debug, Eq, PartialEq)]
+pub struct CollectedFee {
+    pub vote_account: Pubkey,
+
+    /// Balance of the vote account above the rent-exempt minimum.
+    pub rewards: Lamports,
+
+    pub fees: ValidatorFees,
+}
+
+/// The outcome of [`SolidoSnapshot::replay`].
+#[derive(Debug, Eq, PartialEq)]
+pub struct Replay {
+    /// The `Lido` state after the replayed instructions.
+    pub lido: Lido,
+
+    /// Fees collected from validators that had rewards, in validator order.
+    pub collected_fees: Vec<CollectedFee>,
+}
+
+impl SolidoSnapshot {
+    /// Return the addresses of the accounts needed by [`SolidoSnapshot::replay`].
+    ///
+    /// These are the stSOL mint, followed by the vote account of every
+    /// validator whose vote account was not closed.
+    pub fn replay_required_accounts(&self) -> Vec<Pubkey> {
+        let mut result = vec![self.lido.st_sol_mint];
+        result.extend(
+            self.validators
+                .iter()
+                .filter(|v| !v.entry.vote_account_closed)
+                .map(|v| v.vote_account),
+        );
+        result
+    }
+
+    /// Replay `UpdateExchangeRate`, and then `CollectValidatorFee` for every validator, in `epoch`.
+    ///
+    /// This only replays the changes to the `Lido` account. Like the program,
+    /// the exchange rate is based on the stake account balances that the
+    /// instance tracks, not on the observed ones. The checks that the program
+    /// does before it updates, e.g. whether the exchange rate was already
+    /// updated in `epoch`, are left to the caller. The snapshot does not
+    /// include the `Config`, so the replay does not pay `Config::crank_tip`.
+    pub fn replay(
+        &self,
+        epoch: Epoch,
+        accounts: &HashMap<Pubkey, AccountSnapshot>,
+        rent: &Rent,
+    ) -> std::result::Result<Replay, SnapshotError> {
+        let get_account = |address: &Pubkey| {
+            accounts
+                .get(address)
+                .ok_or(SnapshotError::MissingAccount(*address))
+        };
+
+        let mint_account = get_account(&self.lido.st_sol_mint)?;
+        let st_sol_mint = spl_token::state::Mint::unpack(&mint_account.data)
+            .map_err(|_| SnapshotError::InvalidMintAccount)?;
+
+        let mut lido = self.lido.clone();
+        let sol_balance = lido.get_sol_balance_with_reserve(self.reserve_available)?;
+        let exchange_rate = lido
+            .compute_exchange_rate(epoch, sol_balance, &st_sol_mint)
+            .map_err(|_| SnapshotError::CalculationFailure)?;
+        lido.set_exchange_rate(exchange_rate);
+
+        let mut collected_fees = Vec::new();
+        // Like maintainers, skip validators that `DeactivateClosedValidator` flagged.
+        for validator in self
+            .validators
+            .iter()
+            .filter(|v| !v.entry.vote_account_closed)
+        {
+            let vote_account = get_account(&validator.vote_account)?;
+            let rewards =
+                get_vote_account_rewards(rent, vote_account.lamports, vote_account.data.len());
+            if rewards > Lamports::new(0) {
+                let fees = lido
+                    .credit_validator_fees(&validator.vote_account, rewards)
+                    .map_err(|_| SnapshotError::CalculationFailure)?;
+                collected_fees.push(CollectedFee {
+                    vote_account: validator.vote_account,
+                    rewards,
+                    fees,
+                });
+            }
+            let entry = lido
+                .validators
+                .get_mut(&validator.vote_account)
+                .map_err(|_| SnapshotError::InvalidLidoAccount)?;
+            entry.entry.workstate.fee_collected_epoch = epoch;
+        }
+
+        Ok(Replay {
+            lido,
+            collected_fees,
+        })
+    }
+}
+
+/// Iterate the stake seeds and then the unstake seeds of a validator.
+pub fn iter_seeds(validator: &Validator) -> impl Iterator<Item = (u64, StakeType)> + '_ {
+    validator
+        .stake_seeds
+        .into_iter()
+        .map(|seed| (seed, StakeType::Stake))
+        .chain(
+            validator
+                .unstake_seeds
+                .into_iter()
+                .map(|seed| (seed, StakeType::Unstake)),
+        )
+}
+
+impl fmt::Display for SolidoSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Solido instance {}", self.lido_address)?;
+        writeln!(f, "  Manager:           {}", self.lido.manager)?;
+        writeln!(f, "  stSOL mint:        {}", self.lido.st_sol_mint)?;
+        writeln!(
+            f,
+            "  Exchange rate:     {} for {} (epoch {})",
+            self.lido.exchange_rate.sol_balance,
+            self.lido.exchange_rate.st_sol_supply,
+            self.lido.exchange_rate.computed_in_epoch,
+        )?;
+        writeln!(
+            f,
+            "  Reserve:           {} available in {}",
+            self.reserve_available, self.reserve_address,
+        )?;
+        writeln!(f, "  Total SOL:         {}", self.total_sol)?;
+        writeln!(
+            f,
+            "  Validators:        {} of at most {}",
+            self.validators.len(),
+            self.lido.validators.maximum_entries,
+        )?;
+        for validator in self.validators.iter() {
+            writeln!(f, "    {}", validator.vote_account)?;
+            writeln!(f, "      Active:           {}", validator.entry.active)?;
+            writeln!(
+                f,
+                "      Stake paused:     {}",
+                validator.entry.stake_paused
+            )?;
+            writeln!(f, "      Delinquent:       {}", validator.entry.delinquent)?;
+            writeln!(
+                f,
+                "      Vote closed:      {}",
+                validator.entry.vote_account_closed
+            )?;
+            writeln!(f, "      Effective stake:  {}", validator.effective_stake)?;
+            writeln!(f, "      Unstaking:        {}", validator.unstaking)?;
+            if let Some(epoch) = validator.unstaking_available_epoch() {
+                writeln!(f, "      Available from:   epoch {}", epoch)?;
+            }
+            writeln!(f, "      Fee credit:       {}", validator.entry.fee_credit)?;
+            writeln!(f, "      Fee address:      {}", validator.entry.fee_address)?;
+        }
+        writeln!(
+            f,
+            "  Maintainers:       {} of at most {}",
+            self.lido.maintainers.len(),
+            self.lido.maintainers.maximum_entries,
+        )?;
+        for maintainer in self.lido.maintainers.iter_entries() {
+            writeln!(f, "    {}", maintainer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::{RewardDistribution, LIDO_VERSION};
+    use crate::token::StLamports;
+
+    #[test]
+    fn test_snapshot_computes_balances() {
+        let program_id = Pubkey::new_unique();
+        let lido_address = Pubkey::new_unique();
+        let rent = Rent::default();
+
+        let mut lido = Lido {
+            lido_version: LIDO_VERSION,
+            ..Lido::default()
+        };
+        lido.validators.maximum_entries = 1;
+        lido.validators
+            .add(Pubkey::new_unique(), Validator::new(Pubkey::new_unique()))
+            .unwrap();
+        let validator = &mut lido.validators.entries[0].entry;
+        validator.stake_seeds.end = 2;
+        validator.unstake_seeds.end = 1;
+
+        let mut lido_data = Vec::new();
+        lido.try_serialize(&mut lido_data).unwrap();
+
+        let addresses = SolidoSnapshot::required_accounts(&program_id, &lido_address, &lido);
+        assert_eq!(addresses.len(), 4);
+
+        let balances = [rent.minimum_balance(0) + 10, 100, 200, 50];
+        let accounts: HashMap<Pubkey, AccountSnapshot> = addresses
+            .iter()
+            .zip(balances.iter())
+            .map(|(address, lamports)| {
+                let account = AccountSnapshot {
+                    lamports: *lamports,
+                    ..AccountSnapshot::default()
+                };
+                (*address, account)
+            })
+            .collect();
+
+        let snapshot =
+            SolidoSnapshot::new(&program_id, &lido_address, &lido_data, &accounts, &rent).unwrap();
+        assert_eq!(snapshot.reserve_available, Lamports::new(10));
+        assert_eq!(snapshot.validators[0].effective_stake, Lamports::new(300));
+        assert_eq!(snapshot.validators[0].unstaking, Lamports::new(50));
+        assert_eq!(snapshot.total_sol, Lamports::new(360));
+
+        let mut incomplete = accounts.clone();
+        incomplete.remove(&addresses[3]);
+        assert_eq!(
+            SolidoSnapshot::new(&program_id, &lido_address, &lido_data, &incomplete, &rent),
+            Err(SnapshotError::MissingAccount(addresses[3]))
+        );
+    }
+
+    #[test]
+    fn test_snapshot_apy() {
+        let mut snapshot = SolidoSnapshot {
+            lido_address: Pubkey::new_unique(),
+            lido: Lido::default(),
+            reserve_address: Pubkey::new_unique(),
+            reserve_available: Lamports::new(0),
+            validators: Vec::new(),
+            total_sol: Lamports::new(0),
+        };
+        let previous = ExchangeRate {
+            computed_in_epoch: 10,
+            st_sol_supply: StLamports::new(1_000),
+            sol_balance: Lamports::new(1_000),
+        };
+
+        // No epochs passed, so there is no yield to compute.
+        snapshot.lido.exchange_rate = previous.clone();
+        assert_eq!(snapshot.apy(&previous, 10.0), None);
+
+        // 1% growth per epoch, compounded over 10 epochs per year.
+        snapshot.lido.exchange_rate = ExchangeRate {
+            computed_in_epoch: 11,
+            st_sol_supply: StLamports::new(1_000),
+            sol_balance: Lamports::new(1_010),
+        };
+        let apy = snapshot.apy(&previous, 10.0).unwrap();
+        assert!((apy - (1.01_f64.powi(10) - 1.0)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_snapshot_replay() {
+        let rent = Rent::default();
+        let vote_account = Pubkey::new_unique();
+
+        let mut lido = Lido::default();
+        lido.st_sol_mint = Pubkey::new_unique();
+        lido.reward_distribution = RewardDistribution {
+            treasury_fee: 3,
+            validation_fee: 2,
+            developer_fee: 1,
+            st_sol_appreciation: 4,
+        };
+        lido.staked_this_epoch = Lamports::new(20);
+        lido.validators.maximum_entries = 1;
+        lido.validators
+            .add(vote_account, Validator::new(Pubkey::new_unique()))
+            .unwrap();
+        lido.validators.entries[0].entry.stake_accounts_balance = Lamports::new(490);
+        lido.validators.entries[0].entry.fee_credit = StLamports::new(100);
+
+        let mut snapshot = SolidoSnapshot {
+            lido_address: Pubkey::new_unique(),
+            validators: vec![ValidatorSnapshot {
+                vote_account,
+                entry: lido.validators.entries[0].entry.clone(),
+                stake_accounts: Vec::new(),
+                unstake_accounts: Vec::new(),
+                effective_stake: Lamports::new(490),
+                unstaking: Lamports::new(0),
+            }],
+            lido,
+            reserve_address: Pubkey::new_unique(),
+            reserve_available: Lamports::new(10),
+            total_sol: Lamports::new(500),
+        };
+        assert_eq!(
+            snapshot.replay_required_accounts(),
+            vec![snapshot.lido.st_sol_mint, vote_account]
+        );
+
+        let mint = spl_token::state::Mint {
+            supply: 900,
+            is_initialized: true,
+            ..spl_token::state::Mint::default()
+        };
+        let mut mint_data = vec![0; spl_token::state::Mint::LEN];
+        spl_token::state::Mint::pack(mint, &mut mint_data).unwrap();
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            snapshot.lido.st_sol_mint,
+            AccountSnapshot {
+                data: mint_data,
+                ..AccountSnapshot::default()
+            },
+        );
+        accounts.insert(
+            vote_account,
+            AccountSnapshot {
+                lamports: rent.minimum_balance(100) + 1_000,
+                data: vec![0; 100],
+                ..AccountSnapshot::default()
+            },
+        );
+
+        let replay = snapshot.replay(7, &accounts, &rent).unwrap();
+        // The supply includes the unclaimed fee credit.
+        assert_eq!(
+            replay.lido.exchange_rate,
+            ExchangeRate {
+                computed_in_epoch: 7,
+                st_sol_supply: StLamports::new(1_000),
+                sol_balance: Lamports::new(500),
+            }
+        );
+        assert_eq!(replay.lido.staked_this_epoch, Lamports::new(0));
+
+        // Fees are converted at the replayed rate of two stSOL per SOL.
+        assert_eq!(replay.collected_fees.len(), 1);
+        let collected = &replay.collected_fees[0];
+        assert_eq!(collected.rewards, Lamports::new(1_000));
+        assert_eq!(collected.fees.treasury_st_sol, StLamports::new(600));
+        assert_eq!(collected.fees.validation_st_sol, StLamports::new(400));
+        let entry = &replay.lido.validators.entries[0].entry;
+        assert_eq!(entry.fee_credit, StLamports::new(500));
+        assert_eq!(entry.workstate.fee_collected_epoch, 7);
+
+        accounts.remove(&vote_account);
+        assert_eq!(
+            snapshot.replay(7, &accounts, &rent),
+            Err(SnapshotError::MissingAccount(vote_account))
+        );
+
+        // Validators with a closed vote account are skipped.
+        snapshot.validators[0].entry.vote_account_closed = true;
+        assert_eq!(
+            snapshot.replay_required_accounts(),
+            vec![snapshot.lido.st_sol_mint]
+        );
+        let replay = snapshot.replay(7, &accounts, &rent).unwrap();
+        assert!(replay.collected_fees.is_empty());
+    }
+}
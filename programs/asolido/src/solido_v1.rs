@@ -0,0 +1,410 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Account layout of Solido v1, the program that this program is a rewrite of.
+//!
+//! `MigrateFromSolidoV1` reads the `Lido` account of a v1 instance to
+//! reconstruct it in the Anchor layout. The types here mirror the Borsh layout
+//! of v1. They are only used to read v1 accounts, no logic depends on them.
+//!
+//! Solido v1 has no instruction that hands over its mint authority or the
+//! authorities of its stake accounts, all of them are program-derived
+//! addresses of v1. So a migration starts with an upgrade of the v1 program,
+//! by its upgrade authority, to a version that can do that. The steps are:
+//!
+//! 1. Stake the SOL in the v1 reserve with v1's `StakeDeposit`, the reserve
+//!    itself cannot be moved, and `MigrateFromSolidoV1` refuses to leave an
+//!    amount behind that could have been staked.
+//! 2. Upgrade v1 to a version that sets the mint authority of its stSOL mint,
+//!    and the staker and withdrawer of its stake and unstake accounts, to the
+//!    mint authority and stake authority of the new instance.
+//! 3. Run `MigrateFromSolidoV1`, and `MigrateStakeFromSolidoV1` for every
+//!    stake and unstake account, see `migrations/migrate_from_solido_v1.ts`.
+
+use anchor_lang::prelude::*;
+
+use crate::error::LidoError;
+use crate::maintainers::Maintainers;
+use crate::metrics::{LamportsHistogram, Metrics, WithdrawMetric};
+use crate::state::{
+    ExchangeRate, FeeDenomination, FeeRecipients, Lido, RewardDistribution, SeedRange, StakeType,
+    Validator,
+};
+use crate::token::{Lamports, StLamports};
+use crate::MINIMUM_STAKE_ACCOUNT_BALANCE;
+
+/// The program id of Solido v1 on mainnet.
+pub mod program {
+    anchor_lang::declare_id!("CrX7kMhLC3cSsXJdT7JDgqrRVWGnUpX3gfEfxxU2NVLi");
+}
+
+/// The `lido_version` of the v1 accounts that we can read.
+pub const LIDO_V1_VERSION: u8 = 0;
+
+/// Size of the fields of a v1 `Lido` that precede the validators.
+pub const LIDO_V1_HEADER_SIZE: usize = 357;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct LidoV1 {
+    pub lido_version: u8,
+    pub manager: Pubkey,
+    pub st_sol_mint: Pubkey,
+    pub exchange_rate: ExchangeRate,
+    pub sol_reserve_account_bump_seed: u8,
+    pub stake_authority_bump_seed: u8,
+    pub mint_authority_bump_seed: u8,
+    pub rewards_withdraw_authority_bump_seed: u8,
+    pub reward_distribution: RewardDistribution,
+    pub fee_recipients: FeeRecipientsV1,
+    pub metrics: MetricsV1,
+    pub validators: ValidatorsV1,
+    // A v1 `AccountMap<()>` has the same layout as `Maintainers`.
+    pub maintainers: Maintainers,
+}
+
+/// The v1 fee recipients, which are always paid in stSOL.
+#[derive(Clone, Debug, Default, Eq, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct FeeRecipientsV1 {
+    pub treasury_account: Pubkey,
+    pub developer_account: Pubkey,
+}
+
+/// The v1 metrics, which lack `validator_loss_sol_total`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct MetricsV1 {
+    pub fee_treasury_sol_total: Lamports,
+    pub fee_validation_sol_total: Lamports,
+    pub fee_developer_sol_total: Lamports,
+    pub st_sol_appreciation_sol_total: Lamports,
+    pub fee_treasury_st_sol_total: StLamports,
+    pub fee_validation_st_sol_total: StLamports,
+    pub fee_developer_st_sol_total: StLamports,
+    pub deposit_amount: LamportsHistogram,
+    pub withdraw_amount: WithdrawMetric,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct ValidatorsV1 {
+    pub entries: Vec<PubkeyAndValidatorV1>,
+    pub maximum_entries: u32,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct PubkeyAndValidatorV1 {
+    pub pubkey: Pubkey,
+    pub entry: ValidatorV1,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, AnchorSerialize, AnchorDeserialize)]
+pub struct ValidatorV1 {
+    pub fee_credit: StLamports,
+    pub fee_address: Pubkey,
+    pub stake_seeds: SeedRange,
+    pub unstake_seeds: SeedRange,
+    pub stake_accounts_balance: Lamports,
+    pub unstake_accounts_balance: Lamports,
+    pub active: bool,
+}
+
+impl LidoV1 {
+    /// Deserialize the `Lido` account of a v1 instance.
+    ///
+    /// The account is allocated for the maximum number of validators and
+    /// maintainers, so there may be unused bytes at the end.
+    pub fn from_account_info(account: &AccountInfo) -> Result<LidoV1> {
+        if account.owner != &program::ID {
+            msg!(
+                "Solido v1 account {} should be owned by {}, but it is owned by {}.",
+                account.key,
+                program::ID,
+                account.owner
+            );
+            return err!(LidoError::InvalidSolidoV1Account);
+        }
+        let data = account.data.borrow();
+        // Check the version first, another version may have another layout.
+        if data.first() != Some(&LIDO_V1_VERSION) {
+            msg!(
+                "Solido v1 account {} has version {:?}, expected {}.",
+                account.key,
+                data.first(),
+                LIDO_V1_VERSION
+            );
+            return err!(LidoError::InvalidSolidoV1Account);
+        }
+        LidoV1::deserialize(&mut &data[..]).map_err(|_| error!(LidoError::InvalidSolidoV1Account))
+    }
+
+    /// Reconstruct the v1 instance in `lido`, which must not have validators yet.
+    ///
+    /// `reserve_left_behind` is the SOL in the v1 reserve, excluding its
+    /// rent-exempt reserve. It is not migrated, but v1 included it in its
+    /// exchange rate, so we write it off. If it could be staked, this fails.
+    pub fn migrate_into(self, lido: &mut Lido, reserve_left_behind: Lamports) -> Result<()> {
+        if reserve_left_behind >= MINIMUM_STAKE_ACCOUNT_BALANCE {
+            msg!(
+                "The Solido v1 reserve holds {}, stake it before migrating.",
+                reserve_left_behind
+            );
+            return err!(LidoError::SolidoV1ReserveNotStaked);
+        }
+        require!(
+            lido.validators.is_empty(),
+            LidoError::MigrationTargetNotEmpty
+        );
+
+        lido.st_sol_mint = self.st_sol_mint;
+        lido.exchange_rate = self.exchange_rate;
+        lido.exchange_rate.sol_balance = lido
+            .exchange_rate
+            .sol_balance
+            .saturating_sub(reserve_left_behind);
+        lido.reward_distribution = self.reward_distribution;
+        lido.fee_recipients = self.fee_recipients.into();
+        lido.metrics = self.metrics.into();
+
+        // The stake accounts are moved later, so the seeds start out empty,
+        // and the balances grow as `MigrateStakeFromSolidoV1` moves them.
+        for pe in self.validators.entries {
+            let mut validator = Validator::new(pe.entry.fee_address);
+            validator.fee_credit = pe.entry.fee_credit;
+            validator.active = pe.entry.active;
+            lido.validators
+                .add(pe.pubkey, validator)
+                .map_err(|err| error!(err))?;
+        }
+
+        // Maintainers that were added to this instance before are kept.
+        for maintainer in self.maintainers.entries {
+            if !lido.maintainers.entries.contains(&maintainer) {
+                lido.maintainers.add(maintainer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the v1 entry of the validator with the given vote account.
+    pub fn get_validator(&self, validator_vote: &Pubkey) -> Result<&ValidatorV1> {
+        self.validators
+            .entries
+            .iter()
+            .find(|pe| &pe.pubkey == validator_vote)
+            .map(|pe| &pe.entry)
+            .ok_or_else(|| error!(LidoError::InvalidAccountMember))
+    }
+}
+
+impl ValidatorV1 {
+    /// Return the seed range of the given type of stake accounts.
+    pub fn seeds(&self, stake_type: StakeType) -> &SeedRange {
+        match stake_type {
+            StakeType::Stake => &self.stake_seeds,
+            StakeType::Unstake => &self.unstake_seeds,
+        }
+    }
+}
+
+impl From<FeeRecipientsV1> for FeeRecipients {
+    fn from(fee_recipients: FeeRecipientsV1) -> FeeRecipients {
+        FeeRecipients {
+            treasury_account: fee_recipients.treasury_account,
+            developer_account: fee_recipients.developer_account,
+            treasury_fee_denomination: FeeDenomination::StSol,
+            developer_fee_denomination: FeeDenomination::StSol,
+        }
+    }
+}
+
+impl From<MetricsV1> for Metrics {
+    fn from(metrics: MetricsV1) -> Metrics {
+        Metrics {
+            fee_treasury_sol_total: metrics.fee_treasury_sol_total,
+            fee_validation_sol_total: metrics.fee_validation_sol_total,
+            fee_developer_sol_total: metrics.fee_developer_sol_total,
+            st_sol_appreciation_sol_total: metrics.st_sol_appreciation_sol_total,
+            fee_treasury_st_sol_total: metrics.fee_treasury_st_sol_total,
+            fee_validation_st_sol_total: metrics.fee_validation_st_sol_total,
+            fee_developer_st_sol_total: metrics.fee_developer_st_sol_total,
+            deposit_amount: metrics.deposit_amount,
+            withdraw_amount: metrics.withdraw_amount,
+            ..Metrics::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lido_v1_header_size() {
+        let lido = LidoV1::default();
+        let data = lido.try_to_vec().unwrap();
+        // The header, followed by the length and maximum of both account maps.
+        assert_eq!(data.len(), LIDO_V1_HEADER_SIZE + 8 + 8);
+    }
+
+    #[test]
+    fn test_lido_v1_deserializes_with_trailing_bytes() {
+        let mut lido = LidoV1::default();
+        lido.validators.entries.push(PubkeyAndValidatorV1 {
+            pubkey: Pubkey::new_unique(),
+            entry: ValidatorV1 {
+                stake_seeds: SeedRange { begin: 3, end: 5 },
+                active: true,
+                ..ValidatorV1::default()
+            },
+        });
+        lido.validators.maximum_entries = 10;
+        lido.maintainers.entries.push(Pubkey::new_unique());
+        lido.maintainers.maximum_entries = 10;
+
+        let mut data = lido.try_to_vec().unwrap();
+        data.resize(data.len() + 1_000, 0);
+        let parsed = LidoV1::deserialize(&mut &data[..]).unwrap();
+        assert_eq!(parsed, lido);
+
+        let vote = parsed.validators.entries[0].pubkey;
+        let validator = parsed.get_validator(&vote).unwrap();
+        assert_eq!(
+            validator.seeds(StakeType::Stake),
+            &SeedRange { begin: 3, end: 5 }
+        );
+        assert!(parsed.get_validator(&Pubkey::new_unique()).is_err());
+    }
+
+    #[test]
+    fn test_from_account_info_checks_owner_and_version() {
+        let lido = LidoV1::default();
+        let mut data = lido.try_to_vec().unwrap();
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &program::ID,
+            false,
+            0,
+        );
+        assert_eq!(LidoV1::from_account_info(&account).unwrap(), lido);
+
+        account.data.borrow_mut()[0] = LIDO_V1_VERSION + 1;
+        assert!(LidoV1::from_account_info(&account).is_err());
+
+        let mut lamports = 0;
+        let mut data = lido.try_to_vec().unwrap();
+        let other_owner = Pubkey::new_unique();
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &other_owner,
+            false,
+            0,
+        );
+        assert!(LidoV1::from_account_info(&account).is_err());
+    }
+
+    fn lido_v1_with_validator(vote: Pubkey, maintainer: Pubkey) -> LidoV1 {
+        let mut v1 = LidoV1 {
+            exchange_rate: ExchangeRate {
+                computed_in_epoch: 300,
+                st_sol_supply: StLamports::new(900_000_000_000),
+                sol_balance: Lamports::new(1_000_000_000_000),
+            },
+            fee_recipients: FeeRecipientsV1 {
+                treasury_account: Pubkey::new_unique(),
+                developer_account: Pubkey::new_unique(),
+            },
+            ..LidoV1::default()
+        };
+        v1.validators.entries.push(PubkeyAndValidatorV1 {
+            pubkey: vote,
+            entry: ValidatorV1 {
+                fee_credit: StLamports::new(37),
+                fee_address: Pubkey::new_unique(),
+                stake_seeds: SeedRange { begin: 3, end: 5 },
+                stake_accounts_balance: Lamports::new(1_000_000_000_000),
+                active: true,
+                ..ValidatorV1::default()
+            },
+        });
+        v1.maintainers.entries.push(maintainer);
+        v1
+    }
+
+    #[test]
+    fn test_migrate_into() {
+        let vote = Pubkey::new_unique();
+        let maintainer = Pubkey::new_unique();
+        let v1 = lido_v1_with_validator(vote, maintainer);
+
+        let mut lido = Lido::default();
+        lido.validators.maximum_entries = 10;
+        lido.maintainers.maximum_entries = 10;
+        lido.maintainers.add(maintainer).unwrap();
+
+        v1.clone()
+            .migrate_into(&mut lido, Lamports::new(1_000))
+            .unwrap();
+
+        assert_eq!(lido.st_sol_mint, v1.st_sol_mint);
+        assert_eq!(
+            lido.fee_recipients.treasury_account,
+            v1.fee_recipients.treasury_account
+        );
+        assert_eq!(
+            lido.fee_recipients.treasury_fee_denomination,
+            FeeDenomination::StSol
+        );
+
+        // The SOL left behind in the v1 reserve no longer backs the stSOL.
+        assert_eq!(
+            lido.exchange_rate.sol_balance,
+            Lamports::new(1_000_000_000_000 - 1_000)
+        );
+        assert_eq!(
+            lido.exchange_rate.st_sol_supply,
+            v1.exchange_rate.st_sol_supply
+        );
+
+        // The stake accounts are not moved yet, so neither are their seeds and balances.
+        let validator = &lido.validators.get(&vote).unwrap().entry;
+        assert_eq!(validator.fee_credit, StLamports::new(37));
+        assert_eq!(
+            validator.fee_address,
+            v1.validators.entries[0].entry.fee_address
+        );
+        assert!(validator.active);
+        assert_eq!(validator.stake_seeds, SeedRange { begin: 0, end: 0 });
+        assert_eq!(validator.stake_accounts_balance, Lamports::new(0));
+
+        // A maintainer of both instances is not added twice.
+        assert_eq!(lido.maintainers.entries, vec![maintainer]);
+
+        // The instance already has validators now.
+        assert!(v1.migrate_into(&mut lido, Lamports::new(0)).is_err());
+    }
+
+    #[test]
+    fn test_migrate_into_refuses_to_leave_stakeable_reserve_behind() {
+        let v1 = lido_v1_with_validator(Pubkey::new_unique(), Pubkey::new_unique());
+        let mut lido = Lido::default();
+        lido.validators.maximum_entries = 10;
+        lido.maintainers.maximum_entries = 10;
+
+        assert!(v1
+            .clone()
+            .migrate_into(&mut lido, MINIMUM_STAKE_ACCOUNT_BALANCE)
+            .is_err());
+        assert!(lido.validators.is_empty());
+
+        let below_minimum = Lamports::new(MINIMUM_STAKE_ACCOUNT_BALANCE.amount - 1);
+        v1.migrate_into(&mut lido, below_minimum).unwrap();
+    }
+}
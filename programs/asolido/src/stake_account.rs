@@ -0,0 +1,607 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Typed views on the stake accounts that Solido manages.
+//!
+//! Stake accounts are derived from seeds, so an account at the expected
+//! address was created by this program. But the stake program allows the
+//! stake and withdraw authorities to change the account after creation, so we
+//! also check the state of the account itself: who it is delegated to, when it
+//! was activated, and who controls it.
+
+use anchor_lang::prelude::*;
+use solana_program::stake::state::{Meta, Stake, StakeState};
+use solana_program::sysvar::stake_history::StakeHistory;
+
+use crate::error::LidoError;
+use crate::token::Lamports;
+
+/// The balance of a stake account, split by activation state.
+///
+/// The four parts sum to the balance of the account.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct StakeBalance {
+    /// Balance that is not staked, including the rent-exempt reserve.
+    pub inactive: Lamports,
+    pub activating: Lamports,
+    pub active: Lamports,
+    pub deactivating: Lamports,
+}
+
+/// A stake account that is delegated to a validator.
+#[derive(Clone, Debug)]
+pub struct StakeAccount {
+    pub address: Pubkey,
+    pub meta: Meta,
+    pub stake: Stake,
+    pub balance: StakeBalance,
+}
+
+impl StakeAccount {
+    /// Read a delegated stake account, and split its balance by activation state at the current epoch.
+    ///
+    /// Fails with `WrongStakeState` if the account is not owned by the stake
+    /// program, or if it is not delegated.
+    pub fn from_account_info(
+        account: &AccountInfo,
+        clock: &Clock,
+        stake_history: &StakeHistory,
+    ) -> Result<StakeAccount> {
+        if account.owner != &solana_program::stake::program::ID {
+            msg!(
+                "Stake account {} is owned by {}, expected the stake program.",
+                account.key,
+                account.owner
+            );
+            return err!(LidoError::WrongStakeState);
+        }
+
+        let (meta, stake) = match bincode::deserialize::<StakeState>(&account.data.borrow()) {
+            Ok(StakeState::Stake(meta, stake)) => (meta, stake),
+            _ => {
+                msg!("Stake account {} is not delegated.", account.key);
+                return err!(LidoError::WrongStakeState);
+            }
+        };
+
+        // `effective` includes the deactivating part, the stake program
+        // considers deactivating stake still active.
+        let (effective, activating, deactivating) = stake
+            .delegation
+            .stake_activating_and_deactivating(clock.epoch, Some(stake_history));
+        let active = effective
+            .checked_sub(deactivating)
+            .ok_or_else(|| error!(LidoError::CalculationFailure))?;
+        let inactive = account
+            .lamports()
+            .checked_sub(effective)
+            .and_then(|x| x.checked_sub(activating))
+            .ok_or_else(|| error!(LidoError::CalculationFailure))?;
+
+        Ok(StakeAccount {
+            address: *account.key,
+            meta,
+            stake,
+            balance: StakeBalance {
+                inactive: Lamports::new(inactive),
+                activating: Lamports::new(activating),
+                active: Lamports::new(active),
+                deactivating: Lamports::new(deactivating),
+            },
+        })
+    }
+
+    /// Check that the stake is delegated to the given validator.
+    pub fn check_delegated_to(&self, validator_vote: &Pubkey) -> Result<()> {
+        if &self.stake.delegation.voter_pubkey != validator_vote {
+            msg!(
+                "Stake account {} is delegated to {}, expected {}.",
+                self.address,
+                self.stake.delegation.voter_pubkey,
+                validator_vote
+            );
+            return err!(LidoError::InvalidStakeAccount);
+        }
+        Ok(())
+    }
+
+    /// Check that both the staker and the withdrawer are the Solido stake authority.
+    pub fn check_authorities(&self, stake_authority: &Pubkey) -> Result<()> {
+        let authorized = &self.meta.authorized;
+        if &authorized.staker != stake_authority || &authorized.withdrawer != stake_authority {
+            msg!(
+                "Stake account {} has staker {} and withdrawer {}, expected {} for both.",
+                self.address,
+                authorized.staker,
+                authorized.withdrawer,
+                stake_authority
+            );
+            return err!(LidoError::InvalidStakeAuthority);
+        }
+        Ok(())
+    }
+
+    /// Check that the stake is delegated to the validator and controlled by the stake authority.
+    pub fn check_owned_by(&self, validator_vote: &Pubkey, stake_authority: &Pubkey) -> Result<()> {
+        self.check_delegated_to(validator_vote)?;
+        self.check_authorities(stake_authority)
+    }
+
+    /// Check that the stake was activated in the given epoch.
+    pub fn check_activated_in(&self, epoch: u64) -> Result<()> {
+        if self.stake.delegation.activation_epoch != epoch {
+            msg!(
+                "Stake account {} was activated in epoch {}, expected {}.",
+                self.address,
+                self.stake.delegation.activation_epoch,
+                epoch
+            );
+            return err!(LidoError::WrongStakeState);
+        }
+        Ok(())
+    }
+
+    /// Check that the stake is not being deactivated, or already deactivated.
+    pub fn check_not_deactivated(&self) -> Result<()> {
+        if self.is_deactivated() {
+            msg!(
+                "Stake account {} was deactivated in epoch {}.",
+                self.address,
+                self.stake.delegation.deactivation_epoch
+            );
+            return err!(LidoError::WrongStakeState);
+        }
+        Ok(())
+    }
+
+    /// Check that no lockup is in force, a lockup prevents withdrawing from the account.
+    pub fn check_not_locked_up(&self, clock: &Clock) -> Result<()> {
+        if self.meta.lockup.is_in_force(clock, None) {
+            msg!("Stake account {} is locked up.", self.address);
+            return err!(LidoError::WrongStakeState);
+        }
+        Ok(())
+    }
+
+    /// Check that the stake can be imported from the stake pool with authority `pool_authority`.
+    ///
+    /// The stake must be delegated to the validator, controlled by the pool
+    /// authority, fully active, and not locked up.
+    pub fn check_importable(
+        &self,
+        validator_vote: &Pubkey,
+        pool_authority: &Pubkey,
+        clock: &Clock,
+    ) -> Result<()> {
+        self.check_delegated_to(validator_vote)?;
+        self.check_authorities(pool_authority)?;
+        if !self.is_fully_active() {
+            msg!(
+                "Stake account {} is not fully active, import it once it is.",
+                self.address
+            );
+            return err!(LidoError::WrongStakeState);
+        }
+        self.check_not_locked_up(clock)
+    }
+
+    /// Check that a withdrawal from the validator can be merged into this stake account of `owner`.
+    ///
+    /// The stake program merges only stake of the same validator and with the
+    /// same authorities. The withdrawn stake is active, so the account must
+    /// be fully active too, and not deactivating.
+    pub fn check_can_merge_withdrawal(
+        &self,
+        validator_vote: &Pubkey,
+        owner: &Pubkey,
+    ) -> Result<()> {
+        self.check_owned_by(validator_vote, owner)?;
+        self.check_not_deactivated()?;
+        if !self.is_fully_active() {
+            msg!(
+                "Stake account {} is still activating, merge into it once it is active.",
+                self.address
+            );
+            return err!(LidoError::WrongStakeState);
+        }
+        Ok(())
+    }
+
+    /// Return whether none of the balance is staked any more.
+    pub fn is_inactive(&self) -> bool {
+        self.balance.activating == Lamports::new(0)
+            && self.balance.active == Lamports::new(0)
+            && self.balance.deactivating == Lamports::new(0)
+    }
+
+    /// Return whether the stake was already deactivated, or is being deactivated.
+    pub fn is_deactivated(&self) -> bool {
+        self.stake.delegation.deactivation_epoch != u64::MAX
+    }
+
+    /// Return whether all of the staked balance is active.
+    pub fn is_fully_active(&self) -> bool {
+        self.balance.activating == Lamports::new(0) && self.balance.deactivating == Lamports::new(0)
+    }
+
+    /// Return the balance that has to stay in the account for as long as it exists.
+    pub fn rent_exempt_reserve(&self) -> Lamports {
+        Lamports::new(self.meta.rent_exempt_reserve)
+    }
+
+    /// Return the balance that can be withdrawn without touching the stake or the rent-exempt reserve.
+    pub fn withdrawable(&self) -> Lamports {
+        Lamports::new(
+            self.balance
+                .inactive
+                .amount
+                .saturating_sub(self.meta.rent_exempt_reserve),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solana_program::stake::state::{Authorized, Delegation, Lockup};
+
+    /// Read a stake account delegated to `voter` that was activated in epoch 1, as of `epoch`.
+    fn read_stake_account(
+        owner: &Pubkey,
+        meta: Meta,
+        voter: Pubkey,
+        deactivation_epoch: u64,
+        epoch: u64,
+    ) -> Result<StakeAccount> {
+        let stake = Stake {
+            delegation: Delegation {
+                voter_pubkey: voter,
+                stake: 1_000_000_000,
+                activation_epoch: 1,
+                deactivation_epoch,
+                ..Delegation::default()
+            },
+            credits_observed: 0,
+        };
+        let mut data = vec![0; StakeState::size_of()];
+        bincode::serialize_into(&mut data[..], &StakeState::Stake(meta, stake)).unwrap();
+
+        let address = Pubkey::new_unique();
+        let mut lamports = 1_000_000_000 + meta.rent_exempt_reserve;
+        let account = AccountInfo::new(
+            &address,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            owner,
+            false,
+            0,
+        );
+        let clock = Clock {
+            epoch,
+            ..Clock::default()
+        };
+        StakeAccount::from_account_info(&account, &clock, &StakeHistory::default())
+    }
+
+    fn meta_with_authority(stake_authority: Pubkey) -> Meta {
+        Meta {
+            rent_exempt_reserve: 2_282_880,
+            authorized: Authorized {
+                staker: stake_authority,
+                withdrawer: stake_authority,
+            },
+            lockup: Lockup::default(),
+        }
+    }
+
+    fn assert_error<T: std::fmt::Debug>(result: Result<T>, expected: LidoError) {
+        let expected: ProgramError = error!(expected).into();
+        assert_eq!(ProgramError::from(result.unwrap_err()), expected);
+    }
+
+    fn inactive_stake_account(balance: u64, rent_exempt_reserve: u64) -> StakeAccount {
+        StakeAccount {
+            address: Pubkey::new_unique(),
+            meta: Meta {
+                rent_exempt_reserve,
+                ..Meta::default()
+            },
+            stake: Stake::default(),
+            balance: StakeBalance {
+                inactive: Lamports::new(balance),
+                ..StakeBalance::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_rent_exempt_reserve_is_not_withdrawable_before_closing() {
+        let account = inactive_stake_account(5_000_000, 2_282_880);
+        assert!(account.is_inactive());
+        assert_eq!(account.rent_exempt_reserve(), Lamports::new(2_282_880));
+        assert_eq!(account.withdrawable(), Lamports::new(2_717_120));
+
+        // An account with less than its rent-exempt reserve has nothing withdrawable.
+        let account = inactive_stake_account(1_000, 2_282_880);
+        assert_eq!(account.withdrawable(), Lamports::new(0));
+    }
+
+    #[test]
+    fn test_from_account_info_rejects_account_not_owned_by_stake_program() {
+        let voter = Pubkey::new_unique();
+        let meta = meta_with_authority(Pubkey::new_unique());
+        let result = read_stake_account(&Pubkey::new_unique(), meta, voter, u64::MAX, 2);
+        assert_error(result, LidoError::WrongStakeState);
+    }
+
+    #[test]
+    fn test_from_account_info_splits_balance() {
+        let voter = Pubkey::new_unique();
+        let meta = meta_with_authority(Pubkey::new_unique());
+        let owner = solana_program::stake::program::ID;
+
+        let account = read_stake_account(&owner, meta, voter, u64::MAX, 2).unwrap();
+        assert_eq!(account.balance.active, Lamports::new(1_000_000_000));
+        assert_eq!(account.balance.inactive, Lamports::new(2_282_880));
+        assert!(account.is_fully_active());
+        assert_eq!(account.withdrawable(), Lamports::new(0));
+    }
+
+    #[test]
+    fn test_check_owned_by_rejects_other_validator_and_authorities() {
+        let voter = Pubkey::new_unique();
+        let stake_authority = Pubkey::new_unique();
+        let owner = solana_program::stake::program::ID;
+
+        let meta = meta_with_authority(stake_authority);
+        let account = read_stake_account(&owner, meta, voter, u64::MAX, 2).unwrap();
+        account.check_owned_by(&voter, &stake_authority).unwrap();
+        assert_error(
+            account.check_owned_by(&Pubkey::new_unique(), &stake_authority),
+            LidoError::InvalidStakeAccount,
+        );
+        assert_error(
+            account.check_owned_by(&voter, &Pubkey::new_unique()),
+            LidoError::InvalidStakeAuthority,
+        );
+
+        // The staker and the withdrawer can be changed independently, both must match.
+        let mut meta = meta_with_authority(stake_authority);
+        meta.authorized.withdrawer = Pubkey::new_unique();
+        let account = read_stake_account(&owner, meta, voter, u64::MAX, 2).unwrap();
+        assert_error(
+            account.check_authorities(&stake_authority),
+            LidoError::InvalidStakeAuthority,
+        );
+
+        let mut meta = meta_with_authority(stake_authority);
+        meta.authorized.staker = Pubkey::new_unique();
+        let account = read_stake_account(&owner, meta, voter, u64::MAX, 2).unwrap();
+        assert_error(
+            account.check_authorities(&stake_authority),
+            LidoError::InvalidStakeAuthority,
+        );
+    }
+
+    #[test]
+    fn test_check_activated_in() {
+        let voter = Pubkey::new_unique();
+        let meta = meta_with_authority(Pubkey::new_unique());
+        let owner = solana_program::stake::program::ID;
+
+        let account = read_stake_account(&owner, meta, voter, u64::MAX, 2).unwrap();
+        account.check_activated_in(1).unwrap();
+        assert_error(account.check_activated_in(2), LidoError::WrongStakeState);
+    }
+
+    #[test]
+    fn test_is_deactivated_from_the_deactivation_epoch_on() {
+        let voter = Pubkey::new_unique();
+        let meta = meta_with_authority(Pubkey::new_unique());
+        let owner = solana_program::stake::program::ID;
+
+        let account = read_stake_account(&owner, meta, voter, u64::MAX, 2).unwrap();
+        assert!(!account.is_deactivated());
+        assert!(!account.is_inactive());
+
+        // `UnstakeAllFromValidator` skips accounts that are deactivating already,
+        // and once they are inactive, `WithdrawInactiveStake` can close them.
+        let account = read_stake_account(&owner, meta, voter, 3, 3).unwrap();
+        assert!(account.is_deactivated());
+        assert!(!account.is_inactive());
+        let account = read_stake_account(&owner, meta, voter, 3, 4).unwrap();
+        assert!(account.is_deactivated());
+        assert!(account.is_inactive());
+    }
+
+    #[test]
+    fn test_check_not_locked_up() {
+        let voter = Pubkey::new_unique();
+        let owner = solana_program::stake::program::ID;
+        let custodian = Pubkey::new_unique();
+        let clock = Clock {
+            epoch: 2,
+            ..Clock::default()
+        };
+
+        let meta = meta_with_authority(Pubkey::new_unique());
+        let account = read_stake_account(&owner, meta, voter, u64::MAX, 2).unwrap();
+        account.check_not_locked_up(&clock).unwrap();
+
+        let mut meta = meta_with_authority(Pubkey::new_unique());
+        meta.lockup = Lockup {
+            unix_timestamp: 0,
+            epoch: 3,
+            custodian,
+        };
+        let account = read_stake_account(&owner, meta, voter, u64::MAX, 2).unwrap();
+        assert_error(
+            account.check_not_locked_up(&clock),
+            LidoError::WrongStakeState,
+        );
+
+        // Once the lockup epoch is reached, the lockup is no longer in force.
+        let clock = Clock {
+            epoch: 3,
+            ..Clock::default()
+        };
+        account.check_not_locked_up(&clock).unwrap();
+    }
+
+    #[test]
+    fn test_check_can_merge_withdrawal() {
+        let voter = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let stake_program = solana_program::stake::program::ID;
+
+        let account = read_stake_account(
+            &stake_program,
+            meta_with_authority(user),
+            voter,
+            u64::MAX,
+            2,
+        )
+        .unwrap();
+        account.check_can_merge_withdrawal(&voter, &user).unwrap();
+
+        // The stake must be of the validator that serves the withdrawal, and of the stSOL owner.
+        assert_error(
+            account.check_can_merge_withdrawal(&Pubkey::new_unique(), &user),
+            LidoError::InvalidStakeAccount,
+        );
+        assert_error(
+            account.check_can_merge_withdrawal(&voter, &Pubkey::new_unique()),
+            LidoError::InvalidStakeAuthority,
+        );
+
+        // Stake that is still activating, or is deactivating, cannot be merged with active stake.
+        let account = read_stake_account(
+            &stake_program,
+            meta_with_authority(user),
+            voter,
+            u64::MAX,
+            1,
+        )
+        .unwrap();
+        assert_error(
+            account.check_can_merge_withdrawal(&voter, &user),
+            LidoError::WrongStakeState,
+        );
+        let account =
+            read_stake_account(&stake_program, meta_with_authority(user), voter, 2, 2).unwrap();
+        assert_error(
+            account.check_can_merge_withdrawal(&voter, &user),
+            LidoError::WrongStakeState,
+        );
+    }
+
+    #[test]
+    fn test_check_importable_rejects_foreign_and_unsupported_stake() {
+        let voter = Pubkey::new_unique();
+        let pool_authority = Pubkey::new_unique();
+        let owner = solana_program::stake::program::ID;
+        let clock = Clock {
+            epoch: 2,
+            ..Clock::default()
+        };
+
+        let meta = meta_with_authority(pool_authority);
+        let account = read_stake_account(&owner, meta, voter, u64::MAX, 2).unwrap();
+        account
+            .check_importable(&voter, &pool_authority, &clock)
+            .unwrap();
+
+        // Stake of another pool, or delegated to a validator that is not ours.
+        assert_error(
+            account.check_importable(&voter, &Pubkey::new_unique(), &clock),
+            LidoError::InvalidStakeAuthority,
+        );
+        assert_error(
+            account.check_importable(&Pubkey::new_unique(), &pool_authority, &clock),
+            LidoError::InvalidStakeAccount,
+        );
+
+        // Stake that is still activating, or already deactivating.
+        let account = read_stake_account(&owner, meta, voter, u64::MAX, 1).unwrap();
+        assert_error(
+            account.check_importable(&voter, &pool_authority, &clock),
+            LidoError::WrongStakeState,
+        );
+        let account = read_stake_account(&owner, meta, voter, 2, 2).unwrap();
+        assert_error(
+            account.check_importable(&voter, &pool_authority, &clock),
+            LidoError::WrongStakeState,
+        );
+
+        // Stake that is locked up.
+        let mut meta = meta_with_authority(pool_authority);
+        meta.lockup.epoch = 3;
+        let account = read_stake_account(&owner, meta, voter, u64::MAX, 2).unwrap();
+        assert_error(
+            account.check_importable(&voter, &pool_authority, &clock),
+            LidoError::WrongStakeState,
+        );
+    }
+
+    #[test]
+    fn test_deactivating_stake_is_rejected_and_becomes_inactive() {
+        let voter = Pubkey::new_unique();
+        let meta = meta_with_authority(Pubkey::new_unique());
+        let owner = solana_program::stake::program::ID;
+
+        // In the epoch of deactivation, the stake is still effective, but deactivating.
+        let account = read_stake_account(&owner, meta, voter, 5, 5).unwrap();
+        assert!(account.is_deactivated());
+        assert!(!account.is_inactive());
+        assert_eq!(account.balance.deactivating, Lamports::new(1_000_000_000));
+        assert_error(account.check_not_deactivated(), LidoError::WrongStakeState);
+
+        // One epoch later, all of it can be withdrawn.
+        let account = read_stake_account(&owner, meta, voter, 5, 6).unwrap();
+        assert!(account.is_inactive());
+        assert_eq!(account.withdrawable(), Lamports::new(1_000_000_000));
+        assert_error(account.check_not_deactivated(), LidoError::WrongStakeState);
+    }
+
+    #[test]
+    fn test_unstake_limit_is_lifted_after_withdraw_inactive_stake_in_next_epoch() {
+        use crate::state::Validator;
+        use crate::MAXIMUM_UNSTAKE_ACCOUNTS;
+
+        let voter = Pubkey::new_unique();
+        let meta = meta_with_authority(Pubkey::new_unique());
+        let owner = solana_program::stake::program::ID;
+        let mut validator = Validator::new(Pubkey::new_unique());
+
+        // In epoch 5 we unstake up to the limit, after that `Unstake` fails.
+        for _ in 0..MAXIMUM_UNSTAKE_ACCOUNTS {
+            validator.check_can_unstake().unwrap();
+            validator.unstake_seeds.end += 1;
+        }
+        assert_error(
+            validator.check_can_unstake(),
+            LidoError::MaxUnstakeAccountsReached,
+        );
+
+        // `WithdrawInactiveStake` closes the leading unstake accounts that are
+        // inactive. In the epoch of unstaking, none of them are.
+        let num_inactive = |epoch| {
+            (0..MAXIMUM_UNSTAKE_ACCOUNTS)
+                .map(|_| read_stake_account(&owner, meta, voter, 5, epoch).unwrap())
+                .take_while(|account| account.is_inactive())
+                .count() as u64
+        };
+        validator.unstake_seeds.begin += num_inactive(5);
+        assert_error(
+            validator.check_can_unstake(),
+            LidoError::MaxUnstakeAccountsReached,
+        );
+
+        // In the next epoch, all of them are closed, and we can unstake again.
+        validator.unstake_seeds.begin += num_inactive(6);
+        assert_eq!(validator.unstake_seeds.num_seeds().unwrap(), 0);
+        validator.check_can_unstake().unwrap();
+    }
+}
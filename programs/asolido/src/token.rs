@@ -57,12 +57,77 @@ impl Rational {
     }
 }
 
+/// Growth of a rate (e.g. the SOL value of one stSOL) over a number of epochs.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Growth {
+    /// Relative growth over the measured period, e.g. 0.01 for 1%.
+    pub period_growth: f64,
+    /// Annual percentage rate, the period growth extrapolated linearly to a year.
+    pub apr: f64,
+    /// Annual percentage yield, the period growth compounded over a year.
+    pub apy: f64,
+}
+
+/// Compute the growth from `old_rate` to `new_rate`, measured `epochs` epochs apart.
+///
+/// Returns `None` if either rate is undefined (zero denominator), if the old
+/// rate is zero, or if no epochs passed.
+pub fn compute_growth(
+    old_rate: Rational,
+    new_rate: Rational,
+    epochs: u64,
+    epochs_per_year: f64,
+) -> Option<Growth> {
+    if old_rate.numerator == 0
+        || old_rate.denominator == 0
+        || new_rate.denominator == 0
+        || epochs == 0
+    {
+        return None;
+    }
+
+    // Compute `new / old - 1` as `(new.n * old.d - old.n * new.d) / (old.n * new.d)`.
+    // The products of two u64 fit in a u128, and their difference in an i128,
+    // so we only lose precision in the final conversion to f64, rather than
+    // subtracting two nearly equal floats, which would lose most of the digits
+    // for the small per-epoch growth that we are interested in.
+    let new_scaled = new_rate.numerator as u128 * old_rate.denominator as u128;
+    let old_scaled = old_rate.numerator as u128 * new_rate.denominator as u128;
+    let difference = if new_scaled >= old_scaled {
+        (new_scaled - old_scaled) as f64
+    } else {
+        -((old_scaled - new_scaled) as f64)
+    };
+    let period_growth = difference / old_scaled as f64;
+
+    let periods_per_year = epochs_per_year / epochs as f64;
+    Some(Growth {
+        period_growth,
+        apr: period_growth * periods_per_year,
+        // Equal to `(1 + g)^n - 1`, but accurate also when `g` is close to 0.
+        apy: (period_growth.ln_1p() * periods_per_year).exp_m1(),
+    })
+}
+
 /// Error returned when a calculation in a token type overflows, underflows, or divides by zero.
 #[derive(Debug, Eq, PartialEq)]
 pub struct ArithmeticError;
 
 pub type Result<T> = std::result::Result<T, ArithmeticError>;
 
+/// Compute `amount * numerator / denominator`, rounding down.
+///
+/// The intermediate product is computed in 128 bits, so this only fails if
+/// `denominator` is zero, or if the result does not fit in 64 bits.
+pub fn mul_div_floor(amount: u64, numerator: u64, denominator: u64) -> Result<u64> {
+    // This multiplication cannot overflow, because we expand the u64s into
+    // u128, and u64::MAX * u64::MAX < u128::MAX.
+    let result = (amount as u128 * numerator as u128)
+        .checked_div(denominator as u128)
+        .ok_or(ArithmeticError)?;
+    u64::try_from(result).map_err(|_| ArithmeticError)
+}
+
 /// Generate a token type that wraps the minimal unit of the token, it’s
 /// “Lamport”. The symbol is for 10<sup>9</sup> of its minimal units and is
 /// only used for `Debug` and `Display` printing.
@@ -73,6 +138,53 @@ macro_rules! impl_token {
             pub fn new(amount: u64) -> $TokenLamports {
                 $TokenLamports { amount }
             }
+
+            /// Add `other`, or return an error on overflow.
+            pub fn checked_add(self, other: $TokenLamports) -> Result<$TokenLamports> {
+                self.amount
+                    .checked_add(other.amount)
+                    .map($TokenLamports::new)
+                    .ok_or(ArithmeticError)
+            }
+
+            /// Subtract `other`, or return an error on underflow.
+            pub fn checked_sub(self, other: $TokenLamports) -> Result<$TokenLamports> {
+                self.amount
+                    .checked_sub(other.amount)
+                    .map($TokenLamports::new)
+                    .ok_or(ArithmeticError)
+            }
+
+            /// Compute `self * numerator / denominator`, rounding down, see `mul_div_floor`.
+            pub fn checked_mul_div(
+                self,
+                numerator: u64,
+                denominator: u64,
+            ) -> Result<$TokenLamports> {
+                mul_div_floor(self.amount, numerator, denominator).map($TokenLamports::new)
+            }
+
+            /// Add `other`, clamping at the maximum amount instead of overflowing.
+            pub fn saturating_add(self, other: $TokenLamports) -> $TokenLamports {
+                $TokenLamports::new(self.amount.saturating_add(other.amount))
+            }
+
+            /// Subtract `other`, clamping at zero instead of underflowing.
+            pub fn saturating_sub(self, other: $TokenLamports) -> $TokenLamports {
+                $TokenLamports::new(self.amount.saturating_sub(other.amount))
+            }
+
+            /// Sum amounts that may have failed to compute, or return the first error.
+            ///
+            /// Fails on overflow too. For amounts that are known, summing into a
+            /// `Result` with `Iterator::sum` does the same.
+            pub fn try_sum<I>(iter: I) -> Result<$TokenLamports>
+            where
+                I: IntoIterator<Item = Result<$TokenLamports>>,
+            {
+                iter.into_iter()
+                    .try_fold($TokenLamports::new(0), |sum, item| sum + item?)
+            }
         }
 
         impl fmt::Display for $TokenLamports {
@@ -96,14 +208,7 @@ macro_rules! impl_token {
         impl Mul<Rational> for $TokenLamports {
             type Output = Result<$TokenLamports>;
             fn mul(self, other: Rational) -> Result<$TokenLamports> {
-                // This multiplication cannot overflow, because we expand the
-                // u64s into u128, and u64::MAX * u64::MAX < u128::MAX.
-                let result_u128 = ((self.amount as u128) * (other.numerator as u128))
-                    .checked_div(other.denominator as u128)
-                    .ok_or(ArithmeticError)?;
-                u64::try_from(result_u128)
-                    .map($TokenLamports::new)
-                    .map_err(|_| ArithmeticError)
+                self.checked_mul_div(other.numerator, other.denominator)
             }
         }
 
@@ -130,32 +235,29 @@ macro_rules! impl_token {
         impl Sub<$TokenLamports> for $TokenLamports {
             type Output = Result<$TokenLamports>;
             fn sub(self, other: $TokenLamports) -> Result<$TokenLamports> {
-                self.amount
-                    .checked_sub(other.amount)
-                    .map($TokenLamports::new)
-                    .ok_or(ArithmeticError)
+                self.checked_sub(other)
             }
         }
 
         impl Add<$TokenLamports> for $TokenLamports {
             type Output = Result<$TokenLamports>;
             fn add(self, other: $TokenLamports) -> Result<$TokenLamports> {
-                self.amount
-                    .checked_add(other.amount)
-                    .map($TokenLamports::new)
-                    .ok_or(ArithmeticError)
+                self.checked_add(other)
             }
         }
 
         impl Sum<$TokenLamports> for Result<$TokenLamports> {
             fn sum<I: Iterator<Item = $TokenLamports>>(iter: I) -> Self {
-                let mut sum = $TokenLamports::new(0);
-                for item in iter {
-                    sum = (sum + item)?;
-                }
-                Ok(sum)
+                $TokenLamports::try_sum(iter.map(Ok))
             }
         }
+
+        impl<'a> Sum<&'a $TokenLamports> for Result<$TokenLamports> {
+            fn sum<I: Iterator<Item = &'a $TokenLamports>>(iter: I) -> Self {
+                iter.copied().sum()
+            }
+        }
+
         /// Parse a numeric string as an amount of Lamports, i.e., with 9 digit precision.
         ///
         /// Note that this parses the Lamports amount divided by 10<sup>9</sup>,
@@ -177,7 +279,10 @@ macro_rules! impl_token {
                 for ch in s.as_bytes() {
                     match ch {
                         b'0'..=b'9' => {
-                            value = value * 10 + ((ch - b'0') as u64);
+                            value = value
+                                .checked_mul(10)
+                                .and_then(|v| v.checked_add((ch - b'0') as u64))
+                                .ok_or("Value is too large.")?;
                             if is_after_decimal {
                                 exponent -= 1;
                             }
@@ -202,7 +307,7 @@ macro_rules! impl_token {
                 // (or no decimal point at all), scale up the value so it is measured
                 // in lamports.
                 while exponent > 0 {
-                    value *= 10;
+                    value = value.checked_mul(10).ok_or("Value is too large.")?;
                     exponent -= 1;
                 }
 
@@ -363,6 +468,76 @@ pub mod test {
         assert_eq!(y.partial_cmp(&x), None);
     }
 
+    #[test]
+    fn test_checked_arithmetic() {
+        let max = Lamports::new(u64::MAX);
+        let one = Lamports::new(1);
+        assert_eq!(one.checked_add(one), Ok(Lamports::new(2)));
+        assert_eq!(max.checked_add(one), Err(ArithmeticError));
+        assert_eq!(one.checked_sub(one), Ok(Lamports::new(0)));
+        assert_eq!(Lamports::new(0).checked_sub(one), Err(ArithmeticError));
+        assert_eq!(max.saturating_add(one), max);
+        assert_eq!(Lamports::new(0).saturating_sub(one), Lamports::new(0));
+
+        // The intermediate product does not fit in a u64, but the result does.
+        assert_eq!(
+            max.checked_mul_div(3, 4),
+            Ok(Lamports::new(u64::MAX / 4 * 3 + 2))
+        );
+        assert_eq!(max.checked_mul_div(4, 3), Err(ArithmeticError));
+        assert_eq!(one.checked_mul_div(1, 0), Err(ArithmeticError));
+        // The result is rounded down.
+        assert_eq!(
+            Lamports::new(10).checked_mul_div(1, 3),
+            Ok(Lamports::new(3))
+        );
+    }
+
+    #[test]
+    fn test_checked_sum() {
+        let amounts = [Lamports::new(1), Lamports::new(2), Lamports::new(3)];
+        let sum: Result<Lamports> = amounts.iter().sum();
+        assert_eq!(sum, Ok(Lamports::new(6)));
+        let sum: Result<Lamports> = amounts.iter().copied().sum();
+        assert_eq!(sum, Ok(Lamports::new(6)));
+        let empty: [Lamports; 0] = [];
+        let sum: Result<Lamports> = empty.iter().sum();
+        assert_eq!(sum, Ok(Lamports::new(0)));
+
+        let overflow = [Lamports::new(u64::MAX), Lamports::new(1)];
+        let sum: Result<Lamports> = overflow.iter().sum();
+        assert_eq!(sum, Err(ArithmeticError));
+
+        let results = amounts.iter().map(|&a| a * 2);
+        assert_eq!(Lamports::try_sum(results), Ok(Lamports::new(12)));
+        let results = amounts.iter().map(|&a| a - Lamports::new(2));
+        assert_eq!(Lamports::try_sum(results), Err(ArithmeticError));
+        let results = overflow.iter().map(|&a| Ok(a));
+        assert_eq!(Lamports::try_sum(results), Err(ArithmeticError));
+    }
+
+    #[test]
+    fn test_mul_div_floor() {
+        assert_eq!(mul_div_floor(10, 1, 3), Ok(3));
+        // Exact results are not rounded.
+        assert_eq!(mul_div_floor(12, 1, 3), Ok(4));
+        assert_eq!(mul_div_floor(0, 7, 3), Ok(0));
+        assert_eq!(mul_div_floor(u64::MAX, 2, 2), Ok(u64::MAX));
+        assert_eq!(mul_div_floor(u64::MAX, 3, 2), Err(ArithmeticError));
+        assert_eq!(mul_div_floor(1, 1, 0), Err(ArithmeticError));
+    }
+
+    #[test]
+    fn test_lamports_from_str_overflow() {
+        assert_eq!(
+            Lamports::from_str("18446744073.709551615"),
+            Ok(Lamports::new(u64::MAX))
+        );
+        assert!(Lamports::from_str("18446744073.709551616").is_err());
+        assert!(Lamports::from_str("18446744074").is_err());
+        assert!(Lamports::from_str("100000000000000000000").is_err());
+    }
+
     #[test]
     fn test_token_format() {
         assert_eq!(format!("{}", Lamports::new(1)), "0.000000001 SOL");
@@ -377,6 +552,112 @@ pub mod test {
         );
     }
 
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() <= 1e-12 * expected.abs().max(1.0),
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_compute_growth_fixtures() {
+        // 1% growth in a single epoch, with 10 epochs per year.
+        let growth = compute_growth(
+            Rational {
+                numerator: 100,
+                denominator: 100,
+            },
+            Rational {
+                numerator: 101,
+                denominator: 100,
+            },
+            1,
+            10.0,
+        )
+        .unwrap();
+        assert_close(growth.period_growth, 0.01);
+        assert_close(growth.apr, 0.1);
+        assert_close(growth.apy, 0.104_622_125_411_204_5);
+
+        // The same growth measured over two epochs is half the rate.
+        let growth = compute_growth(
+            Rational {
+                numerator: 100,
+                denominator: 100,
+            },
+            Rational {
+                numerator: 101,
+                denominator: 100,
+            },
+            2,
+            10.0,
+        )
+        .unwrap();
+        assert_close(growth.apr, 0.05);
+        assert_close(growth.apy, 0.051_010_050_1);
+
+        // A loss results in negative growth.
+        let growth = compute_growth(
+            Rational {
+                numerator: 2,
+                denominator: 1,
+            },
+            Rational {
+                numerator: 1,
+                denominator: 1,
+            },
+            1,
+            1.0,
+        )
+        .unwrap();
+        assert_close(growth.period_growth, -0.5);
+        assert_close(growth.apy, -0.5);
+    }
+
+    #[test]
+    fn test_compute_growth_large_numbers() {
+        // Balances close to u64::MAX with a growth of one lamport must neither
+        // overflow nor round to zero.
+        let growth = compute_growth(
+            Rational {
+                numerator: u64::MAX - 1,
+                denominator: u64::MAX - 1,
+            },
+            Rational {
+                numerator: u64::MAX,
+                denominator: u64::MAX - 1,
+            },
+            1,
+            1.0,
+        )
+        .unwrap();
+        assert!(growth.period_growth > 0.0);
+        assert_close(growth.period_growth, 1.0 / (u64::MAX - 1) as f64);
+    }
+
+    #[test]
+    fn test_compute_growth_undefined() {
+        let one = Rational {
+            numerator: 1,
+            denominator: 1,
+        };
+        let zero = Rational {
+            numerator: 0,
+            denominator: 1,
+        };
+        let undefined = Rational {
+            numerator: 1,
+            denominator: 0,
+        };
+        assert_eq!(compute_growth(zero, one, 1, 1.0), None);
+        assert_eq!(compute_growth(undefined, one, 1, 1.0), None);
+        assert_eq!(compute_growth(one, undefined, 1, 1.0), None);
+        assert_eq!(compute_growth(one, one, 0, 1.0), None);
+        assert_eq!(compute_growth(one, one, 1, 1.0).map(|g| g.apy), Some(0.0));
+    }
+
     #[test]
     fn test_division_with_large_number() {
         let x = Rational {
@@ -0,0 +1,112 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Human-readable information about a validator of an instance.
+//!
+//! The `Validator` entries in `Lido` only hold what the program needs. Dashboards
+//! that show the stake allocation want a name and an icon as well, so these live
+//! in a companion account per validator, that the validator's identity and the
+//! manager can write.
+
+use anchor_lang::prelude::*;
+
+use crate::error::LidoError;
+
+/// Maximum length of `ValidatorInfo::name`, in bytes.
+pub const MAX_VALIDATOR_NAME_LEN: usize = 64;
+
+/// Maximum length of `ValidatorInfo::metadata_uri`, in bytes.
+pub const MAX_VALIDATOR_METADATA_URI_LEN: usize = 200;
+
+/// Maximum length of `ValidatorInfo::contact`, in bytes.
+pub const MAX_VALIDATOR_CONTACT_LEN: usize = 100;
+
+#[account]
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct ValidatorInfo {
+    /// The instance that the validator is part of.
+    pub lido: Pubkey,
+
+    /// Vote account of the validator.
+    pub validator_vote: Pubkey,
+
+    /// Display name of the validator.
+    pub name: String,
+
+    /// URI of a JSON document with further metadata, such as an icon.
+    pub metadata_uri: String,
+
+    /// How to reach the validator operator, e.g. an email address.
+    pub contact: String,
+}
+
+impl ValidatorInfo {
+    /// Number of bytes needed for the account, with all strings at their maximum length.
+    pub const LEN: usize = 8
+        + 32
+        + 32
+        + (4 + MAX_VALIDATOR_NAME_LEN)
+        + (4 + MAX_VALIDATOR_METADATA_URI_LEN)
+        + (4 + MAX_VALIDATOR_CONTACT_LEN);
+
+    /// Replace the information, if every field fits in the account.
+    pub fn set(&mut self, name: String, metadata_uri: String, contact: String) -> Result<()> {
+        for (field, value, max_len) in [
+            ("name", &name, MAX_VALIDATOR_NAME_LEN),
+            (
+                "metadata_uri",
+                &metadata_uri,
+                MAX_VALIDATOR_METADATA_URI_LEN,
+            ),
+            ("contact", &contact, MAX_VALIDATOR_CONTACT_LEN),
+        ] {
+            if value.len() > max_len {
+                msg!(
+                    "The {} is {} bytes long, but at most {} bytes are allowed.",
+                    field,
+                    value.len(),
+                    max_len
+                );
+                return err!(LidoError::ValidatorInfoTooLong);
+            }
+        }
+
+        self.name = name;
+        self.metadata_uri = metadata_uri;
+        self.contact = contact;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_rejects_too_long_fields() {
+        let mut info = ValidatorInfo::default();
+        info.set(
+            "Validator".to_string(),
+            "https://example.com/validator.json".to_string(),
+            "ops@example.com".to_string(),
+        )
+        .unwrap();
+        assert_eq!(info.name, "Validator");
+
+        let too_long = "x".repeat(MAX_VALIDATOR_NAME_LEN + 1);
+        assert!(info.set(too_long, String::new(), String::new()).is_err());
+        // A failed update leaves the previous information in place.
+        assert_eq!(info.name, "Validator");
+    }
+
+    #[test]
+    fn test_len_fits_maximum_lengths() {
+        let info = ValidatorInfo {
+            name: "n".repeat(MAX_VALIDATOR_NAME_LEN),
+            metadata_uri: "u".repeat(MAX_VALIDATOR_METADATA_URI_LEN),
+            contact: "c".repeat(MAX_VALIDATOR_CONTACT_LEN),
+            ..ValidatorInfo::default()
+        };
+        assert_eq!(8 + info.try_to_vec().unwrap().len(), ValidatorInfo::LEN);
+    }
+}
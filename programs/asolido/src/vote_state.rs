@@ -1,8 +1,9 @@
 use crate::LidoError;
+use anchor_lang::error;
+use anchor_lang::AccountDeserialize;
 use solana_program::pubkey::Pubkey;
 use solana_program::vote::program::ID;
 use std::convert::TryInto;
-use anchor_lang::error;
 
 /// Structure used to read the first 4 fields of a Solana `VoteAccount`.
 /// The original `VoteAccount` structure cannot be used in a Solana
@@ -20,6 +21,55 @@ pub struct PartialVoteState {
     /// percentage (0-100) that represents what part of a rewards
     ///  payout should be given to this VoteAccount
     pub commission: u8,
+    /// slot of the most recent vote in the vote tower, `None` if the
+    /// validator never voted
+    pub last_vote_slot: Option<u64>,
+    /// credits earned per epoch, only read by `try_deserialize_with_epoch_credits`,
+    /// `None` otherwise
+    pub epoch_credits: Option<Vec<EpochCredits>>,
+}
+
+/// The credits that a validator earned in one epoch, an entry of `VoteState::epoch_credits`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EpochCredits {
+    pub epoch: u64,
+    /// total credits at the end of `epoch`
+    pub credits: u64,
+    /// total credits at the end of the previous entry
+    pub prev_credits: u64,
+}
+
+impl EpochCredits {
+    /// Return the credits earned in this epoch.
+    pub fn earned(&self) -> u64 {
+        self.credits.saturating_sub(self.prev_credits)
+    }
+}
+
+/// Size of a vote account in the current layout, `VoteState::size_of()` in the vote program.
+///
+/// Vote accounts are created with this size, so a smaller account cannot hold a vote state.
+pub const MINIMUM_VOTE_ACCOUNT_SIZE: usize = 3731;
+
+/// Size of the `prior_voters` circular buffer: 32 entries of a pubkey and two
+/// epochs, followed by a u64 index and a bool.
+const PRIOR_VOTERS_SIZE: usize = 32 * (32 + 8 + 8) + 8 + 1;
+
+/// Return `offset + count * size`, failing on overflow.
+fn skip(offset: usize, count: u64, size: usize) -> anchor_lang::Result<usize> {
+    (count as usize)
+        .checked_mul(size)
+        .and_then(|len| len.checked_add(offset))
+        .ok_or_else(|| error!(LidoError::InvalidVoteAccount))
+}
+
+/// Read a little-endian u64 at `offset`, failing if `data` is too short.
+fn read_u64(data: &[u8], offset: usize) -> anchor_lang::Result<u64> {
+    offset
+        .checked_add(8)
+        .and_then(|end| data.get(offset..end))
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| error!(LidoError::InvalidVoteAccount))
 }
 
 impl anchor_lang::AccountDeserialize for PartialVoteState {
@@ -42,15 +92,84 @@ impl anchor_lang::AccountDeserialize for PartialVoteState {
         // Read 1 byte for u8.
         let commission = data[68];
 
+        // Read the `votes` deque: a u64 length, followed by lockouts of a u64
+        // slot and a u32 confirmation count each. The most recent vote is last.
+        let num_votes = read_u64(data, 69)?;
+        let last_vote_slot = match num_votes {
+            0 => None,
+            n => Some(read_u64(data, skip(77, n - 1, 12)?)?),
+        };
+
         Ok(PartialVoteState {
             version,
             node_pubkey,
             authorized_withdrawer,
             commission,
+            last_vote_slot,
+            epoch_credits: None,
         })
     }
 }
 
+impl PartialVoteState {
+    /// Deserialize the vote state, including `epoch_credits`.
+    ///
+    /// The credits follow the vote tower, the authorized voters and the
+    /// prior voters, so reading them walks most of the account. Instructions
+    /// that only need the first fields, like `AddValidator`, deserialize the
+    /// account as usual instead.
+    pub fn try_deserialize_with_epoch_credits(data: &[u8]) -> anchor_lang::Result<Self> {
+        let mut vote_state = PartialVoteState::try_deserialize_unchecked(&mut &data[..])?;
+        // Only the current layout is known, older ones do not have the same fields.
+        if vote_state.version != 1 {
+            return Err(error!(LidoError::InvalidVoteAccount));
+        }
+
+        let num_votes = read_u64(data, 69)?;
+        let mut offset = skip(77, num_votes, 12)?;
+
+        // `root_slot` is an `Option<u64>`: a tag byte, followed by the slot if it is set.
+        offset = match data.get(offset) {
+            Some(0) => offset + 1,
+            Some(1) => offset + 1 + 8,
+            _ => return Err(error!(LidoError::InvalidVoteAccount)),
+        };
+
+        // `authorized_voters` maps epochs to pubkeys: a u64 length, followed by the pairs.
+        let num_authorized_voters = read_u64(data, offset)?;
+        offset = skip(offset + 8, num_authorized_voters, 8 + 32)?;
+        offset = skip(offset, 1, PRIOR_VOTERS_SIZE)?;
+
+        // `epoch_credits` is a u64 length, followed by triples of u64s.
+        let num_epoch_credits = read_u64(data, offset)?;
+        offset += 8;
+        let mut epoch_credits = Vec::new();
+        for _ in 0..num_epoch_credits {
+            epoch_credits.push(EpochCredits {
+                epoch: read_u64(data, offset)?,
+                credits: read_u64(data, offset + 8)?,
+                prev_credits: read_u64(data, offset + 16)?,
+            });
+            offset += 24;
+        }
+
+        vote_state.epoch_credits = Some(epoch_credits);
+        Ok(vote_state)
+    }
+
+    /// Return the credits earned in the epochs from `start_epoch` up to but excluding `end_epoch`.
+    ///
+    /// Returns `None` if the vote state was deserialized without `epoch_credits`.
+    pub fn credits_earned(&self, start_epoch: u64, end_epoch: u64) -> Option<u64> {
+        let epoch_credits = self.epoch_credits.as_ref()?;
+        let earned = epoch_credits
+            .iter()
+            .filter(|entry| entry.epoch >= start_epoch && entry.epoch < end_epoch)
+            .fold(0_u64, |sum, entry| sum.saturating_add(entry.earned()));
+        Some(earned)
+    }
+}
+
 impl anchor_lang::AccountSerialize for PartialVoteState {}
 
 impl anchor_lang::Owner for PartialVoteState {
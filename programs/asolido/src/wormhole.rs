@@ -0,0 +1,165 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Messages to the Wormhole core bridge, for deployments of wrapped stSOL on other chains.
+//!
+//! A wrapped stSOL on an EVM chain or an L2 needs the exchange rate to price
+//! its token, and an oracle that relays it from this program has to be
+//! trusted. Instead, once the manager sets `Config::wormhole_program`, anybody
+//! can call `PostExchangeRateMessage` after the exchange rate of the epoch is
+//! updated. It posts the rate as a Wormhole message, emitted by a
+//! program-derived address of the instance, so the guardians sign it, and a
+//! contract on the other chain can check the emitter and trust the payload.
+//!
+//! We do not depend on the Wormhole SDK, the instruction is small enough to
+//! build here. The layout follows the `post_message` instruction of the core
+//! bridge on Solana.
+
+use anchor_lang::prelude::*;
+use solana_program::instruction::{AccountMeta, Instruction};
+
+use crate::error::LidoError;
+use crate::state::ExchangeRate;
+
+/// Index of the `post_message` instruction of the core bridge.
+pub const POST_MESSAGE_INSTRUCTION: u8 = 1;
+
+/// Consistency level `Finalized`, the guardians only sign once the slot is finalized.
+pub const CONSISTENCY_LEVEL_FINALIZED: u8 = 1;
+
+/// First byte of the payload, so that consumers can tell our payloads apart.
+pub const EXCHANGE_RATE_PAYLOAD_ID: u8 = 1;
+
+/// Number of bytes of the payload that `exchange_rate_payload` returns.
+pub const EXCHANGE_RATE_PAYLOAD_LEN: usize = 1 + 32 + 8 + 8 + 8;
+
+/// Offset of the message fee in the data of the bridge account.
+///
+/// The account starts with the guardian set index (`u32`), the last balance
+/// of the fee collector (`u64`), and the guardian set expiration time (`u32`).
+const BRIDGE_FEE_OFFSET: usize = 4 + 8 + 4;
+
+/// Return the payload that announces `rate` as the exchange rate of `lido`.
+///
+/// Integers are big-endian, as is usual for Wormhole payloads, because
+/// contracts on EVM chains decode them more cheaply.
+pub fn exchange_rate_payload(lido: &Pubkey, rate: &ExchangeRate) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(EXCHANGE_RATE_PAYLOAD_LEN);
+    payload.push(EXCHANGE_RATE_PAYLOAD_ID);
+    payload.extend_from_slice(lido.as_ref());
+    payload.extend_from_slice(&rate.computed_in_epoch.to_be_bytes());
+    payload.extend_from_slice(&rate.st_sol_supply.amount.to_be_bytes());
+    payload.extend_from_slice(&rate.sol_balance.amount.to_be_bytes());
+    payload
+}
+
+/// Read the fee that the core bridge charges per message from its bridge account.
+pub fn read_message_fee(bridge_data: &[u8]) -> Result<u64> {
+    match bridge_data.get(BRIDGE_FEE_OFFSET..BRIDGE_FEE_OFFSET + 8) {
+        Some(bytes) => {
+            let mut fee = [0; 8];
+            fee.copy_from_slice(bytes);
+            Ok(u64::from_le_bytes(fee))
+        }
+        None => {
+            msg!(
+                "The Wormhole bridge account holds only {} bytes.",
+                bridge_data.len()
+            );
+            err!(LidoError::InvalidWormholeAccount)
+        }
+    }
+}
+
+/// The accounts of the `post_message` instruction of the core bridge.
+pub struct PostMessageAccounts {
+    pub bridge: Pubkey,
+    pub message: Pubkey,
+    pub emitter: Pubkey,
+    pub sequence: Pubkey,
+    pub payer: Pubkey,
+    pub fee_collector: Pubkey,
+}
+
+/// Return the instruction that posts `payload` as a message of `accounts.emitter`.
+pub fn post_message_instruction(
+    wormhole_program: &Pubkey,
+    accounts: &PostMessageAccounts,
+    nonce: u32,
+    payload: &[u8],
+) -> Instruction {
+    let mut data = Vec::with_capacity(1 + 4 + 4 + payload.len() + 1);
+    data.push(POST_MESSAGE_INSTRUCTION);
+    data.extend_from_slice(&nonce.to_le_bytes());
+    data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    data.extend_from_slice(payload);
+    data.push(CONSISTENCY_LEVEL_FINALIZED);
+
+    Instruction {
+        program_id: *wormhole_program,
+        accounts: vec![
+            AccountMeta::new(accounts.bridge, false),
+            AccountMeta::new(accounts.message, true),
+            AccountMeta::new_readonly(accounts.emitter, true),
+            AccountMeta::new(accounts.sequence, false),
+            AccountMeta::new(accounts.payer, true),
+            AccountMeta::new(accounts.fee_collector, false),
+            AccountMeta::new_readonly(solana_program::sysvar::clock::ID, false),
+            AccountMeta::new_readonly(solana_program::sysvar::rent::ID, false),
+            AccountMeta::new_readonly(solana_program::system_program::ID, false),
+        ],
+        data,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::token::{Lamports, StLamports};
+
+    #[test]
+    fn test_exchange_rate_payload_is_big_endian() {
+        let lido = Pubkey::new_unique();
+        let rate = ExchangeRate {
+            computed_in_epoch: 300,
+            st_sol_supply: StLamports::new(1),
+            sol_balance: Lamports::new(2),
+        };
+        let payload = exchange_rate_payload(&lido, &rate);
+        assert_eq!(payload.len(), EXCHANGE_RATE_PAYLOAD_LEN);
+        assert_eq!(payload[0], EXCHANGE_RATE_PAYLOAD_ID);
+        assert_eq!(&payload[1..33], lido.as_ref());
+        assert_eq!(&payload[33..41], &[0, 0, 0, 0, 0, 0, 1, 44]);
+        assert_eq!(&payload[41..49], &[0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(&payload[49..57], &[0, 0, 0, 0, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_read_message_fee() {
+        let mut bridge_data = vec![0; BRIDGE_FEE_OFFSET];
+        bridge_data.extend_from_slice(&100_u64.to_le_bytes());
+        assert_eq!(read_message_fee(&bridge_data).unwrap(), 100);
+        assert!(read_message_fee(&bridge_data[..BRIDGE_FEE_OFFSET + 7]).is_err());
+    }
+
+    #[test]
+    fn test_post_message_instruction_data() {
+        let accounts = PostMessageAccounts {
+            bridge: Pubkey::new_unique(),
+            message: Pubkey::new_unique(),
+            emitter: Pubkey::new_unique(),
+            sequence: Pubkey::new_unique(),
+            payer: Pubkey::new_unique(),
+            fee_collector: Pubkey::new_unique(),
+        };
+        let wormhole_program = Pubkey::new_unique();
+        let instruction = post_message_instruction(&wormhole_program, &accounts, 7, &[9, 9]);
+        assert_eq!(instruction.program_id, wormhole_program);
+        assert_eq!(instruction.data, vec![1, 7, 0, 0, 0, 2, 0, 0, 0, 9, 9, 1]);
+        assert_eq!(
+            instruction.accounts[2],
+            AccountMeta::new_readonly(accounts.emitter, true)
+        );
+        assert_eq!(instruction.accounts.len(), 9);
+    }
+}
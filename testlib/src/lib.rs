@@ -0,0 +1,291 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Fixtures for testing the Solido program with `solana-program-test`.
+//!
+//! The program only accepts vote accounts that it is the withdraw authority
+//! of, and most maintenance instructions need stake that is fully active.
+//! Setting that up with transactions takes several epochs, so these helpers
+//! write the accounts into the bank of the test directly, and warp to the
+//! epoch in which the stake is active.
+
+use asolido::pda::{
+    find_mint_authority, find_rewards_withdraw_authority, find_stake_account, find_stake_authority,
+};
+use solana_program_test::{ProgramTest, ProgramTestContext};
+use solana_sdk::account::{AccountSharedData, WritableAccount};
+use solana_sdk::clock::{Clock, Epoch};
+use solana_sdk::program_option::COption;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::rent::Rent;
+use solana_sdk::stake::state::{Authorized, Delegation, Lockup, Meta, Stake, StakeState};
+use solana_vote_program::vote_state::{VoteInit, VoteState, VoteStateVersions};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+/// Commission of the vote accounts created by `vote_account`.
+///
+/// Solido collects the rewards of its validators from their vote accounts,
+/// so their commission is 100%, and the program takes its fees from there.
+pub const VOTE_ACCOUNT_COMMISSION: u8 = 100;
+
+/// Return a `ProgramTest` that loads the Solido program.
+///
+/// This runs the compiled program, build it with `anchor build` first, and
+/// point `BPF_OUT_DIR` at `target/deploy`.
+pub fn program_test() -> ProgramTest {
+    ProgramTest::new("asolido", asolido::ID, None)
+}
+
+/// Return a rent-exempt vote account of `node`, with the given withdraw authority.
+///
+/// The vote account has never voted.
+pub fn vote_account(rent: &Rent, node: &Pubkey, withdrawer: &Pubkey) -> AccountSharedData {
+    let vote_init = VoteInit {
+        node_pubkey: *node,
+        authorized_voter: *node,
+        authorized_withdrawer: *withdrawer,
+        commission: VOTE_ACCOUNT_COMMISSION,
+    };
+    let vote_state = VoteState::new(&vote_init, &Clock::default());
+    let mut account = AccountSharedData::new(
+        rent.minimum_balance(VoteState::size_of()),
+        VoteState::size_of(),
+        &solana_vote_program::id(),
+    );
+    VoteState::to(&VoteStateVersions::new_current(vote_state), &mut account)
+        .expect("The vote state fits in an account of its size.");
+    account
+}
+
+/// Return a stake account with `lamports`, delegated to `validator_vote` in epoch 0.
+///
+/// The stake history of a test bank has no entry for epoch 0, so the stake
+/// program considers the stake fully active from epoch 1 on, see `warp_to_epoch`.
+/// `authority` is both the staker and the withdrawer.
+pub fn active_stake_account(
+    rent: &Rent,
+    validator_vote: &Pubkey,
+    authority: &Pubkey,
+    lamports: u64,
+) -> AccountSharedData {
+    let rent_exempt_reserve = rent.minimum_balance(StakeState::size_of());
+    let meta = Meta {
+        rent_exempt_reserve,
+        authorized: Authorized {
+            staker: *authority,
+            withdrawer: *authority,
+        },
+        lockup: Lockup::default(),
+    };
+    let stake = lamports
+        .checked_sub(rent_exempt_reserve)
+        .expect("The stake account must hold at least the rent-exempt reserve.");
+    let stake = Stake {
+        delegation: Delegation::new(validator_vote, stake, 0),
+        credits_observed: 0,
+    };
+    let mut account = AccountSharedData::new(
+        lamports,
+        StakeState::size_of(),
+        &solana_sdk::stake::program::id(),
+    );
+    account
+        .serialize_data(&StakeState::Stake(meta, stake))
+        .expect("The stake state fits in an account of its size.");
+    account
+}
+
+/// Return a rent-exempt SPL token mint without supply, and without freeze authority.
+pub fn mint_account(rent: &Rent, mint_authority: &Pubkey) -> AccountSharedData {
+    let mint = Mint {
+        mint_authority: COption::Some(*mint_authority),
+        decimals: 9,
+        is_initialized: true,
+        ..Mint::default()
+    };
+    let mut account =
+        AccountSharedData::new(rent.minimum_balance(Mint::LEN), Mint::LEN, &spl_token::id());
+    Mint::pack(mint, account.data_as_mut_slice())
+        .expect("The mint fits in an account of its size.");
+    account
+}
+
+/// Return a rent-exempt SPL token account of `mint` without balance, owned by `owner`.
+pub fn token_account(rent: &Rent, mint: &Pubkey, owner: &Pubkey) -> AccountSharedData {
+    let token = TokenAccount {
+        mint: *mint,
+        owner: *owner,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    };
+    let mut account = AccountSharedData::new(
+        rent.minimum_balance(TokenAccount::LEN),
+        TokenAccount::LEN,
+        &spl_token::id(),
+    );
+    TokenAccount::pack(token, account.data_as_mut_slice())
+        .expect("The token account fits in an account of its size.");
+    account
+}
+
+/// Add an stSOL mint for `lido` to the bank, minted by its mint authority.
+///
+/// Returns the address of the mint, which can be passed to `Initialize`.
+pub fn add_st_sol_mint(context: &mut ProgramTestContext, lido: &Pubkey) -> Pubkey {
+    let (mint_authority, _) = find_mint_authority(&asolido::ID, lido);
+    let address = Pubkey::new_unique();
+    context.set_account(&address, &mint_account(&Rent::default(), &mint_authority));
+    address
+}
+
+/// Add an empty token account of `mint` to the bank, owned by `owner`.
+///
+/// Returns the address of the token account.
+pub fn add_token_account(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    let address = Pubkey::new_unique();
+    context.set_account(&address, &token_account(&Rent::default(), mint, owner));
+    address
+}
+
+/// Add a vote account of `node` to the bank, withdrawable by the rewards withdraw authority.
+///
+/// Returns the address of the vote account, which can be passed to `AddValidator`.
+pub fn add_vote_account(context: &mut ProgramTestContext, lido: &Pubkey, node: &Pubkey) -> Pubkey {
+    let (withdrawer, _) = find_rewards_withdraw_authority(&asolido::ID, lido);
+    let address = Pubkey::new_unique();
+    context.set_account(&address, &vote_account(&Rent::default(), node, &withdrawer));
+    address
+}
+
+/// Add a stake account of `lido` to the bank, at the address of the validator's stake seed `seed`.
+///
+/// The stake authority of `lido` is the staker and withdrawer. Returns the
+/// address of the stake account. The program tracks the seed range and the
+/// balance of the stake accounts that it created itself in the `Lido`
+/// account, so instructions that read stake accounts, such as
+/// `WithdrawInactiveStake`, only accept this one if that state matches.
+pub fn add_active_stake_account(
+    context: &mut ProgramTestContext,
+    lido: &Pubkey,
+    validator_vote: &Pubkey,
+    seed: u64,
+    lamports: u64,
+) -> Pubkey {
+    let (stake_authority, _) = find_stake_authority(&asolido::ID, lido);
+    let (address, _) = find_stake_account(&asolido::ID, lido, validator_vote, seed);
+    let account =
+        active_stake_account(&Rent::default(), validator_vote, &stake_authority, lamports);
+    context.set_account(&address, &account);
+    address
+}
+
+/// Warp the bank to the first slot of `epoch`.
+///
+/// Panics if the bank is already past that slot.
+pub fn warp_to_epoch(context: &mut ProgramTestContext, epoch: Epoch) {
+    let slot = context
+        .genesis_config()
+        .epoch_schedule
+        .get_first_slot_in_epoch(epoch);
+    context
+        .warp_to_slot(slot)
+        .expect("Can only warp to a later slot.");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anchor_lang::AccountDeserialize;
+    use asolido::vote_state::{EpochCredits, PartialVoteState};
+    use solana_sdk::account::ReadableAccount;
+    use solana_sdk::stake_history::StakeHistory;
+
+    #[test]
+    fn test_vote_account_is_read_by_the_program() {
+        let rent = Rent::default();
+        let node = Pubkey::new_unique();
+        let withdrawer = Pubkey::new_unique();
+        let account = vote_account(&rent, &node, &withdrawer);
+        assert!(rent.is_exempt(account.lamports(), account.data().len()));
+
+        let vote_state = PartialVoteState::try_deserialize(&mut account.data()).unwrap();
+        assert_eq!(vote_state.version, 1);
+        assert_eq!(vote_state.node_pubkey, node);
+        assert_eq!(vote_state.authorized_withdrawer, withdrawer);
+        assert_eq!(vote_state.commission, VOTE_ACCOUNT_COMMISSION);
+        assert_eq!(vote_state.last_vote_slot, None);
+        assert_eq!(vote_state.epoch_credits, None);
+    }
+
+    #[test]
+    fn test_epoch_credits_are_read_after_the_variable_length_fields() {
+        let vote_init = VoteInit {
+            node_pubkey: Pubkey::new_unique(),
+            authorized_voter: Pubkey::new_unique(),
+            authorized_withdrawer: Pubkey::new_unique(),
+            commission: VOTE_ACCOUNT_COMMISSION,
+        };
+        let mut vote_state = VoteState::new(&vote_init, &Clock::default());
+        for slot in 1..=3 {
+            vote_state.process_slot_vote_unchecked(slot);
+        }
+        vote_state.root_slot = Some(1);
+        vote_state.epoch_credits = vec![(1, 10, 0), (2, 25, 10), (4, 30, 25)];
+
+        let mut account =
+            AccountSharedData::new(1, VoteState::size_of(), &solana_vote_program::id());
+        VoteState::to(&VoteStateVersions::new_current(vote_state), &mut account).unwrap();
+
+        let vote_state =
+            PartialVoteState::try_deserialize_with_epoch_credits(account.data()).unwrap();
+        assert_eq!(vote_state.last_vote_slot, Some(3));
+        let epoch_credits = vote_state.epoch_credits.as_ref().unwrap();
+        assert_eq!(epoch_credits.len(), 3);
+        assert_eq!(
+            epoch_credits[1],
+            EpochCredits {
+                epoch: 2,
+                credits: 25,
+                prev_credits: 10
+            }
+        );
+        assert_eq!(vote_state.credits_earned(0, 5), Some(30));
+        assert_eq!(vote_state.credits_earned(2, 4), Some(15));
+        assert_eq!(vote_state.credits_earned(5, 6), Some(0));
+
+        // The fast path does not read the credits.
+        let vote_state = PartialVoteState::try_deserialize(&mut account.data()).unwrap();
+        assert_eq!(vote_state.credits_earned(0, 5), None);
+    }
+
+    #[test]
+    fn test_stake_account_is_active_from_epoch_one() {
+        let rent = Rent::default();
+        let validator_vote = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let reserve = rent.minimum_balance(StakeState::size_of());
+        let account = active_stake_account(&rent, &validator_vote, &authority, reserve + 1_000);
+
+        let (meta, stake) = match account.deserialize_data::<StakeState>().unwrap() {
+            StakeState::Stake(meta, stake) => (meta, stake),
+            _ => panic!("The stake account should be delegated."),
+        };
+        assert_eq!(meta.authorized.staker, authority);
+        assert_eq!(meta.authorized.withdrawer, authority);
+        assert_eq!(stake.delegation.voter_pubkey, validator_vote);
+
+        let history = StakeHistory::default();
+        let at_epoch = |epoch| {
+            stake
+                .delegation
+                .stake_activating_and_deactivating(epoch, Some(&history))
+        };
+        assert_eq!(at_epoch(0), (0, 1_000, 0));
+        assert_eq!(at_epoch(1), (1_000, 0, 0));
+    }
+}
@@ -0,0 +1,114 @@
+// SPDX-FileCopyrightText: 2021 Chorus One AG
+// SPDX-License-Identifier: GPL-3.0
+
+//! Add a validator to a new instance, like `Should add validator` in `tests/add_remove_validator.ts`.
+
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use asolido::pda::{
+    find_compliance_authority, find_config_address, find_mint_authority, find_reserve_address,
+    find_rewards_withdraw_authority, find_stake_authority,
+};
+use asolido::state::{FeeDenomination, FreezeAuthorityPolicy, Lido, RewardDistribution};
+use solana_program_test::{tokio, ProgramTestContext};
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::system_program;
+use solana_sdk::transaction::Transaction;
+use testlib::{add_st_sol_mint, add_token_account, add_vote_account, program_test};
+
+async fn send(context: &mut ProgramTestContext, instruction: Instruction, signers: &[&Keypair]) {
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        context.last_blockhash,
+    );
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_add_validator() {
+    let mut context = program_test().start_with_context().await;
+    let lido = Keypair::new();
+    let manager = Keypair::new();
+    let owner = Pubkey::new_unique();
+
+    let st_sol_mint = add_st_sol_mint(&mut context, &lido.pubkey());
+    let treasury = add_token_account(&mut context, &st_sol_mint, &owner);
+    let developer = add_token_account(&mut context, &st_sol_mint, &owner);
+    let fee = add_token_account(&mut context, &st_sol_mint, &owner);
+    let vote = add_vote_account(&mut context, &lido.pubkey(), &Pubkey::new_unique());
+
+    let lido_address = lido.pubkey();
+    let (config, _) = find_config_address(&asolido::ID, &lido_address);
+    let (rewards_withdraw_authority, _) =
+        find_rewards_withdraw_authority(&asolido::ID, &lido_address);
+    let initialize = Instruction {
+        program_id: asolido::ID,
+        accounts: asolido::accounts::Initialize {
+            lido: lido_address,
+            manager: manager.pubkey(),
+            st_sol_mint,
+            treasury,
+            developer,
+            reserve: find_reserve_address(&asolido::ID, &lido_address).0,
+            config,
+            mint_authority: find_mint_authority(&asolido::ID, &lido_address).0,
+            compliance_authority: find_compliance_authority(&asolido::ID, &lido_address).0,
+            stake_authority: find_stake_authority(&asolido::ID, &lido_address).0,
+            rewards_withdraw_authority,
+            payer: context.payer.pubkey(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: asolido::instruction::Initialize {
+            reward_distribution: RewardDistribution {
+                treasury_fee: 5,
+                validation_fee: 3,
+                developer_fee: 2,
+                st_sol_appreciation: 90,
+            },
+            max_validators: 1,
+            max_maintainers: 1,
+            freeze_authority_policy: FreezeAuthorityPolicy::None,
+            fee_denomination: FeeDenomination::StSol,
+        }
+        .data(),
+    };
+    send(&mut context, initialize, &[&lido]).await;
+
+    let add_validator = Instruction {
+        program_id: asolido::ID,
+        accounts: asolido::accounts::AddValidator {
+            lido: lido_address,
+            config,
+            manager: manager.pubkey(),
+            validator_vote: vote,
+            validator_fee_st_sol: fee,
+            rewards_withdraw_authority,
+        }
+        .to_account_metas(None),
+        data: asolido::instruction::AddValidator {}.data(),
+    };
+    send(&mut context, add_validator, &[&manager]).await;
+
+    let account = context
+        .banks_client
+        .get_account(lido_address)
+        .await
+        .unwrap()
+        .expect("The instance was initialized.");
+    let lido = Lido::try_deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(lido.validators.len(), 1);
+    let validator = &lido.validators.entries[0];
+    assert_eq!(validator.pubkey, vote);
+    assert_eq!(validator.entry.fee_address, fee);
+    assert!(validator.entry.active);
+}
@@ -95,7 +95,7 @@ describe("Add Remove Validator", () => {
 
     // Initialize Lido
     await program.methods
-      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000)
+      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000, {none: {}}, {stSol: {}})
       .accounts({
         lido: lido.publicKey,
         manager: manager.publicKey,
@@ -126,6 +126,234 @@ describe("Add Remove Validator", () => {
     expect(validator.entry.active).to.be.true;
   });
 
+  it("Should preview the reward split without changing state", async () => {
+    const before = await provider.connection.getAccountInfo(lido.publicKey);
+    const simulation = await program.methods.previewRewardSplit({amount: new BN(1000)})
+      .accounts({lido: lido.publicKey})
+      .simulate();
+
+    const prefix = `Program return: ${program.programId.toBase58()} `;
+    const returned = simulation.raw.find((log) => log.startsWith(prefix));
+    const data = Buffer.from(returned.slice(prefix.length), "base64");
+    const u64 = (i: number) => new BN(data.slice(8 * i, 8 * (i + 1)), "le").toNumber();
+    // The distribution is 5/3/2/90, the validation fee is for this validator only.
+    expect(u64(0)).to.be.equal(50);
+    expect(u64(1)).to.be.equal(30);
+    expect(u64(2)).to.be.equal(20);
+    expect(u64(3)).to.be.equal(900);
+    // All fees are paid in stSOL, at the initial exchange rate of 1:1.
+    expect(u64(4)).to.be.equal(50);
+    expect(u64(5)).to.be.equal(20);
+    expect(u64(6)).to.be.equal(30);
+    expect(u64(7)).to.be.equal(0);
+    expect(u64(8)).to.be.equal(0);
+    expect(u64(9)).to.be.equal(0);
+
+    const after = await provider.connection.getAccountInfo(lido.publicKey);
+    expect(after.data).to.be.deep.equal(before.data);
+  });
+
+  it("Should claim validator fees only above the threshold", async () => {
+    // The validator has not earned any fees yet, so this does nothing.
+    await program.methods.claimValidatorFeeIfAbove({amount: new BN(1)})
+      .accounts({
+        lido: lido.publicKey,
+        validatorVote: vote.publicKey,
+        stSolMint: st_sol_mint.publicKey,
+        validatorFeeStSol: fee.publicKey,
+      })
+      .rpc();
+
+    const feeAccount = await spl_token.account.token.fetch(fee.publicKey);
+    expect(feeAccount.amount.toNumber()).to.be.equal(0);
+
+    // The fee is only ever claimed to the registered fee address.
+    const other = Keypair.generate();
+    await create_token(other, st_sol_mint.publicKey, provider.wallet.publicKey);
+    await expect(program.methods.claimValidatorFeeIfAbove({amount: new BN(1)})
+      .accounts({
+        lido: lido.publicKey,
+        validatorVote: vote.publicKey,
+        stSolMint: st_sol_mint.publicKey,
+        validatorFeeStSol: other.publicKey,
+      })
+      .rpc()).to.be.rejectedWith(/InvalidFeeRecipient/);
+  });
+
+  it("Should claim validator fees to any account only when signed by the validator identity", async () => {
+    const other = Keypair.generate();
+    await create_token(other, st_sol_mint.publicKey, provider.wallet.publicKey);
+
+    // The validator identity can pick the recipient, there is no fee credit
+    // yet, so this does nothing.
+    await program.methods.claimValidatorFeeTo()
+      .accounts({
+        lido: lido.publicKey,
+        validatorVote: vote.publicKey,
+        validatorIdentity: node.publicKey,
+        stSolMint: st_sol_mint.publicKey,
+        recipient: other.publicKey,
+      })
+      .signers([node])
+      .rpc();
+
+    const otherAccount = await spl_token.account.token.fetch(other.publicKey);
+    expect(otherAccount.amount.toNumber()).to.be.equal(0);
+
+    const stranger = Keypair.generate();
+    await expect(program.methods.claimValidatorFeeTo()
+      .accounts({
+        lido: lido.publicKey,
+        validatorVote: vote.publicKey,
+        validatorIdentity: stranger.publicKey,
+        stSolMint: st_sol_mint.publicKey,
+        recipient: other.publicKey,
+      })
+      .signers([stranger])
+      .rpc()).to.be.rejectedWith(/InvalidValidatorIdentity/);
+
+    const other_mint = Keypair.generate();
+    const other_mint_token = Keypair.generate();
+    await create_mint(other_mint, provider.wallet.publicKey);
+    await create_token(other_mint_token, other_mint.publicKey, provider.wallet.publicKey);
+    await expect(program.methods.claimValidatorFeeTo()
+      .accounts({
+        lido: lido.publicKey,
+        validatorVote: vote.publicKey,
+        validatorIdentity: node.publicKey,
+        stSolMint: st_sol_mint.publicKey,
+        recipient: other_mint_token.publicKey,
+      })
+      .signers([node])
+      .rpc()).to.be.rejectedWith(/InvalidStSolAccount/);
+  });
+
+  it("Should check the seed range of the validator's stake accounts", async () => {
+    const stake_account = async (seed: number) => {
+      const [address, _nonce] = await PublicKey.findProgramAddress(
+        [
+          lido.publicKey.toBuffer(),
+          vote.publicKey.toBuffer(),
+          Buffer.from(anchor.utils.bytes.utf8.encode("validator_stake_account")),
+          new BN(seed).toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId);
+      return {pubkey: address, isSigner: false, isWritable: false};
+    };
+
+    // The validator has no stake accounts yet, so its seed range is [0, 0),
+    // and the account at seed 0 must not exist.
+    await program.methods.checkSeedRange({stake: {}})
+      .accounts({
+        lido: lido.publicKey,
+        validatorVote: vote.publicKey,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+        stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+      })
+      .remainingAccounts([await stake_account(0)])
+      .rpc();
+
+    // The manager cannot claim that a stake account exists when it does not.
+    await expect(program.methods.repairSeedRange({stake: {}}, {begin: new BN(0), end: new BN(1)})
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+        validatorVote: vote.publicKey,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+        stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+      })
+      .remainingAccounts([await stake_account(0), await stake_account(1)])
+      .signers([manager])
+      .rpc()).to.be.rejectedWith(/SeedRangeMismatch/);
+
+    const lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    const validator = lidoAccount.validators.entries[0];
+    expect(validator.entry.stakeSeeds.begin.toNumber()).to.be.equal(0);
+    expect(validator.entry.stakeSeeds.end.toNumber()).to.be.equal(0);
+  });
+
+  it("Should set validator info by the validator identity and the manager", async () => {
+    const [validator_info, _nonce] = await PublicKey.findProgramAddress(
+      [lido.publicKey.toBuffer(), vote.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("validator_info"))],
+      program.programId);
+
+    await program.methods
+      .setValidatorInfo("Validator", "https://example.com/validator.json", "ops@example.com")
+      .accounts({
+        lido: lido.publicKey,
+        validatorVote: vote.publicKey,
+        validatorInfo: validator_info,
+        signer: node.publicKey,
+        payer: provider.wallet.publicKey,
+      })
+      .signers([node])
+      .rpc();
+
+    let info = await program.account.validatorInfo.fetch(validator_info);
+    expect(info.name).to.be.equal("Validator");
+    expect(info.validatorVote).to.be.deep.equal(vote.publicKey);
+
+    await program.methods
+      .setValidatorInfo("Renamed", "", "")
+      .accounts({
+        lido: lido.publicKey,
+        validatorVote: vote.publicKey,
+        validatorInfo: validator_info,
+        signer: manager.publicKey,
+        payer: provider.wallet.publicKey,
+      })
+      .signers([manager])
+      .rpc();
+
+    info = await program.account.validatorInfo.fetch(validator_info);
+    expect(info.name).to.be.equal("Renamed");
+
+    const stranger = Keypair.generate();
+    await expect(program.methods
+      .setValidatorInfo("Phishing", "", "")
+      .accounts({
+        lido: lido.publicKey,
+        validatorVote: vote.publicKey,
+        validatorInfo: validator_info,
+        signer: stranger.publicKey,
+        payer: provider.wallet.publicKey,
+      })
+      .signers([stranger])
+      .rpc()).to.be.rejectedWith(/InvalidValidatorInfoAuthority/);
+  });
+
+  it("Should schedule a fee account override and emit an event", async () => {
+    const new_fee = Keypair.generate();
+    await create_token(new_fee, st_sol_mint.publicKey, provider.wallet.publicKey);
+
+    const signature = await program.methods.overrideValidatorFeeAccount()
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+        validatorVote: vote.publicKey,
+        newFeeAddress: new_fee.publicKey,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+      })
+      .signers([manager])
+      .rpc({commitment: "confirmed"});
+
+    const transaction = await provider.connection.getTransaction(signature, {commitment: "confirmed"});
+    const events = [];
+    new anchor.EventParser(program.programId, program.coder)
+      .parseLogs(transaction.meta.logMessages, (event) => events.push(event));
+    const scheduled = events.find((event) => event.name === "ValidatorFeeAccountOverrideScheduled");
+    expect(scheduled.data.validatorVote).to.be.deep.equal(vote.publicKey);
+    expect(scheduled.data.feeAddress).to.be.deep.equal(new_fee.publicKey);
+
+    // The override only takes effect after a delay, until then the fee address stays.
+    const lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    const validator = lidoAccount.validators.entries[0];
+    expect(validator.entry.feeAddress).to.be.deep.equal(fee.publicKey);
+    expect(validator.entry.feeAddressOverride).to.be.deep.equal(new_fee.publicKey);
+    expect(scheduled.data.effectiveEpoch.toNumber())
+      .to.be.equal(validator.entry.feeAddressOverrideEpoch.toNumber());
+  });
+
   // Adding the validator a second time should fail.
   it("Should NOT add the same validator a second time", async () => {
     await expect(program.methods.addValidator()
@@ -160,7 +388,57 @@ describe("Add Remove Validator", () => {
         lamports: rent_voter,
         space: web3.VoteProgram.space,
       })])
-      .rpc()).to.be.rejected;
+      .rpc()).to.be.rejectedWith(/ValidatorVoteAccountHasDifferentOwner/);
+  });
+
+  it("Should NOT add validator with a vote account that is too small", async () => {
+    const space = 100;
+    const rent_voter = await provider.connection.getMinimumBalanceForRentExemption(space);
+    const invalid_vote = Keypair.generate();
+
+    await expect(program.methods.addValidator()
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+        validatorVote: invalid_vote.publicKey,
+        validatorFeeStSol: fee.publicKey,
+      })
+      .signers([manager, invalid_vote])
+      .preInstructions([web3.SystemProgram.createAccount({
+        fromPubkey: provider.wallet.publicKey,
+        newAccountPubkey: invalid_vote.publicKey,
+        programId: web3.VoteProgram.programId,
+        lamports: rent_voter,
+        space: space,
+      })])
+      .rpc()).to.be.rejectedWith(/InvalidVoteAccount/);
+  });
+
+  it("Should NOT deactivate a validator whose vote account still exists", async () => {
+    await expect(program.methods.deactivateClosedValidator()
+      .accounts({
+        lido: lido.publicKey,
+        validatorVote: vote.publicKey,
+      })
+      .rpc()).to.be.rejectedWith(/VoteAccountNotClosed/);
+
+    const lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    expect(lidoAccount.validators.entries[0].entry.active).to.be.true;
+    expect(lidoAccount.validators.entries[0].entry.voteAccountClosed).to.be.false;
+  });
+
+  it("Should NOT unstake all stake of an active validator", async () => {
+    await expect(program.methods.unstakeAllFromValidator(null)
+      .accounts({
+        lido: lido.publicKey,
+        signer: manager.publicKey,
+        validatorVote: vote.publicKey,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+        stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+        stakeProgram: web3.StakeProgram.programId,
+      })
+      .signers([manager])
+      .rpc()).to.be.rejectedWith(/ValidatorIsStillActive/);
   });
 
   // test_successful_remove_validator
@@ -183,14 +461,267 @@ describe("Add Remove Validator", () => {
     lidoAccount = await program.account.lido.fetch(lido.publicKey);
     expect(lidoAccount.validators.entries[0].entry.active).to.be.false;
 
-    await program.methods.removeValidator()
+    // Once inactive, its stake can be unstaked all at once, only by a maintainer.
+    const unstake_all = (signer: Keypair) => program.methods.unstakeAllFromValidator(null)
+      .accounts({
+        lido: lido.publicKey,
+        signer: signer.publicKey,
+        validatorVote: vote.publicKey,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+        stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+        stakeProgram: web3.StakeProgram.programId,
+      })
+      .signers([signer])
+      .rpc();
+    await expect(unstake_all(Keypair.generate())).to.be.rejectedWith(/InvalidMaintainer/);
+    // The validator has no stake accounts, so there is nothing to pass.
+    await unstake_all(manager);
+
+    // SOL sent to the addresses of the next stake and unstake accounts is reclaimed on removal.
+    const stake_account_address = async (authority: string, seed: number) => {
+      const [address, _nonce] = await PublicKey.findProgramAddress(
+        [
+          lido.publicKey.toBuffer(),
+          vote.publicKey.toBuffer(),
+          Buffer.from(anchor.utils.bytes.utf8.encode(authority)),
+          new BN(seed).toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId);
+      return address;
+    };
+    const leftover_stake = await stake_account_address("validator_stake_account", 0);
+    const leftover_unstake = await stake_account_address("validator_unstake_account", 0);
+    const [reserve, _reserve_nonce] = await PublicKey.findProgramAddress(
+      [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("reserve_account"))],
+      program.programId);
+    await provider.send(
+      new web3.Transaction()
+        .add(web3.SystemProgram.transfer({
+          fromPubkey: provider.wallet.publicKey,
+          toPubkey: leftover_stake,
+          lamports: 10_000_000,
+        }))
+        .add(web3.SystemProgram.transfer({
+          fromPubkey: provider.wallet.publicKey,
+          toPubkey: leftover_unstake,
+          lamports: 20_000_000,
+        })));
+    const reserveBalance = await provider.connection.getBalance(reserve);
+    const donations = lidoAccount.metrics.donationSolTotal.amount.toNumber();
+
+    const remove = (accounts: PublicKey[]) => program.methods.removeValidator()
       .accounts({
         lido: lido.publicKey,
         validatorVote: vote.publicKey,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+        stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+        stakeProgram: web3.StakeProgram.programId,
       })
+      .remainingAccounts(accounts.map((pubkey) => ({pubkey: pubkey, isSigner: false, isWritable: true})))
       .rpc();
 
+    // Accounts must follow the end of the seed ranges, without gaps.
+    await expect(remove([await stake_account_address("validator_stake_account", 1)]))
+      .to.be.rejectedWith(/InvalidStakeAccount/);
+    await expect(remove([Keypair.generate().publicKey])).to.be.rejectedWith(/InvalidStakeAccount/);
+    lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    expect(lidoAccount.validators.entries.length).to.be.equal(1);
+
+    // Stake and unstake accounts can be interleaved.
+    await remove([leftover_unstake, leftover_stake]);
+
     lidoAccount = await program.account.lido.fetch(lido.publicKey);
     expect(lidoAccount.validators.entries.length).to.be.equal(0);
+    expect(await provider.connection.getBalance(leftover_stake)).to.be.equal(0);
+    expect(await provider.connection.getBalance(leftover_unstake)).to.be.equal(0);
+    // The balance goes to the reserve, and counts as a donation.
+    expect(await provider.connection.getBalance(reserve)).to.be.equal(reserveBalance + 30_000_000);
+    expect(lidoAccount.metrics.donationSolTotal.amount.toNumber()).to.be.equal(donations + 30_000_000);
+  });
+
+  it("Should remove a validator that left no accounts behind", async () => {
+    const other_node = Keypair.generate();
+    const other_vote = Keypair.generate();
+    const [withdrawer, _withdrawer_nonce] = await PublicKey.findProgramAddress(
+      [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("rewards_withdraw_authority"))], program.programId);
+    await create_vote(other_vote, other_node, withdrawer, 100);
+
+    await program.methods.addValidator()
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+        validatorVote: other_vote.publicKey,
+        validatorFeeStSol: fee.publicKey,
+      })
+      .signers([manager])
+      .rpc();
+    await program.methods.deactivateValidator()
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+        validatorVote: other_vote.publicKey,
+      })
+      .signers([manager])
+      .rpc();
+
+    const [reserve, _reserve_nonce] = await PublicKey.findProgramAddress(
+      [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("reserve_account"))],
+      program.programId);
+    const reserveBalance = await provider.connection.getBalance(reserve);
+    let lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    const donations = lidoAccount.metrics.donationSolTotal.amount.toNumber();
+
+    await program.methods.removeValidator()
+      .accounts({
+        lido: lido.publicKey,
+        validatorVote: other_vote.publicKey,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+        stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+        stakeProgram: web3.StakeProgram.programId,
+      })
+      .rpc();
+
+    lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    expect(lidoAccount.validators.entries.find(v => v.pubkey.equals(other_vote.publicKey))).to.be.undefined;
+    expect(await provider.connection.getBalance(reserve)).to.be.equal(reserveBalance);
+    expect(lidoAccount.metrics.donationSolTotal.amount.toNumber()).to.be.equal(donations);
+  });
+
+  it("Should add a validator and take over the withdraw authority of its vote account", async () => {
+    const other_node = Keypair.generate();
+    const other_vote = Keypair.generate();
+    const withdrawer = Keypair.generate();
+    await create_vote(other_vote, other_node, withdrawer.publicKey, 100);
+
+    // `AddValidator` needs the withdraw authority to be handed over first.
+    await expect(program.methods.addValidator()
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+        validatorVote: other_vote.publicKey,
+        validatorFeeStSol: fee.publicKey,
+      })
+      .signers([manager])
+      .rpc()).to.be.rejectedWith(/InvalidVoteAccount/);
+
+    await program.methods.addValidatorAndAuthorizeWithdrawer()
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+        validatorVote: other_vote.publicKey,
+        currentWithdrawer: withdrawer.publicKey,
+        validatorFeeStSol: fee.publicKey,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+        voteProgram: web3.VoteProgram.programId,
+      })
+      .signers([manager, withdrawer])
+      .rpc();
+
+    const [rewards_withdraw_authority, _nonce] = await PublicKey.findProgramAddress(
+      [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("rewards_withdraw_authority"))], program.programId);
+    const voteInfo = await provider.connection.getAccountInfo(other_vote.publicKey);
+    const voteAccount = web3.VoteAccount.fromAccountData(voteInfo.data);
+    expect(voteAccount.authorizedWithdrawer).to.be.deep.equal(rewards_withdraw_authority);
+
+    const lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    expect(lidoAccount.validators.entries.length).to.be.equal(1);
+    expect(lidoAccount.validators.entries[0].pubkey).to.be.deep.equal(other_vote.publicKey);
+  });
+
+  it("Should add a validator with its own rewards withdraw authority", async () => {
+    const own_node = Keypair.generate();
+    const own_vote = Keypair.generate();
+    const [own_withdrawer, own_nonce] = await PublicKey.findProgramAddress(
+      [
+        lido.publicKey.toBuffer(),
+        own_vote.publicKey.toBuffer(),
+        Buffer.from(anchor.utils.bytes.utf8.encode("rewards_withdraw_authority")),
+      ],
+      program.programId);
+    await create_vote(own_vote, own_node, own_withdrawer, 100);
+
+    await program.methods.addValidator()
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+        validatorVote: own_vote.publicKey,
+        validatorFeeStSol: fee.publicKey,
+      })
+      .signers([manager])
+      .rpc();
+
+    const lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    const validator = lidoAccount.validators.entries.find(v => v.pubkey.equals(own_vote.publicKey));
+    expect(validator.entry.hasOwnRewardsWithdrawAuthority).to.be.true;
+    expect(validator.entry.rewardsWithdrawAuthorityBumpSeed).to.be.equal(own_nonce);
+
+    // Validators added before use the rewards withdraw authority of the instance.
+    const other = lidoAccount.validators.entries.find(v => !v.pubkey.equals(own_vote.publicKey));
+    expect(other.entry.hasOwnRewardsWithdrawAuthority).to.be.false;
+  });
+
+  it("Should rotate the vote account of a validator to its own rewards withdraw authority", async () => {
+    const rotated_node = Keypair.generate();
+    const rotated_vote = Keypair.generate();
+    const [shared_withdrawer, _shared_nonce] = await PublicKey.findProgramAddress(
+      [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("rewards_withdraw_authority"))], program.programId);
+    const [own_withdrawer, own_nonce] = await PublicKey.findProgramAddress(
+      [
+        lido.publicKey.toBuffer(),
+        rotated_vote.publicKey.toBuffer(),
+        Buffer.from(anchor.utils.bytes.utf8.encode("rewards_withdraw_authority")),
+      ],
+      program.programId);
+    await create_vote(rotated_vote, rotated_node, shared_withdrawer, 100);
+
+    await program.methods.addValidator()
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+        validatorVote: rotated_vote.publicKey,
+        validatorFeeStSol: fee.publicKey,
+      })
+      .signers([manager])
+      .rpc();
+
+    const rotate = (rewards_withdraw_authority: PublicKey, new_rewards_withdraw_authority: PublicKey) =>
+      program.methods.rotateRewardsWithdrawAuthority()
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          validatorVote: rotated_vote.publicKey,
+          validatorIdentity: rotated_node.publicKey,
+          rewardsWithdrawAuthority: rewards_withdraw_authority,
+          newRewardsWithdrawAuthority: new_rewards_withdraw_authority,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          voteProgram: web3.VoteProgram.programId,
+        })
+        .signers([manager, rotated_node]);
+
+    // The new authority can only be the validator's own program-derived address.
+    await expect(rotate(shared_withdrawer, Keypair.generate().publicKey).rpc())
+      .to.be.rejectedWith(/ConstraintSeeds/);
+
+    const signature = await rotate(shared_withdrawer, own_withdrawer).rpc({commitment: "confirmed"});
+    const transaction = await provider.connection.getTransaction(signature, {commitment: "confirmed"});
+    const events = [];
+    new anchor.EventParser(program.programId, program.coder)
+      .parseLogs(transaction.meta.logMessages, (event) => events.push(event));
+    const rotated = events.find((event) => event.name === "RewardsWithdrawAuthorityRotated");
+    expect(rotated.data.validatorVote).to.be.deep.equal(rotated_vote.publicKey);
+    expect(rotated.data.rewardsWithdrawAuthority).to.be.deep.equal(own_withdrawer);
+
+    const voteInfo = await provider.connection.getAccountInfo(rotated_vote.publicKey);
+    const voteAccount = web3.VoteAccount.fromAccountData(voteInfo.data);
+    expect(voteAccount.authorizedWithdrawer).to.be.deep.equal(own_withdrawer);
+
+    const lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    const validator = lidoAccount.validators.entries.find(v => v.pubkey.equals(rotated_vote.publicKey));
+    expect(validator.entry.hasOwnRewardsWithdrawAuthority).to.be.true;
+    expect(validator.entry.rewardsWithdrawAuthorityBumpSeed).to.be.equal(own_nonce);
+
+    // The validator now has its own authority, there is nothing left to rotate.
+    await expect(rotate(own_withdrawer, own_withdrawer).rpc())
+      .to.be.rejectedWith(/InvalidRewardsWithdrawAuthority/);
   });
 });
\ No newline at end of file
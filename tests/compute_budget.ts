@@ -0,0 +1,227 @@
+import * as anchor from "@project-serum/anchor";
+import {Program, web3, BN} from "@project-serum/anchor";
+import {PublicKey, Keypair} from '@solana/web3.js';
+import {Asolido} from "../target/types/asolido";
+
+import {expect} from 'chai';
+import * as chai from 'chai';
+import chaiAsPromised from 'chai-as-promised';
+
+chai.use(chaiAsPromised);
+
+// Compute units that an instruction may use, well below the default limit of
+// 200k per instruction. A refactor of the `Validators` storage or the Borsh
+// layout of `Lido` that pushes an instruction over its budget fails here,
+// rather than on mainnet.
+const BUDGETS = {
+  addValidator: 60_000,
+  deposit: 80_000,
+  donate: 50_000,
+  stakeDeposit: 150_000,
+};
+
+// Validator set sizes to measure at, the cost of most instructions grows with
+// the size of the `Lido` account.
+const VALIDATOR_SET_SIZES = [1, 10, 30];
+
+describe("Compute budget", () => {
+
+  // Configure the client to use the local cluster.
+  anchor.setProvider(anchor.Provider.env());
+  const provider = anchor.getProvider();
+  const program = anchor.workspace.Asolido as Program<Asolido>;
+  const spl_token = anchor.Spl.token();
+
+  const STAKE_AMOUNT = 1_000_000_000;
+
+  async function create_mint(mint: Keypair, mint_authority: PublicKey) {
+    await spl_token.methods
+      .initializeMint(9, mint_authority, null)
+      .accounts({
+        mint: mint.publicKey,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .signers([mint])
+      .preInstructions([await spl_token.account.mint.createInstruction(mint)])
+      .rpc();
+  }
+
+  async function create_token(token: Keypair, mint: PublicKey, authority: PublicKey) {
+    await spl_token.methods.initializeAccount()
+      .accounts({
+        account: token.publicKey,
+        mint: mint,
+        authority: authority,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .signers([token])
+      .preInstructions([await spl_token.account.token.createInstruction(token)])
+      .rpc();
+  }
+
+  async function create_vote(vote: Keypair, node: Keypair, authorizedWithdrawer: PublicKey, commission: number) {
+    const rent_voter = await provider.connection.getMinimumBalanceForRentExemption(web3.VoteProgram.space);
+    const minimum = await provider.connection.getMinimumBalanceForRentExemption(0);
+    await provider.send(
+      new web3.Transaction()
+        .add(web3.SystemProgram.createAccount({
+          fromPubkey: provider.wallet.publicKey,
+          newAccountPubkey: node.publicKey,
+          programId: web3.SystemProgram.programId,
+          lamports: minimum,
+          space: 0
+        }))
+        .add(web3.VoteProgram.createAccount({
+          fromPubkey: provider.wallet.publicKey,
+          votePubkey: vote.publicKey,
+          voteInit: {
+            commission: commission,
+            nodePubkey: node.publicKey,
+            authorizedWithdrawer: authorizedWithdrawer,
+            authorizedVoter: node.publicKey,
+          },
+          lamports: rent_voter,
+        })),
+      [node, vote]
+    )
+  }
+
+  // Return the compute units that our program consumed in the given transaction.
+  async function compute_units(signature: string): Promise<number> {
+    await provider.connection.confirmTransaction(signature, "confirmed");
+    const tx = await provider.connection.getTransaction(signature, {commitment: "confirmed"});
+    const prefix = `Program ${program.programId.toBase58()} consumed `;
+    const line = tx.meta.logMessages.find(message => message.startsWith(prefix));
+    return parseInt(line.substring(prefix.length).split(" ")[0]);
+  }
+
+  function check_budget(instruction: keyof typeof BUDGETS, num_validators: number, units: number) {
+    console.log(`      ${instruction} with ${num_validators} validators: ${units} compute units`);
+    expect(units, `${instruction} with ${num_validators} validators`).to.be.at.most(BUDGETS[instruction]);
+  }
+
+  for (const num_validators of VALIDATOR_SET_SIZES) {
+    describe(`With ${num_validators} validators`, () => {
+      const lido = Keypair.generate();
+      const manager = Keypair.generate();
+      const st_sol_mint = Keypair.generate();
+      const recipient = Keypair.generate();
+      const votes: Keypair[] = [];
+
+      let rewards_withdraw_authority: PublicKey;
+
+      async function add_validator(): Promise<string> {
+        const node = Keypair.generate();
+        const vote = Keypair.generate();
+        const fee = Keypair.generate();
+        await create_vote(vote, node, rewards_withdraw_authority, 100);
+        await create_token(fee, st_sol_mint.publicKey, provider.wallet.publicKey);
+        votes.push(vote);
+
+        return await program.methods.addValidator()
+          .accounts({
+            lido: lido.publicKey,
+            manager: manager.publicKey,
+            validatorVote: vote.publicKey,
+            validatorFeeStSol: fee.publicKey,
+          })
+          .signers([manager])
+          .rpc();
+      }
+
+      before(async () => {
+        const treasury = Keypair.generate();
+        const developer = Keypair.generate();
+
+        const [mint_authority, _nonce] = await PublicKey.findProgramAddress(
+          [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("mint_authority"))], program.programId);
+        [rewards_withdraw_authority] = await PublicKey.findProgramAddress(
+          [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("rewards_withdraw_authority"))], program.programId);
+
+        await create_mint(st_sol_mint, mint_authority);
+        await create_token(treasury, st_sol_mint.publicKey, provider.wallet.publicKey);
+        await create_token(developer, st_sol_mint.publicKey, provider.wallet.publicKey);
+        await create_token(recipient, st_sol_mint.publicKey, provider.wallet.publicKey);
+
+        await program.methods
+          .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 100, 10, {none: {}}, {stSol: {}})
+          .accounts({
+            lido: lido.publicKey,
+            manager: manager.publicKey,
+            stSolMint: st_sol_mint.publicKey,
+            treasury: treasury.publicKey,
+            developer: developer.publicKey,
+          })
+          .signers([lido])
+          .rpc();
+
+        // Add all but the last validator, the last one is measured below.
+        for (let i = 0; i < num_validators - 1; i++) {
+          await add_validator();
+        }
+      });
+
+      it("AddValidator stays within budget", async () => {
+        const signature = await add_validator();
+        check_budget("addValidator", num_validators, await compute_units(signature));
+      });
+
+      it("Deposit stays within budget", async () => {
+        const signature = await program.methods
+          .deposit(new BN(2 * STAKE_AMOUNT), {amount: new BN(0)}, null)
+          .accounts({
+            lido: lido.publicKey,
+            user: provider.wallet.publicKey,
+            recipient: recipient.publicKey,
+            stSolMint: st_sol_mint.publicKey,
+            instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+            clock: web3.SYSVAR_CLOCK_PUBKEY,
+          })
+          .rpc();
+        check_budget("deposit", num_validators, await compute_units(signature));
+      });
+
+      it("Donate stays within budget", async () => {
+        const signature = await program.methods
+          .donate(new BN(STAKE_AMOUNT))
+          .accounts({
+            lido: lido.publicKey,
+            donor: provider.wallet.publicKey,
+          })
+          .rpc();
+        check_budget("donate", num_validators, await compute_units(signature));
+      });
+
+      it("StakeDeposit stays within budget", async () => {
+        // All validators have no stake yet, so the first one has the least.
+        const vote = votes[0];
+        const [stake_account, _stake_nonce] = await PublicKey.findProgramAddress(
+          [
+            lido.publicKey.toBuffer(),
+            vote.publicKey.toBuffer(),
+            Buffer.from(anchor.utils.bytes.utf8.encode("validator_stake_account")),
+            new BN(0).toArrayLike(Buffer, "le", 8),
+          ],
+          program.programId);
+
+        const signature = await program.methods
+          .stakeDeposit({amount: new BN(STAKE_AMOUNT)}, null, null)
+          .accounts({
+            lido: lido.publicKey,
+            signer: manager.publicKey,
+            validatorVote: vote.publicKey,
+            stakeAccountEnd: stake_account,
+            stakeAccountMergeInto: stake_account,
+            clock: web3.SYSVAR_CLOCK_PUBKEY,
+            rent: web3.SYSVAR_RENT_PUBKEY,
+            stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+            stakeConfig: web3.STAKE_CONFIG_ID,
+            stakeProgram: web3.StakeProgram.programId,
+          })
+          .signers([manager])
+          .rpc();
+        check_budget("stakeDeposit", num_validators, await compute_units(signature));
+      });
+    });
+  }
+});
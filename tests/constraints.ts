@@ -0,0 +1,1241 @@
+import * as anchor from "@project-serum/anchor";
+import {Program, web3, BN} from "@project-serum/anchor";
+import {PublicKey, Keypair} from '@solana/web3.js';
+import {Asolido} from "../target/types/asolido";
+import {maintenanceStateHash} from "../app/maintenance";
+
+import {expect} from 'chai';
+import * as chai from 'chai';
+import chaiAsPromised from 'chai-as-promised';
+
+chai.use(chaiAsPromised);
+
+// Every test substitutes one account with a plausible-but-wrong one, and
+// checks that the instruction fails with the error of that constraint.
+describe("Account constraints", () => {
+
+  // Configure the client to use the local cluster.
+  anchor.setProvider(anchor.Provider.env());
+  const provider = anchor.getProvider();
+  const program = anchor.workspace.Asolido as Program<Asolido>;
+  const spl_token = anchor.Spl.token();
+
+  const lido = Keypair.generate();
+  const manager = Keypair.generate();
+  const not_manager = Keypair.generate();
+  const st_sol_mint = Keypair.generate();
+  const other_mint = Keypair.generate();
+  const treasury = Keypair.generate();
+  const developer = Keypair.generate();
+
+  const node = Keypair.generate();
+  const fee = Keypair.generate();
+  const vote = Keypair.generate();
+
+  async function find_authority(lido: PublicKey, seed: string): Promise<PublicKey> {
+    const [address, _nonce] = await PublicKey.findProgramAddress(
+      [lido.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode(seed))], program.programId);
+    return address;
+  }
+
+  async function create_mint(mint: Keypair, mint_authority: PublicKey) {
+    await spl_token.methods
+      .initializeMint(9, mint_authority, null)
+      .accounts({
+        mint: mint.publicKey,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .signers([mint])
+      .preInstructions([await spl_token.account.mint.createInstruction(mint)])
+      .rpc();
+  }
+
+  async function create_token(token: Keypair, mint: PublicKey, authority: PublicKey) {
+    await spl_token.methods.initializeAccount()
+      .accounts({
+        account: token.publicKey,
+        mint: mint,
+        authority: authority,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .signers([token])
+      .preInstructions([await spl_token.account.token.createInstruction(token)])
+      .rpc();
+  }
+
+  async function create_vote(vote: Keypair, node: Keypair, authorizedWithdrawer: PublicKey, commission: number) {
+    const rent_voter = await provider.connection.getMinimumBalanceForRentExemption(web3.VoteProgram.space);
+    const minimum = await provider.connection.getMinimumBalanceForRentExemption(0);
+    await provider.send(
+      new web3.Transaction()
+        .add(web3.SystemProgram.createAccount({
+          fromPubkey: provider.wallet.publicKey,
+          newAccountPubkey: node.publicKey,
+          programId: web3.SystemProgram.programId,
+          lamports: minimum,
+          space: 0
+        }))
+        .add(web3.VoteProgram.createAccount({
+          fromPubkey: provider.wallet.publicKey,
+          votePubkey: vote.publicKey,
+          voteInit: {
+            commission: commission,
+            nodePubkey: node.publicKey,
+            authorizedWithdrawer: authorizedWithdrawer,
+            authorizedVoter: node.publicKey,
+          },
+          lamports: rent_voter,
+        })),
+      [node, vote]
+    )
+  }
+
+  async function fund(to: PublicKey, amount: number) {
+    await provider.send(
+      new web3.Transaction()
+        .add(web3.SystemProgram.transfer({
+          fromPubkey: provider.wallet.publicKey,
+          toPubkey: to,
+          lamports: amount + await provider.connection.getMinimumBalanceForRentExemption(0),
+        })));
+  }
+
+  before(async () => {
+    const mint_authority = await find_authority(lido.publicKey, "mint_authority");
+    await create_mint(st_sol_mint, mint_authority);
+    await create_mint(other_mint, provider.wallet.publicKey);
+    await create_token(treasury, st_sol_mint.publicKey, provider.wallet.publicKey);
+    await create_token(developer, st_sol_mint.publicKey, provider.wallet.publicKey);
+    await create_token(fee, st_sol_mint.publicKey, provider.wallet.publicKey);
+
+    const withdrawer = await find_authority(lido.publicKey, "rewards_withdraw_authority");
+    await create_vote(vote, node, withdrawer, 100);
+
+    await program.methods
+      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 100, 100, {none: {}}, {stSol: {}})
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+        stSolMint: st_sol_mint.publicKey,
+        treasury: treasury.publicKey,
+        developer: developer.publicKey,
+      })
+      .signers([lido])
+      .rpc();
+
+    await program.methods.addValidator()
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+        validatorVote: vote.publicKey,
+        validatorFeeStSol: fee.publicKey,
+      })
+      .signers([manager])
+      .rpc();
+  });
+
+  describe("Initialize", () => {
+    it("Should NOT initialize with a mint that has supply", async () => {
+      const lido1 = Keypair.generate();
+      const mint1 = Keypair.generate();
+      const holder = Keypair.generate();
+      const treasury1 = Keypair.generate();
+      const developer1 = Keypair.generate();
+
+      // Mint some tokens before handing the mint authority to the instance.
+      await create_mint(mint1, provider.wallet.publicKey);
+      await create_token(holder, mint1.publicKey, provider.wallet.publicKey);
+      await create_token(treasury1, mint1.publicKey, provider.wallet.publicKey);
+      await create_token(developer1, mint1.publicKey, provider.wallet.publicKey);
+      await spl_token.methods.mintTo(new BN(1))
+        .accounts({
+          mint: mint1.publicKey,
+          to: holder.publicKey,
+          authority: provider.wallet.publicKey,
+        })
+        .rpc();
+      await spl_token.methods.setAuthority({mintTokens: {}}, await find_authority(lido1.publicKey, "mint_authority"))
+        .accounts({
+          mint: mint1.publicKey,
+          authority: provider.wallet.publicKey,
+        })
+        .rpc();
+
+      await expect(program.methods
+        .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 100, 100, {none: {}}, {stSol: {}})
+        .accounts({
+          lido: lido1.publicKey,
+          manager: manager.publicKey,
+          stSolMint: mint1.publicKey,
+          treasury: treasury1.publicKey,
+          developer: developer1.publicKey,
+        })
+        .signers([lido1])
+        .rpc()).to.be.rejectedWith(/InvalidMint/);
+    });
+
+    it("Should NOT initialize with a developer account of another mint", async () => {
+      const lido1 = Keypair.generate();
+      const mint1 = Keypair.generate();
+      const treasury1 = Keypair.generate();
+      const developer1 = Keypair.generate();
+
+      await create_mint(mint1, await find_authority(lido1.publicKey, "mint_authority"));
+      await create_token(treasury1, mint1.publicKey, provider.wallet.publicKey);
+      await create_token(developer1, other_mint.publicKey, provider.wallet.publicKey);
+
+      await expect(program.methods
+        .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 100, 100, {none: {}}, {stSol: {}})
+        .accounts({
+          lido: lido1.publicKey,
+          manager: manager.publicKey,
+          stSolMint: mint1.publicKey,
+          treasury: treasury1.publicKey,
+          developer: developer1.publicKey,
+        })
+        .signers([lido1])
+        .rpc()).to.be.rejectedWith(/InvalidFeeRecipient/);
+    });
+
+    it("Should NOT initialize with a reserve that is not the program address", async () => {
+      const lido1 = Keypair.generate();
+      const mint1 = Keypair.generate();
+      const treasury1 = Keypair.generate();
+      const developer1 = Keypair.generate();
+
+      await create_mint(mint1, await find_authority(lido1.publicKey, "mint_authority"));
+      await create_token(treasury1, mint1.publicKey, provider.wallet.publicKey);
+      await create_token(developer1, mint1.publicKey, provider.wallet.publicKey);
+
+      await expect(program.methods
+        .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 100, 100, {none: {}}, {stSol: {}})
+        .accounts({
+          lido: lido1.publicKey,
+          manager: manager.publicKey,
+          stSolMint: mint1.publicKey,
+          treasury: treasury1.publicKey,
+          developer: developer1.publicKey,
+          // The reserve of another instance.
+          reserve: await find_authority(lido.publicKey, "reserve_account"),
+        })
+        .signers([lido1])
+        .rpc()).to.be.rejectedWith(/ConstraintSeeds/);
+    });
+  });
+
+  describe("AddValidator", () => {
+    it("Should NOT add a validator when not signed by the manager", async () => {
+      const vote1 = Keypair.generate();
+      await create_vote(vote1, Keypair.generate(), await find_authority(lido.publicKey, "rewards_withdraw_authority"), 100);
+
+      await expect(program.methods.addValidator()
+        .accounts({
+          lido: lido.publicKey,
+          manager: not_manager.publicKey,
+          validatorVote: vote1.publicKey,
+          validatorFeeStSol: fee.publicKey,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidManager/);
+    });
+
+    it("Should schedule a max commission change, applicable only after the delay", async () => {
+      await program.methods.scheduleMaxCommissionChange(10)
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([manager])
+        .rpc();
+
+      const configAccount = await program.account.config.fetch(await find_authority(lido.publicKey, "config"));
+      expect(configAccount.maxCommission).to.be.equal(100);
+      expect(configAccount.maxCommissionScheduled).to.be.equal(10);
+
+      await expect(program.methods.applyMaxCommissionChange()
+        .accounts({
+          lido: lido.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .rpc()).to.be.rejectedWith(/MaxCommissionChangeNotYetEffective/);
+    });
+
+    it("Should NOT schedule a max commission above 100%", async () => {
+      await expect(program.methods.scheduleMaxCommissionChange(101)
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/InvalidMaxCommission/);
+    });
+
+    it("Should schedule a mint authority transfer and emit an event, transferable only after the delay", async () => {
+      const new_mint_authority = Keypair.generate();
+      const signature = await program.methods.scheduleMintAuthorityTransfer()
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          newMintAuthority: new_mint_authority.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([manager])
+        .rpc({commitment: "confirmed"});
+
+      const transaction = await provider.connection.getTransaction(signature, {commitment: "confirmed"});
+      const events = [];
+      new anchor.EventParser(program.programId, program.coder)
+        .parseLogs(transaction.meta.logMessages, (event) => events.push(event));
+      const scheduled = events.find((event) => event.name === "MintAuthorityTransferScheduled");
+      expect(scheduled.data.newMintAuthority).to.be.deep.equal(new_mint_authority.publicKey);
+
+      const lidoAccount = await program.account.lido.fetch(lido.publicKey);
+      expect(scheduled.data.effectiveEpoch.toNumber())
+        .to.be.equal(lidoAccount.mintAuthorityTransferEpoch.toNumber());
+
+      await expect(program.methods.transferMintAuthorityOut()
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          mintAuthority: await find_authority(lido.publicKey, "mint_authority"),
+          newMintAuthority: new_mint_authority.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/MintAuthorityTransferNotYetEffective/);
+    });
+
+    it("Should NOT add a validator with another withdraw authority", async () => {
+      const vote1 = Keypair.generate();
+      await create_vote(vote1, Keypair.generate(), provider.wallet.publicKey, 100);
+
+      await expect(program.methods.addValidator()
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          validatorVote: vote1.publicKey,
+          validatorFeeStSol: fee.publicKey,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/InvalidVoteAccount/);
+    });
+
+    it("Should NOT add a validator with a rewards withdraw authority that is not the program address", async () => {
+      // The vote account is consistent with the authority we pass, but the
+      // authority is not derived from the instance.
+      const vote1 = Keypair.generate();
+      const withdrawer = await find_authority(lido.publicKey, "stake_authority");
+      await create_vote(vote1, Keypair.generate(), withdrawer, 100);
+
+      await expect(program.methods.addValidator()
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          validatorVote: vote1.publicKey,
+          validatorFeeStSol: fee.publicKey,
+          rewardsWithdrawAuthority: withdrawer,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT add a validator with a fee account of another mint", async () => {
+      const vote1 = Keypair.generate();
+      const fee1 = Keypair.generate();
+      await create_vote(vote1, Keypair.generate(), await find_authority(lido.publicKey, "rewards_withdraw_authority"), 100);
+      await create_token(fee1, other_mint.publicKey, provider.wallet.publicKey);
+
+      await expect(program.methods.addValidator()
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          validatorVote: vote1.publicKey,
+          validatorFeeStSol: fee1.publicKey,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/InvalidFeeRecipient/);
+    });
+  });
+
+  describe("Deposit", () => {
+    const user = Keypair.generate();
+    const recipient = Keypair.generate();
+    const other_recipient = Keypair.generate();
+
+    before(async () => {
+      await fund(user.publicKey, 1000000000);
+      await create_token(recipient, st_sol_mint.publicKey, user.publicKey);
+      await create_token(other_recipient, other_mint.publicKey, user.publicKey);
+    });
+
+    it("Should NOT deposit zero", async () => {
+      await expect(program.methods
+        .deposit(new BN(0), {amount: new BN(0)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          user: user.publicKey,
+          recipient: recipient.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([user])
+        .rpc()).to.be.rejectedWith(/InvalidAmount/);
+    });
+
+    it("Should NOT deposit with another mint", async () => {
+      await expect(program.methods
+        .deposit(new BN(1000), {amount: new BN(0)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          user: user.publicKey,
+          recipient: other_recipient.publicKey,
+          stSolMint: other_mint.publicKey,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([user])
+        .rpc()).to.be.rejectedWith(/InvalidMint/);
+    });
+
+    it("Should NOT deposit to a recipient of another mint", async () => {
+      await expect(program.methods
+        .deposit(new BN(1000), {amount: new BN(0)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          user: user.publicKey,
+          recipient: other_recipient.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([user])
+        .rpc()).to.be.rejectedWith(/ConstraintRaw/);
+    });
+
+    it("Should NOT deposit into a reserve that is not the program address", async () => {
+      await expect(program.methods
+        .deposit(new BN(1000), {amount: new BN(0)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          user: user.publicKey,
+          recipient: recipient.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          reserve: provider.wallet.publicKey,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([user])
+        .rpc()).to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT deposit with a mint authority that is not the program address", async () => {
+      await expect(program.methods
+        .deposit(new BN(1000), {amount: new BN(0)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          user: user.publicKey,
+          recipient: recipient.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          mintAuthority: await find_authority(lido.publicKey, "stake_authority"),
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([user])
+        .rpc()).to.be.rejectedWith(/ConstraintSeeds/);
+    });
+  });
+
+  describe("Withdraw", () => {
+    const owner = Keypair.generate();
+    const other_owner_account = Keypair.generate();
+    const other_mint_account = Keypair.generate();
+
+    before(async () => {
+      await create_token(other_owner_account, st_sol_mint.publicKey, provider.wallet.publicKey);
+      await create_token(other_mint_account, other_mint.publicKey, owner.publicKey);
+    });
+
+    async function withdraw(st_sol_account: PublicKey) {
+      const destination = Keypair.generate();
+      await program.methods
+        .withdraw({amount: new BN(1000)}, {amount: new BN(0)})
+        .accounts({
+          lido: lido.publicKey,
+          stSolAccountOwner: owner.publicKey,
+          stSolAccount: st_sol_account,
+          stSolMint: st_sol_mint.publicKey,
+          validatorVote: vote.publicKey,
+          sourceStakeAccount: destination.publicKey,
+          destinationStakeAccount: destination.publicKey,
+          stakeProgram: web3.StakeProgram.programId,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .signers([owner, destination])
+        .rpc();
+    }
+
+    it("Should NOT withdraw from an stSOL account of somebody else", async () => {
+      await expect(withdraw(other_owner_account.publicKey)).to.be.rejectedWith(/InvalidTokenOwner/);
+    });
+
+    it("Should NOT withdraw from a token account of another mint", async () => {
+      await expect(withdraw(other_mint_account.publicKey)).to.be.rejectedWith(/InvalidStSolAccount/);
+    });
+
+    async function withdraw_and_merge(st_sol_account: PublicKey, user_stake_account: PublicKey) {
+      const destination = Keypair.generate();
+      await program.methods
+        .withdrawAndMerge({amount: new BN(1000)}, {amount: new BN(0)})
+        .accounts({
+          withdraw: {
+            lido: lido.publicKey,
+            stSolAccountOwner: owner.publicKey,
+            stSolAccount: st_sol_account,
+            stSolMint: st_sol_mint.publicKey,
+            validatorVote: vote.publicKey,
+            sourceStakeAccount: destination.publicKey,
+            destinationStakeAccount: destination.publicKey,
+            stakeProgram: web3.StakeProgram.programId,
+            instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          },
+          userStakeAccount: user_stake_account,
+        })
+        .signers([owner, destination])
+        .rpc();
+    }
+
+    it("Should NOT withdraw and merge from an stSOL account of somebody else", async () => {
+      const user_stake = Keypair.generate();
+      await expect(withdraw_and_merge(other_owner_account.publicKey, user_stake.publicKey))
+        .to.be.rejectedWith(/InvalidTokenOwner/);
+    });
+
+    it("Should NOT withdraw and merge into an account that is not a stake account", async () => {
+      const owner_account = Keypair.generate();
+      await create_token(owner_account, st_sol_mint.publicKey, owner.publicKey);
+
+      // A token account of the owner is writable and plausible, but not stake.
+      await expect(withdraw_and_merge(owner_account.publicKey, owner_account.publicKey))
+        .to.be.rejectedWith(/WrongStakeState/);
+    });
+
+    async function withdraw_from_validators(st_sol_account: PublicKey, remaining: web3.AccountMeta[]) {
+      await program.methods
+        .withdrawFromValidators({amount: new BN(1000)}, {amount: new BN(0)})
+        .accounts({
+          lido: lido.publicKey,
+          stSolAccountOwner: owner.publicKey,
+          stSolAccount: st_sol_account,
+          stSolMint: st_sol_mint.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeProgram: web3.StakeProgram.programId,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .remainingAccounts(remaining)
+        .signers([owner])
+        .rpc();
+    }
+
+    it("Should NOT withdraw from validators from an stSOL account of somebody else", async () => {
+      await expect(withdraw_from_validators(other_owner_account.publicKey, []))
+        .to.be.rejectedWith(/InvalidTokenOwner/);
+    });
+
+    it("Should NOT withdraw from validators without three accounts per validator", async () => {
+      const owner_account = Keypair.generate();
+      await create_token(owner_account, st_sol_mint.publicKey, owner.publicKey);
+
+      await expect(withdraw_from_validators(owner_account.publicKey, []))
+        .to.be.rejectedWith(/InvalidAccountInfo/);
+      await expect(withdraw_from_validators(owner_account.publicKey, [
+        {pubkey: vote.publicKey, isSigner: false, isWritable: false},
+      ])).to.be.rejectedWith(/InvalidAccountInfo/);
+    });
+  });
+
+  describe("Manager instructions", () => {
+    it("Should NOT deactivate a validator when not signed by the manager", async () => {
+      await expect(program.methods.deactivateValidator()
+        .accounts({
+          lido: lido.publicKey,
+          manager: not_manager.publicKey,
+          validatorVote: vote.publicKey,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidManager/);
+    });
+
+    it("Should NOT update the config when not signed by the manager", async () => {
+      await expect(program.methods.updateConfig({
+          minDeposit: {amount: new BN(0)},
+          minReserveBalance: {amount: new BN(1)},
+          depositsPaused: false,
+          withdrawalsPaused: false,
+          maxInstantWithdrawal: {amount: new BN(0)},
+          maxStakePerEpoch: {amount: new BN(0)},
+          maxExchangeRateChangeBps: 0,
+          idleReserveFeeAdjustment: false,
+          rejectCpi: false,
+          staleExchangeRateGuard: false,
+          crankTip: {amount: new BN(0)},
+          minEpochsBeforeStake: new BN(0),
+        })
+        .accounts({
+          lido: lido.publicKey,
+          manager: not_manager.publicKey,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidManager/);
+    });
+
+    it("Should NOT set the Wormhole program when not signed by the manager", async () => {
+      await expect(program.methods.setWormholeProgram(Keypair.generate().publicKey)
+        .accounts({
+          lido: lido.publicKey,
+          manager: not_manager.publicKey,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidManager/);
+    });
+
+    it("Should NOT pause a validator when signed by neither the manager nor a maintainer", async () => {
+      await expect(program.methods.changeValidatorStakePaused(true)
+        .accounts({
+          lido: lido.publicKey,
+          signer: not_manager.publicKey,
+          validatorVote: vote.publicKey,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidMaintainer/);
+    });
+
+    it("Should NOT set a validator target weight when signed by neither the manager nor the delegation authority", async () => {
+      await expect(program.methods.setValidatorTargetWeight(200)
+        .accounts({
+          lido: lido.publicKey,
+          signer: not_manager.publicKey,
+          validatorVote: vote.publicKey,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidDelegationAuthority/);
+    });
+
+    it("Should NOT set the delegation authority when signed by neither the manager nor the delegation authority", async () => {
+      await expect(program.methods.setDelegationAuthority(not_manager.publicKey)
+        .accounts({
+          lido: lido.publicKey,
+          signer: not_manager.publicKey,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidDelegationAuthority/);
+    });
+
+    it("Should NOT override a fee account with an account of another mint", async () => {
+      const fee1 = Keypair.generate();
+      await create_token(fee1, other_mint.publicKey, provider.wallet.publicKey);
+
+      await expect(program.methods.overrideValidatorFeeAccount()
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          validatorVote: vote.publicKey,
+          newFeeAddress: fee1.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/InvalidFeeRecipient/);
+    });
+
+    it("Should NOT add a maintainer when not signed by the manager", async () => {
+      await expect(program.methods.addMaintainer()
+        .accounts({
+          lido: lido.publicKey,
+          manager: not_manager.publicKey,
+          maintainer: not_manager.publicKey,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/ConstraintHasOne/);
+    });
+
+    it("Should NOT remove a maintainer when not signed by the manager", async () => {
+      await expect(program.methods.removeMaintainer()
+        .accounts({
+          lido: lido.publicKey,
+          manager: not_manager.publicKey,
+          maintainer: not_manager.publicKey,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/ConstraintHasOne/);
+    });
+  });
+
+  describe("Maintenance", () => {
+    const stake_account = Keypair.generate();
+    const other_stake_account = Keypair.generate();
+
+    it("Should NOT stake a deposit when not signed by a maintainer", async () => {
+      await expect(program.methods
+        .stakeDeposit({amount: new BN(1000)}, null, null)
+        .accounts({
+          lido: lido.publicKey,
+          signer: not_manager.publicKey,
+          validatorVote: vote.publicKey,
+          stakeAccountEnd: stake_account.publicKey,
+          stakeAccountMergeInto: stake_account.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeConfig: web3.STAKE_CONFIG_ID,
+          stakeProgram: web3.StakeProgram.programId,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidMaintainer/);
+    });
+
+    it("Should NOT sweep the reserve when not signed by a maintainer", async () => {
+      await expect(program.methods
+        .sweepReserve(null)
+        .accounts({
+          lido: lido.publicKey,
+          signer: not_manager.publicKey,
+          validatorVote: vote.publicKey,
+          stakeAccountEnd: stake_account.publicKey,
+          stakeAccountMergeInto: stake_account.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeConfig: web3.STAKE_CONFIG_ID,
+          stakeProgram: web3.StakeProgram.programId,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidMaintainer/);
+    });
+
+    it("Should NOT unstake when not signed by a maintainer", async () => {
+      await expect(program.methods
+        .unstake({amount: new BN(1000)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          signer: not_manager.publicKey,
+          validatorVote: vote.publicKey,
+          sourceStakeAccount: stake_account.publicKey,
+          destinationUnstakeAccount: other_stake_account.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeProgram: web3.StakeProgram.programId,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidMaintainer/);
+    });
+
+    it("Should NOT merge stake when not signed by a maintainer", async () => {
+      await expect(program.methods
+        .mergeStake(null, null)
+        .accounts({
+          lido: lido.publicKey,
+          signer: not_manager.publicKey,
+          validatorVote: vote.publicKey,
+          fromStake: stake_account.publicKey,
+          toStake: other_stake_account.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeProgram: web3.StakeProgram.programId,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidMaintainer/);
+    });
+
+    it("Should NOT unstake all from a validator when not signed by a maintainer", async () => {
+      await expect(program.methods
+        .unstakeAllFromValidator(null)
+        .accounts({
+          lido: lido.publicKey,
+          signer: not_manager.publicKey,
+          validatorVote: vote.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeProgram: web3.StakeProgram.programId,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidMaintainer/);
+    });
+
+    it("Should NOT stake a deposit computed against a different state", async () => {
+      const lidoAccount = await program.account.lido.fetch(lido.publicKey);
+      const stale = maintenanceStateHash(lidoAccount);
+      stale[0] ^= 1;
+
+      await expect(program.methods
+        .stakeDeposit({amount: new BN(1000)}, null, stale)
+        .accounts({
+          lido: lido.publicKey,
+          signer: manager.publicKey,
+          validatorVote: vote.publicKey,
+          stakeAccountEnd: stake_account.publicKey,
+          stakeAccountMergeInto: stake_account.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeConfig: web3.STAKE_CONFIG_ID,
+          stakeProgram: web3.StakeProgram.programId,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/MaintenanceStateChanged/);
+    });
+  });
+
+  describe("Stake accounts", () => {
+    const stake_account = Keypair.generate();
+    const other_stake_account = Keypair.generate();
+
+    it("Should NOT unstake with a stake authority that is not the program address", async () => {
+      await expect(program.methods
+        .unstake({amount: new BN(1000)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          signer: manager.publicKey,
+          validatorVote: vote.publicKey,
+          sourceStakeAccount: stake_account.publicKey,
+          destinationUnstakeAccount: other_stake_account.publicKey,
+          stakeAuthority: await find_authority(lido.publicKey, "mint_authority"),
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeProgram: web3.StakeProgram.programId,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT unstake from a validator vote account that is not a vote account", async () => {
+      await expect(program.methods
+        .unstake({amount: new BN(1000)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          signer: manager.publicKey,
+          // A token account, owned by the token program.
+          validatorVote: fee.publicKey,
+          sourceStakeAccount: stake_account.publicKey,
+          destinationUnstakeAccount: other_stake_account.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeProgram: web3.StakeProgram.programId,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/AccountOwnedByWrongProgram/);
+    });
+
+    async function withdraw_inactive_stake(accounts: object) {
+      await program.methods.withdrawInactiveStake()
+        .accounts({
+          lido: lido.publicKey,
+          validatorVote: vote.publicKey,
+          tipRecipient: provider.wallet.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeProgram: web3.StakeProgram.programId,
+          ...accounts,
+        })
+        .rpc();
+    }
+
+    it("Should NOT withdraw inactive stake into a reserve that is not the program address", async () => {
+      // Owned by the system program like the reserve, but another address.
+      await expect(withdraw_inactive_stake({reserve: provider.wallet.publicKey}))
+        .to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT withdraw inactive stake with a stake authority that is not the program address", async () => {
+      await expect(withdraw_inactive_stake({stakeAuthority: await find_authority(lido.publicKey, "reserve_account")}))
+        .to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT withdraw inactive stake of a validator vote account that is not a vote account", async () => {
+      await expect(withdraw_inactive_stake({validatorVote: fee.publicKey}))
+        .to.be.rejectedWith(/AccountOwnedByWrongProgram/);
+    });
+
+    it("Should NOT merge stake with a stake authority that is not the program address", async () => {
+      await expect(program.methods
+        .mergeStake(null, null)
+        .accounts({
+          lido: lido.publicKey,
+          signer: manager.publicKey,
+          validatorVote: vote.publicKey,
+          fromStake: stake_account.publicKey,
+          toStake: other_stake_account.publicKey,
+          stakeAuthority: await find_authority(lido.publicKey, "mint_authority"),
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeProgram: web3.StakeProgram.programId,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT merge stake of a validator vote account that is not a vote account", async () => {
+      await expect(program.methods
+        .mergeStake(null, null)
+        .accounts({
+          lido: lido.publicKey,
+          signer: manager.publicKey,
+          validatorVote: fee.publicKey,
+          fromStake: stake_account.publicKey,
+          toStake: other_stake_account.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeProgram: web3.StakeProgram.programId,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/AccountOwnedByWrongProgram/);
+    });
+  });
+
+  describe("Validator fees", () => {
+    const other_mint_account = Keypair.generate();
+
+    before(async () => {
+      await create_token(other_mint_account, other_mint.publicKey, provider.wallet.publicKey);
+    });
+
+    async function collect_validator_fee(accounts: object) {
+      await program.methods.collectValidatorFee()
+        .accounts({
+          lido: lido.publicKey,
+          validatorVote: vote.publicKey,
+          rewardsWithdrawAuthority: await find_authority(lido.publicKey, "rewards_withdraw_authority"),
+          stSolMint: st_sol_mint.publicKey,
+          treasuryAccount: treasury.publicKey,
+          developerAccount: developer.publicKey,
+          tipRecipient: provider.wallet.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+          voteProgram: web3.VoteProgram.programId,
+          ...accounts,
+        })
+        .rpc();
+    }
+
+    it("Should NOT collect validator fees into a reserve that is not the program address", async () => {
+      await expect(collect_validator_fee({reserve: provider.wallet.publicKey}))
+        .to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT collect validator fees with a mint authority that is not the program address", async () => {
+      await expect(collect_validator_fee({mintAuthority: await find_authority(lido.publicKey, "stake_authority")}))
+        .to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT collect validator fees with another mint", async () => {
+      await expect(collect_validator_fee({stSolMint: other_mint.publicKey}))
+        .to.be.rejectedWith(/InvalidMint/);
+    });
+
+    it("Should NOT collect validator fees to a treasury other than the fee recipient", async () => {
+      await expect(collect_validator_fee({treasuryAccount: developer.publicKey}))
+        .to.be.rejectedWith(/InvalidFeeRecipient/);
+    });
+
+    it("Should NOT collect validator fees from an account that is not a vote account", async () => {
+      await expect(collect_validator_fee({validatorVote: fee.publicKey}))
+        .to.be.rejectedWith(/AccountOwnedByWrongProgram/);
+    });
+
+    async function claim_validator_fee(accounts: object) {
+      await program.methods.claimValidatorFee()
+        .accounts({
+          lido: lido.publicKey,
+          validatorVote: vote.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          validatorFeeStSol: fee.publicKey,
+          ...accounts,
+        })
+        .rpc();
+    }
+
+    it("Should NOT claim a validator fee with another mint", async () => {
+      await expect(claim_validator_fee({stSolMint: other_mint.publicKey}))
+        .to.be.rejectedWith(/InvalidMint/);
+    });
+
+    it("Should NOT claim a validator fee with a mint authority that is not the program address", async () => {
+      await expect(claim_validator_fee({mintAuthority: await find_authority(lido.publicKey, "stake_authority")}))
+        .to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT claim a validator fee to an account other than the fee address", async () => {
+      await expect(claim_validator_fee({validatorFeeStSol: other_mint_account.publicKey}))
+        .to.be.rejectedWith(/InvalidFeeRecipient/);
+    });
+
+    async function claim_validator_fee_to(identity: Keypair, accounts: object) {
+      await program.methods.claimValidatorFeeTo()
+        .accounts({
+          lido: lido.publicKey,
+          validatorVote: vote.publicKey,
+          validatorIdentity: identity.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          recipient: treasury.publicKey,
+          ...accounts,
+        })
+        .signers([identity])
+        .rpc();
+    }
+
+    it("Should NOT claim a validator fee to any account when not signed by the validator identity", async () => {
+      await expect(claim_validator_fee_to(not_manager, {}))
+        .to.be.rejectedWith(/InvalidValidatorIdentity/);
+    });
+
+    it("Should NOT claim a validator fee to an account of another mint", async () => {
+      await expect(claim_validator_fee_to(node, {recipient: other_mint_account.publicKey}))
+        .to.be.rejectedWith(/InvalidStSolAccount/);
+    });
+
+    it("Should NOT claim a validator fee to any account with a mint authority that is not the program address", async () => {
+      await expect(claim_validator_fee_to(node, {mintAuthority: await find_authority(lido.publicKey, "stake_authority")}))
+        .to.be.rejectedWith(/ConstraintSeeds/);
+    });
+  });
+
+  describe("Validator lifecycle", () => {
+    async function remove_validator(accounts: object) {
+      await program.methods.removeValidator()
+        .accounts({
+          lido: lido.publicKey,
+          validatorVote: vote.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeProgram: web3.StakeProgram.programId,
+          ...accounts,
+        })
+        .rpc();
+    }
+
+    it("Should NOT remove a validator into a reserve that is not the program address", async () => {
+      await expect(remove_validator({reserve: provider.wallet.publicKey}))
+        .to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT remove a validator with a stake authority that is not the program address", async () => {
+      await expect(remove_validator({stakeAuthority: await find_authority(lido.publicKey, "reserve_account")}))
+        .to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT remove a validator with a stake program that is not the stake program", async () => {
+      await expect(remove_validator({stakeProgram: web3.VoteProgram.programId}))
+        .to.be.rejectedWith(/ConstraintAddress/);
+    });
+
+    it("Should NOT acknowledge a loss when not signed by the manager", async () => {
+      await expect(program.methods.acknowledgeLoss()
+        .accounts({
+          lido: lido.publicKey,
+          manager: not_manager.publicKey,
+          validatorVote: vote.publicKey,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidManager/);
+    });
+
+    it("Should NOT acknowledge a loss of an account that is not a vote account", async () => {
+      await expect(program.methods.acknowledgeLoss()
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          validatorVote: fee.publicKey,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/AccountOwnedByWrongProgram/);
+    });
+
+    it("Should NOT mark an account that is not a vote account delinquent", async () => {
+      await expect(program.methods.markDelinquent()
+        .accounts({
+          lido: lido.publicKey,
+          validatorVote: fee.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          epochSchedule: web3.SYSVAR_EPOCH_SCHEDULE_PUBKEY,
+        })
+        .rpc()).to.be.rejectedWith(/AccountOwnedByWrongProgram/);
+    });
+  });
+
+  describe("Imports and migration", () => {
+    const pool_authority = Keypair.generate();
+    const source_stake_account = Keypair.generate();
+    const destination_stake_account = Keypair.generate();
+    const other_mint_account = Keypair.generate();
+
+    before(async () => {
+      await create_token(other_mint_account, other_mint.publicKey, provider.wallet.publicKey);
+    });
+
+    async function import_from_stake_pool(signer: Keypair, accounts: object) {
+      await program.methods.importFromStakePool()
+        .accounts({
+          lido: lido.publicKey,
+          manager: signer.publicKey,
+          validatorVote: vote.publicKey,
+          poolAuthority: pool_authority.publicKey,
+          sourceStakeAccount: source_stake_account.publicKey,
+          destinationStakeAccount: destination_stake_account.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          recipient: treasury.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+          stakeHistory: web3.SYSVAR_STAKE_HISTORY_PUBKEY,
+          stakeProgram: web3.StakeProgram.programId,
+          ...accounts,
+        })
+        .signers([signer, pool_authority])
+        .rpc();
+    }
+
+    it("Should NOT import from a stake pool when not signed by the manager", async () => {
+      await expect(import_from_stake_pool(not_manager, {})).to.be.rejectedWith(/InvalidManager/);
+    });
+
+    it("Should NOT import from a stake pool with another mint", async () => {
+      await expect(import_from_stake_pool(manager, {stSolMint: other_mint.publicKey}))
+        .to.be.rejectedWith(/InvalidMint/);
+    });
+
+    it("Should NOT import from a stake pool to a recipient of another mint", async () => {
+      await expect(import_from_stake_pool(manager, {recipient: other_mint_account.publicKey}))
+        .to.be.rejectedWith(/InvalidStSolAccount/);
+    });
+
+    it("Should NOT import from a stake pool with a stake authority that is not the program address", async () => {
+      await expect(import_from_stake_pool(manager, {stakeAuthority: await find_authority(lido.publicKey, "mint_authority")}))
+        .to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    async function migrate_from_solido_v1(signer: Keypair, accounts: object) {
+      const solido_v1 = Keypair.generate();
+      await program.methods.migrateFromSolidoV1()
+        .accounts({
+          lido: lido.publicKey,
+          manager: signer.publicKey,
+          solidoV1: solido_v1.publicKey,
+          solidoV1Reserve: solido_v1.publicKey,
+          currentStSolMint: st_sol_mint.publicKey,
+          stSolMint: other_mint.publicKey,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+          ...accounts,
+        })
+        .signers([signer])
+        .rpc();
+    }
+
+    it("Should NOT migrate from Solido v1 when not signed by the manager", async () => {
+      await expect(migrate_from_solido_v1(not_manager, {})).to.be.rejectedWith(/InvalidManager/);
+    });
+
+    it("Should NOT migrate from Solido v1 replacing a mint other than the current one", async () => {
+      await expect(migrate_from_solido_v1(manager, {currentStSolMint: other_mint.publicKey}))
+        .to.be.rejectedWith(/InvalidMint/);
+    });
+
+    it("Should NOT migrate from Solido v1 with a mint authority that is not the program address", async () => {
+      await expect(migrate_from_solido_v1(manager, {mintAuthority: await find_authority(lido.publicKey, "stake_authority")}))
+        .to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should NOT migrate from Solido v1 with a compliance authority that is not the program address", async () => {
+      await expect(migrate_from_solido_v1(manager, {complianceAuthority: await find_authority(lido.publicKey, "mint_authority")}))
+        .to.be.rejectedWith(/ConstraintSeeds/);
+    });
+  });
+
+  describe("Reserve attestation", () => {
+    function attest_reserves(reserve: PublicKey) {
+      return program.methods.attestReserves()
+        .accounts({
+          lido: lido.publicKey,
+          reserve: reserve,
+          stSolMint: st_sol_mint.publicKey,
+          payer: provider.wallet.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .rpc();
+    }
+
+    it("Should NOT attest the balance of an account other than the reserve", async () => {
+      await expect(attest_reserves(provider.wallet.publicKey)).to.be.rejectedWith(/ConstraintSeeds/);
+    });
+
+    it("Should attest the balance of the reserve, owned by the system program", async () => {
+      const reserve = await find_authority(lido.publicKey, "reserve_account");
+      const reserveInfo = await provider.connection.getAccountInfo(reserve);
+      expect(reserveInfo.owner.toBase58()).to.be.equal(web3.SystemProgram.programId.toBase58());
+
+      await attest_reserves(reserve);
+
+      const attestation = await program.account.reserveAttestation.fetch(
+        await find_authority(lido.publicKey, "reserve_attestation"));
+      expect(attestation.lido).to.be.deep.equal(lido.publicKey);
+      expect(attestation.reserveBalance.amount.toNumber()).to.be.equal(reserveInfo.lamports);
+      // The only validator has no stake accounts yet.
+      expect(attestation.validators.length).to.be.equal(1);
+      expect(attestation.validators[0].validatorVote).to.be.deep.equal(vote.publicKey);
+      expect(attestation.validators[0].balance.amount.toNumber()).to.be.equal(0);
+      expect(attestation.totalSol.amount.toNumber()).to.be.equal(reserveInfo.lamports);
+    });
+  });
+
+  describe("Protocol treasury", () => {
+    it("Should NOT create the protocol treasury when not signed by the manager", async () => {
+      await expect(program.methods.initializeProtocolTreasury()
+        .accounts({
+          lido: lido.publicKey,
+          manager: not_manager.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          payer: provider.wallet.publicKey,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([not_manager])
+        .rpc()).to.be.rejectedWith(/InvalidManager/);
+    });
+
+    it("Should pay the treasury fee into the protocol treasury, withdrawable only after the delay", async () => {
+      await program.methods.initializeProtocolTreasury()
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          payer: provider.wallet.publicKey,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([manager])
+        .rpc();
+
+      const protocol_treasury = await find_authority(lido.publicKey, "protocol_treasury");
+      const lidoAccount = await program.account.lido.fetch(lido.publicKey);
+      expect(lidoAccount.feeRecipients.treasuryAccount.toBase58()).to.be.equal(protocol_treasury.toBase58());
+
+      await program.methods.scheduleTreasuryWithdrawal({amount: new BN(1000)})
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          recipient: treasury.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([manager])
+        .rpc();
+
+      await expect(program.methods.withdrawTreasury()
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          recipient: treasury.publicKey,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([manager])
+        .rpc()).to.be.rejectedWith(/TreasuryWithdrawalNotYetEffective/);
+    });
+  });
+});
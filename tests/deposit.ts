@@ -1,6 +1,7 @@
 import * as anchor from "@project-serum/anchor";
 import {Program, web3, BN} from "@project-serum/anchor";
 import {PublicKey, Keypair} from '@solana/web3.js';
+import {ASSOCIATED_TOKEN_PROGRAM_ID, Token, TOKEN_PROGRAM_ID} from '@solana/spl-token';
 import {Asolido} from "../target/types/asolido";
 
 import {expect} from 'chai';
@@ -111,7 +112,7 @@ describe("Deposit", () => {
 
     // Initialize Lido
     await program.methods
-      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000)
+      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000, {none: {}}, {stSol: {}})
       .accounts({
         lido: lido.publicKey,
         manager: manager.publicKey,
@@ -152,12 +153,14 @@ describe("Deposit", () => {
     await fund(user.publicKey, TEST_DEPOSIT_AMOUNT);
 
     await program.methods
-      .deposit(new BN(TEST_DEPOSIT_AMOUNT))
+      .deposit(new BN(TEST_DEPOSIT_AMOUNT), {amount: new BN(0)}, null)
       .accounts({
         lido: lido.publicKey,
         user: user.publicKey,
         recipient: recipient.publicKey,
         stSolMint: st_sol_mint.publicKey,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
       })
       .signers([user])
       .rpc();
@@ -178,4 +181,329 @@ describe("Deposit", () => {
     console.log(lidoAccount.metrics);
     expect(lidoAccount.metrics.depositAmount.total.amount.toNumber()).to.be.equal(TEST_DEPOSIT_AMOUNT);
   });
-});
\ No newline at end of file
+
+  it("Should deposit on behalf of another owner", async () => {
+    // The sponsor pays the SOL, but the stSOL goes to an account owned by
+    // somebody else, who does not need to sign.
+    const sponsor = Keypair.generate();
+    const owner = Keypair.generate();
+    const recipient = Keypair.generate();
+    await create_token(recipient, st_sol_mint.publicKey, owner.publicKey);
+    await fund(sponsor.publicKey, TEST_DEPOSIT_AMOUNT);
+    const sponsorBalanceBefore = await provider.connection.getBalance(sponsor.publicKey);
+
+    await program.methods
+      .deposit(new BN(TEST_DEPOSIT_AMOUNT), {amount: new BN(0)}, null)
+      .accounts({
+        lido: lido.publicKey,
+        user: sponsor.publicKey,
+        recipient: recipient.publicKey,
+        stSolMint: st_sol_mint.publicKey,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+      })
+      .signers([sponsor])
+      .rpc();
+
+    const sponsorBalanceAfter = await provider.connection.getBalance(sponsor.publicKey);
+    expect(sponsorBalanceBefore - sponsorBalanceAfter).to.be.equal(TEST_DEPOSIT_AMOUNT);
+    const recipientAccount = await spl_token.account.token.fetch(recipient.publicKey);
+    expect(recipientAccount.authority.toBase58()).to.be.equal(owner.publicKey.toBase58());
+    expect(recipientAccount.amount.toNumber()).to.be.equal(TEST_DEPOSIT_AMOUNT);
+  });
+
+  it("Should include the memo in the Deposited event", async () => {
+    const user = Keypair.generate();
+    const recipient = Keypair.generate();
+    await create_token(recipient, st_sol_mint.publicKey, user.publicKey);
+    await fund(user.publicKey, TEST_DEPOSIT_AMOUNT);
+    const memo = Array.from(Buffer.from("exchange-user-0000000000000042!!"));
+
+    const signature = await program.methods
+      .deposit(new BN(TEST_DEPOSIT_AMOUNT), {amount: new BN(0)}, memo)
+      .accounts({
+        lido: lido.publicKey,
+        user: user.publicKey,
+        recipient: recipient.publicKey,
+        stSolMint: st_sol_mint.publicKey,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+      })
+      .signers([user])
+      .rpc({commitment: "confirmed"});
+
+    const transaction = await provider.connection.getTransaction(signature, {commitment: "confirmed"});
+    const events = [];
+    new anchor.EventParser(program.programId, program.coder)
+      .parseLogs(transaction.meta.logMessages, (event) => events.push(event));
+    const deposited = events.find((event) => event.name === "Deposited");
+    expect(deposited.data.recipient).to.be.deep.equal(recipient.publicKey);
+    expect(deposited.data.memo).to.be.deep.equal(memo);
+  });
+
+  it("Should not deposit if less than the minimum stSOL would be minted", async () => {
+    const user = Keypair.generate();
+    const recipient = Keypair.generate();
+    await create_token(recipient, st_sol_mint.publicKey, user.publicKey);
+    await fund(user.publicKey, TEST_DEPOSIT_AMOUNT);
+
+    // The exchange rate is 1, so the deposit mints exactly its amount in stSOL.
+    await expect(program.methods
+      .deposit(new BN(TEST_DEPOSIT_AMOUNT), {amount: new BN(TEST_DEPOSIT_AMOUNT + 1)}, null)
+      .accounts({
+        lido: lido.publicKey,
+        user: user.publicKey,
+        recipient: recipient.publicKey,
+        stSolMint: st_sol_mint.publicKey,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+      })
+      .signers([user])
+      .rpc()).to.be.rejectedWith(/SlippageExceeded/);
+
+    await program.methods
+      .deposit(new BN(TEST_DEPOSIT_AMOUNT), {amount: new BN(TEST_DEPOSIT_AMOUNT)}, null)
+      .accounts({
+        lido: lido.publicKey,
+        user: user.publicKey,
+        recipient: recipient.publicKey,
+        stSolMint: st_sol_mint.publicKey,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+      })
+      .signers([user])
+      .rpc();
+
+    const recipientAccount = await spl_token.account.token.fetch(recipient.publicKey);
+    expect(recipientAccount.amount.toNumber()).to.be.equal(TEST_DEPOSIT_AMOUNT);
+  });
+
+  it("Should not deposit while deposits are paused or below the minimum deposit", async () => {
+    const user = Keypair.generate();
+    const recipient = Keypair.generate();
+    await create_token(recipient, st_sol_mint.publicKey, user.publicKey);
+    await fund(user.publicKey, TEST_DEPOSIT_AMOUNT);
+
+    async function update_config(depositsPaused: boolean, minDeposit: number) {
+      await program.methods
+        .updateConfig({
+          minDeposit: {amount: new BN(minDeposit)},
+          minReserveBalance: {amount: new BN(0)},
+          depositsPaused: depositsPaused,
+          withdrawalsPaused: false,
+          maxInstantWithdrawal: {amount: new BN(0)},
+          maxStakePerEpoch: {amount: new BN(0)},
+          maxExchangeRateChangeBps: 0,
+          idleReserveFeeAdjustment: false,
+          rejectCpi: false,
+          staleExchangeRateGuard: false,
+          crankTip: {amount: new BN(0)},
+          minEpochsBeforeStake: new BN(0),
+        })
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+        })
+        .signers([manager])
+        .rpc();
+    }
+
+    function deposit() {
+      return program.methods
+        .deposit(new BN(TEST_DEPOSIT_AMOUNT), {amount: new BN(0)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          user: user.publicKey,
+          recipient: recipient.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([user])
+        .rpc();
+    }
+
+    await update_config(true, 0);
+    await expect(deposit()).to.be.rejectedWith(/DepositsPaused/);
+
+    await update_config(false, TEST_DEPOSIT_AMOUNT + 1);
+    await expect(deposit()).to.be.rejectedWith(/DepositTooSmall/);
+
+    await update_config(false, 0);
+    await deposit();
+  });
+
+  it("Should deposit directly when deposits through CPI are rejected", async () => {
+    const user = Keypair.generate();
+    const recipient = Keypair.generate();
+    await create_token(recipient, st_sol_mint.publicKey, user.publicKey);
+    await fund(user.publicKey, TEST_DEPOSIT_AMOUNT);
+
+    async function update_config(rejectCpi: boolean) {
+      await program.methods
+        .updateConfig({
+          minDeposit: {amount: new BN(0)},
+          minReserveBalance: {amount: new BN(0)},
+          depositsPaused: false,
+          withdrawalsPaused: false,
+          maxInstantWithdrawal: {amount: new BN(0)},
+          maxStakePerEpoch: {amount: new BN(0)},
+          maxExchangeRateChangeBps: 0,
+          idleReserveFeeAdjustment: false,
+          rejectCpi: rejectCpi,
+          staleExchangeRateGuard: false,
+          crankTip: {amount: new BN(0)},
+          minEpochsBeforeStake: new BN(0),
+        })
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+        })
+        .signers([manager])
+        .rpc();
+    }
+
+    function deposit(instructions: PublicKey) {
+      return program.methods
+        .deposit(new BN(TEST_DEPOSIT_AMOUNT), {amount: new BN(0)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          user: user.publicKey,
+          recipient: recipient.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          instructions: instructions,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([user])
+        .rpc();
+    }
+
+    await update_config(true);
+    // The guard reads the sysvar, so it must not be replaced by an account that the caller controls.
+    await expect(deposit(Keypair.generate().publicKey)).to.be.rejectedWith(/ConstraintAddress/);
+    // A top-level instruction of this program is not a CPI.
+    await deposit(web3.SYSVAR_INSTRUCTIONS_PUBKEY);
+    await update_config(false);
+
+    const recipientAccount = await spl_token.account.token.fetch(recipient.publicKey);
+    expect(recipientAccount.amount.toNumber()).to.be.equal(TEST_DEPOSIT_AMOUNT);
+  });
+
+  it("Should only invoke the deposit hook once the manager enables it", async () => {
+    const user = Keypair.generate();
+    const recipient = Keypair.generate();
+    await create_token(recipient, st_sol_mint.publicKey, user.publicKey);
+    await fund(user.publicKey, TEST_DEPOSIT_AMOUNT);
+
+    const [depositHook, _deposit_hook_nonce] = await PublicKey.findProgramAddress(
+      [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("deposit_hook"))], program.programId);
+    const partner = Keypair.generate().publicKey;
+    const hookAccounts = [
+      {source: {user: {}}, pubkey: PublicKey.default, isWritable: false},
+      {source: {recipient: {}}, pubkey: PublicKey.default, isWritable: true},
+    ];
+
+    function set_deposit_hook(programId: PublicKey, signer: Keypair) {
+      return program.methods
+        .setDepositHook(false, programId, [1, 2, 3, 4, 5, 6, 7, 8], hookAccounts)
+        .accounts({
+          lido: lido.publicKey,
+          manager: signer.publicKey,
+          depositHook: depositHook,
+        })
+        .signers([signer])
+        .rpc();
+    }
+
+    await fund(manager.publicKey, 100000000);
+    await expect(set_deposit_hook(partner, user)).to.be.rejectedWith(/InvalidManager/);
+    await expect(set_deposit_hook(program.programId, manager)).to.be.rejectedWith(/InvalidDepositHook/);
+    await set_deposit_hook(partner, manager);
+
+    const hook = await program.account.depositHook.fetch(depositHook);
+    expect(hook.enabled).to.be.false;
+    expect(hook.programId.toBase58()).to.be.equal(partner.toBase58());
+    expect(hook.accounts.length).to.be.equal(2);
+
+    function deposit(remainingAccounts) {
+      return program.methods
+        .deposit(new BN(TEST_DEPOSIT_AMOUNT / 2), {amount: new BN(0)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          user: user.publicKey,
+          recipient: recipient.publicKey,
+          stSolMint: st_sol_mint.publicKey,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .remainingAccounts(remainingAccounts)
+        .signers([user])
+        .rpc();
+    }
+
+    // The hook is disabled, so deposits that pass it fail, and others are not affected.
+    await expect(deposit([
+      {pubkey: depositHook, isSigner: false, isWritable: false},
+      {pubkey: partner, isSigner: false, isWritable: false},
+    ])).to.be.rejectedWith(/DepositHookDisabled/);
+    await deposit([]);
+
+    const recipientAccount = await spl_token.account.token.fetch(recipient.publicKey);
+    expect(recipientAccount.amount.toNumber()).to.be.equal(TEST_DEPOSIT_AMOUNT / 2);
+  });
+
+  it("Should not deposit from an account that is not owned by the system program", async () => {
+    // The token account signs, but it is owned by the token program, so the
+    // system program cannot transfer from it.
+    const owner = Keypair.generate();
+    const source = Keypair.generate();
+    await create_token(source, st_sol_mint.publicKey, owner.publicKey);
+
+    await expect(program.methods
+      .deposit(new BN(TEST_DEPOSIT_AMOUNT), {amount: new BN(0)}, null)
+      .accounts({
+        lido: lido.publicKey,
+        user: source.publicKey,
+        recipient: source.publicKey,
+        stSolMint: st_sol_mint.publicKey,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        clock: web3.SYSVAR_CLOCK_PUBKEY,
+      })
+      .signers([source])
+      .rpc()).to.be.rejectedWith(/InvalidDepositSource/);
+  });
+
+  it("Should deposit to an associated token account, creating it if needed", async () => {
+    // The user pays for the deposit, and for the associated stSOL account of
+    // the owner, which does not exist yet.
+    const user = Keypair.generate();
+    const owner = Keypair.generate();
+    await fund(user.publicKey, 10 * TEST_DEPOSIT_AMOUNT);
+
+    const recipient = await Token.getAssociatedTokenAddress(
+      ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, st_sol_mint.publicKey, owner.publicKey);
+    expect(await provider.connection.getAccountInfo(recipient)).to.be.null;
+
+    // The second deposit goes to the account that the first one created.
+    for (let i = 0; i < 2; i++) {
+      await program.methods
+        .depositToAssociatedAccount(new BN(TEST_DEPOSIT_AMOUNT), {amount: new BN(0)}, null)
+        .accounts({
+          lido: lido.publicKey,
+          user: user.publicKey,
+          recipientOwner: owner.publicKey,
+          recipient: recipient,
+          stSolMint: st_sol_mint.publicKey,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+          clock: web3.SYSVAR_CLOCK_PUBKEY,
+        })
+        .signers([user])
+        .rpc();
+    }
+
+    const recipientAccount = await spl_token.account.token.fetch(recipient);
+    expect(recipientAccount.authority.toBase58()).to.be.equal(owner.publicKey.toBase58());
+    expect(recipientAccount.amount.toNumber()).to.be.equal(2 * TEST_DEPOSIT_AMOUNT);
+  });
+});
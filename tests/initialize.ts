@@ -24,9 +24,9 @@ describe("Initialize anchored-solido", () => {
   const treasury = Keypair.generate();
   const developer = Keypair.generate();
 
-  async function create_mint(mint: Keypair, mint_authority: PublicKey) {
+  async function create_mint(mint: Keypair, mint_authority: PublicKey, freeze_authority: PublicKey = null) {
     await spl_token.methods
-      .initializeMint(9, mint_authority, null)
+      .initializeMint(9, mint_authority, freeze_authority)
       .accounts({
         mint: mint.publicKey,
         rent: web3.SYSVAR_RENT_PUBKEY,
@@ -83,7 +83,7 @@ describe("Initialize anchored-solido", () => {
         validationFee: 3,
         developerFee: 2,
         stSolAppreciation: 90
-      }, max_validators, max_maintainers)
+      }, max_validators, max_maintainers, {none: {}}, {stSol: {}})
       .accounts({
         lido: lido.publicKey,
         manager: manager.publicKey,
@@ -97,9 +97,16 @@ describe("Initialize anchored-solido", () => {
     const lidoAccount = await program.account.lido.fetch(lido.publicKey);
     expect(lidoAccount.manager).to.be.deep.equal(manager.publicKey);
     expect(lidoAccount.stSolMint).to.be.deep.equal(st_sol_mint.publicKey);
+    expect(lidoAccount.freezeAuthorityPolicy).to.be.deep.equal({none: {}});
+    expect(lidoAccount.feeDenomination).to.be.deep.equal({stSol: {}});
     expect(lidoAccount.feeRecipients.treasuryAccount).to.be.deep.equal(treasury.publicKey);
     expect(lidoAccount.feeRecipients.developerAccount).to.be.deep.equal(developer.publicKey);
 
+    // The exchange rate of a new instance counts as computed in the current epoch.
+    const epochInfo = await provider.connection.getEpochInfo();
+    expect(lidoAccount.exchangeRate.computedInEpoch.toNumber()).to.be.equal(epochInfo.epoch);
+    expect(lidoAccount.exchangeRate.stSolSupply.amount.toNumber()).to.be.equal(0);
+
     const [reserve, _reserve_nonce] = await PublicKey.findProgramAddress(
       [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("reserve_account"))], program.programId);
 
@@ -107,6 +114,46 @@ describe("Initialize anchored-solido", () => {
     const rentExempt = await provider.connection.getMinimumBalanceForRentExemption(0);
 
     expect(reserveBalance).to.be.equal(rentExempt);
+
+    // The bump seeds that Anchor found for the program addresses are recorded.
+    const bump_seed = async (seed: string) => {
+      const [_address, nonce] = await PublicKey.findProgramAddress(
+        [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode(seed))], program.programId);
+      return nonce;
+    };
+    expect(lidoAccount.solReserveAccountBumpSeed).to.be.equal(await bump_seed("reserve_account"));
+    expect(lidoAccount.mintAuthorityBumpSeed).to.be.equal(await bump_seed("mint_authority"));
+    expect(lidoAccount.stakeAuthorityBumpSeed).to.be.equal(await bump_seed("stake_authority"));
+    expect(lidoAccount.rewardsWithdrawAuthorityBumpSeed).to.be.equal(await bump_seed("rewards_withdraw_authority"));
+  });
+
+  it("Should initialize the exchange rate history", async () => {
+    const [exchangeRateHistory, _history_nonce] = await PublicKey.findProgramAddress(
+      [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("exchange_rate_history"))], program.programId);
+
+    await program.methods
+      .initializeExchangeRateHistory()
+      .accounts({
+        lido: lido.publicKey,
+        exchangeRateHistory: exchangeRateHistory,
+        payer: provider.wallet.publicKey,
+      })
+      .rpc();
+
+    const historyAccount = await program.account.exchangeRateHistory.fetch(exchangeRateHistory);
+    expect(historyAccount.lido).to.be.deep.equal(lido.publicKey);
+    expect(historyAccount.numRecorded.toNumber()).to.be.equal(0);
+    expect(historyAccount.rates.length).to.be.equal(32);
+
+    // The history of an instance can only be created once.
+    await expect(program.methods
+      .initializeExchangeRateHistory()
+      .accounts({
+        lido: lido.publicKey,
+        exchangeRateHistory: exchangeRateHistory,
+        payer: provider.wallet.publicKey,
+      })
+      .rpc()).to.be.rejected;
   });
 
   it("Should NOT initialize with incorrect mint", async () => {
@@ -123,7 +170,7 @@ describe("Initialize anchored-solido", () => {
     await create_token(developer1, st_sol_mint1.publicKey, provider.wallet.publicKey);
 
     await expect(program.methods
-      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000)
+      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000, {none: {}}, {stSol: {}})
       .accounts({
         lido: lido1.publicKey,
         manager: manager.publicKey,
@@ -135,6 +182,77 @@ describe("Initialize anchored-solido", () => {
       .rpc()).to.be.rejectedWith(/InvalidMint/);
   });
 
+  it("Should NOT initialize with a freeze authority if the policy is none", async () => {
+    const lido1 = Keypair.generate();
+    const st_sol_mint1 = Keypair.generate();
+    const treasury1 = Keypair.generate();
+    const developer1 = Keypair.generate();
+
+    const [mint_authority, _nonce] = await PublicKey.findProgramAddress(
+      [lido1.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("mint_authority"))], program.programId);
+
+    // Create mint that anybody with the wallet key can freeze
+    await create_mint(st_sol_mint1, mint_authority, provider.wallet.publicKey);
+    await create_token(treasury1, st_sol_mint1.publicKey, provider.wallet.publicKey);
+    await create_token(developer1, st_sol_mint1.publicKey, provider.wallet.publicKey);
+
+    await expect(program.methods
+      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000, {none: {}}, {stSol: {}})
+      .accounts({
+        lido: lido1.publicKey,
+        manager: manager.publicKey,
+        stSolMint: st_sol_mint1.publicKey,
+        treasury: treasury1.publicKey,
+        developer: developer1.publicKey,
+      })
+      .signers([lido1])
+      .rpc()).to.be.rejectedWith(/InvalidFreezeAuthority/);
+  });
+
+  it("Should initialize with the compliance authority as freeze authority", async () => {
+    const lido1 = Keypair.generate();
+    const st_sol_mint1 = Keypair.generate();
+    const treasury1 = Keypair.generate();
+    const developer1 = Keypair.generate();
+
+    const [mint_authority, _nonce] = await PublicKey.findProgramAddress(
+      [lido1.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("mint_authority"))], program.programId);
+    const [compliance_authority, _compliance_nonce] = await PublicKey.findProgramAddress(
+      [lido1.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("compliance_authority"))], program.programId);
+
+    await create_mint(st_sol_mint1, mint_authority, compliance_authority);
+    await create_token(treasury1, st_sol_mint1.publicKey, provider.wallet.publicKey);
+    await create_token(developer1, st_sol_mint1.publicKey, provider.wallet.publicKey);
+
+    // Without the compliance policy, a freeze authority is not allowed.
+    await expect(program.methods
+      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000, {none: {}}, {stSol: {}})
+      .accounts({
+        lido: lido1.publicKey,
+        manager: manager.publicKey,
+        stSolMint: st_sol_mint1.publicKey,
+        treasury: treasury1.publicKey,
+        developer: developer1.publicKey,
+      })
+      .signers([lido1])
+      .rpc()).to.be.rejectedWith(/InvalidFreezeAuthority/);
+
+    await program.methods
+      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000, {complianceAuthority: {}}, {stSol: {}})
+      .accounts({
+        lido: lido1.publicKey,
+        manager: manager.publicKey,
+        stSolMint: st_sol_mint1.publicKey,
+        treasury: treasury1.publicKey,
+        developer: developer1.publicKey,
+      })
+      .signers([lido1])
+      .rpc();
+
+    const lidoAccount = await program.account.lido.fetch(lido1.publicKey);
+    expect(lidoAccount.freezeAuthorityPolicy).to.be.deep.equal({complianceAuthority: {}});
+  });
+
   it("Should NOT initialize with incorrect treasury", async () => {
     const lido1 = Keypair.generate();
     const st_sol_mint1 = Keypair.generate();
@@ -148,7 +266,7 @@ describe("Initialize anchored-solido", () => {
     await create_mint(st_sol_mint1, mint_authority);
 
     await expect(program.methods
-      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000)
+      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000, {none: {}}, {stSol: {}})
       .accounts({
         lido: lido1.publicKey,
         manager: manager.publicKey,
@@ -159,4 +277,62 @@ describe("Initialize anchored-solido", () => {
       .signers([lido1])
       .rpc()).to.be.rejectedWith(/InvalidFeeRecipient/);
   });
+
+  it("Should change the reward distribution, but NOT to an empty one", async () => {
+    await expect(program.methods
+      .changeRewardDistribution({treasuryFee: 0, validationFee: 0, developerFee: 0, stSolAppreciation: 0})
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+      })
+      .signers([manager])
+      .rpc()).to.be.rejectedWith(/InvalidRewardDistribution/);
+
+    await program.methods
+      .changeRewardDistribution({treasuryFee: 400, validationFee: 300, developerFee: 100, stSolAppreciation: 9200})
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+      })
+      .signers([manager])
+      .rpc();
+
+    const lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    expect(lidoAccount.rewardDistribution.treasuryFee).to.be.equal(400);
+    expect(lidoAccount.rewardDistribution.stSolAppreciation).to.be.equal(9200);
+  });
+
+  it("Should pay the treasury in SOL only to a system account", async () => {
+    function change_fee_recipients(treasuryAccount: PublicKey, treasuryDenomination, developerAccount: PublicKey) {
+      return program.methods
+        .changeFeeRecipients(treasuryDenomination, {stSol: {}})
+        .accounts({
+          lido: lido.publicKey,
+          manager: manager.publicKey,
+          treasury: treasuryAccount,
+          developer: developerAccount,
+        })
+        .signers([manager])
+        .rpc();
+    }
+
+    const solTreasury = provider.wallet.publicKey;
+    await expect(change_fee_recipients(treasury.publicKey, {sol: {}}, developer.publicKey))
+      .to.be.rejectedWith(/InvalidFeeRecipient/);
+    await expect(change_fee_recipients(solTreasury, {stSol: {}}, developer.publicKey))
+      .to.be.rejectedWith(/InvalidFeeRecipient/);
+    await expect(change_fee_recipients(solTreasury, {sol: {}}, solTreasury))
+      .to.be.rejectedWith(/InvalidFeeRecipient/);
+
+    await change_fee_recipients(solTreasury, {sol: {}}, developer.publicKey);
+    let lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    expect(lidoAccount.feeRecipients.treasuryAccount).to.be.deep.equal(solTreasury);
+    expect(lidoAccount.feeRecipients.treasuryFeeDenomination).to.be.deep.equal({sol: {}});
+    expect(lidoAccount.feeRecipients.developerFeeDenomination).to.be.deep.equal({stSol: {}});
+
+    await change_fee_recipients(treasury.publicKey, {stSol: {}}, developer.publicKey);
+    lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    expect(lidoAccount.feeRecipients.treasuryAccount).to.be.deep.equal(treasury.publicKey);
+    expect(lidoAccount.feeRecipients.treasuryFeeDenomination).to.be.deep.equal({stSol: {}});
+  });
 });
@@ -62,7 +62,7 @@ describe("Maintainers", () => {
 
     // Initialize Lido
     await program.methods
-      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000)
+      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10000, 1000, {none: {}}, {stSol: {}})
       .accounts({
         lido: lido.publicKey,
         manager: manager.publicKey,
@@ -128,4 +128,49 @@ describe("Maintainers", () => {
 
   })
 
+  it("Should set maintainers in one instruction", async () => {
+    await program.methods.setMaintainers([maintainer1.publicKey, maintainer2.publicKey])
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+      })
+      .signers([manager])
+      .rpc();
+
+    let lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    expect(lidoAccount.maintainers.entries).to.be.deep.equal([maintainer1.publicKey, maintainer2.publicKey]);
+
+    // Only the difference is applied: maintainer1 is removed, maintainer2 stays.
+    await program.methods.setMaintainers([maintainer2.publicKey])
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+      })
+      .signers([manager])
+      .rpc();
+
+    lidoAccount = await program.account.lido.fetch(lido.publicKey);
+    expect(lidoAccount.maintainers.entries).to.be.deep.equal([maintainer2.publicKey]);
+  });
+
+  it("Should NOT set maintainers with a duplicate", async () => {
+    await expect(program.methods.setMaintainers([maintainer1.publicKey, maintainer1.publicKey])
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+      })
+      .signers([manager])
+      .rpc()).to.be.rejectedWith(/DuplicatedEntry/);
+  });
+
+  it("Should NOT set maintainers when not signed by the manager", async () => {
+    await expect(program.methods.setMaintainers([])
+      .accounts({
+        lido: lido.publicKey,
+        manager: maintainer2.publicKey,
+      })
+      .signers([maintainer2])
+      .rpc()).to.be.rejectedWith(/InvalidManager/);
+  });
+
 });
\ No newline at end of file
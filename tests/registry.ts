@@ -0,0 +1,145 @@
+import * as anchor from "@project-serum/anchor";
+import {Program, web3} from "@project-serum/anchor";
+import {PublicKey, Keypair} from '@solana/web3.js';
+import {Asolido} from "../target/types/asolido";
+import {findRegistryAddress, isOfficialInstance, resolveCanonicalInstance} from "../app/registry";
+
+import {expect} from 'chai';
+import * as chai from 'chai';
+import chaiAsPromised from 'chai-as-promised';
+
+chai.use(chaiAsPromised);
+
+describe("Registry", () => {
+
+  // Configure the client to use the local cluster.
+  anchor.setProvider(anchor.Provider.env());
+  const provider = anchor.getProvider();
+  const program = anchor.workspace.Asolido as Program<Asolido>;
+  const spl_token = anchor.Spl.token();
+
+  const authority = Keypair.generate();
+
+  const lido = Keypair.generate();
+  const manager = Keypair.generate();
+  const lookalike = Keypair.generate();
+  const lookalike_manager = Keypair.generate();
+
+  async function create_mint(mint: Keypair, mint_authority: PublicKey) {
+    await spl_token.methods
+      .initializeMint(9, mint_authority, null)
+      .accounts({
+        mint: mint.publicKey,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .signers([mint])
+      .preInstructions([await spl_token.account.mint.createInstruction(mint)])
+      .rpc();
+  }
+
+  async function create_token(token: Keypair, mint: PublicKey, authority: PublicKey) {
+    await spl_token.methods.initializeAccount()
+      .accounts({
+        account: token.publicKey,
+        mint: mint,
+        authority: authority,
+        rent: web3.SYSVAR_RENT_PUBKEY,
+      })
+      .signers([token])
+      .preInstructions([await spl_token.account.token.createInstruction(token)])
+      .rpc();
+  }
+
+  async function initialize_lido(lido: Keypair, manager: PublicKey) {
+    const st_sol_mint = Keypair.generate();
+    const treasury = Keypair.generate();
+    const developer = Keypair.generate();
+
+    const [mint_authority, _nonce] = await PublicKey.findProgramAddress(
+      [lido.publicKey.toBuffer(), Buffer.from(anchor.utils.bytes.utf8.encode("mint_authority"))], program.programId);
+
+    await create_mint(st_sol_mint, mint_authority);
+    await create_token(treasury, st_sol_mint.publicKey, provider.wallet.publicKey);
+    await create_token(developer, st_sol_mint.publicKey, provider.wallet.publicKey);
+
+    await program.methods
+      .initialize({treasuryFee: 5, validationFee: 3, developerFee: 2, stSolAppreciation: 90}, 10, 10, {none: {}}, {stSol: {}})
+      .accounts({
+        lido: lido.publicKey,
+        manager: manager,
+        stSolMint: st_sol_mint.publicKey,
+        treasury: treasury.publicKey,
+        developer: developer.publicKey,
+      })
+      .signers([lido])
+      .rpc();
+  }
+
+  before(async () => {
+    await initialize_lido(lido, manager.publicKey);
+    await initialize_lido(lookalike, lookalike_manager.publicKey);
+
+    await provider.send(
+      new web3.Transaction().add(web3.SystemProgram.transfer({
+        fromPubkey: provider.wallet.publicKey,
+        toPubkey: authority.publicKey,
+        lamports: web3.LAMPORTS_PER_SOL,
+      })));
+
+    await program.methods
+      .initializeRegistry(4)
+      .accounts({
+        registry: await findRegistryAddress(program.programId, authority.publicKey),
+        authority: authority.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+  });
+
+  it("Should NOT register an instance without the signature of its manager", async () => {
+    await expect(program.methods
+      .registerInstance(true)
+      .accounts({
+        registry: await findRegistryAddress(program.programId, authority.publicKey),
+        authority: authority.publicKey,
+        lido: lookalike.publicKey,
+        manager: manager.publicKey,
+      })
+      .signers([authority, manager])
+      .rpc()).to.be.rejectedWith(/InvalidManager/);
+
+    expect(await isOfficialInstance(program, authority.publicKey, lookalike.publicKey)).to.be.false;
+  });
+
+  it("Should register the canonical instance", async () => {
+    await program.methods
+      .registerInstance(true)
+      .accounts({
+        registry: await findRegistryAddress(program.programId, authority.publicKey),
+        authority: authority.publicKey,
+        lido: lido.publicKey,
+        manager: manager.publicKey,
+      })
+      .signers([authority, manager])
+      .rpc();
+
+    expect(await isOfficialInstance(program, authority.publicKey, lido.publicKey)).to.be.true;
+    const canonical = await resolveCanonicalInstance(program, authority.publicKey);
+    expect(canonical.toBase58()).to.be.equal(lido.publicKey.toBase58());
+  });
+
+  it("Should unregister the canonical instance", async () => {
+    await program.methods
+      .unregisterInstance()
+      .accounts({
+        registry: await findRegistryAddress(program.programId, authority.publicKey),
+        authority: authority.publicKey,
+        lido: lido.publicKey,
+      })
+      .signers([authority])
+      .rpc();
+
+    expect(await isOfficialInstance(program, authority.publicKey, lido.publicKey)).to.be.false;
+    expect(await resolveCanonicalInstance(program, authority.publicKey)).to.be.null;
+  });
+});